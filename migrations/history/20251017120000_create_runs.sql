@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS runs (
+	id TEXT PRIMARY KEY NOT NULL,
+	pipeline_name TEXT NOT NULL,
+	branch TEXT,
+	commit_id TEXT,
+	status TEXT NOT NULL,
+	started_at INTEGER NOT NULL,
+	finished_at INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS runs_pipeline_started ON runs (pipeline_name, started_at);
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS run_notes (
+	id INTEGER PRIMARY KEY NOT NULL,
+	run_id TEXT NOT NULL,
+	body TEXT NOT NULL,
+	created_at INTEGER NOT NULL,
+	FOREIGN KEY (run_id) REFERENCES runs(id)
+);
+
+CREATE INDEX IF NOT EXISTS run_notes_run ON run_notes (run_id, created_at);
@@ -0,0 +1,13 @@
+ALTER TABLE runs ADD COLUMN kind TEXT NOT NULL DEFAULT 'run';
+
+CREATE TABLE IF NOT EXISTS run_events (
+	id INTEGER PRIMARY KEY NOT NULL,
+	run_id TEXT NOT NULL,
+	name TEXT NOT NULL,
+	started_at INTEGER NOT NULL,
+	finished_at INTEGER NOT NULL,
+	error TEXT,
+	FOREIGN KEY (run_id) REFERENCES runs(id)
+);
+
+CREATE INDEX IF NOT EXISTS run_events_run ON run_events (run_id, id);
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS run_tags (
+	run_id TEXT NOT NULL,
+	key TEXT NOT NULL,
+	value TEXT NOT NULL,
+	PRIMARY KEY (run_id, key),
+	FOREIGN KEY (run_id) REFERENCES runs(id)
+);
+
+CREATE INDEX IF NOT EXISTS run_tags_key ON run_tags (key, value);
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS step_logs (
+	run_id TEXT NOT NULL,
+	step_name TEXT NOT NULL,
+	body TEXT NOT NULL,
+	truncated_bytes INTEGER NOT NULL DEFAULT 0,
+	PRIMARY KEY (run_id, step_name),
+	FOREIGN KEY (run_id) REFERENCES runs(id)
+);
@@ -0,0 +1,17 @@
+-- Provisioning phases keep their output next to the steps', a step may share
+-- the name of a phase
+CREATE TABLE step_logs_new (
+	run_id TEXT NOT NULL,
+	kind TEXT NOT NULL DEFAULT 'step',
+	step_name TEXT NOT NULL,
+	body TEXT NOT NULL,
+	truncated_bytes INTEGER NOT NULL DEFAULT 0,
+	PRIMARY KEY (run_id, kind, step_name),
+	FOREIGN KEY (run_id) REFERENCES runs(id)
+);
+
+INSERT INTO step_logs_new (run_id, step_name, body, truncated_bytes)
+	SELECT run_id, step_name, body, truncated_bytes FROM step_logs;
+
+DROP TABLE step_logs;
+ALTER TABLE step_logs_new RENAME TO step_logs;
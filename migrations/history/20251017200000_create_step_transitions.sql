@@ -0,0 +1,13 @@
+-- States the steps of a run went through, with millisecond timestamps so
+-- the timeline of a finished run can be replayed, parallel steps included
+CREATE TABLE IF NOT EXISTS step_transitions (
+	id INTEGER PRIMARY KEY NOT NULL,
+	run_id TEXT NOT NULL,
+	step_name TEXT NOT NULL,
+	state TEXT NOT NULL,
+	at INTEGER NOT NULL,
+	log_lines INTEGER NOT NULL DEFAULT 0,
+	FOREIGN KEY (run_id) REFERENCES runs(id)
+);
+
+CREATE INDEX IF NOT EXISTS step_transitions_run ON step_transitions (run_id, at);
@@ -0,0 +1,13 @@
+-- Fingerprint of the inputs of every step that succeeded, so later runs on
+-- the same base can skip the step when its inputs didn't change
+CREATE TABLE IF NOT EXISTS step_fingerprints (
+	run_id TEXT NOT NULL,
+	step_name TEXT NOT NULL,
+	-- Apply or refresh run that built the base zone the step ran on
+	base TEXT NOT NULL,
+	fingerprint TEXT NOT NULL,
+	PRIMARY KEY (run_id, step_name),
+	FOREIGN KEY (run_id) REFERENCES runs(id)
+);
+
+CREATE INDEX IF NOT EXISTS step_fingerprints_lookup ON step_fingerprints (step_name, base, fingerprint);
@@ -0,0 +1,12 @@
+-- Outcome of every step that ran, to query past runs without replaying their
+-- transitions
+CREATE TABLE IF NOT EXISTS step_results (
+	run_id TEXT NOT NULL,
+	step_name TEXT NOT NULL,
+	status TEXT NOT NULL,
+	started_at INTEGER NOT NULL,
+	finished_at INTEGER,
+	exit_code INTEGER,
+	PRIMARY KEY (run_id, step_name),
+	FOREIGN KEY (run_id) REFERENCES runs(id)
+);
@@ -1,16 +1,550 @@
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{Result, anyhow};
+use clap::{Parser, Subcommand};
+use renzokutai::bootstrap;
+use renzokutai::cache;
+use renzokutai::color::{self, ColorMode, Colorize};
+use renzokutai::confirm;
+use renzokutai::config::{EnvVar, RunOptions, ValidatedPipeline};
+use renzokutai::history::{self, History, RunTag, StepSpan};
+use renzokutai::interrupt::Cancellation;
+use renzokutai::inventory::Inventory;
+use renzokutai::policy::Policy;
+#[cfg(feature = "daemon")]
+use renzokutai::trigger::{Scheduler, WatchTrigger};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use renzokutai::version::{self, VersionInfo};
 
 #[derive(Parser, Debug)]
 struct Args {
-    #[arg(short, required = true)]
-    pipeline: String,
+    #[arg(short, global = true)]
+    pipeline: Option<String>,
+
+    /// When to color output, `NO_COLOR` turns it off in auto mode
+    #[arg(long, global = true, value_enum, default_value_t)]
+    color: ColorMode,
+
+    /// Don't color output, same as `--color never`
+    #[arg(long, global = true)]
+    plain: bool,
+
+    /// Don't ask before halting or replacing existing zones
+    #[arg(short, long, global = true)]
+    yes: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run the pipeline (default)
+    Run {
+        /// Branch being built, recorded in the run history
+        #[arg(long)]
+        branch: Option<String>,
+        /// Commit checked out in the first repo, recorded in the run history
+        #[arg(long)]
+        commit: Option<String>,
+        /// Run against this local working tree, uncommitted changes included
+        #[arg(long)]
+        local_src: Option<PathBuf>,
+        /// Tag the run, e.g. `--tag release=1.2`
+        #[arg(long = "tag")]
+        tags: Vec<RunTag>,
+        /// Who or what triggered the run, the current user by default
+        #[arg(long)]
+        actor: Option<String>,
+        /// Override a parameter of the pipeline, e.g. `-e VERSION=1.2`, can
+        /// be repeated
+        #[arg(short = 'e', long = "param")]
+        params: Vec<EnvVar>,
+        /// Stop every step as soon as one fails, by default the steps that
+        /// don't depend on it keep running
+        #[arg(long)]
+        fail_fast: bool,
+        /// Print the output of each step in one block once it's over, rather
+        /// than as it comes
+        #[arg(long)]
+        group_output: bool,
+    },
+    /// Run the pipeline given with -p against a local working tree every
+    /// time files in it change
+    #[cfg(feature = "daemon")]
+    Watch {
+        /// Working tree to watch and run against
+        path: PathBuf,
+        /// How long the tree has to stay untouched before a run starts
+        #[arg(long, value_parser = renzokutai::policy::parse_duration, default_value = "2s")]
+        debounce: std::time::Duration,
+    },
+    /// List the saved pipelines
+    List,
+    /// List recent runs, of the pipeline given with -p or of all of them
+    Runs {
+        /// Only list runs with this tag, can be repeated
+        #[arg(long = "tag")]
+        tags: Vec<RunTag>,
+        /// Maximum number of runs listed
+        #[arg(long, default_value_t = 20)]
+        limit: i64,
+    },
+    /// Show a run or apply with its phases and notes
+    Show {
+        /// Run id as printed when the run started
+        run: String,
+    },
+    /// Print the output of the provisioning phases and steps of a run
+    Log {
+        /// Run id as printed when the run started
+        run: String,
+        /// Only print the output of this step or phase
+        step: Option<String>,
+    },
+    /// Tag a run, e.g. with the ticket it fixes
+    Tag {
+        /// Run id as printed when the run started
+        run: String,
+        /// Tag as key=value
+        tag: RunTag,
+    },
+    /// Attach a note to a run, e.g. why it failed
+    Note {
+        /// Run id as printed when the run started
+        run: String,
+        /// Text of the note
+        text: String,
+    },
+    /// Refresh the base zones whose `base_refresh` schedule is due, meant to
+    /// be run periodically from cron with `--yes`
+    Refresh {
+        /// Refresh even if the schedule isn't due, is unset or skips today
+        #[arg(long)]
+        force: bool,
+    },
+    /// Download the pkgsrc bootstrap zones are installed from into the host
+    /// cache when it changed, meant to be run periodically from cron
+    Prefetch,
+    /// Show the timeouts and log retention applying to the pipeline given
+    /// with -p, or the host defaults
+    Policy,
+    /// List the zones, VNICs and datasets renzokutai manages on the host
+    /// with the pipeline and run owning them
+    Host,
+    /// Host network configuration
+    Network {
+        #[command(subcommand)]
+        command: NetworkCommand,
+    },
+    /// Prepare the host for pipelines: the build network etherstub, the
+    /// parent dataset of the zones, the directories and the history database
+    Init,
+    /// Print version information
+    Version {
+        /// Check the configured update URL for a newer release
+        #[arg(long)]
+        check: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum NetworkCommand {
+    /// Route the build network to the internet through the host (opt-in)
+    Setup {
+        /// External interface the build network is NATed through
+        #[arg(long)]
+        external: String,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
+    color::init(if args.plain { ColorMode::Never } else { args.color });
+    confirm::init(args.yes);
+
+    let command = args.command.unwrap_or(Command::Run {
+        branch: None,
+        commit: None,
+        local_src: None,
+        tags: Vec::new(),
+        actor: None,
+        params: Vec::new(),
+        fail_fast: false,
+        group_output: false,
+    });
+
+    match command {
+        Command::Run {
+            branch,
+            commit,
+            local_src,
+            tags,
+            actor,
+            params,
+            fail_fast,
+            group_output,
+        } => {
+            let pipeline = args.pipeline.ok_or(anyhow!("Missing pipeline (-p)"))?;
+            let vp = renzokutai::config::ValidatedPipeline::load(&pipeline)?.expect("Unknown pipeline");
+            let cancellation = Cancellation::new();
+            cancellation.on_ctrl_c();
+            vp.run(&RunOptions {
+                branch,
+                commit,
+                local_src,
+                tags,
+                actor: actor.or(std::env::var("USER").ok()),
+                params,
+                fail_fast,
+                group_output,
+                cancellation,
+            })
+            .await
+        }
+        #[cfg(feature = "daemon")]
+        Command::Watch { path, debounce } => {
+            let pipeline = args.pipeline.ok_or(anyhow!("Missing pipeline (-p)"))?;
+            watch_local(pipeline, &path, debounce).await
+        }
+        Command::List => list_pipelines(),
+        Command::Runs { tags, limit } => list_runs(args.pipeline.as_deref(), &tags, limit).await,
+        Command::Tag { run, tag } => {
+            History::open().await?.add_tag(&run, &tag).await?;
+            println!("Tagged run {} with {}", run.cyan(), tag);
+            Ok(())
+        }
+        Command::Show { run } => show_run(&run).await,
+        Command::Log { run, step } => print_log(&run, step.as_deref()).await,
+        Command::Note { run, text } => {
+            History::open().await?.add_note(&run, &text).await?;
+            println!("Added note to run {}", run.cyan());
+            Ok(())
+        }
+        Command::Refresh { force } => refresh(args.pipeline, force).await,
+        Command::Prefetch => {
+            let url = cache::bootstrap_url();
+            if cache::prefetch().await? {
+                println!("Cached {} at {}", url.cyan(), cache::bootstrap_path(&url).display());
+            } else {
+                println!("Cached bootstrap {} is up to date", url.cyan());
+            }
+            Ok(())
+        }
+        Command::Policy => {
+            let policy = match args.pipeline {
+                Some(name) => ValidatedPipeline::load(&name)?
+                    .ok_or(anyhow!("Unknown pipeline {}", name))?
+                    .policy()?,
+                None => Policy::host()?,
+            };
+            println!("{}", policy);
+            Ok(())
+        }
+        Command::Host => print_inventory().await,
+        Command::Network {
+            command: NetworkCommand::Setup { external },
+        } => renzokutai::network::setup(&external).await,
+        Command::Init => init().await,
+        Command::Version { check } => print_version(check).await,
+    }
+}
+
+/// Refresh the base of `pipeline`, or of every pipeline when none is given
+async fn refresh(pipeline: Option<String>, force: bool) -> Result<()> {
+    let pipelines = match pipeline {
+        Some(name) => vec![ValidatedPipeline::load(&name)?.ok_or(anyhow!("Unknown pipeline {}", name))?],
+        None => ValidatedPipeline::list()?,
+    };
+    let history = History::open().await?;
+    let mut failed = Vec::new();
+
+    for vp in pipelines {
+        let due = match vp.refresh_schedule()? {
+            Some(schedule) => schedule.is_due(history.last_refresh(&vp.name).await?.as_ref(), history::now()),
+            None => false,
+        };
+        if !due && !force {
+            continue;
+        }
+        if let (Some(exclusion), false) = (vp.schedule_calendar()?.exclusion(history::now())?, force) {
+            println!("Holding off refresh of {}: schedules skip {}", vp.name.cyan(), exclusion.yellow());
+            continue;
+        }
+        if !vp.enabled {
+            println!("Skipping disabled pipeline {}", vp.name.cyan());
+            continue;
+        }
+
+        if let Err(err) = vp.refresh_base().await {
+            println!("{} {:?}", "error:".red(), err);
+            failed.push(vp.name.clone());
+        }
+    }
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!("Couldn't refresh {}", failed.join(", ")))
+    }
+}
+
+/// Run `pipeline` against `path` after every change, one run at a time
+#[cfg(feature = "daemon")]
+async fn watch_local(pipeline: String, path: &std::path::Path, debounce: std::time::Duration) -> Result<()> {
+    let vp = ValidatedPipeline::load(&pipeline)?.ok_or(anyhow!("Unknown pipeline {}", pipeline))?;
+    vp.ensure_enabled()?;
+    let path = path.canonicalize()?;
+
+    let mut scheduler = Scheduler::new();
+    scheduler.add(Box::new(WatchTrigger::new(&pipeline, &path, debounce)));
+    println!("Watching {} for changes", path.display().cyan());
+
+    // Ctrl-C cancels the run in progress and stops watching
+    let cancellation = Cancellation::new();
+    cancellation.on_ctrl_c();
+    loop {
+        let request = tokio::select! {
+            request = scheduler.next() => request,
+            _ = cancellation.cancelled() => None,
+        };
+        let Some(request) = request else {
+            break;
+        };
+        let options = RunOptions {
+            branch: request.branch,
+            commit: request.commit,
+            local_src: request.local_src,
+            tags: request.tags,
+            actor: Some(request.actor),
+            params: Vec::new(),
+            fail_fast: false,
+            group_output: false,
+            cancellation: cancellation.clone(),
+        };
+        if let Err(err) = vp.run(&options).await {
+            println!("{} {:?}", "error:".red(), err);
+        }
+    }
+
+    Ok(())
+}
+
+async fn init() -> Result<()> {
+    for requirement in bootstrap::requirements() {
+        print!("{}...", requirement);
+        io::stdout().lock().flush()?;
+
+        if requirement.converge().await? {
+            println!(" {}", "DONE".green());
+        } else {
+            println!(" {}", "OK".dimmed());
+        }
+    }
+
+    Ok(())
+}
+
+async fn print_version(check: bool) -> Result<()> {
+    let info = VersionInfo::current();
+    println!("{}", info);
+
+    if check {
+        let url = version::update_url().ok_or(anyhow!(
+            "No update URL configured, set {}",
+            version::UPDATE_URL_VAR
+        ))?;
+        let latest = version::latest_release(&url).await?;
+
+        if version::is_newer(&latest, &info.version) {
+            println!("Newer release available: {}", latest.yellow());
+        } else {
+            println!("{}", "Up to date".green());
+        }
+    }
+
+    Ok(())
+}
+
+fn list_pipelines() -> Result<()> {
+    let mut pipelines = ValidatedPipeline::list()?;
+    pipelines.sort_by(|a, b| a.name.cmp(&b.name));
+
+    for vp in pipelines {
+        let mut line = format!(
+            "{:<30} {:<18} {:<10} {:<20}",
+            vp.name,
+            vp.subnet.as_deref().unwrap_or("-"),
+            vp.base_refresh.as_deref().unwrap_or("-"),
+            vp.owner.as_deref().unwrap_or("-")
+        );
+        if !vp.labels.is_empty() {
+            line.push_str(&format!(" [{}]", vp.labels.join(", ")));
+        }
+
+        if vp.enabled {
+            println!("{}", line);
+        } else {
+            println!("{} (disabled)", line.dimmed());
+        }
+        if let Some(description) = &vp.description {
+            println!("    {}", description.dimmed());
+        }
+    }
+
+    Ok(())
+}
+
+async fn print_inventory() -> Result<()> {
+    let inventory = Inventory::discover(&History::open().await?).await?;
+    for (title, resources) in inventory.sections() {
+        println!("{}:", title.bold());
+        for resource in resources {
+            let owner = match (&resource.pipeline, &resource.run) {
+                (Some(pipeline), Some(run)) => format!("{} run {}", pipeline, run),
+                (Some(pipeline), None) => pipeline.clone(),
+                (None, _) => "-".to_string(),
+            };
+            let line = format!(
+                "  {:<40} {:<24} {:<20} {}",
+                resource.name,
+                owner,
+                resource.state,
+                resource.run_status.as_deref().unwrap_or("")
+            );
+
+            if resource.is_leftover(&inventory.pipelines) {
+                println!("{} {}", line, "(leftover)".yellow());
+            } else {
+                println!("{}", line);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn list_runs(pipeline: Option<&str>, tags: &[RunTag], limit: i64) -> Result<()> {
+    let history = History::open().await?;
+
+    for run in history.tagged_runs(pipeline, tags, limit).await? {
+        let tags = history.tags(&run.id).await?;
+        println!(
+            "{} {:<8} {:<20} {:<10} {:<16} {}",
+            run.id.cyan(),
+            run.kind,
+            run.pipeline_name,
+            run.status,
+            run.started_ago(),
+            tags.iter().map(|t| t.to_string()).collect::<Vec<_>>().join(" ")
+        );
+    }
+
+    Ok(())
+}
+
+async fn show_run(id: &str) -> Result<()> {
+    let history = History::open().await?;
+    let run = history.run(id).await?.ok_or(anyhow!("Unknown run {}", id))?;
+
+    println!("{} {} of {}", run.kind, run.id.cyan(), run.pipeline_name.cyan());
+    if let Some(branch) = &run.branch {
+        println!("branch: {}", branch);
+    }
+    if let Some(commit) = &run.commit_id {
+        println!("commit: {}", commit);
+    }
+    if let Some(vnic) = &run.vnic {
+        println!("vnic: {}", vnic);
+    }
+    if let Some(actor) = &run.actor {
+        println!("triggered by: {}", actor);
+    }
+    println!("status: {}", run.status);
+    println!("started: {}", run.started_ago());
+    for tag in history.tags(id).await? {
+        println!("tag: {}", tag);
+    }
+
+    for event in history.events(id).await? {
+        let result = match &event.error {
+            None => "DONE".green().to_string(),
+            Some(_) => "FAILED".red().to_string(),
+        };
+        println!("  {:<20} {:>5}s {}", event.name, event.duration(), result);
+        if let Some(error) = &event.error {
+            println!("    {}", error);
+        }
+    }
+
+    // Grouped by the stages the pipeline defines now, in their order
+    let steps = match ValidatedPipeline::load(&run.pipeline_name) {
+        Ok(Some(pipeline)) => pipeline.steps,
+        _ => Default::default(),
+    };
+    let stages = steps.stages();
+    let mut spans = StepSpan::from_timeline(&history.timeline(id).await?);
+    spans.sort_by_key(|span| steps.stage_index(&span.step_name));
+    let mut shown_stage = None;
+
+    for span in spans {
+        let stage = steps.stage_index(&span.step_name);
+        if !stages.is_empty() && shown_stage != Some(stage) {
+            println!("  stage {}", stages[stage].bold());
+            shown_stage = Some(stage);
+        }
+        let state = match span.state.as_str() {
+            "succeeded" => "DONE".green().to_string(),
+            "failed" => "FAILED".red().to_string(),
+            state => state.yellow().to_string(),
+        };
+        println!(
+            "  {:<20} +{:>4}s {:>5}s {} ({} lines)",
+            span.step_name,
+            span.offset / 1000,
+            span.duration / 1000,
+            state,
+            span.log_lines
+        );
+    }
+
+    for log in history.step_logs(id).await? {
+        if log.truncated_bytes > 0 {
+            println!(
+                "{}: output of {} {} truncated, {} bytes dropped",
+                "warning".yellow(),
+                log.kind,
+                log.step_name.cyan(),
+                log.truncated_bytes
+            );
+        }
+    }
+
+    for note in history.notes(id).await? {
+        println!("note: {}", note.body);
+    }
+
+    Ok(())
+}
+
+async fn print_log(id: &str, step: Option<&str>) -> Result<()> {
+    let history = History::open().await?;
+    history.run(id).await?.ok_or(anyhow!("Unknown run {}", id))?;
+
+    let logs: Vec<_> = history
+        .step_logs(id)
+        .await?
+        .into_iter()
+        .filter(|log| step.is_none_or(|s| s == log.step_name))
+        .collect();
+    if let (Some(step), true) = (step, logs.is_empty()) {
+        return Err(anyhow!("No output of {} in run {}", step, id));
+    }
+
+    for log in logs {
+        println!("{}", format!("==> {} {} <==", log.kind, log.step_name).bold());
+        print!("{}", log.body);
+    }
 
-    let vp = renzokutai::config::ValidatedPipeline::load(&args.pipeline)?.expect("Unknown pipeline");
-    vp.run().await
+    Ok(())
 }
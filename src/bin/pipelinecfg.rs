@@ -1,15 +1,31 @@
 use anyhow::Result;
 use clap::Parser;
+use renzokutai::color::{self, ColorMode};
+use renzokutai::confirm;
 
 #[derive(Parser, Debug)]
 struct Args {
     #[arg(short, required = true)]
     pipeline: String,
+
+    /// When to color output, `NO_COLOR` turns it off in auto mode
+    #[arg(long, value_enum, default_value_t)]
+    color: ColorMode,
+
+    /// Don't color output, same as `--color never`
+    #[arg(long)]
+    plain: bool,
+
+    /// Don't ask before halting or replacing existing zones
+    #[arg(short, long)]
+    yes: bool,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
+    color::init(if args.plain { ColorMode::Never } else { args.color });
+    confirm::init(args.yes);
 
     renzokutai::config::builder(&args.pipeline).await
 }
@@ -1,21 +1,39 @@
+#[cfg(feature = "gitserver")]
 use git2::{Repository, TreeWalkMode, TreeWalkResult};
 use askama::Template;
 use anyhow::Result;
+#[cfg(feature = "gitserver")]
 use std::path::{Path, PathBuf};
+#[cfg(feature = "gitserver")]
 use std::str::FromStr;
 use axum::{
-    response::{Html, IntoResponse},
-    routing::get,
-    Router,
+    extract::{Query, Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Extension, Form, Json, Router,
 };
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use renzokutai::config::ValidatedPipeline;
+use renzokutai::history::{History, RunEvent, RunNote, RunRecord, RunTag, StepSpan, StepTransition};
+use renzokutai::health;
+use renzokutai::inventory::Inventory;
+use renzokutai::trigger::{CronTrigger, RunRequest, RunSubmitter, Scheduler};
+use renzokutai::version::VersionInfo;
+use renzokutai::zones::validate_pipeline_name;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 use tower_http::services::ServeDir;
 
+#[cfg(feature = "gitserver")]
 struct RCommit {
     id: String,
     message: String,
     author: String,
 }
 
+#[cfg(feature = "gitserver")]
 impl RCommit {
     pub fn from_commit(c: &git2::Commit) -> Self {
         let author = c.author();
@@ -28,6 +46,7 @@ impl RCommit {
     }
 }
 
+#[cfg(feature = "gitserver")]
 struct RNode {
     name: String,
     kind: git2::ObjectType,
@@ -35,25 +54,132 @@ struct RNode {
     filemode: i32,
 }
 
+#[cfg(feature = "gitserver")]
 #[derive(Template)]
 #[template(path="index.html")]
 struct RepoTemplate {
     path: PathBuf,
     repo_name: String,
     commit: RCommit,
+    csrf_token: String,
     view: RepoView
 }
 
+#[derive(Template)]
+#[template(path="branches.html")]
+struct BranchesTemplate {
+    pipeline: String,
+    enabled: bool,
+    description: Option<String>,
+    owner: Option<String>,
+    labels: Vec<String>,
+    csrf_token: String,
+    runs: Vec<RunRecord>,
+}
+
+#[derive(Template)]
+#[template(path="run.html")]
+struct RunTemplate {
+    csrf_token: String,
+    run: RunRecord,
+    events: Vec<RunEvent>,
+    notes: Vec<RunNote>,
+    tags: Vec<RunTag>,
+    spans: Vec<StepSpan>,
+    /// Milliseconds from the first step starting to the last one finishing
+    spans_total: i64,
+}
+
+#[derive(Template)]
+#[template(path="inventory.html")]
+struct InventoryTemplate {
+    inventory: Inventory,
+}
+
+struct AppState {
+    /// Token required to trigger runs, triggering is disabled without one
+    api_token: Option<String>,
+    /// Queue of the scheduler starting the runs
+    runs: RunSubmitter,
+}
+
+/// Cookie keeping the CSRF token of a browser's session
+const CSRF_COOKIE: &str = "renzokutai_csrf";
+const CSRF_TOKEN_LEN: usize = 32;
+/// Most runs `/runs` lists at once, whatever `limit` asks for
+const MAX_RUNS_LIMIT: i64 = 500;
+
+/// Session of the browser making the request, kept in `CSRF_COOKIE`
+#[derive(Clone)]
+struct Session {
+    /// Embedded in the session's pages and required on its form submissions
+    csrf_token: String,
+}
+
+#[derive(Deserialize)]
+struct RunForm {
+    commit: String,
+    #[serde(default)]
+    branch: Option<String>,
+    /// Comma separated key=value tags
+    #[serde(default)]
+    tags: String,
+    csrf_token: String,
+    api_token: String,
+}
+
+#[derive(Deserialize)]
+struct NoteForm {
+    body: String,
+    csrf_token: String,
+    api_token: String,
+}
+
+#[derive(Deserialize)]
+struct RunsQuery {
+    #[serde(default)]
+    pipeline: Option<String>,
+    /// Comma separated key=value tags runs must all carry
+    #[serde(default)]
+    tag: String,
+    #[serde(default = "default_runs_limit")]
+    limit: i64,
+}
+
+fn default_runs_limit() -> i64 {
+    50
+}
+
+#[derive(Serialize)]
+struct TaggedRun {
+    #[serde(flatten)]
+    run: RunRecord,
+    tags: Vec<RunTag>,
+}
+
+/// Everything recorded while a run went on, for clients replaying it
+#[derive(Serialize)]
+struct RunTimeline {
+    run: RunRecord,
+    phases: Vec<RunEvent>,
+    steps: Vec<StepTransition>,
+}
+
+#[cfg(feature = "gitserver")]
 enum RepoView {
     TreeView { files: Vec<RNode>, readme: Option<String> },
     BlobView { content: String },
 }
 
+#[cfg(feature = "gitserver")]
 pub struct Repo {
     name: String
 }
 
+/// Browse the files of the repository the server runs from
+#[cfg(feature = "gitserver")]
 async fn view_repo(
+    Extension(session): Extension<Session>,
     path: Option<axum::extract::Path<String>>,
     ) -> impl IntoResponse {
     let repo_name = "renzokutai".to_string();
@@ -79,6 +205,7 @@ async fn view_repo(
                 path: path,
                 repo_name,
                 commit: RCommit::from_commit(&commit),
+                csrf_token: session.csrf_token.clone(),
                 view: RepoView::BlobView {
                     content: content,
                 }
@@ -124,6 +251,7 @@ async fn view_repo(
                 path: path,
                 repo_name,
                 commit: RCommit::from_commit(&commit),
+                csrf_token: session.csrf_token.clone(),
                 view: RepoView::TreeView {
                     files,
                     readme: None,
@@ -136,6 +264,282 @@ async fn view_repo(
     Html(template.render().unwrap())
 }
 
+async fn version() -> Json<VersionInfo> {
+    Json(VersionInfo::current())
+}
+
+/// Zones, VNICs and datasets renzokutai manages on the host as JSON
+async fn host() -> Response {
+    match discover().await {
+        Ok(inventory) => Json(inventory).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Couldn't list host resources: {}", err)).into_response(),
+    }
+}
+
+async fn view_inventory() -> Response {
+    match discover().await {
+        Ok(inventory) => Html(InventoryTemplate { inventory }.render().unwrap()).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Couldn't list host resources: {}", err)).into_response(),
+    }
+}
+
+async fn discover() -> Result<Inventory> {
+    Inventory::discover(&History::open().await?).await
+}
+
+/// Liveness, answering at all is enough
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+async fn readyz() -> Response {
+    let readiness = health::readiness().await;
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status, Json(readiness)).into_response()
+}
+
+async fn view_branches(
+    Extension(session): Extension<Session>,
+    axum::extract::Path(pipeline): axum::extract::Path<String>,
+    ) -> Response {
+    if validate_pipeline_name(&pipeline).is_err() {
+        return (StatusCode::NOT_FOUND, "Unknown pipeline").into_response();
+    }
+
+    let runs = match History::open().await {
+        Ok(history) => history.latest_per_branch(&pipeline).await,
+        Err(err) => Err(err),
+    };
+    let vp = ValidatedPipeline::load(&pipeline).ok().flatten();
+    let enabled = vp.as_ref().is_none_or(|vp| vp.enabled);
+    let (description, owner, labels) = match vp {
+        Some(vp) => (vp.description, vp.owner, vp.labels),
+        None => (None, None, Vec::new()),
+    };
+
+    match runs {
+        Ok(runs) => {
+            let template = BranchesTemplate {
+                pipeline,
+                enabled,
+                description,
+                owner,
+                labels,
+                csrf_token: session.csrf_token.clone(),
+                runs,
+            };
+            Html(template.render().unwrap()).into_response()
+        }
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Couldn't read run history: {}", err)).into_response(),
+    }
+}
+
+async fn view_run(
+    Extension(session): Extension<Session>,
+    axum::extract::Path(run): axum::extract::Path<String>,
+    ) -> Response {
+    let found = async {
+        let history = History::open().await?;
+        match history.run(&run).await? {
+            Some(record) => Ok(Some((
+                record,
+                history.events(&run).await?,
+                history.notes(&run).await?,
+                history.tags(&run).await?,
+                history.timeline(&run).await?,
+            ))),
+            None => Ok::<_, anyhow::Error>(None),
+        }
+    }
+    .await;
+
+    match found {
+        Ok(Some((run, events, notes, tags, timeline))) => {
+            let spans = StepSpan::from_timeline(&timeline);
+            let template = RunTemplate {
+                csrf_token: session.csrf_token.clone(),
+                run,
+                events,
+                notes,
+                tags,
+                spans_total: spans.iter().map(|s| s.offset + s.duration).max().unwrap_or(0),
+                spans,
+            };
+            Html(template.render().unwrap()).into_response()
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, "Unknown run").into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Couldn't read run history: {}", err)).into_response(),
+    }
+}
+
+/// Phases and step transitions of a run as JSON, in the order they happened
+async fn run_timeline(axum::extract::Path(run): axum::extract::Path<String>) -> Response {
+    let found = async {
+        let history = History::open().await?;
+        match history.run(&run).await? {
+            Some(record) => Ok(Some(RunTimeline {
+                run: record,
+                phases: history.events(&run).await?,
+                steps: history.timeline(&run).await?,
+            })),
+            None => Ok::<_, anyhow::Error>(None),
+        }
+    }
+    .await;
+
+    match found {
+        Ok(Some(timeline)) => Json(timeline).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "Unknown run").into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Couldn't read run history: {}", err)).into_response(),
+    }
+}
+
+/// Recent runs as JSON, e.g. `/runs?tag=release=1.2`
+async fn list_runs(Query(query): Query<RunsQuery>) -> Response {
+    let tags = match RunTag::parse_list(&query.tag) {
+        Ok(tags) => tags,
+        Err(err) => return (StatusCode::BAD_REQUEST, format!("{}", err)).into_response(),
+    };
+
+    let runs = async {
+        let history = History::open().await?;
+        let mut tagged = Vec::new();
+        // A negative limit would list every run
+        let limit = query.limit.clamp(1, MAX_RUNS_LIMIT);
+        for run in history.tagged_runs(query.pipeline.as_deref(), &tags, limit).await? {
+            let tags = history.tags(&run.id).await?;
+            tagged.push(TaggedRun { run, tags });
+        }
+        Ok::<_, anyhow::Error>(tagged)
+    }
+    .await;
+
+    match runs {
+        Ok(runs) => Json(runs).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Couldn't read run history: {}", err)).into_response(),
+    }
+}
+
+async fn add_note(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    axum::extract::Path(run): axum::extract::Path<String>,
+    Form(form): Form<NoteForm>,
+    ) -> Response {
+    if !tokens_match(&session.csrf_token, &form.csrf_token) {
+        return (StatusCode::FORBIDDEN, "Invalid CSRF token").into_response();
+    }
+
+    match &state.api_token {
+        Some(token) if tokens_match(token, &form.api_token) => (),
+        Some(_) => return (StatusCode::UNAUTHORIZED, "Invalid API token").into_response(),
+        None => return (StatusCode::FORBIDDEN, "Adding notes is disabled").into_response(),
+    }
+
+    let added = match History::open().await {
+        Ok(history) => history.add_note(&run, &form.body).await,
+        Err(err) => Err(err),
+    };
+
+    match added {
+        Ok(_) => Redirect::to(&format!("/runs/{}", run)).into_response(),
+        Err(err) => (StatusCode::BAD_REQUEST, format!("Couldn't add note: {}", err)).into_response(),
+    }
+}
+
+async fn trigger_run(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    axum::extract::Path(pipeline): axum::extract::Path<String>,
+    Form(form): Form<RunForm>,
+    ) -> Response {
+    if !tokens_match(&session.csrf_token, &form.csrf_token) {
+        return (StatusCode::FORBIDDEN, "Invalid CSRF token").into_response();
+    }
+
+    match &state.api_token {
+        Some(token) if tokens_match(token, &form.api_token) => (),
+        Some(_) => return (StatusCode::UNAUTHORIZED, "Invalid API token").into_response(),
+        None => return (StatusCode::FORBIDDEN, "Triggering runs is disabled").into_response(),
+    }
+
+    if validate_pipeline_name(&pipeline).is_err() {
+        return (StatusCode::NOT_FOUND, "Unknown pipeline").into_response();
+    }
+
+    match ValidatedPipeline::load(&pipeline) {
+        Ok(Some(vp)) if !vp.enabled => return (StatusCode::CONFLICT, "Pipeline is disabled").into_response(),
+        Ok(Some(_)) => (),
+        _ => return (StatusCode::NOT_FOUND, "Unknown pipeline").into_response(),
+    }
+
+    let tags = match RunTag::parse_list(&form.tags) {
+        Ok(tags) => tags,
+        Err(err) => return (StatusCode::BAD_REQUEST, format!("Invalid tags: {}", err)).into_response(),
+    };
+
+    let mut request = RunRequest::new(&pipeline, "web");
+    request.commit = Some(form.commit).filter(|c| !c.is_empty());
+    request.tags = tags;
+
+    let back = match form.branch.filter(|b| !b.is_empty()) {
+        Some(branch) => {
+            request.branch = Some(branch);
+            format!("/pipelines/{}/branches", pipeline)
+        }
+        None => format!("/repos/{}", pipeline),
+    };
+
+    match state.runs.submit(request).await {
+        Ok(_) => Redirect::to(&back).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Couldn't start run: {}", err)).into_response(),
+    }
+}
+
+/// Whether `given` is the `expected` token, comparing every byte so the time
+/// taken doesn't tell how much of it was right
+fn tokens_match(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected.bytes().zip(given.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn new_csrf_token() -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(CSRF_TOKEN_LEN)
+        .map(char::from)
+        .collect()
+}
+
+/// Hand the request the session of its cookie, starting a new one when it
+/// has none
+async fn session(mut request: Request, next: Next) -> Response {
+    let existing = request
+        .headers()
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().strip_prefix(CSRF_COOKIE)?.strip_prefix('='))
+        .find(|token| token.len() == CSRF_TOKEN_LEN && token.chars().all(|c| c.is_ascii_alphanumeric()))
+        .map(str::to_string);
+    let started = existing.is_none();
+    let csrf_token = existing.unwrap_or_else(new_csrf_token);
+    request.extensions_mut().insert(Session { csrf_token: csrf_token.clone() });
+
+    let mut response = next.run(request).await;
+    let cookie = format!("{}={}; Path=/; HttpOnly; SameSite=Strict", CSRF_COOKIE, csrf_token);
+    if started && let Ok(cookie) = HeaderValue::from_str(&cookie) {
+        response.headers_mut().append(header::SET_COOKIE, cookie);
+    }
+    response
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
 
@@ -147,11 +551,35 @@ async fn main() -> Result<()> {
     println!("{}", content);
     */
 
+    let scheduler = Scheduler::new();
+    let state = Arc::new(AppState {
+        api_token: std::env::var("RENZOKUTAI_API_TOKEN").ok().filter(|t| !t.is_empty()),
+        runs: scheduler.submitter(),
+    });
+    scheduler.add(Box::new(CronTrigger::new()));
+    tokio::spawn(scheduler.run());
+
     let app = Router::new()
+        .route("/version", get(version))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/host", get(host))
+        .route("/inventory", get(view_inventory))
+        .route("/pipelines/{pipeline}/runs", post(trigger_run))
+        .route("/pipelines/{pipeline}/branches", get(view_branches))
+        .route("/runs", get(list_runs))
+        .route("/runs/{run}", get(view_run))
+        .route("/runs/{run}/timeline", get(run_timeline))
+        .route("/runs/{run}/notes", post(add_note));
+    #[cfg(feature = "gitserver")]
+    let app = app
         .route("/repos/renzokutai", get(view_repo))
         .route("/repos/renzokutai/", get(view_repo))
-        .route("/repos/renzokutai/{*path}", get(view_repo))
-        .nest_service("/static", ServeDir::new("static"));
+        .route("/repos/renzokutai/{*path}", get(view_repo));
+    let app = app
+        .nest_service("/static", ServeDir::new("static"))
+        .layer(middleware::from_fn(session))
+        .with_state(state);
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
     axum::serve(listener, app).await.unwrap();
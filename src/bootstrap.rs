@@ -0,0 +1,100 @@
+//! What a fresh host needs before pipelines can be applied
+//!
+//! `pipelineadm init` converges the host to `requirements()`, creating what
+//! is missing and fixing directory permissions, so it is safe to run again.
+
+use crate::history::History;
+use anyhow::{Result, anyhow};
+use std::fmt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Requirement {
+    /// Etherstub the VNICs of the build network hang off
+    Etherstub(&'static str),
+    Dataset(String),
+    Directory { path: PathBuf, mode: u32 },
+    /// History database with its schema up to date
+    HistoryDb(PathBuf),
+}
+
+/// Everything the host needs, at the locations its configuration points to
+pub fn requirements() -> Vec<Requirement> {
+    let history = History::path();
+    let mut requirements = vec![
+        Requirement::Etherstub(crate::network::ETHERSTUB),
+        Requirement::Dataset(crate::inventory::DATASET_ROOT.to_string()),
+        Requirement::Directory {
+            path: crate::config::ValidatedPipeline::dir(),
+            mode: 0o755,
+        },
+        Requirement::Directory {
+            path: crate::cache::cache_dir(),
+            mode: 0o755,
+        },
+        Requirement::Directory {
+            path: crate::config::ArtifactPath::collected_root(),
+            mode: 0o755,
+        },
+    ];
+    if let Some(parent) = history.parent() {
+        // Runs may have printed anything, only root gets to read them
+        requirements.push(Requirement::Directory {
+            path: parent.to_path_buf(),
+            mode: 0o700,
+        });
+    }
+    requirements.push(Requirement::HistoryDb(history));
+
+    requirements
+}
+
+impl Requirement {
+    /// Create or fix what the requirement describes, returning whether the
+    /// host had to change
+    pub async fn converge(&self) -> Result<bool> {
+        match self {
+            Requirement::Etherstub(_) => crate::network::ensure_etherstub().await,
+            Requirement::Dataset(name) => {
+                if crate::zfs::base_dataset_exists(name).await? {
+                    Ok(false)
+                } else {
+                    crate::zfs::create_dataset(name).await?;
+                    Ok(true)
+                }
+            }
+            Requirement::Directory { path, mode } => {
+                let created = !path.is_dir();
+                if created {
+                    std::fs::create_dir_all(path)
+                        .map_err(|e| anyhow!("Couldn't create {}: {}", path.display(), e))?;
+                }
+
+                let permissions = std::fs::metadata(path)?.permissions();
+                if permissions.mode() & 0o7777 == *mode {
+                    return Ok(created);
+                }
+                std::fs::set_permissions(path, std::fs::Permissions::from_mode(*mode))?;
+                Ok(true)
+            }
+            Requirement::HistoryDb(path) => {
+                let created = !path.exists();
+                // Opening applies any migration still pending
+                History::open_at(path).await?;
+                Ok(created)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Requirement::Etherstub(name) => write!(f, "etherstub {}", name),
+            Requirement::Dataset(name) => write!(f, "dataset {}", name),
+            Requirement::Directory { path, mode } => write!(f, "directory {} ({:o})", path.display(), mode),
+            Requirement::HistoryDb(path) => write!(f, "history database {}", path.display()),
+        }
+    }
+}
@@ -0,0 +1,82 @@
+//! Host-side cache of the pkgsrc bootstrap zones are installed from
+//!
+//! `pipelineadm prefetch`, meant to be run periodically from cron, keeps the
+//! bootstrap up to date so zone installs don't download it every time.
+
+use anyhow::{Result, anyhow};
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::process::Stdio;
+
+/// Directory holding the host caches, overridable for tests
+pub const CACHE_DIR_VAR: &str = "RENZOKUTAI_CACHE_DIR";
+
+/// Environment variable pointing at the pkgsrc bootstrap to cache
+pub const BOOTSTRAP_URL_VAR: &str = "RENZOKUTAI_PKGSRC_BOOTSTRAP_URL";
+
+pub const DEFAULT_BOOTSTRAP_URL: &str =
+    "https://pkgsrc.smartos.org/packages/SmartOS/bootstrap/bootstrap-2024Q4-x86_64.tar.gz";
+
+pub fn cache_dir() -> PathBuf {
+    match std::env::var(CACHE_DIR_VAR) {
+        Ok(path) if !path.is_empty() => PathBuf::from(path),
+        _ => PathBuf::from("/var/cache/renzokutai"),
+    }
+}
+
+pub fn bootstrap_url() -> String {
+    std::env::var(BOOTSTRAP_URL_VAR)
+        .ok()
+        .filter(|u| !u.is_empty())
+        .unwrap_or(DEFAULT_BOOTSTRAP_URL.to_string())
+}
+
+/// Where the bootstrap from `url` is cached, named after the file it downloads
+pub fn bootstrap_path(url: &str) -> PathBuf {
+    let file = url.rsplit('/').find(|s| !s.is_empty()).unwrap_or("bootstrap.tar.gz");
+    cache_dir().join("pkgsrc").join(file)
+}
+
+/// Brand options for `zoneadm install`, installing from the cached bootstrap
+/// once it has been fetched
+pub fn install_options() -> Vec<OsString> {
+    let path = bootstrap_path(&bootstrap_url());
+
+    if path.is_file() {
+        vec!["-s".into(), path.into()]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Download the bootstrap into the cache unless the cached copy is up to
+/// date, returning whether it changed
+pub async fn prefetch() -> Result<bool> {
+    let url = bootstrap_url();
+    let path = bootstrap_path(&url);
+    let partial = path.with_extension("partial");
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let _ = std::fs::remove_file(&partial);
+
+    let mut command = tokio::process::Command::new("curl");
+    command.arg("-sSfL").arg("-o").arg(&partial);
+    // Only download when the server has something newer
+    if path.is_file() {
+        command.arg("-z").arg(&path);
+    }
+    let status = command.arg(&url).stderr(Stdio::null()).status().await?;
+
+    if !status.success() {
+        let _ = std::fs::remove_file(&partial);
+        return Err(anyhow!("Couldn't fetch the pkgsrc bootstrap from {}", url));
+    }
+
+    if partial.is_file() {
+        std::fs::rename(&partial, &path)?;
+        Ok(true)
+    } else {
+        Ok(false)
+    }
+}
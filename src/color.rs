@@ -0,0 +1,114 @@
+//! Terminal colors that can be turned off
+//!
+//! Drop-in replacement for the `owo_colors::OwoColorize` methods used across
+//! the crate, painting only when the color mode picked at startup allows it.
+
+use owo_colors::{OwoColorize, Stream};
+use std::fmt;
+
+/// When to color output, picked with `--color` or `--plain`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ColorMode {
+    /// Color unless `NO_COLOR` is set or stdout isn't a terminal
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// Set the color mode, `Auto` leaves it to what stdout supports
+pub fn init(mode: ColorMode) {
+    match mode {
+        ColorMode::Auto => owo_colors::unset_override(),
+        ColorMode::Always => owo_colors::set_override(true),
+        ColorMode::Never => owo_colors::set_override(false),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Style {
+    Bold,
+    Dimmed,
+    Blue,
+    Cyan,
+    Green,
+    Magenta,
+    Red,
+    Yellow,
+}
+
+/// Colors told apart at a glance, handed out in turn to things printed side
+/// by side, red is left for failures
+const PALETTE: [Style; 5] = [Style::Cyan, Style::Magenta, Style::Blue, Style::Green, Style::Yellow];
+
+/// Value displayed with a style when colors are enabled
+pub struct Painted<'a, T: ?Sized> {
+    value: &'a T,
+    style: Style,
+}
+
+impl<T: fmt::Display + ?Sized> fmt::Display for Painted<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = &self.value;
+        match self.style {
+            Style::Bold => value.if_supports_color(Stream::Stdout, |v| OwoColorize::bold(v)).fmt(f),
+            Style::Dimmed => value.if_supports_color(Stream::Stdout, |v| OwoColorize::dimmed(v)).fmt(f),
+            Style::Blue => value.if_supports_color(Stream::Stdout, |v| OwoColorize::blue(v)).fmt(f),
+            Style::Cyan => value.if_supports_color(Stream::Stdout, |v| OwoColorize::cyan(v)).fmt(f),
+            Style::Green => value.if_supports_color(Stream::Stdout, |v| OwoColorize::green(v)).fmt(f),
+            Style::Magenta => value.if_supports_color(Stream::Stdout, |v| OwoColorize::magenta(v)).fmt(f),
+            Style::Red => value.if_supports_color(Stream::Stdout, |v| OwoColorize::red(v)).fmt(f),
+            Style::Yellow => value.if_supports_color(Stream::Stdout, |v| OwoColorize::yellow(v)).fmt(f),
+        }
+    }
+}
+
+impl<T: fmt::Debug + ?Sized> fmt::Debug for Painted<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = &self.value;
+        match self.style {
+            Style::Bold => value.if_supports_color(Stream::Stdout, |v| OwoColorize::bold(v)).fmt(f),
+            Style::Dimmed => value.if_supports_color(Stream::Stdout, |v| OwoColorize::dimmed(v)).fmt(f),
+            Style::Blue => value.if_supports_color(Stream::Stdout, |v| OwoColorize::blue(v)).fmt(f),
+            Style::Cyan => value.if_supports_color(Stream::Stdout, |v| OwoColorize::cyan(v)).fmt(f),
+            Style::Green => value.if_supports_color(Stream::Stdout, |v| OwoColorize::green(v)).fmt(f),
+            Style::Magenta => value.if_supports_color(Stream::Stdout, |v| OwoColorize::magenta(v)).fmt(f),
+            Style::Red => value.if_supports_color(Stream::Stdout, |v| OwoColorize::red(v)).fmt(f),
+            Style::Yellow => value.if_supports_color(Stream::Stdout, |v| OwoColorize::yellow(v)).fmt(f),
+        }
+    }
+}
+
+pub trait Colorize: fmt::Display {
+    fn bold(&self) -> Painted<'_, Self> {
+        Painted { value: self, style: Style::Bold }
+    }
+
+    fn dimmed(&self) -> Painted<'_, Self> {
+        Painted { value: self, style: Style::Dimmed }
+    }
+
+    fn cyan(&self) -> Painted<'_, Self> {
+        Painted { value: self, style: Style::Cyan }
+    }
+
+    fn green(&self) -> Painted<'_, Self> {
+        Painted { value: self, style: Style::Green }
+    }
+
+    fn red(&self) -> Painted<'_, Self> {
+        Painted { value: self, style: Style::Red }
+    }
+
+    fn yellow(&self) -> Painted<'_, Self> {
+        Painted { value: self, style: Style::Yellow }
+    }
+
+    /// The `index`th color of a palette of colors easy to tell apart, going
+    /// round once past the last one
+    fn palette(&self, index: usize) -> Painted<'_, Self> {
+        Painted { value: self, style: PALETTE[index % PALETTE.len()] }
+    }
+}
+
+impl<T: fmt::Display + ?Sized> Colorize for T {}
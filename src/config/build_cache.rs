@@ -0,0 +1,225 @@
+use crate::config::{Filter, Frame, Value, info_line};
+use crate::zones::PipelineZone;
+use anyhow::{Result, anyhow};
+use crate::color::Colorize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::{cell::RefCell, rc::Rc};
+
+/// Directories kept between runs, each backed by a dataset of the pipeline
+/// mounted into every run zone
+#[derive(Debug, PartialEq)]
+pub struct BuildCaches {
+    vec: Vec<Rc<RefCell<BuildCache>>>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct ValidatedBuildCaches {
+    #[serde(default)]
+    #[serde(rename = "cache")]
+    vec: Vec<ValidatedBuildCache>,
+}
+
+impl Default for BuildCaches {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BuildCaches {
+    pub fn new() -> BuildCaches {
+        Self { vec: Vec::new() }
+    }
+
+    pub fn add_empty(&mut self) -> Frame {
+        let c = Rc::new(RefCell::new(BuildCache::default()));
+        self.vec.push(c.clone());
+        Frame::BuildCache(c.clone())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Rc<RefCell<BuildCache>>> {
+        self.vec.iter()
+    }
+
+    pub fn append(&mut self, other: BuildCaches) -> usize {
+        crate::filterable::append(&mut self.vec, other.vec)
+    }
+
+    pub fn select(&self, filters: &[Filter], index: Option<usize>) -> Result<Frame> {
+        crate::filterable::select(&self.vec, filters, index, BuildCache::name).map(Frame::BuildCache)
+    }
+
+    /// Append a copy of the element matching `filters`, optionally with a new key
+    pub fn copy(&mut self, filters: &[Filter], key: Option<String>) -> Result<Frame> {
+        crate::filterable::copy(&mut self.vec, filters, BuildCache::name, |c| {
+            if let Some(key) = key {
+                c.key = Value::Set(key);
+            }
+        })
+        .map(Frame::BuildCache)
+    }
+
+    pub fn validate(&self) -> Result<ValidatedBuildCaches> {
+        let vcaches = self
+            .vec
+            .iter()
+            .map(|c| c.borrow().validate())
+            .collect::<Result<Vec<ValidatedBuildCache>>>()?;
+
+        let mut paths = HashSet::new();
+        if let Some(cache) = vcaches.iter().find(|c| !paths.insert(&c.path)) {
+            return Err(anyhow!("Path {} is cached more than once", cache.path));
+        }
+        Ok(ValidatedBuildCaches { vec: vcaches })
+    }
+
+    pub fn validation_errors(&self) -> Vec<String> {
+        crate::filterable::validation_errors(&self.vec, BuildCache::name, BuildCache::validate)
+    }
+}
+
+impl Clone for BuildCaches {
+    fn clone(&self) -> Self {
+        Self { vec: crate::filterable::detach(&self.vec) }
+    }
+}
+
+impl From<Vec<ValidatedBuildCache>> for ValidatedBuildCaches {
+    fn from(vec: Vec<ValidatedBuildCache>) -> Self {
+        Self { vec }
+    }
+}
+
+impl ValidatedBuildCaches {
+    pub fn as_caches(&self) -> BuildCaches {
+        let caches = self
+            .vec
+            .iter()
+            .map(|c| Rc::new(RefCell::new(c.as_cache())))
+            .collect();
+        BuildCaches { vec: caches }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ValidatedBuildCache> {
+        self.vec.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vec.is_empty()
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BuildCache {
+    pub path: Value<String>,
+    pub key: Value<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ValidatedBuildCache {
+    /// Directory of the run zones the cache is mounted on, e.g.
+    /// `/root/.cargo/registry`
+    #[serde(rename = "@path")]
+    pub path: String,
+    /// Name of the dataset backing the cache, changing it starts afresh
+    #[serde(rename = "@key")]
+    pub key: String,
+}
+
+impl BuildCache {
+    pub const ATTRIBUTES: &[&str] = &["path", "key"];
+
+    pub fn validate(&self) -> Result<ValidatedBuildCache> {
+        let path = self.path.require("path")?;
+        let key = self.key.require("key")?;
+        validate_path(&path)?;
+        validate_key(&key)?;
+
+        Ok(ValidatedBuildCache { path, key })
+    }
+
+    pub fn name(&self) -> String {
+        match &self.key {
+            Value::Unset => "cache".to_string(),
+            Value::Set(v) => format!("cache({})", v.cyan()),
+        }
+    }
+
+    pub fn info(&self) -> String {
+        [
+            info_line("path", self.path.display()),
+            info_line("key", self.key.display()),
+        ]
+        .join("\n")
+    }
+
+    pub fn unset(&mut self, key: String) -> Result<()> {
+        match key.as_str() {
+            "path" => self.path = Value::Unset,
+            "key" => self.key = Value::Unset,
+            _ => return Err(anyhow!("Unknown attribute for cache: {}", key)),
+        }
+        Ok(())
+    }
+
+    pub fn set(&mut self, key: String, value: String) -> Result<()> {
+        match key.as_str() {
+            "path" => {
+                validate_path(&value)?;
+                self.path = Value::Set(value);
+                Ok(())
+            }
+            "key" => {
+                validate_key(&value)?;
+                self.key = Value::Set(value);
+                Ok(())
+            }
+            _ => Err(anyhow!("Unknown attribute for cache: {}", key)),
+        }
+    }
+}
+
+impl ValidatedBuildCache {
+    pub fn as_cache(&self) -> BuildCache {
+        BuildCache {
+            path: Value::Set(self.path.clone()),
+            key: Value::Set(self.key.clone()),
+        }
+    }
+
+    /// Dataset backing the cache for the zones of `pzone`'s pipeline
+    pub fn dataset(&self, pzone: &PipelineZone) -> String {
+        format!("{}/{}", pzone.caches_dataset(), self.key)
+    }
+
+    /// Where the dataset is mounted on the host
+    pub fn mountpoint(&self, pzone: &PipelineZone) -> String {
+        format!("{}/caches/{}", pzone.root_path(), self.key)
+    }
+}
+
+fn validate_path(path: &str) -> Result<()> {
+    let valid = path.starts_with('/')
+        && path != "/"
+        && !path.split('/').any(|c| c == "..")
+        && !path.contains(|c: char| c.is_whitespace());
+
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow!("Cache path must be an absolute directory of the zone, got {}", path))
+    }
+}
+
+fn validate_key(key: &str) -> Result<()> {
+    let valid = !key.is_empty()
+        && key.len() <= 64
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        && !key.starts_with('.');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow!("Cache key must be letters, digits, -, _ and ., got {}", key))
+    }
+}
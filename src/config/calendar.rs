@@ -0,0 +1,133 @@
+//! When the schedules of a pipeline may fire
+//!
+//! Schedules are checked against the local date in the pipeline's timezone,
+//! and hold off on the days excluded by its calendar, e.g. weekends and
+//! holidays nobody is around to look at a failure.
+
+use anyhow::{Result, anyhow};
+use jiff::Timestamp;
+use jiff::civil::{Date, DateTime, Weekday};
+use jiff::tz::TimeZone;
+
+const WEEKDAYS: [(&str, Weekday); 7] = [
+    ("mon", Weekday::Monday),
+    ("tue", Weekday::Tuesday),
+    ("wed", Weekday::Wednesday),
+    ("thu", Weekday::Thursday),
+    ("fri", Weekday::Friday),
+    ("sat", Weekday::Saturday),
+    ("sun", Weekday::Sunday),
+];
+
+/// A day schedules don't fire on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exclusion {
+    Weekday(Weekday),
+    /// Every year on the same day, e.g. `12-25`
+    Annual { month: i8, day: i8 },
+    Date(Date),
+}
+
+impl Exclusion {
+    /// Parse a comma separated list of weekdays (`sat`, or `weekends` for
+    /// both days), annual dates (`12-25`) and dates (`2026-04-03`)
+    pub fn parse_list(value: &str) -> Result<Vec<Exclusion>> {
+        let mut exclusions = Vec::new();
+
+        for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            match entry {
+                "weekends" => exclusions.extend([Weekday::Saturday, Weekday::Sunday].map(Exclusion::Weekday)),
+                entry => exclusions.push(entry.parse()?),
+            }
+        }
+        Ok(exclusions)
+    }
+
+    pub fn excludes(&self, date: Date) -> bool {
+        match self {
+            Exclusion::Weekday(weekday) => date.weekday() == *weekday,
+            Exclusion::Annual { month, day } => date.month() == *month && date.day() == *day,
+            Exclusion::Date(excluded) => date == *excluded,
+        }
+    }
+}
+
+impl std::str::FromStr for Exclusion {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some((_, weekday)) = WEEKDAYS.iter().find(|(name, _)| *name == s) {
+            return Ok(Exclusion::Weekday(*weekday));
+        }
+        if let Ok(date) = s.parse::<Date>() {
+            return Ok(Exclusion::Date(date));
+        }
+
+        // Checked against a leap year so 02-29 is accepted
+        match format!("2024-{}", s).parse::<Date>() {
+            Ok(date) if s.len() == 5 => Ok(Exclusion::Annual {
+                month: date.month(),
+                day: date.day(),
+            }),
+            _ => Err(anyhow!(
+                "Unknown exclusion {}, use a weekday like sat, weekends, MM-DD or YYYY-MM-DD",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Exclusion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Exclusion::Weekday(weekday) => {
+                let (name, _) = WEEKDAYS.iter().find(|(_, w)| w == weekday).unwrap();
+                write!(f, "{}", name)
+            }
+            Exclusion::Annual { month, day } => write!(f, "{:02}-{:02}", month, day),
+            Exclusion::Date(date) => write!(f, "{}", date),
+        }
+    }
+}
+
+/// Timezone and excluded days of the schedules of a pipeline
+#[derive(Debug, Clone)]
+pub struct ScheduleCalendar {
+    pub timezone: TimeZone,
+    pub exclusions: Vec<Exclusion>,
+}
+
+impl ScheduleCalendar {
+    /// Calendar out of the `timezone` and `schedule_exclude` attributes of a
+    /// pipeline, in the timezone of the host unless given
+    pub fn new(timezone: Option<&str>, exclusions: Option<&str>) -> Result<Self> {
+        Ok(Self {
+            timezone: match timezone {
+                Some(name) => parse_timezone(name)?,
+                None => TimeZone::system(),
+            },
+            exclusions: exclusions.map(Exclusion::parse_list).transpose()?.unwrap_or_default(),
+        })
+    }
+
+    /// Date in the calendar's timezone at `now`, in seconds since the epoch
+    pub fn date(&self, now: i64) -> Result<Date> {
+        Ok(self.datetime(now)?.date())
+    }
+
+    /// Local time in the calendar's timezone at `now`
+    pub fn datetime(&self, now: i64) -> Result<DateTime> {
+        Ok(Timestamp::from_second(now)?.to_zoned(self.timezone.clone()).datetime())
+    }
+
+    /// Exclusion holding schedules off at `now`, if any
+    pub fn exclusion(&self, now: i64) -> Result<Option<Exclusion>> {
+        let date = self.date(now)?;
+        Ok(self.exclusions.iter().find(|e| e.excludes(date)).copied())
+    }
+}
+
+/// Check that `name` is a timezone of the host's database, e.g. `Europe/Madrid`
+pub fn parse_timezone(name: &str) -> Result<TimeZone> {
+    TimeZone::get(name).map_err(|_| anyhow!("Unknown timezone {}, expected e.g. Europe/Madrid or UTC", name))
+}
@@ -0,0 +1,124 @@
+//! Credentials of private repos, installed in the zone only while cloning
+//!
+//! The `credential` of a repo names a secret of the pipeline. For SSH urls
+//! the secret is a private key, for HTTPS ones a token, or `user:password`
+//! for servers that want a user name. Either is written to
+//! `CREDENTIAL_PATH` before `git clone` and shredded once the repo is checked
+//! out, git finds it through environment variables so it never shows up in
+//! the commands the provisioning log records. Both happen through commands in
+//! the zone, so links in the zone's filesystem can't point them at the host.
+
+use crate::config::Secret;
+use crate::zones::PipelineZone;
+use anyhow::{Result, anyhow};
+
+/// Where the credential of the repo being cloned is written in the zone
+pub const CREDENTIAL_PATH: &str = "/root/.renzokutai/credential";
+
+/// User sent along tokens without one, which GitHub expects
+const TOKEN_USER: &str = "x-access-token";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CredentialKind {
+    SshKey,
+    Token,
+}
+
+impl CredentialKind {
+    /// Kind of credential `url` is cloned with, if it takes one
+    pub fn for_url(url: &str) -> Result<Self> {
+        if url.starts_with("https://") || url.starts_with("http://") {
+            Ok(CredentialKind::Token)
+        } else if url.starts_with("ssh://") || (url.contains('@') && url.contains(':') && !url.contains("://")) {
+            Ok(CredentialKind::SshKey)
+        } else {
+            Err(anyhow!("Credentials only apply to SSH and HTTPS urls, not {}", url))
+        }
+    }
+}
+
+/// A secret to clone a repo with
+pub struct RepoCredential<'a> {
+    kind: CredentialKind,
+    url: &'a str,
+    secret: &'a Secret,
+}
+
+impl<'a> RepoCredential<'a> {
+    pub fn new(url: &'a str, secret: &'a Secret) -> Result<Self> {
+        Ok(Self {
+            kind: CredentialKind::for_url(url)?,
+            url,
+            secret,
+        })
+    }
+
+    /// What the credential file holds, a key ending in a newline as ssh
+    /// wants, or a line of git's credential store
+    pub fn contents(&self) -> Result<String> {
+        match self.kind {
+            CredentialKind::SshKey => Ok(format!("{}\n", self.secret.value.trim_end())),
+            CredentialKind::Token => {
+                let (user, password) = self.secret.value.split_once(':').unwrap_or((TOKEN_USER, &self.secret.value));
+                let (scheme, rest) = self.url.split_once("://").unwrap_or(("https", self.url));
+                let host = rest.split('/').next().unwrap_or(rest);
+                let host = host.rsplit('@').next().unwrap_or(host);
+                if host.is_empty() {
+                    return Err(anyhow!("No host in {}", self.url));
+                }
+
+                Ok(format!("{}://{}:{}@{}\n", scheme, encode(user), encode(password), host))
+            }
+        }
+    }
+
+    /// Variables making git use the credential, to prefix its commands with
+    pub fn env(&self) -> String {
+        match self.kind {
+            CredentialKind::SshKey => format!(
+                "GIT_SSH_COMMAND='ssh -i {} -o IdentitiesOnly=yes -o StrictHostKeyChecking=accept-new'",
+                CREDENTIAL_PATH
+            ),
+            CredentialKind::Token => format!(
+                "GIT_CONFIG_COUNT=1 GIT_CONFIG_KEY_0=credential.helper GIT_CONFIG_VALUE_0='store --file={}'",
+                CREDENTIAL_PATH
+            ),
+        }
+    }
+
+    /// Write the credential into the zone, readable by root only
+    pub async fn install(&self, pzone: &PipelineZone) -> Result<()> {
+        pzone.exec_with_input(install_command(), self.contents()?.as_bytes()).await
+    }
+
+    /// Overwrite the credential with zeros before removing it
+    pub async fn shred(pzone: &PipelineZone) -> Result<()> {
+        pzone.exec_with_input(shred_command(), &[]).await
+    }
+}
+
+/// Command writing its input to `CREDENTIAL_PATH`, in place of whatever was
+/// there, with only root allowed to read it
+pub fn install_command() -> String {
+    let dir = CREDENTIAL_PATH.rsplit_once('/').map_or("/", |(dir, _)| dir);
+    format!("umask 077 && mkdir -p {dir} && chmod 700 {dir} && rm -f {path} && cat > {path}", dir = dir, path = CREDENTIAL_PATH)
+}
+
+/// Command zeroing `CREDENTIAL_PATH` before removing it, if it's there
+pub fn shred_command() -> String {
+    format!(
+        "[ ! -f {path} ] || {{ dd if=/dev/zero of={path} bs=1 count=$(wc -c < {path}) conv=notrunc 2>/dev/null; rm -f {path}; }}",
+        path = CREDENTIAL_PATH
+    )
+}
+
+/// Percent-encode what isn't allowed as is in the user info of a url
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
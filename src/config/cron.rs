@@ -0,0 +1,127 @@
+//! Cron expressions of the schedules triggering runs
+//!
+//! The usual five fields, minute, hour, day of month, month and day of week,
+//! each `*`, a value, a range `1-5` or a list of them, optionally stepped
+//! with `/n`. Months and weekdays can be named (`jan`, `mon`), and `0` or
+//! `7` is Sunday. Nicknames like `@daily` stand for the common schedules.
+//! Like cron, when both days are restricted a day matching either counts.
+
+use anyhow::{Result, anyhow};
+use jiff::civil::DateTime;
+
+const NICKNAMES: &[(&str, &str)] = &[
+    ("@hourly", "0 * * * *"),
+    ("@daily", "0 0 * * *"),
+    ("@midnight", "0 0 * * *"),
+    ("@weekly", "0 0 * * 0"),
+    ("@monthly", "0 0 1 * *"),
+    ("@yearly", "0 0 1 1 *"),
+    ("@annually", "0 0 1 1 *"),
+];
+
+/// Offset so `jan` is 1
+const MONTHS: &[&str] = &["", "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+const WEEKDAYS: &[&str] = &["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// When a cron expression fires, one bit per allowed value of every field
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    /// Sunday is bit 0
+    weekdays: u64,
+    /// Whether the day fields were given, rather than `*`
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl CronSchedule {
+    /// Whether the schedule fires on the minute of `time`
+    pub fn matches(&self, time: DateTime) -> bool {
+        let bit = |mask: u64, value: i8| mask & (1 << value) != 0;
+        let day = bit(self.days, time.day());
+        let weekday = bit(self.weekdays, time.weekday().to_sunday_zero_offset());
+        let day = match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day || weekday,
+            _ => day && weekday,
+        };
+
+        bit(self.minutes, time.minute()) && bit(self.hours, time.hour()) && bit(self.months, time.month()) && day
+    }
+}
+
+impl std::str::FromStr for CronSchedule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let expr = NICKNAMES
+            .iter()
+            .find(|(nickname, _)| *nickname == s.trim())
+            .map_or(s, |(_, expr)| expr);
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(anyhow!(
+                "Expected minute hour day month weekday or a nickname like @daily, got {}",
+                s
+            ));
+        };
+
+        let mut weekdays_mask = parse_field(weekdays, 0, 7, WEEKDAYS)?;
+        // 7 is Sunday too
+        if weekdays_mask & (1 << 7) != 0 {
+            weekdays_mask = (weekdays_mask | 1) & !(1 << 7);
+        }
+
+        Ok(CronSchedule {
+            minutes: parse_field(minutes, 0, 59, &[])?,
+            hours: parse_field(hours, 0, 23, &[])?,
+            days: parse_field(days, 1, 31, &[])?,
+            months: parse_field(months, 1, 12, MONTHS)?,
+            weekdays: weekdays_mask,
+            days_restricted: !days.starts_with('*'),
+            weekdays_restricted: !weekdays.starts_with('*'),
+        })
+    }
+}
+
+/// Bit mask of the values a field allows between `min` and `max`, `names`
+/// naming the values from 0
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<u64> {
+    let invalid = || anyhow!("Invalid cron field {}, expected values between {} and {}", field, min, max);
+    let value = |s: &str| -> Result<u32> {
+        let lowercase = s.to_lowercase();
+        let value = match names.iter().position(|name| !name.is_empty() && *name == lowercase) {
+            Some(position) => position as u32,
+            None => s.parse().map_err(|_| invalid())?,
+        };
+        match (min..=max).contains(&value) {
+            true => Ok(value),
+            false => Err(invalid()),
+        }
+    };
+
+    let mut mask = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|s| *s > 0).ok_or_else(invalid)?),
+            None => (part, 1),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (value(start)?, value(end)?),
+            // Like cron, a stepped single value runs to the end of the range
+            None if part.contains('/') => (value(range)?, max),
+            None => (value(range)?, value(range)?),
+        };
+        if start > end {
+            return Err(invalid());
+        }
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+
+    Ok(mask)
+}
@@ -0,0 +1,128 @@
+//! Line based unified diffs, used to review unsaved changes in the shell
+
+use crate::color::Colorize;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Line<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Edit script turning `old` into `new`, from their longest common subsequence
+fn lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Line<'a>> {
+    // lcs[i][j] is the length of the LCS of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut edits = Vec::new();
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            edits.push(Line::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            edits.push(Line::Removed(old[i]));
+            i += 1;
+        } else {
+            edits.push(Line::Added(new[j]));
+            j += 1;
+        }
+    }
+    edits.extend(old[i..].iter().map(|l| Line::Removed(l)));
+    edits.extend(new[j..].iter().map(|l| Line::Added(l)));
+
+    edits
+}
+
+/// Unified diff of `old` and `new` with `context` lines around each change,
+/// empty when both are the same
+pub fn unified(old: &str, new: &str, old_label: &str, new_label: &str, context: usize) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let edits = lines(&old, &new);
+
+    let changed: Vec<usize> = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, l)| !matches!(l, Line::Same(_)))
+        .map(|(i, _)| i)
+        .collect();
+    if changed.is_empty() {
+        return String::new();
+    }
+
+    // Group changes whose context overlaps into the same hunk
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for i in changed {
+        let start = i.saturating_sub(context);
+        let end = (i + context + 1).min(edits.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", old_label, new_label);
+    for (start, end) in hunks {
+        // Line numbers where the hunk starts in each file
+        let old_start = edits[..start].iter().filter(|l| !matches!(l, Line::Added(_))).count();
+        let new_start = edits[..start].iter().filter(|l| !matches!(l, Line::Removed(_))).count();
+        let hunk = &edits[start..end];
+        let old_len = hunk.iter().filter(|l| !matches!(l, Line::Added(_))).count();
+        let new_len = hunk.iter().filter(|l| !matches!(l, Line::Removed(_))).count();
+
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            range(old_start, old_len),
+            range(new_start, new_len)
+        ));
+        for line in hunk {
+            match line {
+                Line::Same(l) => out.push_str(&format!(" {}\n", l)),
+                Line::Removed(l) => out.push_str(&format!("-{}\n", l)),
+                Line::Added(l) => out.push_str(&format!("+{}\n", l)),
+            }
+        }
+    }
+
+    out
+}
+
+// Hunk range as written by diff -u, where empty ranges point at the line before
+fn range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, len),
+    }
+}
+
+/// Color a unified diff for the terminal
+pub fn colorize(diff: &str) -> String {
+    diff.lines()
+        .map(|line| {
+            if line.starts_with("---") || line.starts_with("+++") {
+                line.bold().to_string()
+            } else if line.starts_with("@@") {
+                line.cyan().to_string()
+            } else if line.starts_with('-') {
+                line.red().to_string()
+            } else if line.starts_with('+') {
+                line.green().to_string()
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,221 @@
+//! Steps out of Drone and Woodpecker pipelines, for users migrating from them
+//!
+//! Only what maps onto a shell step is kept: the name, `commands`,
+//! `depends_on` and `environment` of every step. Images, plugin settings,
+//! secrets and `when` filters have no equivalent here, they are dropped with
+//! a warning. Steps run scripts of the repository rather than inline
+//! commands, so the commands of every step become a script under
+//! `SCRIPT_DIR`, to be committed next to the YAML file. The scripts are
+//! only written once the pipeline is saved.
+
+use crate::config::{Dependency, EnvVar, Step, Steps, Value};
+use anyhow::{Result, anyhow};
+use serde::Deserialize;
+use serde_yaml::Value as Yaml;
+use std::path::{Path, PathBuf};
+
+/// Directory of the repository the scripts of the imported steps go in
+pub const SCRIPT_DIR: &str = "ci";
+
+/// Write `scripts`, as listed by `DroneImport::scripts_under`, refusing to
+/// overwrite existing files
+pub fn write_scripts(scripts: &[(PathBuf, String)]) -> Result<()> {
+    if let Some((path, _)) = scripts.iter().find(|(path, _)| path.exists()) {
+        return Err(anyhow!("{} already exists", path.display()));
+    }
+
+    for (path, contents) in scripts {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, contents)?;
+    }
+    Ok(())
+}
+
+/// Steps converted from a Drone or Woodpecker file
+#[derive(Debug)]
+pub struct DroneImport {
+    pub steps: Steps,
+    /// Path relative to the repository and contents of the script of every
+    /// step with commands
+    pub scripts: Vec<(String, String)>,
+    /// What couldn't be converted, one line per step and setting
+    pub warnings: Vec<String>,
+}
+
+impl DroneImport {
+    pub fn load(path: &str) -> Result<Self> {
+        let yaml = std::fs::read_to_string(path).map_err(|e| anyhow!("Couldn't read {}: {}", path, e))?;
+        Self::parse(&yaml)
+    }
+
+    /// Convert every pipeline of a `.drone.yml` or `.woodpecker.yml`, Drone
+    /// files may hold several separated by `---`
+    pub fn parse(yaml: &str) -> Result<Self> {
+        let mut import = DroneImport {
+            steps: Steps::new(),
+            scripts: Vec::new(),
+            warnings: Vec::new(),
+        };
+
+        for document in serde_yaml::Deserializer::from_str(yaml) {
+            let document = Yaml::deserialize(document).map_err(|e| anyhow!("Invalid YAML: {}", e))?;
+            // Secrets and signatures share the file with the pipelines
+            if document.get("kind").and_then(Yaml::as_str).is_some_and(|kind| kind != "pipeline") {
+                continue;
+            }
+
+            let steps = document
+                .get("steps")
+                .or_else(|| document.get("pipeline"))
+                .ok_or(anyhow!("Expected steps in every pipeline"))?;
+            import.add_pipeline(steps)?;
+        }
+
+        Ok(import)
+    }
+
+    /// Where the scripts of the steps go under the repository at `root`,
+    /// along with their contents
+    pub fn scripts_under(&self, root: &Path) -> Vec<(PathBuf, String)> {
+        self.scripts.iter().map(|(path, contents)| (root.join(path), contents.clone())).collect()
+    }
+
+    /// Add the steps of a pipeline, a list of named steps in Drone and a map
+    /// of them by name in Woodpecker
+    fn add_pipeline(&mut self, steps: &Yaml) -> Result<()> {
+        let steps: Vec<(String, &Yaml)> = match steps {
+            Yaml::Sequence(steps) => steps
+                .iter()
+                .map(|step| {
+                    let name = step.get("name").and_then(Yaml::as_str).ok_or(anyhow!("Expected a name in every step"))?;
+                    Ok((name.to_string(), step))
+                })
+                .collect::<Result<_>>()?,
+            Yaml::Mapping(steps) => steps
+                .iter()
+                .map(|(name, step)| Ok((scalar(name).ok_or(anyhow!("Invalid step name {:?}", name))?, step)))
+                .collect::<Result<_>>()?,
+            _ => return Err(anyhow!("Expected steps to be a list or a map")),
+        };
+
+        // Without any depends_on the steps run one after the other
+        let sequential = steps.iter().all(|(_, step)| step.get("depends_on").is_none());
+        let mut previous: Option<String> = None;
+
+        for (name, step) in steps {
+            let mut depends: Vec<String> = strings(step.get("depends_on")).iter().map(|d| step_name(d)).collect();
+            let renamed = step_name(&name);
+            if renamed != name {
+                self.warnings.push(format!("Step {}: renamed to {}", name, renamed));
+            }
+            if sequential {
+                depends.extend(previous.replace(renamed.clone()));
+            }
+            let step = self.convert(&renamed, step, depends);
+            self.steps.push(step);
+        }
+
+        Ok(())
+    }
+
+    fn convert(&mut self, name: &str, step: &Yaml, depends: Vec<String>) -> Step {
+        for key in ["image", "settings", "secrets", "when", "services", "volumes"] {
+            if step.get(key).is_some() {
+                self.warnings.push(format!("Step {}: {} is not supported, dropped", name, key));
+            }
+        }
+        let commands = strings(step.get("commands"));
+        let script = match commands.is_empty() {
+            true => {
+                self.warnings.push(format!("Step {}: no commands, left without a script", name));
+                Value::Unset
+            }
+            false => {
+                let path = self.script_path(name);
+                // Like Drone, stop at the first failing command
+                self.scripts.push((path.clone(), format!("set -e\n{}\n", commands.join("\n"))));
+                Value::Set(path)
+            }
+        };
+
+        let mut env = Vec::new();
+        if let Some(Yaml::Mapping(environment)) = step.get("environment") {
+            for (key, value) in environment {
+                match (scalar(key), scalar(value)) {
+                    (Some(key), _) if EnvVar::validate_name(&key).is_err() => {
+                        self.warnings.push(format!("Step {}: invalid variable name {}, dropped", name, key))
+                    }
+                    (Some(key), Some(value)) => env.push(EnvVar { name: key, value }),
+                    // e.g. from_secret, secrets are set on the pipeline instead
+                    (Some(key), None) => self.warnings.push(format!(
+                        "Step {}: variable {} is not a plain value, dropped",
+                        name, key
+                    )),
+                    (None, _) => self.warnings.push(format!("Step {}: invalid variable name {:?}, dropped", name, key)),
+                }
+            }
+        }
+
+        Step {
+            name: Value::Set(name.to_string()),
+            script,
+            depends: depends
+                .into_iter()
+                .map(|name| Dependency { name: Value::Set(name) })
+                .collect(),
+            env,
+            ..Step::default()
+        }
+    }
+
+    /// Script path for step `name`, made unique among the scripts so far
+    fn script_path(&self, name: &str) -> String {
+        let stem: String = name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c.to_ascii_lowercase() } else { '_' })
+            .collect();
+        let taken = |path: &String| self.scripts.iter().any(|(p, _)| p == path);
+
+        (1..)
+            .map(|n| match n {
+                1 => format!("{}/{}.sh", SCRIPT_DIR, stem),
+                n => format!("{}/{}_{}.sh", SCRIPT_DIR, stem, n),
+            })
+            .find(|path| !taken(path))
+            .unwrap()
+    }
+}
+
+/// `name` as a valid step name, with '_' in place of the characters step
+/// names can't hold
+fn step_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    match name.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+        true => name,
+        false => format!("step{}", name),
+    }
+}
+
+/// A string, number or boolean as a string
+fn scalar(value: &Yaml) -> Option<String> {
+    match value {
+        Yaml::String(s) => Some(s.clone()),
+        Yaml::Number(n) => Some(n.to_string()),
+        Yaml::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// A list of strings, or a single one
+fn strings(value: Option<&Yaml>) -> Vec<String> {
+    match value {
+        Some(Yaml::Sequence(values)) => values.iter().filter_map(scalar).collect(),
+        Some(value) => scalar(value).into_iter().collect(),
+        None => Vec::new(),
+    }
+}
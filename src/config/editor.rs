@@ -0,0 +1,112 @@
+//! Line editor of the interactive config shell, with history and completion
+
+use crate::config::shell::complete;
+use anyhow::Result;
+use rustyline::completion::{Completer, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::{DefaultHistory, History};
+use rustyline::validate::Validator;
+use rustyline::error::ReadlineError;
+use rustyline::{Context, Editor, Helper};
+use std::path::PathBuf;
+
+/// Number of entries listed by the `history` command
+const HISTORY_LISTED: usize = 20;
+
+/// Line editor for the config shell
+pub struct Shell {
+    editor: Editor<CfgHelper, DefaultHistory>,
+    history_path: Option<PathBuf>,
+}
+
+impl Shell {
+    pub fn new() -> Result<Self> {
+        let mut editor = Editor::new()?;
+        editor.set_helper(Some(CfgHelper::default()));
+
+        let history_path = history_path();
+        if let Some(path) = &history_path {
+            // A missing history file just means this is the first session
+            let _ = editor.load_history(path);
+        }
+
+        Ok(Self {
+            editor,
+            history_path,
+        })
+    }
+
+    /// Read a command, completing attribute keys from `attributes`
+    ///
+    /// Returns `None` when the user pressed Ctrl-D or Ctrl-C.
+    pub fn read_line(&mut self, prompt: &str, attributes: &'static [&'static str]) -> Result<Option<String>> {
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.attributes = attributes;
+        }
+
+        let line = match self.editor.readline(prompt) {
+            Ok(line) => line.trim().to_string(),
+            Err(ReadlineError::Eof | ReadlineError::Interrupted) => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        // Secret values don't belong in the history file
+        if !line.is_empty() && line.split_whitespace().next() != Some("secret") {
+            self.editor.add_history_entry(line.as_str())?;
+
+            if let Some(path) = &self.history_path {
+                self.editor.append_history(path)?;
+            }
+        }
+
+        Ok(Some(line))
+    }
+
+    /// Print the most recent history entries
+    pub fn print_history(&self) {
+        let history = self.editor.history();
+        let skip = history.len().saturating_sub(HISTORY_LISTED);
+
+        for (idx, entry) in history.iter().enumerate().skip(skip) {
+            println!("{:>5}  {}", idx + 1, entry);
+        }
+    }
+}
+
+/// `~/.renzokutai_history`, if there is a home directory to keep it in
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".renzokutai_history"))
+}
+
+#[derive(Default)]
+pub struct CfgHelper {
+    attributes: &'static [&'static str],
+}
+
+impl Helper for CfgHelper {}
+impl Highlighter for CfgHelper {}
+impl Validator for CfgHelper {}
+
+impl Hinter for CfgHelper {
+    type Hint = String;
+}
+
+impl Completer for CfgHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let (start, candidates) = complete(&line[..pos], self.attributes);
+
+        Ok((
+            start,
+            candidates
+                .into_iter()
+                .map(|c| Pair {
+                    display: c.clone(),
+                    replacement: c,
+                })
+                .collect(),
+        ))
+    }
+}
@@ -0,0 +1,50 @@
+//! Formats pipelines are saved in, picked from the extension of their file
+//!
+//! XML is what the builder writes new pipelines in. TOML and YAML files are
+//! easier to edit by hand, they are read and saved back in their own format.
+
+use anyhow::Result;
+use serde::{Serialize, de::DeserializeOwned};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    Xml,
+    Toml,
+    Yaml,
+}
+
+impl FileFormat {
+    /// Every format, in the order their files are looked for
+    pub const ALL: &[FileFormat] = &[FileFormat::Xml, FileFormat::Toml, FileFormat::Yaml];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            FileFormat::Xml => "xml",
+            FileFormat::Toml => "toml",
+            FileFormat::Yaml => "yaml",
+        }
+    }
+
+    /// Format of the file at `path`, `None` for other files
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?;
+        Self::ALL.iter().copied().find(|format| format.extension() == extension)
+    }
+
+    pub fn parse<T: DeserializeOwned>(&self, text: &str) -> Result<T> {
+        Ok(match self {
+            FileFormat::Xml => serde_xml_rs::from_str(text)?,
+            FileFormat::Toml => toml::from_str(text)?,
+            FileFormat::Yaml => serde_yaml::from_str(text)?,
+        })
+    }
+
+    pub fn to_string<T: Serialize>(&self, value: &T) -> Result<String> {
+        Ok(match self {
+            FileFormat::Xml => serde_xml_rs::to_string(value)?,
+            FileFormat::Toml => toml::to_string_pretty(value)?,
+            FileFormat::Yaml => serde_yaml::to_string(value)?,
+        })
+    }
+}
@@ -0,0 +1,126 @@
+//! Axes a pipeline runs across, e.g. rust versions or pkgsrc branches
+//!
+//! Every combination of their values gets its own run, in its own run zone,
+//! with the values exported to the steps and recorded as tags of the run.
+
+use crate::color::Colorize;
+use crate::config::EnvVar;
+use crate::history::RunTag;
+use anyhow::{Result, anyhow};
+use itertools::Itertools;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MatrixAxis {
+    /// Exported to the steps, so it has to be a variable name
+    #[serde(rename = "@name")]
+    pub name: String,
+    #[serde(rename = "@values", serialize_with = "join_values", deserialize_with = "split_values")]
+    pub values: Vec<String>,
+}
+
+fn join_values<S: Serializer>(values: &[String], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&values.join(","))
+}
+
+fn split_values<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    let values = String::deserialize(deserializer)?;
+    MatrixAxis::parse_values(&values).map_err(serde::de::Error::custom)
+}
+
+impl MatrixAxis {
+    /// Parse a comma separated list of values, each at most once
+    pub fn parse_values(value: &str) -> Result<Vec<String>> {
+        let values: Vec<String> = value
+            .split(',')
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(str::to_string)
+            .collect();
+
+        if values.is_empty() {
+            Err(anyhow!("Expected comma separated values, got {}", value))
+        } else if let Some(value) = values.iter().duplicates().next() {
+            Err(anyhow!("Value {} is repeated", value))
+        } else {
+            Ok(values)
+        }
+    }
+
+    /// Set the values of axis `name` in `axes`, replacing its previous ones
+    pub fn set(axes: &mut Vec<MatrixAxis>, name: String, values: &str) -> Result<()> {
+        EnvVar::validate_name(&name)?;
+        let values = Self::parse_values(values)?;
+
+        match axes.iter_mut().find(|a| a.name == name) {
+            Some(axis) => axis.values = values,
+            None => axes.push(MatrixAxis { name, values }),
+        }
+        Ok(())
+    }
+
+    pub fn unset(axes: &mut Vec<MatrixAxis>, name: &str) -> Result<()> {
+        let before = axes.len();
+        axes.retain(|a| a.name != name);
+
+        if axes.len() == before {
+            Err(anyhow!("No matrix axis {}", name))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Every combination of the values of `axes`, as the variables exporting
+    /// them, in the order the axes were added
+    pub fn combinations(axes: &[MatrixAxis]) -> Vec<Vec<EnvVar>> {
+        axes.iter()
+            .map(|axis| {
+                axis.values.iter().map(|value| EnvVar {
+                    name: axis.name.clone(),
+                    value: value.clone(),
+                })
+            })
+            .multi_cartesian_product()
+            .collect()
+    }
+
+    /// Tags recording the combination a run was made for
+    pub fn tags(combination: &[EnvVar]) -> Vec<RunTag> {
+        combination
+            .iter()
+            .map(|var| RunTag {
+                key: var.name.clone(),
+                value: var.value.clone(),
+            })
+            .collect()
+    }
+
+    /// Succeeded and failed runs of every value of `axes`, out of the
+    /// outcome of each combination
+    pub fn report(axes: &[MatrixAxis], results: &[(Vec<EnvVar>, bool)]) -> String {
+        axes.iter()
+            .flat_map(|axis| {
+                axis.values.iter().map(move |value| {
+                    let (succeeded, failed) = results
+                        .iter()
+                        .filter(|(combination, _)| combination.iter().any(|v| v.name == axis.name && v.value == *value))
+                        .fold((0, 0), |(succeeded, failed), (_, ok)| {
+                            if *ok { (succeeded + 1, failed) } else { (succeeded, failed + 1) }
+                        });
+                    let failed = match failed {
+                        0 => format!("{} failed", failed),
+                        _ => format!("{} failed", failed).red().to_string(),
+                    };
+
+                    format!("{}={}: {} succeeded, {}", axis.name, value, succeeded, failed)
+                })
+            })
+            .join("\n")
+    }
+}
+
+impl std::fmt::Display for MatrixAxis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}={}", self.name, self.values.join(","))
+    }
+}
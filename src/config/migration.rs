@@ -0,0 +1,120 @@
+//! Upgrades of pipelines saved before a change to their schema
+//!
+//! Saved pipelines carry the `version` of the schema they were written in,
+//! files from before it was added are version 1. Loading an older XML file
+//! runs the migrations from its version up to `SCHEMA_VERSION` on the
+//! document before it's deserialized. TOML and YAML files came with version
+//! 2, they have nothing to migrate.
+
+use anyhow::{Result, anyhow};
+use xml::attribute::OwnedAttribute;
+use xml::name::OwnedName;
+use xml::namespace::Namespace;
+use xml::reader::{EventReader, XmlEvent};
+
+/// Version of the schema pipelines are saved in
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Version of the files saved before the schema had one
+const UNVERSIONED: u32 = 1;
+
+/// Turns the events of a document into those of the next schema version
+type Migration = fn(Vec<XmlEvent>) -> Vec<XmlEvent>;
+
+/// Migration from every version, starting at 1
+const MIGRATIONS: &[Migration] = &[split_depends];
+
+/// Check that a pipeline saved in schema `version` can be read
+pub fn check_version(version: u32) -> Result<()> {
+    if version < UNVERSIONED {
+        Err(anyhow!("Invalid schema version {}", version))
+    } else if version > SCHEMA_VERSION {
+        Err(anyhow!(
+            "Saved in schema version {}, newer than the {} this version reads",
+            version,
+            SCHEMA_VERSION
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// The pipeline in `xml` upgraded to `SCHEMA_VERSION`, `None` when it's
+/// already there
+pub fn migrate_xml(xml: &str) -> Result<Option<String>> {
+    let mut events = EventReader::from_str(xml).into_iter().collect::<Result<Vec<XmlEvent>, _>>()?;
+    let version = match events.iter().find(|e| matches!(e, XmlEvent::StartElement { .. })) {
+        Some(XmlEvent::StartElement { attributes, .. }) => match attributes.iter().find(|a| a.name.local_name == "version") {
+            Some(attr) => attr
+                .value
+                .parse()
+                .map_err(|_| anyhow!("Invalid schema version {}", attr.value))?,
+            None => UNVERSIONED,
+        },
+        _ => return Err(anyhow!("The pipeline has no root element")),
+    };
+    check_version(version)?;
+    if version == SCHEMA_VERSION {
+        return Ok(None);
+    }
+
+    for migration in &MIGRATIONS[(version - UNVERSIONED) as usize..] {
+        events = migration(events);
+    }
+    set_version(&mut events);
+
+    let mut out = Vec::new();
+    let mut writer = xml::EmitterConfig::new().create_writer(&mut out);
+    for event in events.iter().filter_map(|e| e.as_writer_event()) {
+        writer.write(event)?;
+    }
+    Ok(Some(String::from_utf8(out)?))
+}
+
+/// Write `SCHEMA_VERSION` on the root element of `events`
+fn set_version(events: &mut [XmlEvent]) {
+    if let Some(XmlEvent::StartElement { attributes, .. }) =
+        events.iter_mut().find(|e| matches!(e, XmlEvent::StartElement { .. }))
+    {
+        attributes.retain(|a| a.name.local_name != "version");
+        attributes.insert(0, OwnedAttribute::new(OwnedName::local("version"), SCHEMA_VERSION.to_string()));
+    }
+}
+
+fn element(name: &str, children: Vec<XmlEvent>) -> Vec<XmlEvent> {
+    let start = XmlEvent::StartElement {
+        name: OwnedName::local(name),
+        attributes: Vec::new(),
+        namespace: Namespace::empty(),
+    };
+    let end = XmlEvent::EndElement { name: OwnedName::local(name) };
+
+    std::iter::once(start).chain(children).chain(std::iter::once(end)).collect()
+}
+
+/// Version 1 steps named the one step they depended on in a `depends`
+/// attribute, version 2 has a `<depend>` element per dependency
+fn split_depends(events: Vec<XmlEvent>) -> Vec<XmlEvent> {
+    let mut migrated = Vec::with_capacity(events.len());
+
+    for event in events {
+        match event {
+            XmlEvent::StartElement { name, mut attributes, namespace } if name.local_name == "step" => {
+                let depends = attributes
+                    .iter()
+                    .position(|a| a.name.local_name == "depends")
+                    .map(|i| attributes.remove(i).value);
+                migrated.push(XmlEvent::StartElement { name, attributes, namespace });
+
+                let names = depends.iter().flat_map(|d| d.split(',')).map(str::trim);
+                for dependency in names.filter(|n| !n.is_empty()) {
+                    let text = XmlEvent::Characters(dependency.to_string());
+                    migrated.extend(element("depend", element("name", vec![text])));
+                }
+            }
+            event => migrated.push(event),
+        }
+    }
+
+    migrated
+}
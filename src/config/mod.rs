@@ -1,11 +1,40 @@
+pub mod build_cache;
+pub mod calendar;
+pub mod credential;
+pub mod cron;
+pub mod diff;
+pub mod drone;
+#[cfg(feature = "builder")]
+pub mod editor;
+pub mod format;
+pub mod matrix;
+pub mod migration;
 pub mod package;
+pub mod param;
 pub mod pipeline;
+pub mod pipeline_trigger;
 pub mod repo;
+pub mod repo_definition;
+pub mod secret;
+pub mod service;
+pub mod shell;
 pub mod step;
 
+pub use build_cache::*;
+pub use calendar::*;
+pub use credential::*;
+pub use cron::*;
+pub use format::*;
+pub use matrix::*;
 pub use package::*;
+pub use param::*;
 pub use pipeline::*;
+pub use pipeline_trigger::*;
 pub use repo::*;
+pub use secret::*;
+pub use service::*;
+#[cfg(feature = "builder")]
+pub use editor::Shell;
 pub use step::*;
 
 use anyhow::{Result, anyhow};
@@ -13,14 +42,16 @@ use itertools::Itertools;
 use nom::{
     IResult, Parser,
     branch::alt,
-    bytes::complete::{tag, take_while1},
-    character::complete::{char, multispace0, multispace1},
-    combinator::{map, opt, rest},
-    sequence::{preceded, separated_pair},
+    bytes::complete::{escaped_transform, tag, take_till, take_till1, take_while1},
+    character::complete::{char, multispace0, multispace1, usize},
+    combinator::{eof, map, opt, rest, value},
+    multi::separated_list1,
+    sequence::{delimited, preceded, separated_pair},
 };
-use owo_colors::OwoColorize;
+use crate::color::Colorize;
+use crate::history::History;
+use drone::DroneImport;
 use std::cell::RefCell;
-use std::io::{self, Write};
 use std::rc::Rc;
 
 #[derive(Debug, Default, PartialEq, Eq, Clone)]
@@ -37,69 +68,406 @@ impl<T: Clone> Value<T> {
             Value::Set(v) => Ok(v.clone()),
         }
     }
+
+    pub fn option(&self) -> Option<T> {
+        match self {
+            Value::Unset => None,
+            Value::Set(v) => Some(v.clone()),
+        }
+    }
+
+    /// Like `ensure`, naming the attribute in the error
+    pub fn require(&self, attribute: &str) -> Result<T> {
+        self.ensure().map_err(|_| anyhow!("{} is unset", attribute))
+    }
+}
+
+impl<T: Clone> From<Option<T>> for Value<T> {
+    fn from(value: Option<T>) -> Self {
+        match value {
+            None => Value::Unset,
+            Some(v) => Value::Set(v),
+        }
+    }
+}
+
+impl<T: Clone + std::fmt::Display> Value<T> {
+    /// Render the value for `info`, highlighting it when it still needs to be set
+    pub fn display(&self) -> String {
+        match self {
+            Value::Unset => "unset".red().to_string(),
+            Value::Set(v) => v.to_string(),
+        }
+    }
 }
 
 /// Configure interactive loop
+#[cfg(feature = "builder")]
 pub async fn builder(pipeline_name: &String) -> Result<()> {
     let mut state = CfgState::new(pipeline_name)?;
+    let mut shell = Shell::new()?;
 
     loop {
         let prompt = state.prompt();
-        let response = prompt_read(prompt.as_str());
+        let attributes = state.stack_top().unwrap().attributes();
+        let Some(response) = shell.read_line(prompt.as_str(), attributes)? else {
+            if confirm_exit(&state)? {
+                return Ok(());
+            }
+            continue;
+        };
 
         match parse_command(response.as_str()) {
-            Ok((_, CfgCommand::Select { ty, filter })) => state.select(ty, filter),
-            Ok((_, CfgCommand::Set { key, value })) => state.set(key, value),
-            Ok((_, CfgCommand::Add { ty })) => Ok(state.add(ty)),
-            Ok((_, CfgCommand::Print)) => {
-                println!("{:?}", state.stack_top().unwrap());
-                Ok(())
-            }
-            Ok((_, CfgCommand::End)) => {
-                state.end()?;
-                Ok(())
+            Ok(CfgCommand::Exit) => {
+                if confirm_exit(&state)? {
+                    return Ok(());
+                }
             }
-            Ok((_, CfgCommand::Commit)) => {
-                match state.inner.borrow().validate() {
-                    Ok(vp) => {
-                        vp.save()?;
-                        vp.apply().await?;
+            Ok(CfgCommand::History) => shell.print_history(),
+            Ok(command @ (CfgCommand::Apply | CfgCommand::Commit)) => {
+                if let Some(summary) = state.apply_summary().await? {
+                    println!("{}", summary);
+                    if !crate::confirm::assume_yes() && !crate::confirm::ask("Go ahead? [y/N] ")? {
+                        continue;
                     }
-                    Err(err) => println!("{:?}", err),
-                };
-                Ok(())
+                }
+                state.execute(command).await?
             }
-            Err(_) => {
-                println!("Unrecognized command");
-                Ok(())
+            Ok(CfgCommand::Revert) => {
+                if crate::confirm::ask("Discard all unsaved changes? [y/N] ")? {
+                    state.execute(CfgCommand::Revert).await?;
+                }
             }
-        }?;
+            Ok(command) => state.execute(command).await?,
+            Err(ParseError::Empty) => (),
+            Err(err) => println!("{}", err),
+        }
     }
 }
 
+/// Whether the shell may exit, asking first when there are unsaved changes
+#[cfg(feature = "builder")]
+fn confirm_exit(state: &CfgState) -> Result<bool> {
+    Ok(!state.is_dirty() || crate::confirm::ask("Discard unsaved changes and exit? [y/N] ")?)
+}
+
 #[derive(Debug)]
 pub struct CfgState {
+    name: String,
     stack: Vec<Frame>,
     inner: Rc<RefCell<Pipeline>>,
+    /// Pipeline as it was last loaded or saved
+    saved: Pipeline,
+    undo: Vec<Snapshot>,
+    redo: Vec<Snapshot>,
+    /// Scripts of the imported Drone steps, written once the pipeline is
+    /// saved
+    scripts: Vec<(std::path::PathBuf, String)>,
+}
+
+/// Pipeline, selected element and pending scripts as they were before a
+/// command, restored by `undo` and `redo`
+#[derive(Debug)]
+struct Snapshot {
+    pipeline: Pipeline,
+    selected: Option<(&'static str, usize)>,
+    scripts: Vec<(std::path::PathBuf, String)>,
 }
 
 impl CfgState {
     pub fn new(pipeline_name: &String) -> Result<CfgState> {
         let p = Pipeline::load_or_create(pipeline_name)?;
+        let saved = p.clone();
         let p = Rc::new(RefCell::new(p));
 
         Ok(Self {
+            name: pipeline_name.clone(),
+            saved,
             inner: p.clone(),
             stack: vec![Frame::Pipeline(p)],
+            undo: Vec::new(),
+            redo: Vec::new(),
+            scripts: Vec::new(),
         })
     }
 
+    /// Reload the pipeline from disk, dropping unsaved changes and the
+    /// undo history
+    pub fn revert(&mut self) -> Result<()> {
+        *self = Self::new(&self.name)?;
+        println!("Reverted {} to the saved configuration", self.name);
+
+        Ok(())
+    }
+
+    /// Execute a single command against the configuration state
+    ///
+    /// Commands changing the pipeline either apply fully or, when they fail
+    /// halfway, leave it as it was.
+    pub async fn execute(&mut self, command: CfgCommand) -> Result<()> {
+        let snapshot = command.mutates().then(|| self.snapshot());
+        let result = self.dispatch(command).await;
+
+        match (snapshot, &result) {
+            (Some(snapshot), Ok(_)) => {
+                self.undo.push(snapshot);
+                self.redo.clear();
+            }
+            (Some(snapshot), Err(_)) => {
+                self.restore(snapshot);
+            }
+            (None, _) => (),
+        }
+        result
+    }
+
+    async fn dispatch(&mut self, command: CfgCommand) -> Result<()> {
+        match command {
+            CfgCommand::Select { ty, filters, index } => self.select(ty, filters, index),
+            CfgCommand::Set { pairs } => pairs
+                .into_iter()
+                .try_for_each(|(key, value)| self.set(key, value)),
+            CfgCommand::Unset { key } => self.unset(key),
+            CfgCommand::Add { ty } => {
+                self.add(ty);
+                Ok(())
+            }
+            CfgCommand::Copy { ty, filters, name } => self.copy(ty, filters, name),
+            CfgCommand::Import { pipeline, ty } => self.import(pipeline, ty),
+            CfgCommand::ImportDrone { path } => self.import_drone(path),
+            CfgCommand::Depends { action, step } => self.depends(action, step),
+            CfgCommand::Move { step, position, other } => self.move_step(step, position, other),
+            CfgCommand::EnvSet { key, value } => self.env_set(key, value),
+            CfgCommand::EnvUnset { key } => self.env_unset(key),
+            CfgCommand::SecretSet { key, value } => self.secret_set(key, value),
+            CfgCommand::SecretUnset { key } => self.secret_unset(key),
+            CfgCommand::MatrixSet { key, value } => self.matrix_set(key, value),
+            CfgCommand::MatrixUnset { key } => self.matrix_unset(key),
+            CfgCommand::ParamSet { key, value } => self.param_set(key, value),
+            CfgCommand::ParamUnset { key } => self.param_unset(key),
+            CfgCommand::Print => {
+                println!("{:?}", self.stack_top().unwrap());
+                Ok(())
+            }
+            CfgCommand::Info => {
+                println!("{}", self.stack_top().unwrap().info());
+                Ok(())
+            }
+            CfgCommand::Help => {
+                println!("{}", shell::help(self.stack_top().unwrap()));
+                Ok(())
+            }
+            CfgCommand::Validate => {
+                let errors = self.inner.borrow().validation_errors();
+
+                if errors.is_empty() {
+                    println!("{}", "Pipeline is valid".green());
+                } else {
+                    for err in errors {
+                        println!("{} {}", "error:".red(), err);
+                    }
+                }
+                Ok(())
+            }
+            CfgCommand::End => self.end(),
+            CfgCommand::Undo => self.undo(),
+            CfgCommand::Redo => self.redo(),
+            CfgCommand::Revert => self.revert(),
+            CfgCommand::History => Err(anyhow!("history is only available in the interactive shell")),
+            CfgCommand::Exit => Err(anyhow!("exit is only available in the interactive shell")),
+            CfgCommand::Save => {
+                match self.claimed() {
+                    Ok(vp) => {
+                        self.write_scripts()?;
+                        vp.save()?;
+                        self.inner.borrow_mut().subnet = vp.subnet.clone().into();
+                        self.saved = self.inner.borrow().clone();
+                        println!("Saved {}", ValidatedPipeline::file_path(&vp.name).display());
+                    }
+                    Err(err) => println!("{:?}", err),
+                };
+                Ok(())
+            }
+            CfgCommand::Diff => {
+                match self.diff() {
+                    Ok(diff) if diff.is_empty() => println!("No unsaved changes"),
+                    Ok(diff) => println!("{}", diff::colorize(&diff)),
+                    Err(err) => println!("{:?}", err),
+                };
+                Ok(())
+            }
+            CfgCommand::Export { format, path } => {
+                match self.validate() {
+                    Ok(vp) => {
+                        let exported = vp.export(format)?;
+                        match path {
+                            Some(path) => {
+                                std::fs::write(&path, exported)?;
+                                println!("Exported to {}", path);
+                            }
+                            None => println!("{}", exported),
+                        }
+                    }
+                    Err(err) => println!("{:?}", err),
+                };
+                Ok(())
+            }
+            CfgCommand::Apply => {
+                match self.claimed() {
+                    Ok(vp) => vp.apply().await?,
+                    Err(err) => println!("{:?}", err),
+                };
+                Ok(())
+            }
+            CfgCommand::Commit => {
+                match self.claimed() {
+                    Ok(vp) => {
+                        self.write_scripts()?;
+                        vp.save()?;
+                        self.inner.borrow_mut().subnet = vp.subnet.clone().into();
+                        self.saved = self.inner.borrow().clone();
+                        vp.apply().await?;
+                    }
+                    Err(err) => println!("{:?}", err),
+                };
+                Ok(())
+            }
+        }
+    }
+
+    /// What applying the pipeline would do, `None` while it isn't valid
+    pub async fn apply_summary(&self) -> Result<Option<String>> {
+        match self.validate() {
+            Ok(vp) => Ok(Some(vp.apply_summary(&History::open().await?).await?)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Parse and execute a command line as if it was typed in the shell
+    pub async fn execute_line(&mut self, line: &str) -> Result<()> {
+        match parse_command(line) {
+            Ok(command) => self.execute(command).await,
+            Err(err) => Err(anyhow!("{}: {}", err, line)),
+        }
+    }
+
+    pub fn validate(&self) -> Result<ValidatedPipeline> {
+        self.inner.borrow().validate()
+    }
+
+    /// The pipeline as saved or applied, with its subnet claimed
+    pub fn claimed(&self) -> Result<ValidatedPipeline> {
+        let mut vp = self.validate()?;
+        vp.claim_subnet()?;
+        Ok(vp)
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        let pipeline = self.inner.borrow();
+        let selected = self.stack.get(1).and_then(|frame| {
+            let index = match frame {
+                Frame::Package(p) => pipeline.packages.iter().position(|e| Rc::ptr_eq(e, p)),
+                Frame::Repo(r) => pipeline.repos.iter().position(|e| Rc::ptr_eq(e, r)),
+                Frame::Step(s) => pipeline.steps.iter().position(|e| Rc::ptr_eq(e, s)),
+                Frame::BuildCache(c) => pipeline.caches.iter().position(|e| Rc::ptr_eq(e, c)),
+                Frame::Trigger(t) => pipeline.triggers.iter().position(|e| Rc::ptr_eq(e, t)),
+                Frame::Service(s) => pipeline.services.iter().position(|e| Rc::ptr_eq(e, s)),
+                Frame::Pipeline(_) => None,
+            };
+            index.map(|index| (frame.kind(), index))
+        });
+
+        Snapshot {
+            pipeline: pipeline.clone(),
+            selected,
+            scripts: self.scripts.clone(),
+        }
+    }
+
+    /// Put back a snapshot, returning the state it replaced
+    fn restore(&mut self, snapshot: Snapshot) -> Snapshot {
+        let current = self.snapshot();
+        *self.inner.borrow_mut() = snapshot.pipeline;
+        self.scripts = snapshot.scripts;
+
+        let pipeline = self.inner.borrow();
+        let selected = snapshot.selected.and_then(|(kind, index)| match kind {
+            "package" => pipeline.packages.iter().nth(index).cloned().map(Frame::Package),
+            "repo" => pipeline.repos.iter().nth(index).cloned().map(Frame::Repo),
+            "step" => pipeline.steps.iter().nth(index).cloned().map(Frame::Step),
+            "cache" => pipeline.caches.iter().nth(index).cloned().map(Frame::BuildCache),
+            "trigger" => pipeline.triggers.iter().nth(index).cloned().map(Frame::Trigger),
+            "service" => pipeline.services.iter().nth(index).cloned().map(Frame::Service),
+            _ => None,
+        });
+        drop(pipeline);
+
+        self.stack.truncate(1);
+        self.stack.extend(selected);
+        current
+    }
+
+    /// Write the scripts of the steps imported since the last save
+    fn write_scripts(&mut self) -> Result<()> {
+        drone::write_scripts(&self.scripts)?;
+        for (path, _) in self.scripts.drain(..) {
+            println!("Wrote {}", path.display());
+        }
+        Ok(())
+    }
+
+    /// Roll back the last command that changed the pipeline
+    pub fn undo(&mut self) -> Result<()> {
+        let snapshot = self.undo.pop().ok_or(anyhow!("Nothing to undo"))?;
+        let current = self.restore(snapshot);
+        self.redo.push(current);
+        Ok(())
+    }
+
+    /// Apply again the last command rolled back by `undo`
+    pub fn redo(&mut self) -> Result<()> {
+        let snapshot = self.redo.pop().ok_or(anyhow!("Nothing to redo"))?;
+        let current = self.restore(snapshot);
+        self.undo.push(current);
+        Ok(())
+    }
+
+    /// Unified diff from the saved pipeline to the one being edited
+    pub fn diff(&self) -> Result<String> {
+        let edited = self.validate()?;
+        let (saved, saved_secrets) = match ValidatedPipeline::load_own(&self.name)? {
+            Some(saved) => (saved.to_pretty_xml()?, saved.secrets),
+            None => (String::new(), Vec::new()),
+        };
+        // Secrets live in their own file, listed with their values masked
+        let with_secrets = |xml: String, secrets: String| match secrets.is_empty() {
+            true => xml,
+            false => format!("{}\n{}", xml, secrets),
+        };
+        let saved = with_secrets(saved, Secret::masked_lines(&saved_secrets, &saved_secrets));
+        let edited = with_secrets(edited.to_pretty_xml()?, Secret::masked_lines(&edited.secrets, &saved_secrets));
+
+        Ok(diff::unified(
+            &saved,
+            &edited,
+            &ValidatedPipeline::file_path(&self.name).display().to_string(),
+            "unsaved",
+            3,
+        ))
+    }
+
+    /// Whether the pipeline has changed since it was last loaded or saved
+    pub fn is_dirty(&self) -> bool {
+        *self.inner.borrow() != self.saved
+    }
+
     pub fn prompt(&self) -> String {
         [
             ["cicfg".to_string()]
                 .into_iter()
                 .chain(self.stack.iter().map(|x| format!("{}", x.name().yellow())))
                 .join(":"),
+            if self.is_dirty() { "*".red().to_string() } else { String::new() },
             "> ".to_string(),
         ]
         .join("")
@@ -109,10 +477,10 @@ impl CfgState {
         self.stack.last()
     }
 
-    pub fn select(&mut self, ty: String, filter: Option<Filter>) -> Result<()> {
+    pub fn select(&mut self, ty: String, filters: Vec<Filter>, index: Option<usize>) -> Result<()> {
         match self.stack_top() {
             Some(Frame::Pipeline(pipeline)) => {
-                let frame = pipeline.borrow().select(ty, filter)?;
+                let frame = pipeline.borrow().select(ty, filters, index)?;
                 self.stack.push(frame);
                 Ok(())
             }
@@ -126,11 +494,155 @@ impl CfgState {
             Some(Frame::Package(package)) => package.borrow_mut().set(key, value),
             Some(Frame::Repo(repo)) => repo.borrow_mut().set(key, value),
             Some(Frame::Step(step)) => step.borrow_mut().set(key, value),
+            Some(Frame::BuildCache(cache)) => cache.borrow_mut().set(key, value),
+            Some(Frame::Trigger(trigger)) => trigger.borrow_mut().set(key, value),
+            Some(Frame::Service(service)) => service.borrow_mut().set(key, value),
             None => unreachable!(),
         }?;
         Ok(())
     }
 
+    pub fn unset(&mut self, key: String) -> Result<()> {
+        match self.stack_top() {
+            Some(Frame::Pipeline(pipeline)) => pipeline.borrow_mut().unset(key),
+            Some(Frame::Package(package)) => package.borrow_mut().unset(key),
+            Some(Frame::Repo(repo)) => repo.borrow_mut().unset(key),
+            Some(Frame::Step(step)) => step.borrow_mut().unset(key),
+            Some(Frame::BuildCache(cache)) => cache.borrow_mut().unset(key),
+            Some(Frame::Trigger(trigger)) => trigger.borrow_mut().unset(key),
+            Some(Frame::Service(service)) => service.borrow_mut().unset(key),
+            None => unreachable!(),
+        }
+    }
+
+    pub fn depends(&mut self, action: DependsAction, step: String) -> Result<()> {
+        match self.stack_top() {
+            Some(Frame::Step(current)) => match action {
+                DependsAction::Add => current.borrow_mut().add_dependency(step),
+                DependsAction::Remove => current.borrow_mut().remove_dependency(&step),
+            },
+            _ => Err(anyhow!("depends is only available inside a step")),
+        }
+    }
+
+    pub fn env_set(&mut self, key: String, value: String) -> Result<()> {
+        match self.stack_top() {
+            Some(Frame::Pipeline(current)) => current.borrow_mut().set_env(key, value),
+            Some(Frame::Step(current)) => current.borrow_mut().set_env(key, value),
+            _ => Err(anyhow!("env is only available in the pipeline or a step")),
+        }
+    }
+
+    pub fn env_unset(&mut self, key: String) -> Result<()> {
+        match self.stack_top() {
+            Some(Frame::Pipeline(current)) => current.borrow_mut().unset_env(&key),
+            Some(Frame::Step(current)) => current.borrow_mut().unset_env(&key),
+            _ => Err(anyhow!("env is only available in the pipeline or a step")),
+        }
+    }
+
+    pub fn secret_set(&mut self, key: String, value: String) -> Result<()> {
+        match self.stack_top() {
+            Some(Frame::Pipeline(current)) => current.borrow_mut().set_secret(key, value),
+            _ => Err(anyhow!("secret is only available in the pipeline")),
+        }
+    }
+
+    pub fn secret_unset(&mut self, key: String) -> Result<()> {
+        match self.stack_top() {
+            Some(Frame::Pipeline(current)) => current.borrow_mut().unset_secret(&key),
+            _ => Err(anyhow!("secret is only available in the pipeline")),
+        }
+    }
+
+    pub fn matrix_set(&mut self, key: String, value: String) -> Result<()> {
+        match self.stack_top() {
+            Some(Frame::Pipeline(current)) => current.borrow_mut().set_axis(key, &value),
+            _ => Err(anyhow!("matrix is only available in the pipeline")),
+        }
+    }
+
+    pub fn matrix_unset(&mut self, key: String) -> Result<()> {
+        match self.stack_top() {
+            Some(Frame::Pipeline(current)) => current.borrow_mut().unset_axis(&key),
+            _ => Err(anyhow!("matrix is only available in the pipeline")),
+        }
+    }
+
+    pub fn param_set(&mut self, key: String, value: String) -> Result<()> {
+        match self.stack_top() {
+            Some(Frame::Pipeline(current)) => current.borrow_mut().set_param(key, value),
+            _ => Err(anyhow!("param is only available in the pipeline")),
+        }
+    }
+
+    pub fn param_unset(&mut self, key: String) -> Result<()> {
+        match self.stack_top() {
+            Some(Frame::Pipeline(current)) => current.borrow_mut().unset_param(&key),
+            _ => Err(anyhow!("param is only available in the pipeline")),
+        }
+    }
+
+    pub fn move_step(&mut self, step: String, position: MovePosition, other: String) -> Result<()> {
+        match self.stack_top() {
+            Some(Frame::Pipeline(current)) => current.borrow_mut().steps.move_step(&step, position, &other),
+            _ => Err(anyhow!("Can only move steps at the pipeline level")),
+        }
+    }
+
+    pub fn import(&mut self, pipeline: String, ty: Option<String>) -> Result<()> {
+        match self.stack_top() {
+            Some(Frame::Pipeline(current)) => {
+                let count = current.borrow_mut().import(&pipeline, ty.as_deref())?;
+                println!("Imported {} elements from {}", count, pipeline);
+                Ok(())
+            }
+            _ => Err(anyhow!("Can only import at the pipeline level")),
+        }
+    }
+
+    /// Append the steps of a Drone or Woodpecker file, writing their scripts
+    /// next to it
+    pub fn import_drone(&mut self, path: String) -> Result<()> {
+        match self.stack_top() {
+            Some(Frame::Pipeline(current)) => {
+                let import = DroneImport::load(&path)?;
+                let root = std::path::Path::new(&path).parent().unwrap_or(std::path::Path::new("."));
+                let scripts = import.scripts_under(root);
+                let pending = |script: &std::path::PathBuf| self.scripts.iter().any(|(path, _)| path == script);
+                if let Some((script, _)) = scripts.iter().find(|(script, _)| script.exists() || pending(script)) {
+                    return Err(anyhow!("{} already exists", script.display()));
+                }
+                for warning in import.warnings.iter() {
+                    println!("{}", warning.yellow());
+                }
+
+                let count = current.borrow_mut().steps.append(import.steps);
+                println!(
+                    "Imported {} steps from {}, save to write their {} scripts under {}",
+                    count,
+                    path,
+                    scripts.len(),
+                    root.join(drone::SCRIPT_DIR).display()
+                );
+                self.scripts.extend(scripts);
+                Ok(())
+            }
+            _ => Err(anyhow!("Can only import at the pipeline level")),
+        }
+    }
+
+    pub fn copy(&mut self, ty: String, filters: Vec<Filter>, name: Option<String>) -> Result<()> {
+        match self.stack_top() {
+            Some(Frame::Pipeline(pipeline)) => {
+                let frame = pipeline.borrow_mut().copy(ty, filters, name)?;
+                self.stack.push(frame);
+                Ok(())
+            }
+            _ => Err(anyhow!("Can't copy anything from here")),
+        }
+    }
+
     pub fn add(&mut self, ty: String) {
         match self.stack_top() {
             Some(Frame::Pipeline(pipeline)) => match ty.as_str() {
@@ -146,6 +658,18 @@ impl CfgState {
                     let frame = pipeline.borrow_mut().steps.add_empty();
                     self.stack.push(frame);
                 }
+                "cache" => {
+                    let frame = pipeline.borrow_mut().caches.add_empty();
+                    self.stack.push(frame);
+                }
+                "trigger" => {
+                    let frame = pipeline.borrow_mut().triggers.add_empty();
+                    self.stack.push(frame);
+                }
+                "service" => {
+                    let frame = pipeline.borrow_mut().services.add_empty();
+                    self.stack.push(frame);
+                }
                 _ => todo!(),
             },
             _ => todo!(),
@@ -168,6 +692,9 @@ pub enum Frame {
     Step(Rc<RefCell<Step>>),
     Package(Rc<RefCell<Package>>),
     Repo(Rc<RefCell<Repo>>),
+    BuildCache(Rc<RefCell<BuildCache>>),
+    Trigger(Rc<RefCell<PipelineTrigger>>),
+    Service(Rc<RefCell<Service>>),
 }
 
 impl Frame {
@@ -177,31 +704,369 @@ impl Frame {
             Frame::Step(s) => s.borrow().name(),
             Frame::Package(p) => p.borrow().name(),
             Frame::Repo(r) => r.borrow().name(),
+            Frame::BuildCache(c) => c.borrow().name(),
+            Frame::Trigger(t) => t.borrow().name(),
+            Frame::Service(s) => s.borrow().name(),
+        }
+    }
+
+    /// Type of the entity this frame edits, as used by `add` and `select`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Frame::Pipeline(_) => "pipeline",
+            Frame::Step(_) => "step",
+            Frame::Package(_) => "package",
+            Frame::Repo(_) => "repo",
+            Frame::BuildCache(_) => "cache",
+            Frame::Trigger(_) => "trigger",
+            Frame::Service(_) => "service",
+        }
+    }
+
+    /// Attributes that can be `set` on this frame
+    pub fn attributes(&self) -> &'static [&'static str] {
+        match self {
+            Frame::Pipeline(_) => Pipeline::ATTRIBUTES,
+            Frame::Step(_) => Step::ATTRIBUTES,
+            Frame::Package(_) => Package::ATTRIBUTES,
+            Frame::Repo(_) => Repo::ATTRIBUTES,
+            Frame::BuildCache(_) => BuildCache::ATTRIBUTES,
+            Frame::Trigger(_) => PipelineTrigger::ATTRIBUTES,
+            Frame::Service(_) => Service::ATTRIBUTES,
+        }
+    }
+
+    pub fn info(&self) -> String {
+        match self {
+            Frame::Pipeline(p) => p.borrow().info(),
+            Frame::Step(s) => s.borrow().info(),
+            Frame::Package(p) => p.borrow().info(),
+            Frame::Repo(r) => r.borrow().info(),
+            Frame::BuildCache(c) => c.borrow().info(),
+            Frame::Trigger(t) => t.borrow().info(),
+            Frame::Service(s) => s.borrow().info(),
         }
     }
 }
 
+/// Format a single attribute line for `info`
+pub fn info_line(key: &str, value: String) -> String {
+    format!("{}: {}", key.bold(), value)
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Filter {
     pub key: String,
     pub value: String,
 }
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DependsAction {
+    Add,
+    Remove,
+}
+
+/// Where `move` puts a step relative to another one
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MovePosition {
+    Before,
+    After,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum CfgCommand {
-    Select { ty: String, filter: Option<Filter> },
-    Set { key: String, value: String },
+    Select {
+        ty: String,
+        filters: Vec<Filter>,
+        index: Option<usize>,
+    },
+    Set { pairs: Vec<(String, String)> },
+    Unset { key: String },
     Add { ty: String },
+    Copy {
+        ty: String,
+        filters: Vec<Filter>,
+        name: Option<String>,
+    },
+    Import {
+        pipeline: String,
+        ty: Option<String>,
+    },
+    /// Import the steps of a Drone or Woodpecker YAML file
+    ImportDrone {
+        path: String,
+    },
+    Depends {
+        action: DependsAction,
+        step: String,
+    },
+    Move {
+        step: String,
+        position: MovePosition,
+        other: String,
+    },
+    EnvSet { key: String, value: String },
+    EnvUnset { key: String },
+    SecretSet { key: String, value: String },
+    SecretUnset { key: String },
+    MatrixSet { key: String, value: String },
+    MatrixUnset { key: String },
+    ParamSet { key: String, value: String },
+    ParamUnset { key: String },
     Print,
+    Info,
+    History,
+    Undo,
+    Redo,
+    Help,
+    Validate,
     End,
+    Exit,
+    Save,
+    Diff,
+    Export {
+        format: ExportFormat,
+        path: Option<String>,
+    },
+    Apply,
     Commit,
+    Revert,
+}
+
+impl CfgCommand {
+    /// Whether the command changes the pipeline and can be undone
+    pub fn mutates(&self) -> bool {
+        matches!(
+            self,
+            CfgCommand::Set { .. }
+                | CfgCommand::Unset { .. }
+                | CfgCommand::Add { .. }
+                | CfgCommand::Copy { .. }
+                | CfgCommand::Import { .. }
+                | CfgCommand::ImportDrone { .. }
+                | CfgCommand::Depends { .. }
+                | CfgCommand::Move { .. }
+                | CfgCommand::EnvSet { .. }
+                | CfgCommand::EnvUnset { .. }
+                | CfgCommand::SecretSet { .. }
+                | CfgCommand::SecretUnset { .. }
+                | CfgCommand::MatrixSet { .. }
+                | CfgCommand::MatrixUnset { .. }
+                | CfgCommand::ParamSet { .. }
+                | CfgCommand::ParamUnset { .. }
+        )
+    }
+}
+
+/// Renders the command back into the syntax accepted by `parse_command`
+impl std::fmt::Display for CfgCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CfgCommand::Select {
+                ty,
+                filters,
+                index: Some(index),
+            } if filters.is_empty() => write!(f, "select {} {}", ty, index),
+            CfgCommand::Select { ty, filters, .. } => {
+                write!(f, "select {}", ty)?;
+                for filter in filters {
+                    write!(f, " {}={}", filter.key, quote_value(&filter.value))?;
+                }
+                Ok(())
+            }
+            CfgCommand::Set { pairs } => {
+                write!(f, "set")?;
+                for (key, value) in pairs {
+                    write!(f, " {}={}", key, quote_value(value))?;
+                }
+                Ok(())
+            }
+            CfgCommand::Unset { key } => write!(f, "unset {}", key),
+            CfgCommand::Add { ty } => write!(f, "add {}", ty),
+            CfgCommand::Depends {
+                action: DependsAction::Add,
+                step,
+            } => write!(f, "depends add {}", quote_value(step)),
+            CfgCommand::Depends {
+                action: DependsAction::Remove,
+                step,
+            } => write!(f, "depends remove {}", quote_value(step)),
+            CfgCommand::EnvSet { key, value } => write!(f, "env set {}={}", key, quote_value(value)),
+            CfgCommand::EnvUnset { key } => write!(f, "env unset {}", key),
+            CfgCommand::SecretSet { key, value } => write!(f, "secret set {}={}", key, quote_value(value)),
+            CfgCommand::SecretUnset { key } => write!(f, "secret unset {}", key),
+            CfgCommand::MatrixSet { key, value } => write!(f, "matrix set {}={}", key, quote_value(value)),
+            CfgCommand::MatrixUnset { key } => write!(f, "matrix unset {}", key),
+            CfgCommand::ParamSet { key, value } => write!(f, "param set {}={}", key, quote_value(value)),
+            CfgCommand::ParamUnset { key } => write!(f, "param unset {}", key),
+            CfgCommand::Move { step, position, other } => {
+                let position = match position {
+                    MovePosition::Before => "before",
+                    MovePosition::After => "after",
+                };
+                write!(f, "move step {} {} {}", quote_word(step), position, quote_word(other))
+            }
+            CfgCommand::Import { pipeline, ty: None } => write!(f, "import {}", pipeline),
+            CfgCommand::Import {
+                pipeline,
+                ty: Some(ty),
+            } => write!(f, "import {} {}", pipeline, ty),
+            CfgCommand::ImportDrone { path } => write!(f, "import --drone {}", quote_word(path)),
+            CfgCommand::Copy { ty, filters, name } => {
+                write!(f, "copy {}", ty)?;
+                for filter in filters {
+                    write!(f, " {}={}", filter.key, quote_value(&filter.value))?;
+                }
+                match name {
+                    // Would otherwise be missing or read back as a filter
+                    Some(name) if name.is_empty() || name.contains('=') => write!(f, " {}", quote(name))?,
+                    Some(name) => write!(f, " {}", quote_value(name))?,
+                    None => (),
+                }
+                Ok(())
+            }
+            CfgCommand::Print => write!(f, "print"),
+            CfgCommand::Info => write!(f, "info"),
+            CfgCommand::History => write!(f, "history"),
+            CfgCommand::Undo => write!(f, "undo"),
+            CfgCommand::Redo => write!(f, "redo"),
+            CfgCommand::Help => write!(f, "help"),
+            CfgCommand::Validate => write!(f, "validate"),
+            CfgCommand::End => write!(f, "end"),
+            CfgCommand::Exit => write!(f, "exit"),
+            CfgCommand::Save => write!(f, "save"),
+            CfgCommand::Diff => write!(f, "diff"),
+            CfgCommand::Export { format, path } => {
+                write!(f, "export --format {}", format.as_str())?;
+                match path {
+                    Some(path) if path.is_empty() || path.starts_with('-') => write!(f, " {}", quote(path)),
+                    Some(path) => write!(f, " {}", quote_value(path)),
+                    None => Ok(()),
+                }
+            }
+            CfgCommand::Apply => write!(f, "apply"),
+            CfgCommand::Commit => write!(f, "commit"),
+            CfgCommand::Revert => write!(f, "revert"),
+        }
+    }
 }
 
 fn identifier(input: &str) -> IResult<&str, &str> {
     take_while1(|c: char| c.is_alphanumeric() || c == '_').parse(input)
 }
 
-// Parse a key=value pair
-fn key_value_pair(input: &str) -> IResult<&str, (&str, &str)> {
-    separated_pair(identifier, char('='), rest).parse(input)
+// Parse a value between `quote` characters, e.g. "./run tests.sh"
+fn quoted(quote: char) -> impl Fn(&str) -> IResult<&str, String> {
+    move |input| {
+        let normal = take_while1(|c: char| c != quote && c != '\\');
+        let escape = alt((
+            value("\n", char('n')),
+            value("\t", char('t')),
+            value("\\", char('\\')),
+            value("\"", char('"')),
+            value("'", char('\'')),
+        ));
+
+        delimited(
+            char(quote),
+            map(opt(escaped_transform(normal, '\\', escape)), Option::unwrap_or_default),
+            char(quote),
+        )
+        .parse(input)
+    }
+}
+
+// Parse a quoted value or everything up to the end of the line
+fn attribute_value(input: &str) -> IResult<&str, String> {
+    alt((quoted('"'), quoted('\''), map(rest, str::to_string))).parse(input)
+}
+
+/// Render a value so `attribute_value` parses it back unchanged
+pub fn quote_value(value: &str) -> String {
+    let needs_quotes = value.starts_with(['"', '\''])
+        || value.contains(|c: char| c.is_whitespace() || c == ',');
+
+    if needs_quotes {
+        quote(value)
+    } else {
+        value.to_string()
+    }
+}
+
+/// Render a value so `word` parses it back unchanged
+fn quote_word(value: &str) -> String {
+    if value.is_empty() {
+        quote(value)
+    } else {
+        quote_value(value)
+    }
+}
+
+fn quote(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+    format!("\"{}\"", escaped)
+}
+
+// Parse a quoted value or everything up to the next whitespace
+fn word(input: &str) -> IResult<&str, String> {
+    alt((
+        quoted('"'),
+        quoted('\''),
+        map(take_till1(|c: char| c.is_whitespace()), str::to_string),
+    ))
+    .parse(input)
+}
+
+// Parse a quoted value or a single word, as used by `select` filters
+fn filter_value(input: &str) -> IResult<&str, String> {
+    alt((
+        quoted('"'),
+        quoted('\''),
+        map(take_till(|c: char| c.is_whitespace() || c == ','), str::to_string),
+    ))
+    .parse(input)
+}
+
+// Parse key=value filters separated by whitespace or commas
+fn filters(input: &str) -> IResult<&str, Vec<Filter>> {
+    let filter = map(
+        separated_pair(identifier, char('='), filter_value),
+        |(key, value)| Filter {
+            key: key.to_string(),
+            value,
+        },
+    );
+    let separator = alt((
+        map((multispace0, char(','), multispace0), |_| ()),
+        map(multispace1, |_| ()),
+    ));
+
+    separated_list1(separator, filter).parse(input)
+}
+
+// Parse an unquoted value up to the next " key=" or the end of the line,
+// values with a " key=" of their own are quoted
+fn unquoted_value(input: &str) -> IResult<&str, String> {
+    let next_pair = |i: usize| (multispace1, identifier, char('=')).parse(&input[i..]).is_ok();
+    let end = input
+        .char_indices()
+        .filter(|(_, c)| c.is_whitespace())
+        .map(|(i, _)| i)
+        .find(|&i| next_pair(i))
+        .unwrap_or(input.len());
+
+    Ok((&input[end..], input[..end].to_string()))
+}
+
+// Parse a key=value pair, where unquoted values may contain spaces
+fn key_value_pair(input: &str) -> IResult<&str, (&str, String)> {
+    let value = alt((quoted('"'), quoted('\''), unquoted_value));
+
+    separated_pair(identifier, char('='), value).parse(input)
 }
 
 // Parse "end" command
@@ -209,49 +1074,291 @@ fn parse_end(input: &str) -> IResult<&str, CfgCommand> {
     map(tag("end"), |_| CfgCommand::End).parse(input)
 }
 
+// Parse "exit" or its alias "quit"
+fn parse_exit(input: &str) -> IResult<&str, CfgCommand> {
+    map(alt((tag("exit"), tag("quit"))), |_| CfgCommand::Exit).parse(input)
+}
+
+fn parse_validate(input: &str) -> IResult<&str, CfgCommand> {
+    map(tag("validate"), |_| CfgCommand::Validate).parse(input)
+}
+
+fn parse_save(input: &str) -> IResult<&str, CfgCommand> {
+    map(tag("save"), |_| CfgCommand::Save).parse(input)
+}
+
+fn parse_diff(input: &str) -> IResult<&str, CfgCommand> {
+    map(tag("diff"), |_| CfgCommand::Diff).parse(input)
+}
+
+// Parse "export --format json /tmp/pipeline.json" command
+fn parse_export(input: &str) -> IResult<&str, CfgCommand> {
+    let format = alt((
+        value(ExportFormat::Xml, tag("xml")),
+        value(ExportFormat::Json, tag("json")),
+        value(ExportFormat::Toml, tag("toml")),
+        value(ExportFormat::Yaml, tag("yaml")),
+    ));
+    let path = alt((
+        quoted('"'),
+        quoted('\''),
+        map(take_till1(|c: char| c.is_whitespace()), str::to_string),
+    ));
+
+    map(
+        (
+            tag("export"),
+            opt(preceded((multispace1, tag("--format"), multispace1), format)),
+            opt(preceded(multispace1, path)),
+        ),
+        |(_, format, path)| CfgCommand::Export {
+            format: format.unwrap_or_default(),
+            path,
+        },
+    )
+    .parse(input)
+}
+
+fn parse_apply(input: &str) -> IResult<&str, CfgCommand> {
+    map(tag("apply"), |_| CfgCommand::Apply).parse(input)
+}
+
 fn parse_commit(input: &str) -> IResult<&str, CfgCommand> {
     map(tag("commit"), |_| CfgCommand::Commit).parse(input)
 }
 
+fn parse_revert(input: &str) -> IResult<&str, CfgCommand> {
+    map(tag("revert"), |_| CfgCommand::Revert).parse(input)
+}
+
 fn parse_print(input: &str) -> IResult<&str, CfgCommand> {
     map(tag("print"), |_| CfgCommand::Print).parse(input)
 }
 
-// Parse "select attr name=test" command
+fn parse_info(input: &str) -> IResult<&str, CfgCommand> {
+    map(tag("info"), |_| CfgCommand::Info).parse(input)
+}
+
+fn parse_help(input: &str) -> IResult<&str, CfgCommand> {
+    map(tag("help"), |_| CfgCommand::Help).parse(input)
+}
+
+fn parse_history(input: &str) -> IResult<&str, CfgCommand> {
+    map(tag("history"), |_| CfgCommand::History).parse(input)
+}
+
+fn parse_undo(input: &str) -> IResult<&str, CfgCommand> {
+    map(tag("undo"), |_| CfgCommand::Undo).parse(input)
+}
+
+fn parse_redo(input: &str) -> IResult<&str, CfgCommand> {
+    map(tag("redo"), |_| CfgCommand::Redo).parse(input)
+}
+
+// Parse "select attr name=test script=test.sh" or "select attr 2" command
+//
+// Filters are separated by whitespace or commas and must all match.
 fn parse_select(input: &str) -> IResult<&str, CfgCommand> {
+    alt((parse_select_index, parse_select_filters)).parse(input)
+}
+
+// Parse "depends add build" and "depends remove build" commands
+fn parse_depends(input: &str) -> IResult<&str, CfgCommand> {
+    let action = alt((
+        value(DependsAction::Add, tag("add")),
+        value(DependsAction::Remove, tag("remove")),
+    ));
+
+    map(
+        (tag("depends"), multispace1, action, multispace1, attribute_value),
+        |(_, _, action, _, step)| CfgCommand::Depends { action, step },
+    )
+    .parse(input)
+}
+
+// Parse "env set RUST_LOG=debug" or "env unset RUST_LOG" command
+fn parse_env(input: &str) -> IResult<&str, CfgCommand> {
+    let set = map(
+        (tag("set"), multispace1, separated_pair(identifier, char('='), attribute_value)),
+        |(_, _, (key, value))| CfgCommand::EnvSet {
+            key: key.to_string(),
+            value,
+        },
+    );
+    let unset = map((tag("unset"), multispace1, identifier), |(_, _, key)| {
+        CfgCommand::EnvUnset { key: key.to_string() }
+    });
+
+    preceded((tag("env"), multispace1), alt((set, unset))).parse(input)
+}
+
+// Parse "secret set API_TOKEN=abc" or "secret unset API_TOKEN" command
+fn parse_secret(input: &str) -> IResult<&str, CfgCommand> {
+    let set = map(
+        (tag("set"), multispace1, separated_pair(identifier, char('='), attribute_value)),
+        |(_, _, (key, value))| CfgCommand::SecretSet {
+            key: key.to_string(),
+            value,
+        },
+    );
+    let unset = map((tag("unset"), multispace1, identifier), |(_, _, key)| {
+        CfgCommand::SecretUnset { key: key.to_string() }
+    });
+
+    preceded((tag("secret"), multispace1), alt((set, unset))).parse(input)
+}
+
+// Parse "matrix set RUST=1.80,1.81" or "matrix unset RUST" command
+fn parse_matrix(input: &str) -> IResult<&str, CfgCommand> {
+    let set = map(
+        (tag("set"), multispace1, separated_pair(identifier, char('='), attribute_value)),
+        |(_, _, (key, value))| CfgCommand::MatrixSet {
+            key: key.to_string(),
+            value,
+        },
+    );
+    let unset = map((tag("unset"), multispace1, identifier), |(_, _, key)| {
+        CfgCommand::MatrixUnset { key: key.to_string() }
+    });
+
+    preceded((tag("matrix"), multispace1), alt((set, unset))).parse(input)
+}
+
+// Parse "param set VERSION=1.0" or "param unset VERSION" command
+fn parse_param(input: &str) -> IResult<&str, CfgCommand> {
+    let set = map(
+        (tag("set"), multispace1, separated_pair(identifier, char('='), attribute_value)),
+        |(_, _, (key, value))| CfgCommand::ParamSet {
+            key: key.to_string(),
+            value,
+        },
+    );
+    let unset = map((tag("unset"), multispace1, identifier), |(_, _, key)| {
+        CfgCommand::ParamUnset { key: key.to_string() }
+    });
+
+    preceded((tag("param"), multispace1), alt((set, unset))).parse(input)
+}
+
+// Parse "move step test after build" command
+fn parse_move(input: &str) -> IResult<&str, CfgCommand> {
+    let position = alt((
+        value(MovePosition::Before, tag("before")),
+        value(MovePosition::After, tag("after")),
+    ));
+
+    map(
+        (
+            tag("move"),
+            multispace1,
+            tag("step"),
+            multispace1,
+            word,
+            multispace1,
+            position,
+            multispace1,
+            word,
+        ),
+        |(_, _, _, _, step, _, position, _, other)| CfgCommand::Move { step, position, other },
+    )
+    .parse(input)
+}
+
+// Parse "import toolchain package" or "import --drone .drone.yml" command
+fn parse_import(input: &str) -> IResult<&str, CfgCommand> {
+    let pipeline = take_while1(|c: char| c.is_alphanumeric() || c == '_' || c == '-');
+    let drone = map(
+        (tag("import"), multispace1, tag("--drone"), multispace1, word),
+        |(_, _, _, _, path)| CfgCommand::ImportDrone { path },
+    );
+    let saved = map(
+        (
+            tag("import"),
+            multispace1,
+            pipeline,
+            opt(preceded(multispace1, identifier)),
+        ),
+        |(_, _, pipeline, ty): (_, _, &str, Option<&str>)| CfgCommand::Import {
+            pipeline: pipeline.to_string(),
+            ty: ty.map(str::to_string),
+        },
+    );
+
+    alt((drone, saved)).parse(input)
+}
+
+// Parse "copy step name=build build_release" command
+fn parse_copy(input: &str) -> IResult<&str, CfgCommand> {
+    map(
+        (
+            tag("copy"),
+            multispace1,
+            identifier,
+            opt(preceded(multispace1, filters)),
+            opt(preceded(multispace1, word)),
+        ),
+        |(_, _, ty, filters, name)| CfgCommand::Copy {
+            ty: ty.to_string(),
+            filters: filters.unwrap_or_default(),
+            name,
+        },
+    )
+    .parse(input)
+}
+
+fn parse_select_index(input: &str) -> IResult<&str, CfgCommand> {
+    map(
+        (tag("select"), multispace1, identifier, multispace1, usize, multispace0, eof),
+        |(_, _, ty, _, index, _, _)| CfgCommand::Select {
+            ty: ty.to_string(),
+            filters: Vec::new(),
+            index: Some(index),
+        },
+    )
+    .parse(input)
+}
+
+fn parse_select_filters(input: &str) -> IResult<&str, CfgCommand> {
     map(
         (
             tag("select"),
             multispace1,
             identifier,
-            opt((multispace1, key_value_pair)),
+            opt(preceded(multispace1, filters)),
         ),
-        |(_, _, ty, kv)| CfgCommand::Select {
+        |(_, _, ty, filters)| CfgCommand::Select {
             ty: ty.to_string(),
-            filter: match kv {
-                Some((_, (name, value))) => Some(Filter {
-                    key: name.to_string(),
-                    value: value.to_string(),
-                }),
-                None => None,
-            },
+            filters: filters.unwrap_or_default(),
+            index: None,
         },
     )
     .parse(input)
 }
 
-// Parse "set name=test" command
+// Parse "set name=test" or "set name=build script=build.sh" command
 fn parse_set(input: &str) -> IResult<&str, CfgCommand> {
     map(
-        (tag("set"), multispace1, key_value_pair),
-        |(_, _, (name, value))| CfgCommand::Set {
-            key: name.to_string(),
-            value: value.to_string(),
+        (tag("set"), multispace1, separated_list1(multispace1, key_value_pair)),
+        |(_, _, pairs)| CfgCommand::Set {
+            pairs: pairs
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
         },
     )
     .parse(input)
 }
 
+// Parse "unset name" command
+fn parse_unset(input: &str) -> IResult<&str, CfgCommand> {
+    map((tag("unset"), multispace1, identifier), |(_, _, key)| {
+        CfgCommand::Unset {
+            key: key.to_string(),
+        }
+    })
+    .parse(input)
+}
+
 // Parse "add attr" command
 fn parse_add(input: &str) -> IResult<&str, CfgCommand> {
     map((tag("add"), multispace1, identifier), |(_, _, ty)| {
@@ -260,30 +1367,107 @@ fn parse_add(input: &str) -> IResult<&str, CfgCommand> {
     .parse(input)
 }
 
-// Main parser that tries all command types
-pub fn parse_command(input: &str) -> IResult<&str, CfgCommand> {
-    preceded(
-        multispace0,
-        alt((
-            parse_end,
-            parse_print,
-            parse_select,
-            parse_set,
-            parse_add,
-            parse_commit,
-        )),
-    )
-    .parse(input)
+/// Why a command line couldn't be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The line is blank
+    Empty,
+    /// The first word isn't a command
+    UnknownCommand(String),
+    /// The command is known but what follows it isn't valid
+    Unexpected {
+        command: String,
+        expected: &'static str,
+        /// Token where parsing stopped, `None` at the end of the line
+        found: Option<String>,
+    },
 }
 
-pub fn prompt_read(prompt: &str) -> String {
-    print!("{}", prompt);
-    io::stdout().lock().flush().unwrap();
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "Expected a command"),
+            ParseError::UnknownCommand(command) => {
+                write!(f, "Unknown command '{}', type help for the list", command)
+            }
+            ParseError::Unexpected {
+                command,
+                expected,
+                found: None,
+            } => write!(f, "Expected {} after '{}'", expected, command),
+            ParseError::Unexpected {
+                command,
+                expected,
+                found: Some(found),
+            } => write!(f, "Expected {} after '{}', found '{}'", expected, command, found),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
 
-    let mut input = String::new();
-    io::stdin()
-        .read_line(&mut input)
-        .expect("Failed to read input");
+type CommandParser = fn(&str) -> IResult<&str, CfgCommand>;
 
-    input.trim().to_string()
+/// Parser of every command and what it expects after its name
+const COMMAND_PARSERS: &[(&str, CommandParser, &str)] = &[
+    ("end", parse_end, "nothing"),
+    ("exit", parse_exit, "nothing"),
+    ("quit", parse_exit, "nothing"),
+    ("print", parse_print, "nothing"),
+    ("info", parse_info, "nothing"),
+    ("history", parse_history, "nothing"),
+    ("help", parse_help, "nothing"),
+    ("undo", parse_undo, "nothing"),
+    ("redo", parse_redo, "nothing"),
+    ("select", parse_select, "a type followed by an index or key=value filters"),
+    ("set", parse_set, "key=value"),
+    ("unset", parse_unset, "an attribute name"),
+    ("add", parse_add, "a type, e.g. step"),
+    ("copy", parse_copy, "a type followed by key=value filters and a new name"),
+    ("import", parse_import, "a pipeline name and optionally a type, or --drone and a path"),
+    ("depends", parse_depends, "add or remove and a step name"),
+    ("move", parse_move, "step <name> before|after <name>"),
+    ("env", parse_env, "set KEY=value or unset KEY"),
+    ("secret", parse_secret, "set NAME=value or unset NAME"),
+    ("matrix", parse_matrix, "set NAME=value,... or unset NAME"),
+    ("param", parse_param, "set NAME=default or unset NAME"),
+    ("commit", parse_commit, "nothing"),
+    ("validate", parse_validate, "nothing"),
+    ("save", parse_save, "nothing"),
+    ("diff", parse_diff, "nothing"),
+    ("export", parse_export, "--format xml|json|toml|yaml and/or a path"),
+    ("apply", parse_apply, "nothing"),
+    ("revert", parse_revert, "nothing"),
+];
+
+/// Parse a whole command line, telling what was wrong with it otherwise
+pub fn parse_command(input: &str) -> Result<CfgCommand, ParseError> {
+    let line = input.trim_start();
+    let name = line.split_whitespace().next().ok_or(ParseError::Empty)?;
+    let (_, parser, expected) = COMMAND_PARSERS
+        .iter()
+        .find(|(command, _, _)| *command == name)
+        .ok_or(ParseError::UnknownCommand(name.to_string()))?;
+
+    let rest = match parser(line) {
+        Ok((rest, command)) if rest.trim().is_empty() => return Ok(command),
+        Ok((rest, _)) => rest,
+        Err(nom::Err::Error(e) | nom::Err::Failure(e)) => e.input,
+        Err(nom::Err::Incomplete(_)) => "",
+    };
+
+    // Point at the whole word parsing stopped in, not just its tail
+    let offset = line.len() - rest.len();
+    let start = line[..offset]
+        .char_indices()
+        .rev()
+        .find(|(_, c)| c.is_whitespace())
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    let found = if start == 0 { rest } else { &line[start..] };
+
+    Err(ParseError::Unexpected {
+        command: name.to_string(),
+        expected,
+        found: found.split_whitespace().next().map(str::to_string),
+    })
 }
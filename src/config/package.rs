@@ -1,23 +1,29 @@
 use crate::zones::PipelineZone;
-use crate::config::{Filter, Frame, Value};
-use crate::filterable::Filterable;
+use crate::config::{Filter, Frame, PackageRequirement, ProvisionLog, Value, info_line};
 use anyhow::{Result, anyhow};
-use owo_colors::OwoColorize;
+use crate::color::Colorize;
 use serde::{Deserialize, Serialize};
 use std::{cell::RefCell, rc::Rc};
 use std::io::{self, Write};
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Packages {
     vec: Vec<Rc<RefCell<Package>>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
 pub struct ValidatedPackages {
+    #[serde(default)]
     #[serde(rename = "package")]
     vec: Vec<ValidatedPackage>,
 }
 
+impl Default for Packages {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Packages {
     pub fn new() -> Self {
         Self { vec: Vec::new() }
@@ -29,18 +35,26 @@ impl Packages {
         Frame::Package(p.clone())
     }
 
-    pub fn select(&self, filter: &Option<Filter>) -> Result<Frame> {
-        let matching: Vec<_> = self
-            .vec
-            .iter()
-            .filter(|f| f.borrow().filter(filter))
-            .collect();
+    pub fn iter(&self) -> impl Iterator<Item = &Rc<RefCell<Package>>> {
+        self.vec.iter()
+    }
 
-        match matching[..] {
-            [x] => Ok(Frame::Package(x.clone())),
-            [] => Err(anyhow!("No element matched the filter")),
-            _ => Err(anyhow!("More than one element matched the filter")),
-        }
+    pub fn append(&mut self, other: Packages) -> usize {
+        crate::filterable::append(&mut self.vec, other.vec)
+    }
+
+    pub fn select(&self, filters: &[Filter], index: Option<usize>) -> Result<Frame> {
+        crate::filterable::select(&self.vec, filters, index, Package::name).map(Frame::Package)
+    }
+
+    /// Append a copy of the element matching `filters`, optionally with a new name
+    pub fn copy(&mut self, filters: &[Filter], name: Option<String>) -> Result<Frame> {
+        crate::filterable::copy(&mut self.vec, filters, Package::name, |p| {
+            if let Some(name) = name {
+                p.name = Value::Set(name);
+            }
+        })
+        .map(Frame::Package)
     }
 
     pub fn validate(&self) -> Result<ValidatedPackages> {
@@ -52,9 +66,47 @@ impl Packages {
 
         Ok(ValidatedPackages { vec: vpacks })
     }
+
+    pub fn validation_errors(&self) -> Vec<String> {
+        crate::filterable::validation_errors(&self.vec, Package::name, Package::validate)
+    }
+}
+
+impl Clone for Packages {
+    fn clone(&self) -> Self {
+        Self { vec: crate::filterable::detach(&self.vec) }
+    }
+}
+
+impl From<Vec<ValidatedPackage>> for ValidatedPackages {
+    fn from(vec: Vec<ValidatedPackage>) -> Self {
+        Self { vec }
+    }
 }
 
 impl ValidatedPackages {
+    /// These packages, except those `own` installs too, followed by `own`
+    ///
+    /// A package of `own` replaces the one of the same provider and name,
+    /// whatever version either pins.
+    pub fn extended_by(self, own: ValidatedPackages) -> ValidatedPackages {
+        let mut vec: Vec<_> = self
+            .vec
+            .into_iter()
+            .filter(|package| {
+                !own.vec
+                    .iter()
+                    .any(|p| p.provider == package.provider && p.name == package.name)
+            })
+            .collect();
+        vec.extend(own.vec);
+        ValidatedPackages { vec }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ValidatedPackage> {
+        self.vec.iter()
+    }
+
     pub fn as_packages(&self) -> Packages {
         let packs = self
             .vec
@@ -65,42 +117,112 @@ impl ValidatedPackages {
         Packages { vec: packs }
     }
 
-    pub async fn install(&self, pzone: &PipelineZone) -> Result<()> {
+    /// Commands installing the packages, one per provider
+    pub fn install_commands(&self) -> Vec<String> {
+        let requirements: Vec<PackageRequirement> = self
+            .vec
+            .iter()
+            .map(|p| PackageRequirement {
+                provider: p.provider.to_string(),
+                name: p.spec(),
+            })
+            .collect();
+        PackageRequirement::install_commands(&requirements)
+    }
+
+    pub async fn install(&self, pzone: &PipelineZone, log: &ProvisionLog) -> Result<()> {
         print!(
             "Installing packages ({}) This may take a while...",
             "rust".yellow()
         );
         io::stdout().lock().flush().unwrap();
-        pzone.exec("pkg install git gcc14")?.wait().await?;
-        pzone.exec("curl --proto '=https' --tlsv1.2 -sSf https://sh.rustup.rs | sh")?.wait().await?;
+        log.exec(pzone, "pkg install git gcc14").await?;
+        for command in self.install_commands() {
+            if !log.exec(pzone, command.as_str()).await?.success() {
+                println!("{}", "FAILED".red());
+                return Err(anyhow!("Couldn't install the packages of the pipeline"));
+            }
+        }
+        log.exec(pzone, "curl --proto '=https' --tlsv1.2 -sSf https://sh.rustup.rs | sh").await?;
         // pzone.exec("pkgin -y install rust")?.wait().await?;
         println!("{}", "DONE".green());
         Ok(())
     }
 }
 
-#[derive(Debug)]
+/// Package manager a package is installed with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 #[repr(u8)]
-enum Provider {
+pub enum Provider {
+    /// IPS, the system's own packages
     Pkg,
+    /// pkgsrc packages, installed through pkgin
     PkgSrc,
 }
 
-#[derive(Debug, Default)]
+impl Provider {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Provider::Pkg => "pkg",
+            Provider::PkgSrc => "pkgsrc",
+        }
+    }
+}
+
+impl std::str::FromStr for Provider {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pkg" => Ok(Provider::Pkg),
+            "pkgsrc" => Ok(Provider::PkgSrc),
+            _ => Err(anyhow!("Unknown provider {}, expected pkg or pkgsrc", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Provider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Package {
     pub provider: Value<String>,
     pub name: Value<String>,
+    pub version: Value<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct ValidatedPackage {
     #[serde(rename = "@provider")]
-    pub provider: String,
+    pub provider: Provider,
     #[serde(rename = "@name")]
     pub name: String,
+    /// Version installed, e.g. `1.78`, whatever the provider ships when unset
+    #[serde(rename = "@version", default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+/// A version the install commands can take as is
+fn validate_version(version: &str) -> Result<()> {
+    let valid = !version.is_empty()
+        && !version.starts_with('-')
+        && version
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | ',' | ':' | '_' | '+' | '-'));
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow!("Invalid version {}", version))
+    }
 }
 
 impl Package {
+    pub const ATTRIBUTES: &[&str] = &["name", "provider", "version"];
+
     pub fn validate(&self) -> Result<ValidatedPackage> {
         let name = match &self.name {
             Value::Unset => Err(anyhow!("name is unset")),
@@ -108,12 +230,18 @@ impl Package {
         }?;
         let provider = match &self.provider {
             Value::Unset => Err(anyhow!("provider is unset")),
-            Value::Set(provider) => Ok(provider),
+            Value::Set(provider) => provider.parse::<Provider>(),
         }?;
 
+        let version = self.version.option();
+        if let Some(version) = &version {
+            validate_version(version)?;
+        }
+
         Ok(ValidatedPackage {
             name: name.clone(),
-            provider: provider.clone(),
+            provider,
+            version,
         })
     }
 
@@ -124,6 +252,25 @@ impl Package {
         }
     }
 
+    pub fn info(&self) -> String {
+        [
+            info_line("name", self.name.display()),
+            info_line("provider", self.provider.display()),
+            info_line("version", self.version.display()),
+        ]
+        .join("\n")
+    }
+
+    pub fn unset(&mut self, key: String) -> Result<()> {
+        match key.as_str() {
+            "name" => self.name = Value::Unset,
+            "provider" => self.provider = Value::Unset,
+            "version" => self.version = Value::Unset,
+            _ => return Err(anyhow!("Unknown attribute for package: {}", key)),
+        }
+        Ok(())
+    }
+
     pub fn set(&mut self, key: String, value: String) -> Result<()> {
         match key.as_str() {
             "name" => {
@@ -131,9 +278,15 @@ impl Package {
                 Ok(())
             }
             "provider" => {
+                value.parse::<Provider>()?;
                 self.provider = Value::Set(value);
                 Ok(())
             }
+            "version" => {
+                validate_version(&value)?;
+                self.version = Value::Set(value);
+                Ok(())
+            }
             _ => Err(anyhow!("Unknown attribute for package: {}", key)),
         }
     }
@@ -142,8 +295,19 @@ impl Package {
 impl ValidatedPackage {
     pub fn as_package(&self) -> Package {
         Package {
-            provider: Value::Set(self.provider.clone()),
+            provider: Value::Set(self.provider.to_string()),
             name: Value::Set(self.name.clone()),
+            version: self.version.clone().into(),
+        }
+    }
+
+    /// What the provider's install command takes to install the package at
+    /// its version, e.g. `rust@1.78` for pkg or `rust-1.78*` for pkgin
+    pub fn spec(&self) -> String {
+        match (&self.version, self.provider) {
+            (None, _) => self.name.clone(),
+            (Some(version), Provider::Pkg) => format!("{}@{}", self.name, version),
+            (Some(version), Provider::PkgSrc) => format!("{}-{}*", self.name, version),
         }
     }
 }
@@ -0,0 +1,134 @@
+//! Parameters a pipeline is run with, e.g. the version being released
+//!
+//! Every parameter has a default, which `pipelineadm run -e NAME=value`
+//! overrides for a single run. Step scripts, repo urls and the values of the
+//! environment of the pipeline and its steps refer to them as
+//! `${param.NAME}`, which isn't valid shell so it can't clash with a
+//! variable the script expands itself.
+
+use crate::config::EnvVar;
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+
+const REFERENCE_START: &str = "${param.";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Param {
+    #[serde(rename = "@name")]
+    pub name: String,
+    #[serde(rename = "@default")]
+    pub default: String,
+}
+
+impl Param {
+    /// Set the default of `name` in `params`, adding it if it's new
+    pub fn set(params: &mut Vec<Param>, name: String, default: String) -> Result<()> {
+        EnvVar::validate_name(&name)?;
+
+        match params.iter_mut().find(|p| p.name == name) {
+            Some(param) => param.default = default,
+            None => params.push(Param { name, default }),
+        }
+        Ok(())
+    }
+
+    pub fn unset(params: &mut Vec<Param>, name: &str) -> Result<()> {
+        let before = params.len();
+        params.retain(|p| p.name != name);
+
+        if params.len() == before {
+            Err(anyhow!("No parameter {}", name))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Value of every parameter in `params`, its default unless `overrides`
+    /// gives another, failing on overrides of undeclared parameters
+    pub fn resolve(params: &[Param], overrides: &[EnvVar]) -> Result<Vec<EnvVar>> {
+        if let Some(unknown) = overrides.iter().find(|o| params.iter().all(|p| p.name != o.name)) {
+            return Err(anyhow!("The pipeline has no parameter {}", unknown.name));
+        }
+
+        Ok(params
+            .iter()
+            .map(|param| EnvVar {
+                name: param.name.clone(),
+                value: overrides
+                    .iter()
+                    .rfind(|o| o.name == param.name)
+                    .map_or(param.default.clone(), |o| o.value.clone()),
+            })
+            .collect())
+    }
+
+    /// Names of the parameters `value` refers to
+    pub fn references(value: &str) -> Result<Vec<&str>> {
+        let mut names = Vec::new();
+        let mut rest = value;
+
+        while let Some(start) = rest.find(REFERENCE_START) {
+            let after = &rest[start + REFERENCE_START.len()..];
+            let end = after
+                .find('}')
+                .ok_or(anyhow!("Unterminated parameter reference in {}", value))?;
+            names.push(&after[..end]);
+            rest = &after[end + 1..];
+        }
+
+        Ok(names)
+    }
+
+    /// Check that every parameter `value` refers to is one of `params`
+    pub fn check_references(value: &str, params: &[Param]) -> Result<()> {
+        match Self::references(value)?
+            .into_iter()
+            .find(|name| params.iter().all(|p| p.name != *name))
+        {
+            Some(name) => Err(anyhow!("Unknown parameter {} in {}", name, value)),
+            None => Ok(()),
+        }
+    }
+
+    /// `value` with its references replaced by the values in `resolved`,
+    /// values aren't expanded again
+    pub fn expand(value: &str, resolved: &[EnvVar]) -> Result<String> {
+        let mut expanded = String::new();
+        let mut rest = value;
+
+        while let Some(start) = rest.find(REFERENCE_START) {
+            let after = &rest[start + REFERENCE_START.len()..];
+            let end = after
+                .find('}')
+                .ok_or(anyhow!("Unterminated parameter reference in {}", value))?;
+            let param = resolved
+                .iter()
+                .find(|p| p.name == after[..end])
+                .ok_or(anyhow!("Unknown parameter {} in {}", &after[..end], value))?;
+            expanded.push_str(&rest[..start]);
+            expanded.push_str(&param.value);
+            rest = &after[end + 1..];
+        }
+        expanded.push_str(rest);
+
+        Ok(expanded)
+    }
+
+    /// `env` with the references in its values expanded
+    pub fn expand_env(env: &[EnvVar], resolved: &[EnvVar]) -> Result<Vec<EnvVar>> {
+        env.iter()
+            .map(|var| {
+                Ok(EnvVar {
+                    name: var.name.clone(),
+                    value: Self::expand(&var.value, resolved)?,
+                })
+            })
+            .collect()
+    }
+}
+
+impl std::fmt::Display for Param {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}={}", self.name, self.default)
+    }
+}
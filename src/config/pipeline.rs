@@ -1,45 +1,562 @@
-use crate::zones::PipelineZone;
+use crate::color::Colorize;
+use crate::config::migration::SCHEMA_VERSION;
 use crate::config::{
-    Frame, Filter, Packages, Repos, Steps,
-    ValidatedPackages, ValidatedRepos, ValidatedSteps, Value,
+    BuildCaches, EnvVar, Exclusion, FileFormat, Filter, Frame, MatrixAxis, Packages, Param,
+    PipelineTriggers, ProvisionLog, Repos, RunContext, RunnableSteps, ScheduleCalendar, Secret,
+    Services, StepLogFile, Steps, ValidatedBuildCaches, ValidatedPackages,
+    ValidatedPipelineTriggers, ValidatedRepos, ValidatedServices, ValidatedSteps, Value, info_line,
 };
+use crate::history::{History, RunKind, RunRecord, RunStatus, RunTag};
+use crate::interrupt::Cancellation;
+use crate::network::Subnet;
+use crate::policy::{Deadline, Policy, PolicyOverrides};
+use crate::zones::PipelineZone;
 use anyhow::{Result, anyhow};
-use owo_colors::OwoColorize;
+use itertools::Itertools;
+use rand::distributions::Alphanumeric;
+use rand::{Rng, thread_rng};
 use serde::{Deserialize, Serialize};
 use std::{
+    ffi::OsStr,
     fs::File,
     io::{self, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
-use tokio::time::Duration;
-use rand::{thread_rng, Rng};
-use rand::distributions::Alphanumeric;
 
-#[derive(Debug)]
+pub const PIPELINES_DIR_VAR: &str = "RENZOKUTAI_PIPELINES_DIR";
+
+/// Phases of an apply, in the order they run
+pub const APPLY_PHASES: &[&str] = &[
+    "create_dataset",
+    "install_zone",
+    "install_packages",
+    "create_users",
+    "clone_repos",
+    "execute_steps",
+    "halt_zone",
+];
+
+/// What triggered a run, recorded in the run history
+#[derive(Debug, Default, Clone)]
+pub struct RunOptions {
+    pub branch: Option<String>,
+    pub commit: Option<String>,
+    /// Local working tree used instead of the zone's checkout
+    pub local_src: Option<PathBuf>,
+    pub tags: Vec<RunTag>,
+    /// Who or what triggered the run
+    pub actor: Option<String>,
+    /// Values overriding the defaults of the parameters of the pipeline
+    pub params: Vec<EnvVar>,
+    /// Stop every step once one fails, instead of running those that don't
+    /// depend on it
+    pub fail_fast: bool,
+    /// Print the output of each step in one block once it's over, rather
+    /// than interleaved with the steps running alongside it
+    pub group_output: bool,
+    /// Cancels the run once triggered, e.g. on Ctrl-C
+    pub cancellation: Cancellation,
+}
+
+/// Serialization formats accepted by `export`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    #[default]
+    Xml,
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl ExportFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExportFormat::Xml => "xml",
+            ExportFormat::Json => "json",
+            ExportFormat::Toml => "toml",
+            ExportFormat::Yaml => "yaml",
+        }
+    }
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "xml" => Ok(ExportFormat::Xml),
+            "json" => Ok(ExportFormat::Json),
+            "toml" => Ok(ExportFormat::Toml),
+            "yaml" => Ok(ExportFormat::Yaml),
+            _ => Err(anyhow!(
+                "Unknown export format {}, use xml, json, toml or yaml",
+                s
+            )),
+        }
+    }
+}
+
+/// How often the base zone is rebuilt with updated packages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefreshSchedule {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl RefreshSchedule {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RefreshSchedule::Daily => "daily",
+            RefreshSchedule::Weekly => "weekly",
+            RefreshSchedule::Monthly => "monthly",
+        }
+    }
+
+    /// Seconds between two refreshes
+    pub fn interval(&self) -> i64 {
+        const DAY: i64 = 24 * 60 * 60;
+
+        match self {
+            RefreshSchedule::Daily => DAY,
+            RefreshSchedule::Weekly => 7 * DAY,
+            RefreshSchedule::Monthly => 30 * DAY,
+        }
+    }
+
+    /// Whether a refresh is due at `now` given the previous one
+    pub fn is_due(&self, last: Option<&RunRecord>, now: i64) -> bool {
+        match last {
+            Some(last) => now - last.started_at >= self.interval(),
+            None => true,
+        }
+    }
+}
+
+impl std::str::FromStr for RefreshSchedule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "daily" => Ok(RefreshSchedule::Daily),
+            "weekly" => Ok(RefreshSchedule::Weekly),
+            "monthly" => Ok(RefreshSchedule::Monthly),
+            _ => Err(anyhow!(
+                "Unknown refresh schedule {}, use daily, weekly or monthly",
+                s
+            )),
+        }
+    }
+}
+
+/// Run a provisioning phase, keeping the output of its commands with the
+/// step logs of the run
+async fn provision(
+    history: &History,
+    run_id: &str,
+    name: &str,
+    log: &ProvisionLog,
+    phase: impl Future<Output = Result<()>>,
+) -> Result<()> {
+    let result = history.timed(run_id, name, phase).await;
+    history
+        .add_provision_log(run_id, name, &log.render(), log.truncated_bytes())
+        .await?;
+    result
+}
+
+/// `parent` followed by `own`, whose elements replace those of `parent`
+/// with the same name
+fn extend_named<T>(parent: Vec<T>, own: Vec<T>, name: impl Fn(&T) -> &String) -> Vec<T> {
+    let mut merged: Vec<T> = parent
+        .into_iter()
+        .filter(|p| own.iter().all(|o| name(o) != name(p)))
+        .collect();
+    merged.extend(own);
+    merged
+}
+
+/// Report an error of the run history, which the run goes on without
+fn history_warning(err: anyhow::Error) {
+    eprintln!(
+        "{} run history failed, the run goes on without it: {:?}",
+        "WARNING".yellow(),
+        err
+    );
+}
+
+/// Result of a run once its zone is destroyed, reporting the error
+/// destroying the zone rather than hiding that of the run
+fn with_cleanup(result: Result<()>, destroyed: Result<()>) -> Result<()> {
+    match (result, destroyed) {
+        (Err(err), Err(cleanup)) => {
+            eprintln!("Couldn't destroy the run zone: {:?}", cleanup);
+            Err(err)
+        }
+        (result, destroyed) => result.and(destroyed),
+    }
+}
+
+fn current_schema_version() -> u32 {
+    SCHEMA_VERSION
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+fn is_enabled(enabled: &bool) -> bool {
+    *enabled
+}
+
+fn validate_label(label: &str) -> Result<()> {
+    if label.is_empty() || label.contains(',') || label.chars().any(char::is_whitespace) {
+        return Err(anyhow!(
+            "invalid label '{}', labels can't be empty or contain commas or whitespace",
+            label
+        ));
+    }
+    Ok(())
+}
+
+/// Parses a comma separated list of labels, e.g. `team-web,nightly`
+fn parse_labels(value: &str) -> Result<Vec<String>> {
+    let labels: Vec<String> = value
+        .split(',')
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(String::from)
+        .collect();
+    for label in &labels {
+        validate_label(label)?;
+    }
+    Ok(labels.into_iter().unique().collect())
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Pipeline {
     pub name: Value<String>,
+    pub description: Value<String>,
+    pub owner: Value<String>,
+    pub labels: Vec<String>,
     pub repos: Repos,
     pub packages: Packages,
     pub steps: Steps,
+    pub caches: BuildCaches,
+    pub triggers: PipelineTriggers,
+    pub services: Services,
+    pub extends: Value<String>,
+    pub disk_quota: Value<String>,
+    pub swap: Value<String>,
+    pub subnet: Value<String>,
+    pub base_refresh: Value<String>,
+    pub timezone: Value<String>,
+    pub schedule_exclude: Value<String>,
+    pub enabled: Value<bool>,
+    pub step_timeout: Value<String>,
+    pub run_timeout: Value<String>,
+    pub total_timeout: Value<String>,
+    pub boot_timeout: Value<String>,
+    pub log_retention: Value<String>,
+    pub max_parallel: Value<u32>,
+    pub user: Value<String>,
+    pub env: Vec<EnvVar>,
+    pub secrets: Vec<Secret>,
+    pub matrix: Vec<MatrixAxis>,
+    pub params: Vec<Param>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
 pub struct ValidatedPipeline {
+    /// Schema the pipeline was saved in, older XML files are migrated on load
+    #[serde(rename = "@version", default = "current_schema_version")]
+    pub version: u32,
     #[serde(rename = "@name")]
     pub name: String,
+    /// What the pipeline builds, shown next to its name
+    #[serde(
+        rename = "@description",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub description: Option<String>,
+    /// Who to ask about the pipeline, e.g. a team or an email address
+    #[serde(rename = "@owner", default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    /// Saved pipeline whose packages, repos and steps come before these,
+    /// steps with the same name replacing its own
+    #[serde(rename = "@extends", default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+    /// ZFS quota applied to every run zone, e.g. `20G`
+    #[serde(
+        rename = "@disk_quota",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub disk_quota: Option<String>,
+    /// Size of the swap volume dedicated to every run zone
+    #[serde(rename = "@swap", default, skip_serializing_if = "Option::is_none")]
+    pub swap: Option<String>,
+    /// Build network of the pipeline zones, e.g. `10.0.5.0/24`
+    #[serde(rename = "@subnet", default, skip_serializing_if = "Option::is_none")]
+    pub subnet: Option<String>,
+    /// How often the base zone is refreshed by `pipelineadm refresh`
+    #[serde(
+        rename = "@base_refresh",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub base_refresh: Option<String>,
+    /// Timezone the schedules are evaluated in, e.g. `Europe/Madrid`,
+    /// defaults to the host's
+    #[serde(rename = "@timezone", default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    /// Days the schedules don't fire on, e.g. `weekends,12-25`
+    #[serde(
+        rename = "@schedule_exclude",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub schedule_exclude: Option<String>,
+    /// Disabled pipelines keep their history but can't be run
+    #[serde(
+        rename = "@enabled",
+        default = "enabled_by_default",
+        skip_serializing_if = "is_enabled"
+    )]
+    pub enabled: bool,
+    /// Overrides the host's step timeout, e.g. `30m`
+    #[serde(
+        rename = "@step_timeout",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub step_timeout: Option<String>,
+    /// Overrides the host's run timeout
+    #[serde(
+        rename = "@run_timeout",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub run_timeout: Option<String>,
+    /// Bounds the whole run, zone creation included, overrides the host's
+    #[serde(
+        rename = "@total_timeout",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub total_timeout: Option<String>,
+    /// Overrides the host's boot timeout
+    #[serde(
+        rename = "@boot_timeout",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub boot_timeout: Option<String>,
+    /// Overrides how long the host keeps step output, e.g. `30d`
+    #[serde(
+        rename = "@log_retention",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub log_retention: Option<String>,
+    /// Steps of a run running at the same time at most, unlimited when unset
+    #[serde(
+        rename = "@max_parallel",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_parallel: Option<u32>,
+    /// User the steps run as instead of root, steps override it with their own
+    #[serde(rename = "@user", default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
 
+    #[serde(default)]
     pub repos: ValidatedRepos,
+    #[serde(default)]
     pub packages: ValidatedPackages,
+    #[serde(default)]
     pub steps: ValidatedSteps,
+    /// Directories kept between runs
+    #[serde(default, skip_serializing_if = "ValidatedBuildCaches::is_empty")]
+    pub caches: ValidatedBuildCaches,
+    /// Schedules starting runs of the pipeline
+    #[serde(default, skip_serializing_if = "ValidatedPipelineTriggers::is_empty")]
+    pub triggers: ValidatedPipelineTriggers,
+    /// Daemons started in the run zone before the steps and stopped after
+    #[serde(default, skip_serializing_if = "ValidatedServices::is_empty")]
+    pub services: ValidatedServices,
+    /// Free-form tags to group pipelines by, e.g. `team-web` or `nightly`
+    #[serde(default, rename = "label", skip_serializing_if = "Vec::is_empty")]
+    pub labels: Vec<String>,
+    /// Exported to every step, steps override them with their own
+    #[serde(default)]
+    #[serde(rename = "env")]
+    pub env: Vec<EnvVar>,
+    /// Stored in `<name>.secrets` rather than in the XML
+    #[serde(skip)]
+    pub secrets: Vec<Secret>,
+    /// Axes the steps run across, one run per combination of their values
+    #[serde(default)]
+    #[serde(rename = "matrix")]
+    pub matrix: Vec<MatrixAxis>,
+    /// Referred to as `${param.NAME}`, runs override their defaults
+    #[serde(default)]
+    #[serde(rename = "param")]
+    pub params: Vec<Param>,
 }
 
 impl Pipeline {
+    pub const ATTRIBUTES: &[&str] = &[
+        "name",
+        "description",
+        "owner",
+        "labels",
+        "extends",
+        "disk_quota",
+        "swap",
+        "subnet",
+        "base_refresh",
+        "timezone",
+        "schedule_exclude",
+        "enabled",
+        "step_timeout",
+        "run_timeout",
+        "total_timeout",
+        "boot_timeout",
+        "log_retention",
+        "max_parallel",
+        "user",
+    ];
+
     pub fn new(name: &String) -> Pipeline {
         Pipeline {
             name: Value::Set(name.clone()),
+            description: Value::Unset,
+            owner: Value::Unset,
+            labels: Vec::new(),
             repos: Repos::new(),
             packages: Packages::new(),
             steps: Steps::new(),
+            caches: BuildCaches::new(),
+            triggers: PipelineTriggers::new(),
+            services: Services::new(),
+            extends: Value::Unset,
+            disk_quota: Value::Unset,
+            swap: Value::Unset,
+            subnet: Value::Unset,
+            base_refresh: Value::Unset,
+            timezone: Value::Unset,
+            schedule_exclude: Value::Unset,
+            enabled: Value::Unset,
+            step_timeout: Value::Unset,
+            run_timeout: Value::Unset,
+            total_timeout: Value::Unset,
+            boot_timeout: Value::Unset,
+            log_retention: Value::Unset,
+            max_parallel: Value::Unset,
+            user: Value::Unset,
+            env: Vec::new(),
+            secrets: Vec::new(),
+            matrix: Vec::new(),
+            params: Vec::new(),
+        }
+    }
+
+    /// Set an environment variable of every step, replacing its previous value
+    pub fn set_env(&mut self, name: String, value: String) -> Result<()> {
+        EnvVar::set(&mut self.env, name, value)
+    }
+
+    pub fn unset_env(&mut self, name: &str) -> Result<()> {
+        EnvVar::unset(&mut self.env, name)
+    }
+
+    /// Set a secret exported to every step, replacing its previous value
+    pub fn set_secret(&mut self, name: String, value: String) -> Result<()> {
+        Secret::set(&mut self.secrets, name, value)
+    }
+
+    pub fn unset_secret(&mut self, name: &str) -> Result<()> {
+        Secret::unset(&mut self.secrets, name)
+    }
+
+    /// Set the values of a matrix axis, replacing its previous ones
+    pub fn set_axis(&mut self, name: String, values: &str) -> Result<()> {
+        MatrixAxis::set(&mut self.matrix, name, values)
+    }
+
+    pub fn unset_axis(&mut self, name: &str) -> Result<()> {
+        MatrixAxis::unset(&mut self.matrix, name)
+    }
+
+    /// Set the default of a parameter, adding it if it's new
+    pub fn set_param(&mut self, name: String, default: String) -> Result<()> {
+        Param::set(&mut self.params, name, default)
+    }
+
+    pub fn unset_param(&mut self, name: &str) -> Result<()> {
+        Param::unset(&mut self.params, name)
+    }
+
+    /// Values that may refer to parameters, with what they belong to
+    fn param_references(&self) -> Vec<(String, String)> {
+        let env = self.env.iter().map(|var| (self.name(), var.value.clone()));
+        let repos = self.repos.iter().flat_map(|r| {
+            let r = r.borrow();
+            r.url
+                .option()
+                .into_iter()
+                .chain(r.reference.option())
+                .map(|value| (r.name(), value))
+                .collect::<Vec<_>>()
+        });
+        let steps = self.steps.iter().flat_map(|s| {
+            let s = s.borrow();
+            s.script
+                .option()
+                .into_iter()
+                .chain(s.env.iter().map(|var| var.value.clone()))
+                .map(|value| (s.name(), value))
+                .collect::<Vec<_>>()
+        });
+        let services = self.services.iter().flat_map(|s| {
+            let s = s.borrow();
+            s.script
+                .option()
+                .into_iter()
+                .chain(s.ready.option())
+                .map(|value| (s.name(), value))
+                .collect::<Vec<_>>()
+        });
+
+        env.chain(repos).chain(steps).chain(services).collect()
+    }
+
+    /// Repos whose credential isn't a secret of the pipeline, with the name
+    /// of the missing secret
+    fn missing_credentials(&self) -> Vec<(String, String)> {
+        self.repos
+            .iter()
+            .filter_map(|r| {
+                let r = r.borrow();
+                r.credential
+                    .option()
+                    .filter(|name| self.secrets.iter().all(|s| s.name != *name))
+                    .map(|name| (r.name(), name))
+            })
+            .collect()
+    }
+
+    /// Attribute holding a duration like `30m`, if `key` names one
+    fn duration_mut(&mut self, key: &str) -> Option<&mut Value<String>> {
+        match key {
+            "step_timeout" => Some(&mut self.step_timeout),
+            "run_timeout" => Some(&mut self.run_timeout),
+            "total_timeout" => Some(&mut self.total_timeout),
+            "boot_timeout" => Some(&mut self.boot_timeout),
+            "log_retention" => Some(&mut self.log_retention),
+            _ => None,
         }
     }
 
@@ -47,8 +564,61 @@ impl Pipeline {
         "pipeline".to_string()
     }
 
+    pub fn info(&self) -> String {
+        let children = self
+            .packages
+            .iter()
+            .map(|p| p.borrow().name())
+            .chain(self.repos.iter().map(|r| r.borrow().name()))
+            .chain(self.steps.iter().map(|s| s.borrow().name()))
+            .chain(self.caches.iter().map(|c| c.borrow().name()))
+            .chain(self.triggers.iter().map(|t| t.borrow().name()))
+            .chain(self.services.iter().map(|s| s.borrow().name()))
+            .map(|name| format!("  {}", name));
+
+        [
+            info_line("name", self.name.display()),
+            info_line("description", self.description.display()),
+            info_line("owner", self.owner.display()),
+            info_line("labels", self.labels.join(", ")),
+            info_line("extends", self.extends.display()),
+            info_line("disk_quota", self.disk_quota.display()),
+            info_line("swap", self.swap.display()),
+            info_line("subnet", self.subnet.display()),
+            info_line("base_refresh", self.base_refresh.display()),
+            info_line(
+                "timezone",
+                self.timezone.option().unwrap_or("host".to_string()),
+            ),
+            info_line("schedule_exclude", self.schedule_exclude.display()),
+            info_line("enabled", self.enabled.option().unwrap_or(true).to_string()),
+            info_line("step_timeout", self.step_timeout.display()),
+            info_line("run_timeout", self.run_timeout.display()),
+            info_line("total_timeout", self.total_timeout.display()),
+            info_line("boot_timeout", self.boot_timeout.display()),
+            info_line("log_retention", self.log_retention.display()),
+            info_line(
+                "max_parallel",
+                self.max_parallel
+                    .option()
+                    .map_or("unlimited".to_string(), |m| m.to_string()),
+            ),
+            info_line("user", self.user.option().unwrap_or("root".to_string())),
+            info_line("env", self.env.iter().join(", ")),
+            info_line("secrets", self.secrets.iter().map(|s| &s.name).join(", ")),
+            info_line("matrix", self.matrix.iter().join(" ")),
+            info_line("params", self.params.iter().join(", ")),
+        ]
+        .into_iter()
+        .chain(children)
+        .collect::<Vec<_>>()
+        .join("\n")
+    }
+
+    /// The saved pipeline `name` to edit, without what it extends, or a new
+    /// one
     pub fn load_or_create(name: &String) -> Result<Self> {
-        match ValidatedPipeline::load(name) {
+        match ValidatedPipeline::load_own(name) {
             Ok(Some(vpipeline)) => Ok(vpipeline.as_pipeline()),
             Ok(None) => Ok(Pipeline::new(name)),
             Err(err) => panic!("{:?}", err),
@@ -61,76 +631,1095 @@ impl Pipeline {
             Value::Set(name) => Ok(name),
         }?
         .clone();
+        crate::zones::validate_pipeline_name(&name)?;
+        for label in &self.labels {
+            validate_label(label)?;
+        }
         let repos = self.repos.validate()?;
         let packages = self.packages.validate()?;
-        let steps = self.steps.validate()?;
+        let inherited = self.inherited_steps(&name)?;
+        let steps = self.steps.validate(&inherited)?;
+        let caches = self.caches.validate()?;
+        let triggers = self.triggers.validate()?;
+        let services = self.services.validate()?;
+        let disk_quota = self.disk_quota.option();
+        let swap = self.swap.option();
+
+        for size in disk_quota.iter().chain(swap.iter()) {
+            crate::zfs::validate_size(size)?;
+        }
+        let subnet = self
+            .subnet
+            .option()
+            .map(|subnet| subnet.parse::<Subnet>())
+            .transpose()?;
+        let base_refresh = self.base_refresh.option();
+        if let Some(schedule) = &base_refresh {
+            schedule.parse::<RefreshSchedule>()?;
+        }
+        ScheduleCalendar::new(
+            self.timezone.option().as_deref(),
+            self.schedule_exclude.option().as_deref(),
+        )?;
+        let durations = [
+            &self.step_timeout,
+            &self.run_timeout,
+            &self.total_timeout,
+            &self.boot_timeout,
+            &self.log_retention,
+        ];
+        for duration in durations.iter().filter_map(|d| d.option()) {
+            crate::policy::parse_duration(&duration)?;
+        }
+        if self.max_parallel == Value::Set(0) {
+            return Err(anyhow!("max_parallel must be a number above 0"));
+        }
+        if let Some(user) = self.user.option() {
+            crate::zones::validate_user_name(&user)?;
+        }
+        for (_, value) in self.param_references() {
+            Param::check_references(&value, &self.params)?;
+        }
+        if let Some((_, secret)) = self.missing_credentials().first() {
+            return Err(anyhow!("No secret {} to clone the repo with", secret));
+        }
 
         Ok(ValidatedPipeline {
+            version: SCHEMA_VERSION,
             name,
+            description: self.description.option(),
+            owner: self.owner.option(),
+            labels: self.labels.clone(),
+            extends: self.extends.option(),
+            disk_quota,
+            swap,
+            subnet: subnet.map(|subnet| subnet.to_string()),
+            base_refresh,
+            timezone: self.timezone.option(),
+            schedule_exclude: self.schedule_exclude.option(),
+            enabled: self.enabled.option().unwrap_or(true),
+            step_timeout: self.step_timeout.option(),
+            run_timeout: self.run_timeout.option(),
+            total_timeout: self.total_timeout.option(),
+            boot_timeout: self.boot_timeout.option(),
+            log_retention: self.log_retention.option(),
+            max_parallel: self.max_parallel.option(),
+            user: self.user.option(),
             repos,
             packages,
             steps,
+            caches,
+            triggers,
+            services,
+            env: self.env.clone(),
+            secrets: self.secrets.clone(),
+            matrix: self.matrix.clone(),
+            params: self.params.clone(),
         })
     }
 
+    /// Steps of the pipeline this one extends, which the steps of pipeline
+    /// `name` may depend on without defining them
+    fn inherited_steps(&self, name: &String) -> Result<ValidatedSteps> {
+        let Some(extends) = self.extends.option() else {
+            return Ok(ValidatedSteps::default());
+        };
+        if extends == *name {
+            return Err(anyhow!("A pipeline can't extend itself"));
+        }
+
+        let parent = ValidatedPipeline::load(&extends)?
+            .ok_or(anyhow!("Can't extend unknown pipeline {}", extends))?;
+        if parent.ancestors()?.contains(name) {
+            return Err(anyhow!("{} already extends {}", extends, name));
+        }
+        Ok(parent.steps)
+    }
+
+    /// Every problem that would make `validate` fail
+    pub fn validation_errors(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        let name = self.name.require("name");
+        if let Err(err) = name.and_then(|name| crate::zones::validate_pipeline_name(&name)) {
+            errors.push(format!("{}: {}", self.name(), err));
+        }
+        errors.extend(self.packages.validation_errors());
+        errors.extend(self.repos.validation_errors());
+        match self.inherited_steps(&self.name.option().unwrap_or_default()) {
+            Ok(inherited) => errors.extend(self.steps.validation_errors(&inherited)),
+            Err(err) => errors.push(format!("{}: {}", self.name(), err)),
+        }
+        errors.extend(self.caches.validation_errors());
+        errors.extend(self.triggers.validation_errors());
+        errors.extend(self.services.validation_errors());
+        for (owner, value) in self.param_references() {
+            if let Err(err) = Param::check_references(&value, &self.params) {
+                errors.push(format!("{}: {}", owner, err));
+            }
+        }
+        for (owner, secret) in self.missing_credentials() {
+            errors.push(format!(
+                "{}: No secret {} to clone the repo with",
+                owner, secret
+            ));
+        }
+
+        errors
+    }
+
+    pub fn unset(&mut self, key: String) -> Result<()> {
+        match key.as_str() {
+            "name" => self.name = Value::Unset,
+            "description" => self.description = Value::Unset,
+            "owner" => self.owner = Value::Unset,
+            "labels" => self.labels.clear(),
+            "extends" => self.extends = Value::Unset,
+            "disk_quota" => self.disk_quota = Value::Unset,
+            "swap" => self.swap = Value::Unset,
+            "subnet" => self.subnet = Value::Unset,
+            "base_refresh" => self.base_refresh = Value::Unset,
+            "timezone" => self.timezone = Value::Unset,
+            "schedule_exclude" => self.schedule_exclude = Value::Unset,
+            "enabled" => self.enabled = Value::Unset,
+            "max_parallel" => self.max_parallel = Value::Unset,
+            "user" => self.user = Value::Unset,
+            _ => match self.duration_mut(&key) {
+                Some(duration) => *duration = Value::Unset,
+                None => return Err(anyhow!("Unknown key: {}", key)),
+            },
+        }
+        Ok(())
+    }
+
     pub fn set(&mut self, key: String, value: String) -> Result<()> {
         match key.as_str() {
             "name" => {
+                crate::zones::validate_pipeline_name(&value)?;
                 self.name = Value::Set(value);
                 Ok(())
             }
-            _ => Err(anyhow!("Unknown key: {}", key)),
+            "description" => {
+                self.description = Value::Set(value);
+                Ok(())
+            }
+            "owner" => {
+                self.owner = Value::Set(value);
+                Ok(())
+            }
+            "labels" => {
+                self.labels = parse_labels(&value)?;
+                Ok(())
+            }
+            "extends" => {
+                crate::zones::validate_pipeline_name(&value)?;
+                self.extends = Value::Set(value);
+                Ok(())
+            }
+            "disk_quota" => {
+                crate::zfs::validate_size(&value)?;
+                self.disk_quota = Value::Set(value);
+                Ok(())
+            }
+            "swap" => {
+                crate::zfs::validate_size(&value)?;
+                self.swap = Value::Set(value);
+                Ok(())
+            }
+            "subnet" => {
+                value.parse::<Subnet>()?;
+                self.subnet = Value::Set(value);
+                Ok(())
+            }
+            "base_refresh" => {
+                value.parse::<RefreshSchedule>()?;
+                self.base_refresh = Value::Set(value);
+                Ok(())
+            }
+            "timezone" => {
+                crate::config::calendar::parse_timezone(&value)?;
+                self.timezone = Value::Set(value);
+                Ok(())
+            }
+            "schedule_exclude" => {
+                Exclusion::parse_list(&value)?;
+                self.schedule_exclude = Value::Set(value);
+                Ok(())
+            }
+            "enabled" => {
+                let enabled = value
+                    .parse()
+                    .map_err(|_| anyhow!("enabled must be true or false, got {}", value))?;
+                self.enabled = Value::Set(enabled);
+                Ok(())
+            }
+            "max_parallel" => {
+                let max_parallel = value.parse().ok().filter(|max| *max > 0).ok_or(anyhow!(
+                    "max_parallel must be a number above 0, got {}",
+                    value
+                ))?;
+                self.max_parallel = Value::Set(max_parallel);
+                Ok(())
+            }
+            "user" => {
+                crate::zones::validate_user_name(&value)?;
+                self.user = Value::Set(value);
+                Ok(())
+            }
+            _ => match self.duration_mut(&key) {
+                Some(duration) => {
+                    crate::policy::parse_duration(&value)?;
+                    *duration = Value::Set(value);
+                    Ok(())
+                }
+                None => Err(anyhow!("Unknown key: {}", key)),
+            },
         }
     }
 
-    pub fn select(&self, ty: String, filter: Option<Filter>) -> Result<Frame> {
+    pub fn select(&self, ty: String, filters: Vec<Filter>, index: Option<usize>) -> Result<Frame> {
         match ty.as_str() {
-            "package" => self.packages.select(&filter),
-            "repo" => self.repos.select(&filter),
-            "step" => self.steps.select(&filter),
+            "package" => self.packages.select(&filters, index),
+            "repo" => self.repos.select(&filters, index),
+            "step" => self.steps.select(&filters, index),
+            "cache" => self.caches.select(&filters, index),
+            "trigger" => self.triggers.select(&filters, index),
+            "service" => self.services.select(&filters, index),
             _ => unreachable!(),
         }
     }
+
+    /// Append the packages, repos, steps, caches, triggers and services of the
+    /// saved pipeline `name`, or only the ones of type `ty`
+    pub fn import(&mut self, name: &String, ty: Option<&str>) -> Result<usize> {
+        let other = ValidatedPipeline::load(name)?
+            .ok_or(anyhow!("Unknown pipeline {}", name))?
+            .as_pipeline();
+
+        match ty {
+            None => Ok(self.packages.append(other.packages)
+                + self.repos.append(other.repos)
+                + self.steps.append(other.steps)
+                + self.caches.append(other.caches)
+                + self.triggers.append(other.triggers)
+                + self.services.append(other.services)),
+            Some("package") => Ok(self.packages.append(other.packages)),
+            Some("repo") => Ok(self.repos.append(other.repos)),
+            Some("step") => Ok(self.steps.append(other.steps)),
+            Some("cache") => Ok(self.caches.append(other.caches)),
+            Some("trigger") => Ok(self.triggers.append(other.triggers)),
+            Some("service") => Ok(self.services.append(other.services)),
+            Some(ty) => Err(anyhow!("Can't import {}", ty)),
+        }
+    }
+
+    pub fn copy(
+        &mut self,
+        ty: String,
+        filters: Vec<Filter>,
+        name: Option<String>,
+    ) -> Result<Frame> {
+        match ty.as_str() {
+            "package" => self.packages.copy(&filters, name),
+            "repo" => self.repos.copy(&filters, name),
+            "step" => self.steps.copy(&filters, name),
+            "cache" => self.caches.copy(&filters, name),
+            "trigger" => self.triggers.copy(&filters, name),
+            "service" => self.services.copy(&filters, name),
+            _ => Err(anyhow!("Can't copy {}", ty)),
+        }
+    }
 }
 
 impl ValidatedPipeline {
     pub async fn apply(&self) -> Result<()> {
-        println!("Applying pipeline {}", self.name.cyan());
+        let history = History::open().await?;
+        let run_id = self.allocate_run_id(Some(&history)).await?;
+        println!("Applying pipeline {} ({})", self.name.cyan(), run_id.cyan());
+
+        history.start_apply(&run_id, &self.name).await?;
+
+        let result = self.apply_phases(&history, &run_id).await;
+        let status = match result {
+            Ok(_) => RunStatus::Succeeded,
+            Err(_) => RunStatus::Failed,
+        };
+        history.finish_run(&run_id, status).await?;
+
+        if result.is_ok() {
+            println!("Pipeline {} created", self.name.cyan());
+        }
+        result
+    }
+
+    /// What `apply` is about to do, with each phase timed after the last
+    /// successful apply when there is one
+    pub async fn apply_summary(&self, history: &History) -> Result<String> {
+        let base_pzone = self.base_pzone();
+        let dataset_exists = matches!(
+            crate::zfs::base_dataset_exists(&self.dataset()).await,
+            Ok(true)
+        );
+        let zone_exists = matches!(base_pzone.exists(), Ok(true));
+        let list = |items: Vec<String>| match items.is_empty() {
+            true => "none".to_string(),
+            false => items.join(", "),
+        };
+
+        let mut lines = vec![
+            format!("Applying {} will:", self.name.cyan()),
+            match dataset_exists {
+                true => format!("  reuse dataset {}", self.dataset()),
+                false => format!("  create dataset {}", self.dataset()),
+            },
+            match zone_exists {
+                true => format!("  replace the existing zone {}", base_pzone.name()),
+                false => format!("  install zone {}", base_pzone.name()),
+            },
+            format!(
+                "  install packages: {}",
+                list(
+                    self.packages
+                        .iter()
+                        .map(|p| format!("{}:{}", p.provider, p.spec()))
+                        .collect()
+                )
+            ),
+            format!(
+                "  create users: {}",
+                list(self.steps.with_user(self.user.as_deref()).users())
+            ),
+            format!(
+                "  clone repos: {}",
+                list(self.repos.iter().map(|r| r.url.clone()).collect())
+            ),
+            format!(
+                "  run steps: {}",
+                list(self.steps.iter().map(|s| s.name.clone()).collect())
+            ),
+        ];
+
+        let events = match history.last_succeeded(&self.name, RunKind::Apply).await? {
+            Some(last) => history.events(&last.id).await?,
+            None => Vec::new(),
+        };
+        if events.is_empty() {
+            lines.push(format!("Phases: {}", APPLY_PHASES.join(", ")));
+        } else {
+            lines.push("Phases, timed after the last apply:".to_string());
+            for phase in APPLY_PHASES {
+                match events.iter().find(|e| e.name == *phase) {
+                    Some(event) => lines.push(format!("  {:<20} ~{}s", phase, event.duration())),
+                    None => lines.push(format!("  {:<20} ?", phase)),
+                }
+            }
+            let total: i64 = events.iter().map(|e| e.duration()).sum();
+            lines.push(format!("  {:<20} ~{}s", "total", total));
+        }
+
+        Ok(lines.join("\n"))
+    }
+
+    async fn apply_phases(&self, history: &History, run_id: &str) -> Result<()> {
         let base_pzone = self.base_pzone();
 
-        self.ensure_dataset_exists().await?;
-        self.ensure_zone_exists(&base_pzone).await?;
-        self.install_packages(&base_pzone).await?;
-        self.clone_repos(&base_pzone).await?;
-        self.execute_steps(&base_pzone).await?;
+        history
+            .timed(run_id, "create_dataset", self.ensure_dataset_exists())
+            .await?;
+        let log = ProvisionLog::default();
+        provision(
+            history,
+            run_id,
+            "install_zone",
+            &log,
+            self.ensure_zone_exists(&base_pzone, &log),
+        )
+        .await?;
+        let log = ProvisionLog::default();
+        provision(
+            history,
+            run_id,
+            "install_packages",
+            &log,
+            self.install_packages(&base_pzone, &log),
+        )
+        .await?;
+        let log = ProvisionLog::default();
+        provision(
+            history,
+            run_id,
+            "create_users",
+            &log,
+            self.create_users(&base_pzone, &log),
+        )
+        .await?;
+        let log = ProvisionLog::default();
+        provision(
+            history,
+            run_id,
+            "clone_repos",
+            &log,
+            self.clone_repos(&base_pzone, &log),
+        )
+        .await?;
+        history
+            .timed(run_id, "execute_steps", self.execute_steps(&base_pzone))
+            .await?;
+        history
+            .timed(run_id, "halt_zone", self.halt_zone(&base_pzone))
+            .await?;
+
+        Ok(())
+    }
+
+    /// Configured base refresh schedule, if any
+    pub fn refresh_schedule(&self) -> Result<Option<RefreshSchedule>> {
+        self.base_refresh.as_deref().map(str::parse).transpose()
+    }
+
+    /// Timezone and excluded days the schedules are evaluated with
+    pub fn schedule_calendar(&self) -> Result<ScheduleCalendar> {
+        ScheduleCalendar::new(self.timezone.as_deref(), self.schedule_exclude.as_deref())
+    }
+
+    /// Update the packages of a copy of the base zone and swap it in only if
+    /// a smoke run of the pipeline against it succeeds
+    pub async fn refresh_base(&self) -> Result<()> {
+        self.ensure_enabled()?;
+        crate::confirm::destructive(&format!(
+            "Base zone {} will be halted while it's refreshed.",
+            self.base_pzone().name()
+        ))?;
+        let history = History::open().await?;
+        let run_id = self.allocate_run_id(Some(&history)).await?;
+        println!(
+            "Refreshing base of {} ({})",
+            self.name.cyan(),
+            run_id.cyan()
+        );
+
+        history.start_refresh(&run_id, &self.name).await?;
+        let result = self.refresh_candidate(&history, &run_id).await;
+        let status = match result {
+            Ok(_) => RunStatus::Succeeded,
+            Err(_) => RunStatus::Failed,
+        };
+        history.finish_run(&run_id, status).await?;
+
+        match &result {
+            Ok(_) => println!("Base of {} refreshed", self.name.cyan()),
+            Err(_) => println!("Base of {} {}", self.name.cyan(), "KEPT".red()),
+        }
+        result
+    }
+
+    /// Build the refreshed base in a candidate zone cloned from the base
+    /// zone, which is only touched to swap the candidate in
+    ///
+    /// Run zones cloned from the base keep working meanwhile, and a failed
+    /// refresh only leaves the candidate to discard.
+    async fn refresh_candidate(&self, history: &History, run_id: &String) -> Result<()> {
+        let base_pzone = self.base_pzone();
+        let candidate = base_pzone.candidate_pzone();
+        // Left behind by a refresh cut short
+        self.discard_candidate(&candidate)?;
+        history
+            .timed(
+                run_id,
+                "clone_base",
+                crate::zones::clone_candidate(&candidate, &base_pzone),
+            )
+            .await?;
+
+        match self.refresh_phases(history, run_id, &candidate).await {
+            Ok(_) => {
+                history
+                    .timed(
+                        run_id,
+                        "promote",
+                        self.promote_candidate(&candidate, run_id),
+                    )
+                    .await
+            }
+            Err(err) => {
+                if let Err(discard) = self.discard_candidate(&candidate) {
+                    eprintln!("Couldn't discard the candidate zone: {:?}", discard);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    async fn refresh_phases(
+        &self,
+        history: &History,
+        run_id: &String,
+        candidate: &PipelineZone,
+    ) -> Result<()> {
+        let log = ProvisionLog::default();
+        provision(
+            history,
+            run_id,
+            "update_packages",
+            &log,
+            self.update_packages(candidate, &log),
+        )
+        .await?;
+        history
+            .timed(run_id, "halt_zone", self.halt_zone(candidate))
+            .await?;
+        // The first combination of the matrix stands for all of them
+        let combination = MatrixAxis::combinations(&self.matrix)
+            .into_iter()
+            .next()
+            .unwrap_or_default();
+        let options = RunOptions::default();
+        let context = self.run_context(&options, &combination)?;
+        history
+            .timed(run_id, "smoke_run", async {
+                self.run_in_zone(candidate, run_id, &options, &context, Some(history))
+                    .await
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Halt and delete the candidate zone along with its datasets, if any
+    fn discard_candidate(&self, candidate: &PipelineZone) -> Result<()> {
+        if candidate.exists()? {
+            candidate.cleanup()?;
+            candidate.clone().delete()?;
+        }
+        Ok(())
+    }
+
+    /// Swap the datasets of the halted candidate in under the base zone
+    ///
+    /// The previous base is destroyed when no run zone was cloned from it
+    /// anymore, otherwise it's kept as `retired-<run_id>` next to the base.
+    async fn promote_candidate(&self, candidate: &PipelineZone, run_id: &String) -> Result<()> {
+        let base_pzone = self.base_pzone();
+        let retired = format!("rpool{}/retired-{}", base_pzone.root_path(), run_id);
+
         self.halt_zone(&base_pzone).await?;
+        crate::zones::detach(candidate).await?;
+        crate::zfs::rename(&base_pzone.dataset(), &retired).await?;
+        if let Err(err) = crate::zfs::rename(&candidate.dataset(), &base_pzone.dataset()).await {
+            crate::zfs::rename(&retired, &base_pzone.dataset()).await?;
+            return Err(err);
+        }
+        // The runs cloned from the previous base keep it alive otherwise
+        crate::zfs::promote(&format!("{}/root", base_pzone.dataset())).await?;
 
-        println!("Pipeline {} created", self.name.cyan());
+        if crate::zfs::destroy_recursive(&retired).await.is_err() {
+            eprintln!(
+                "{} keeping the previous base {} while runs cloned from it are left",
+                "WARNING".yellow(),
+                retired
+            );
+        }
         Ok(())
     }
 
-    pub async fn run(&self) -> Result<()> {
-        let run_id = self.generate_run_id();
+    /// Fail when the pipeline has been disabled
+    pub fn ensure_enabled(&self) -> Result<()> {
+        if self.enabled {
+            Ok(())
+        } else {
+            Err(anyhow!("Pipeline {} is disabled", self.name))
+        }
+    }
+
+    /// Run the steps once, or once per combination of the matrix, each run
+    /// in its own zone
+    ///
+    /// Cancelling `options.cancellation` cuts the run short, its zone is
+    /// cleaned up and the run recorded as cancelled. Matrix combinations left
+    /// aren't run.
+    pub async fn run(&self, options: &RunOptions) -> Result<()> {
+        self.ensure_enabled()?;
+        // Fail on unknown parameters before any zone is created
+        Param::resolve(&self.params, &options.params)?;
+        if self.matrix.is_empty() {
+            return self.run_combination(options, &[]).await;
+        }
+
+        let mut results = Vec::new();
+        for combination in MatrixAxis::combinations(&self.matrix) {
+            if options.cancellation.is_cancelled() {
+                return Err(crate::interrupt::Interrupted.into());
+            }
+            println!("Matrix {}", combination.iter().join(" ").bold());
+            let result = self.run_combination(options, &combination).await;
+            if let Err(err) = &result {
+                eprintln!("{}", err);
+            }
+            results.push((combination, result.is_ok()));
+        }
+
+        println!("{}", MatrixAxis::report(&self.matrix, &results));
+        match results.iter().filter(|(_, ok)| !ok).count() {
+            0 => Ok(()),
+            failed => Err(anyhow!(
+                "{} of {} matrix runs failed",
+                failed,
+                results.len()
+            )),
+        }
+    }
+
+    /// Run the steps with the variables of a matrix `combination`, tagging the
+    /// run with them
+    async fn run_combination(&self, options: &RunOptions, combination: &[EnvVar]) -> Result<()> {
+        let policy = self.policy()?;
+        // The run goes on without the history, only left out of it
+        let history = History::open().await.map_err(history_warning).ok();
+        let run_id = self.allocate_run_id(history.as_ref()).await?;
         println!("Starting run {}", run_id.cyan());
-        let base_pzone = self.base_pzone();
-        let run_pzone = base_pzone.get_run_pzone(&run_id);
 
-        crate::zones::create_zone_from_base(&run_pzone, &base_pzone).await?;
-        self.execute_steps(&run_pzone).await?;
+        let history = match history {
+            Some(history) => match self
+                .start_recording(&history, &run_id, options, combination)
+                .await
+            {
+                Ok(_) => Some(history),
+                Err(err) => {
+                    history_warning(err);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let context = self.run_context(options, combination)?;
+        let result = self
+            .run_in_zone(
+                &self.base_pzone(),
+                &run_id,
+                options,
+                &context,
+                history.as_ref(),
+            )
+            .await;
+        let status = match &result {
+            Ok(_) => RunStatus::Succeeded,
+            Err(err) if crate::interrupt::is_interrupted(err) => RunStatus::Cancelled,
+            Err(_) => RunStatus::Failed,
+        };
+        if let Some(history) = &history {
+            if let Err(err) = history.finish_run(&run_id, status).await {
+                history_warning(err);
+            }
+            if let Some(retention) = policy.log_retention {
+                let before = crate::history::now() - retention.as_secs() as i64;
+                if let Err(err) = history.prune_step_logs(&self.name, before).await {
+                    history_warning(err);
+                }
+            }
+        }
+        if let Some(retention) = policy.log_retention
+            && let Err(err) =
+                StepLogFile::prune(&self.name, std::time::SystemTime::now() - retention)
+        {
+            eprintln!("Couldn't prune the log files of {}: {}", self.name, err);
+        }
 
-        run_pzone.cleanup()?;
-        run_pzone.delete()
+        result
+    }
+
+    /// Add a run of the matrix `combination` with `options` to the history
+    async fn start_recording(
+        &self,
+        history: &History,
+        run_id: &str,
+        options: &RunOptions,
+        combination: &[EnvVar],
+    ) -> Result<()> {
+        history
+            .start_run(
+                run_id,
+                &self.name,
+                options.branch.as_deref(),
+                options.commit.as_deref(),
+            )
+            .await?;
+        let vnic = self
+            .base_pzone()
+            .get_run_pzone(&run_id.to_string())
+            .vnic_name();
+        history.set_vnic(run_id, &vnic).await?;
+        history.add_tags(run_id, &options.tags).await?;
+        history
+            .add_tags(run_id, &MatrixAxis::tags(combination))
+            .await?;
+        if let Some(actor) = &options.actor {
+            history.set_actor(run_id, actor).await?;
+        }
+        Ok(())
+    }
+
+    /// Run the steps in a fresh clone of `base_pzone`, recording their tags
+    /// and output in the history
+    ///
+    /// The total timeout of the pipeline bounds the whole run, from creating
+    /// the zone to the last step, and cancelling it cuts it short the same
+    /// way. The zone is destroyed however the run ended.
+    async fn run_in_zone(
+        &self,
+        base_pzone: &PipelineZone,
+        run_id: &String,
+        options: &RunOptions,
+        context: &RunContext,
+        history: Option<&History>,
+    ) -> Result<()> {
+        let run_pzone = base_pzone.get_run_pzone(run_id);
+        let policy = self.policy()?;
+        let deadline = Deadline::after(policy.total_timeout);
+
+        let created = options
+            .cancellation
+            .bound(Deadline::bound(deadline, async {
+                crate::zones::create_zone_from_base(
+                    &run_pzone,
+                    base_pzone,
+                    self.swap.as_ref(),
+                    self.steps.uses_pkgin(),
+                    &self.caches,
+                    policy.boot_timeout,
+                )
+                .await?;
+                if let Some(quota) = &self.disk_quota {
+                    crate::zfs::set_quota(&run_pzone.dataset(), quota).await?;
+                }
+                Ok(())
+            }))
+            .await;
+        if let Err(err) = created {
+            // Creating the zone may have been cut short halfway
+            let destroyed = self.destroy_run_zone(run_pzone).await;
+            return with_cleanup(Err(err), destroyed);
+        }
+
+        let result = match &options.local_src {
+            Some(src) => {
+                let synced = Deadline::bound(
+                    deadline,
+                    crate::zones::sync_local_source(&run_pzone, src, self.workdir()),
+                );
+                options.cancellation.bound(synced).await
+            }
+            None => {
+                let checkout = self.checkout_run_refs(&run_pzone, context);
+                options
+                    .cancellation
+                    .bound(Deadline::bound(deadline, checkout))
+                    .await
+            }
+        };
+        let result = match result {
+            Ok(_) => {
+                self.run_steps(
+                    &run_pzone,
+                    context,
+                    options,
+                    history.map(|h| (h, run_id.as_str())),
+                    deadline,
+                )
+                .await
+            }
+            Err(err) => Err(err),
+        };
+        // The zone is destroyed below even when the quota can't be checked
+        let result = match &self.disk_quota {
+            Some(quota) => match crate::zfs::quota_exceeded(&run_pzone.dataset()).await {
+                Ok(true) => Err(anyhow!(
+                    "Run {} failed: disk quota of {} exceeded",
+                    run_id,
+                    quota
+                )),
+                Ok(false) => result,
+                Err(err) => result.and(Err(err)),
+            },
+            None => result,
+        };
+
+        let destroyed = self.destroy_run_zone(run_pzone).await;
+        with_cleanup(result, destroyed)
+    }
+
+    /// Halt and delete a run zone along with its swap volume and VNIC, as
+    /// much of them as was created
+    async fn destroy_run_zone(&self, run_pzone: PipelineZone) -> Result<()> {
+        let vnic = run_pzone.vnic_name();
+        if run_pzone.exists()? {
+            run_pzone.cleanup()?;
+            if self.swap.is_some()
+                && crate::zfs::base_dataset_exists(&run_pzone.swap_volume()).await?
+            {
+                crate::zfs::destroy(&run_pzone.swap_volume()).await?;
+            }
+            run_pzone.delete()?;
+        }
+        if crate::dladm::nic_exists(&vnic).await? {
+            crate::dladm::delete_nic(&vnic).await?;
+        }
+        Ok(())
     }
 
     pub async fn execute_steps(&self, pzone: &PipelineZone) -> Result<()> {
-        self.steps.as_runnable().run(pzone).await
+        let context = self.run_context(&RunOptions::default(), &[])?;
+        self.run_steps(pzone, &context, &RunOptions::default(), None, None)
+            .await
+    }
+
+    /// What the `when` conditions of the steps are checked against in a run
+    /// with `options`, for a matrix `combination`
+    pub fn run_context(&self, options: &RunOptions, combination: &[EnvVar]) -> Result<RunContext> {
+        let params = Param::resolve(&self.params, &options.params)?;
+
+        Ok(RunContext {
+            branch: options.branch.clone(),
+            commit: options.commit.clone(),
+            actor: options.actor.clone(),
+            env: EnvVar::merge(&Param::expand_env(&self.env, &params)?, combination),
+            tags: options
+                .tags
+                .iter()
+                .cloned()
+                .chain(MatrixAxis::tags(combination))
+                .collect(),
+            params,
+        })
+    }
+
+    /// Execute the steps with the variables of the run `context`, skipping
+    /// those whose condition doesn't hold or whose inputs didn't change since
+    /// a previous run, and record what they output in the
+    /// history of `run`
+    ///
+    /// The services of the pipeline run alongside the steps, started and
+    /// ready before the first and stopped once they are over. Steps defined in
+    /// the checkout are merged over the pipeline's own. Steps that always run
+    /// do so once the others are over, even when they failed or ran out of
+    /// time. Past the `deadline` of the run, steps still running are killed
+    /// and those left are recorded as failed. Cancelling the run does the
+    /// same and skips the steps that always run. Once a step fails the others keep going,
+    /// unless `options` asks to fail fast.
+    async fn run_steps(
+        &self,
+        pzone: &PipelineZone,
+        context: &RunContext,
+        options: &RunOptions,
+        run: Option<(&History, &str)>,
+        deadline: Option<Deadline>,
+    ) -> Result<()> {
+        let policy = self.policy()?;
+        // Loading and sharing the checkout take part of the run's time too
+        let prepared = self.prepare_steps(pzone, context, options, run);
+        let mut steps = options
+            .cancellation
+            .bound(Deadline::bound(deadline, prepared))
+            .await?;
+        let started = options
+            .cancellation
+            .bound(Deadline::bound(
+                deadline,
+                self.services.start(pzone, &context.params),
+            ))
+            .await;
+        let result = match started {
+            Ok(_) => {
+                options
+                    .cancellation
+                    .bound(Deadline::bound(deadline, async {
+                        match policy.run_timeout {
+                            Some(timeout) => tokio::time::timeout(
+                                timeout,
+                                steps.run(
+                                    pzone,
+                                    policy.step_timeout,
+                                    self.max_parallel,
+                                    options.fail_fast,
+                                ),
+                            )
+                            .await
+                            .unwrap_or_else(|_| {
+                                Err(anyhow!(
+                                    "Steps didn't finish within the run timeout of {}",
+                                    crate::policy::format_duration(timeout)
+                                ))
+                            }),
+                            None => {
+                                steps
+                                    .run(
+                                        pzone,
+                                        policy.step_timeout,
+                                        self.max_parallel,
+                                        options.fail_fast,
+                                    )
+                                    .await
+                            }
+                        }
+                    }))
+                    .await
+            }
+            Err(err) => Err(err),
+        };
+        steps.fail_interrupted(false).await;
+        let finalized = options
+            .cancellation
+            .bound(Deadline::bound(
+                deadline,
+                steps.run_always(pzone, policy.step_timeout, self.max_parallel),
+            ))
+            .await;
+        steps.fail_interrupted(true).await;
+        let stopped = self.services.stop(pzone).await;
+        if let Some((history, run_id)) = run
+            && let Err(err) = steps.record(history, run_id).await
+        {
+            history_warning(err);
+        }
+
+        result.and(finalized).and(stopped)
+    }
+
+    /// The steps of a run with the variables of `context`, those defined in
+    /// the checkout merged in, and those whose condition doesn't hold or whose
+    /// inputs didn't change skipped
+    async fn prepare_steps(
+        &self,
+        pzone: &PipelineZone,
+        context: &RunContext,
+        options: &RunOptions,
+        run: Option<(&History, &str)>,
+    ) -> Result<RunnableSteps> {
+        let steps = crate::config::repo_definition::load(pzone, &self.steps, self.workdir())?;
+        if steps.uses_library() {
+            crate::config::library::sync(pzone).await?;
+        }
+
+        let steps = steps
+            .with_user(self.user.as_deref())
+            .with_workdir(Some(self.workdir()))
+            .with_producers()
+            .with_params(&context.params)?;
+        if !steps.users().is_empty() {
+            let paths: Vec<&str> = std::iter::once(self.workdir())
+                .chain(self.caches.iter().map(|cache| cache.path.as_str()))
+                .collect();
+            let status = pzone
+                .exec(crate::zones::share_command(&paths))?
+                .wait()
+                .await?;
+            if !status.success() {
+                return Err(anyhow!(
+                    "Couldn't share the checkout of {} with the step users",
+                    pzone.name()
+                ));
+            }
+        }
+        let mut steps = steps
+            .with_env(&context.env)
+            .with_secrets(&self.secrets)
+            .as_runnable()
+            .with_grouped_output(options.group_output);
+        steps.skip(context).await?;
+        let base = match run {
+            Some((history, _)) => history
+                .base_build(&self.name)
+                .await
+                .map_err(history_warning)
+                .ok()
+                .flatten(),
+            None => None,
+        };
+        if let (Some((history, _)), Some(base)) = (run, base) {
+            steps.reuse(pzone, history, &self.name, &base.id).await?;
+        }
+        Ok(steps)
     }
 
+    /// The saved pipeline `name`, with the packages, repos and steps of the
+    /// pipelines it extends
     pub fn load(name: &String) -> Result<Option<Self>> {
+        match Self::load_own(name)? {
+            Some(pipeline) => Ok(Some(pipeline.inherit(&mut vec![name.clone()])?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Merge in what the pipeline extends, `chain` being the pipelines
+    /// already merged to catch cycles
+    fn inherit(mut self, chain: &mut Vec<String>) -> Result<Self> {
+        let Some(extends) = self.extends.clone() else {
+            return Ok(self);
+        };
+        if chain.contains(&extends) {
+            return Err(anyhow!(
+                "Pipelines extend each other in a cycle: {} -> {}",
+                chain.join(" -> "),
+                extends
+            ));
+        }
+        chain.push(extends.clone());
+
+        let parent = Self::load_own(&extends)?
+            .ok_or(anyhow!(
+                "{} extends unknown pipeline {}",
+                self.name,
+                extends
+            ))?
+            .inherit(chain)?;
+        self.packages = parent.packages.extended_by(self.packages);
+        self.repos = parent.repos.extended_by(self.repos);
+        self.steps = parent.steps.extended_by(self.steps);
+        self.params = extend_named(parent.params, self.params, |p| &p.name);
+        self.env = extend_named(parent.env, self.env, |e| &e.name);
+        self.secrets = extend_named(parent.secrets, self.secrets, |s| &s.name);
+        // The parent may have changed since this pipeline was saved
+        self.steps
+            .check()
+            .map_err(|e| anyhow!("{} no longer fits what it extends: {}", self.name, e))?;
+        Ok(self)
+    }
+
+    /// Names of the pipelines this one extends, directly or not
+    pub fn ancestors(&self) -> Result<Vec<String>> {
+        let mut ancestors: Vec<String> = Vec::new();
+        let mut extends = self.extends.clone();
+
+        while let Some(name) = extends {
+            if ancestors.contains(&name) {
+                break;
+            }
+            extends = Self::load_own(&name)?.and_then(|p| p.extends);
+            ancestors.push(name);
+        }
+        Ok(ancestors)
+    }
+
+    /// The saved pipeline `name` as written, without what it extends
+    ///
+    /// Files saved in an older schema are upgraded and saved again.
+    pub fn load_own(name: &String) -> Result<Option<Self>> {
         let pipeline_path = Self::file_path(name);
 
-        match File::options().read(true).write(true).open(pipeline_path) {
-            Ok(file) => Ok(Some(serde_xml_rs::from_reader(file)?)),
+        match std::fs::read_to_string(&pipeline_path) {
+            Ok(text) => {
+                let (mut pipeline, migrated) = Self::parse_file(&pipeline_path, &text)?;
+                pipeline.secrets = Secret::load(&Self::secrets_path(name))?;
+                if migrated {
+                    pipeline.save()?;
+                    eprintln!(
+                        "Upgraded {} to schema version {}",
+                        pipeline_path.display(),
+                        SCHEMA_VERSION
+                    );
+                }
+                Ok(Some(pipeline))
+            }
             Err(err) => {
                 // TODO(Marce): Handle error kinds
                 Ok(None)
@@ -138,6 +1727,125 @@ impl ValidatedPipeline {
         }
     }
 
+    /// Every pipeline saved in the pipelines directory, in any format
+    pub fn list() -> Result<Vec<Self>> {
+        let entries = match std::fs::read_dir(Self::dir()) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut pipelines = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            let (Some(_), Some(name)) = (FileFormat::from_path(&path), path.file_stem()) else {
+                continue;
+            };
+            // Saved in several formats, only the one `load` reads counts
+            if Self::file_path(&name.to_string_lossy().to_string()) != path {
+                continue;
+            }
+
+            // One broken file shouldn't hide every other pipeline
+            let parsed = std::fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|text| Self::parse_file(&path, &text));
+            match parsed {
+                Ok((pipeline, _)) => pipelines.push(pipeline),
+                Err(err) => eprintln!(
+                    "{} skipping {}: {}",
+                    "WARNING".yellow(),
+                    path.display(),
+                    err
+                ),
+            }
+        }
+
+        Ok(pipelines)
+    }
+
+    /// The pipeline saved at `path` as `text`, upgraded to the current
+    /// schema, and whether it had to be
+    fn parse_file(path: &Path, text: &str) -> Result<(Self, bool)> {
+        let format = FileFormat::from_path(path).unwrap_or(FileFormat::Xml);
+        let migrated = match format {
+            FileFormat::Xml => crate::config::migration::migrate_xml(text)?,
+            _ => None,
+        };
+
+        let pipeline: Self = format.parse(migrated.as_deref().unwrap_or(text))?;
+        crate::config::migration::check_version(pipeline.version)?;
+        Ok((pipeline, migrated.is_some()))
+    }
+
+    /// Check the configured subnet against every other saved pipeline, or
+    /// allocate a free one from the pool when none is configured
+    ///
+    /// Done before saving or applying rather than in `validate`, since it
+    /// reads every saved pipeline. Pipelines whose subnet doesn't parse
+    /// don't claim any.
+    pub fn claim_subnet(&mut self) -> Result<()> {
+        let used: Vec<(Subnet, String)> = ValidatedPipeline::list()?
+            .into_iter()
+            .filter(|p| p.name != self.name)
+            .filter_map(|p| Some((p.subnet().ok()?, p.name)))
+            .collect();
+
+        let subnet = match &self.subnet {
+            Some(subnet) => {
+                let subnet: Subnet = subnet.parse()?;
+
+                match used.iter().find(|(other, _)| other.overlaps(&subnet)) {
+                    Some((other, pipeline)) => {
+                        return Err(anyhow!(
+                            "subnet {} overlaps {} used by pipeline {}",
+                            subnet,
+                            other,
+                            pipeline
+                        ));
+                    }
+                    None => subnet,
+                }
+            }
+            None => {
+                let pool = crate::network::pool()?;
+                let used: Vec<Subnet> = used.into_iter().map(|(subnet, _)| subnet).collect();
+
+                crate::network::allocate(&pool, &used)
+                    .ok_or(anyhow!("No free subnet left in pool {}", pool))?
+            }
+        };
+        self.subnet = Some(subnet.to_string());
+        Ok(())
+    }
+
+    /// Build network of the pipeline, pipelines saved without one use the
+    /// original fixed subnet
+    pub fn subnet(&self) -> Result<Subnet> {
+        match &self.subnet {
+            Some(subnet) => subnet.parse(),
+            None => Ok(crate::network::default_subnet()),
+        }
+    }
+
+    /// A run id that isn't in the history and whose VNIC isn't taken
+    pub async fn allocate_run_id(&self, history: Option<&History>) -> Result<String> {
+        for _ in 0..16 {
+            let id = self.generate_run_id();
+            let vnic = self.base_pzone().get_run_pzone(&id).vnic_name();
+            let recorded = match history {
+                Some(history) => history.run(&id).await?.is_some(),
+                None => false,
+            };
+
+            if !recorded && !crate::dladm::nic_exists(&vnic).await? {
+                return Ok(id);
+            }
+        }
+
+        Err(anyhow!("Couldn't find a free run id for {}", self.name))
+    }
+
     pub fn generate_run_id(&self) -> String {
         thread_rng()
             .sample_iter(&Alphanumeric)
@@ -147,23 +1855,54 @@ impl ValidatedPipeline {
             .to_lowercase()
     }
 
+    /// Directory the steps run in, the `dest` of the first repo or the
+    /// checkout when it has none
+    pub fn workdir(&self) -> &str {
+        self.repos.workdir().unwrap_or(crate::zones::CHECKOUT_DIR)
+    }
+
     pub fn base_pzone(&self) -> PipelineZone {
-        PipelineZone {
-            pipeline: self.name.clone(),
-            zone_type: crate::zones::ZoneType::Base,
+        PipelineZone::new(self.name.clone(), crate::zones::ZoneType::Base)
+    }
+
+    /// Directory holding the pipeline definitions, overridable through
+    /// `RENZOKUTAI_PIPELINES_DIR`
+    pub fn dir() -> PathBuf {
+        match std::env::var(PIPELINES_DIR_VAR) {
+            Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from("/etc/pipelines"),
         }
     }
 
+    /// File of the pipeline `name`, the first found of the formats or XML for
+    /// pipelines not saved yet
     pub fn file_path(name: &String) -> PathBuf {
-        let filename = format!("{}.xml", name);
-        ["/etc", "pipelines", filename.as_str()].iter().collect()
+        FileFormat::ALL
+            .iter()
+            .map(|format| Self::dir().join(format!("{}.{}", name, format.extension())))
+            .find(|path| path.exists())
+            .unwrap_or_else(|| Self::dir().join(format!("{}.xml", name)))
     }
 
+    /// Where the secrets of the pipeline are kept, out of its XML
+    pub fn secrets_path(name: &String) -> PathBuf {
+        Self::dir().join(format!("{}.secrets", name))
+    }
+
+    /// Write the pipeline to its file, in the format it was saved in before
     pub fn save(&self) -> Result<()> {
         let pipeline_path = Self::file_path(&self.name);
+        Secret::save(&Self::secrets_path(&self.name), &self.secrets)?;
+        let format = FileFormat::from_path(&pipeline_path).unwrap_or(FileFormat::Xml);
+        let contents = format.to_string(self)?;
 
-        match File::options().write(true).create(true).open(pipeline_path) {
-            Ok(file) => Ok(serde_xml_rs::to_writer(file, self)?),
+        match File::options()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(pipeline_path)
+        {
+            Ok(mut file) => Ok(file.write_all(contents.as_bytes())?),
             Err(err) => {
                 // TODO(Marce): Handle it more gracefully
                 panic!("COULDNT WRITE");
@@ -171,17 +1910,75 @@ impl ValidatedPipeline {
         }
     }
 
+    /// Indented XML, one element per line, as compared by `diff`
+    pub fn to_pretty_xml(&self) -> Result<String> {
+        let emitter = xml::EmitterConfig::new().perform_indent(true);
+        Ok(serde_xml_rs::SerdeXml::new()
+            .emitter(emitter)
+            .to_string(self)?)
+    }
+
+    pub fn export(&self, format: ExportFormat) -> Result<String> {
+        Ok(match format {
+            ExportFormat::Xml => FileFormat::Xml.to_string(self)?,
+            ExportFormat::Json => serde_json::to_string_pretty(self)?,
+            ExportFormat::Toml => FileFormat::Toml.to_string(self)?,
+            ExportFormat::Yaml => FileFormat::Yaml.to_string(self)?,
+        })
+    }
+
     pub fn as_pipeline(&self) -> Pipeline {
         Pipeline {
             name: Value::Set(self.name.clone()),
+            description: self.description.clone().into(),
+            owner: self.owner.clone().into(),
+            labels: self.labels.clone(),
             packages: self.packages.as_packages(),
             repos: self.repos.as_repos(),
             steps: self.steps.as_steps(),
+            caches: self.caches.as_caches(),
+            triggers: self.triggers.as_triggers(),
+            services: self.services.as_services(),
+            extends: self.extends.clone().into(),
+            disk_quota: self.disk_quota.clone().into(),
+            swap: self.swap.clone().into(),
+            subnet: self.subnet.clone().into(),
+            base_refresh: self.base_refresh.clone().into(),
+            timezone: self.timezone.clone().into(),
+            schedule_exclude: self.schedule_exclude.clone().into(),
+            // Left unset while enabled, like in the saved file
+            enabled: if self.enabled {
+                Value::Unset
+            } else {
+                Value::Set(false)
+            },
+            step_timeout: self.step_timeout.clone().into(),
+            run_timeout: self.run_timeout.clone().into(),
+            total_timeout: self.total_timeout.clone().into(),
+            boot_timeout: self.boot_timeout.clone().into(),
+            log_retention: self.log_retention.clone().into(),
+            max_parallel: self.max_parallel.into(),
+            user: self.user.clone().into(),
+            env: self.env.clone(),
+            secrets: self.secrets.clone(),
+            matrix: self.matrix.clone(),
+            params: self.params.clone(),
         }
     }
 
+    /// Limits of the pipeline, the host policy with its overrides applied
+    pub fn policy(&self) -> Result<Policy> {
+        Policy::host()?.with_overrides(&PolicyOverrides {
+            step_timeout: self.step_timeout.clone(),
+            run_timeout: self.run_timeout.clone(),
+            total_timeout: self.total_timeout.clone(),
+            boot_timeout: self.boot_timeout.clone(),
+            log_retention: self.log_retention.clone(),
+        })
+    }
+
     pub fn vnic_name(&self) -> String {
-        format!("{}_internal0", self.zone_name())
+        self.base_pzone().vnic_name()
     }
 
     pub async fn ensure_dataset_exists(&self) -> Result<()> {
@@ -202,7 +1999,13 @@ impl ValidatedPipeline {
         pzone.halt()
     }
 
-    pub async fn ensure_zone_exists(&self, pzone: &PipelineZone) -> Result<()> {
+    pub async fn ensure_zone_exists(&self, pzone: &PipelineZone, log: &ProvisionLog) -> Result<()> {
+        if pzone.exists()? {
+            crate::confirm::destructive(&format!(
+                "Zone {} already exists and will be halted, uninstalled and replaced.",
+                pzone.name()
+            ))?;
+        }
         pzone.cleanup()?;
 
         print!("Creating VNIC {}...", self.vnic_name().cyan());
@@ -217,28 +2020,96 @@ impl ValidatedPipeline {
 
         print!("Installing zone...");
         io::stdout().lock().flush().unwrap();
-        zone::Adm::new(self.zone_name()).install_blocking(&[])?;
+        let options = crate::cache::install_options();
+        let options: Vec<&OsStr> = options.iter().map(|o| o.as_os_str()).collect();
+        log.push_output(&zone::Adm::new(self.zone_name()).install_blocking(&options)?);
         println!("{}", "DONE".green());
 
         print!("Booting zone...");
         io::stdout().lock().flush().unwrap();
-        zone::Adm::new(self.zone_name()).boot_blocking()?;
+        log.push_output(&zone::Adm::new(self.zone_name()).boot_blocking()?);
+        crate::zones::wait_for_boot(pzone, self.policy()?.boot_timeout).await?;
         println!("{}", "DONE".green());
 
-        tokio::time::sleep(Duration::new(30, 0)).await;
-
         // Setup network access
-        crate::zones::configure_zone_networking(&pzone).await?;
+        let subnet = self.subnet()?;
+        crate::network::ensure_gateway(&subnet).await?;
+        crate::zones::configure_zone_networking(pzone, &subnet).await?;
 
         Ok(())
     }
 
-    pub async fn clone_repos(&self, pzone: &PipelineZone) -> Result<()> {
-        self.repos.clone(pzone).await
+    pub async fn clone_repos(&self, pzone: &PipelineZone, log: &ProvisionLog) -> Result<()> {
+        self.repos
+            .clone(
+                pzone,
+                log,
+                &Param::resolve(&self.params, &[])?,
+                &self.secrets,
+            )
+            .await
+    }
+
+    /// Check out the commit of the run and the refs its parameters point the
+    /// repos at, when they differ from the ones cloned into the base zone
+    async fn checkout_run_refs(&self, pzone: &PipelineZone, context: &RunContext) -> Result<()> {
+        let cloned = Param::resolve(&self.params, &[])?;
+        let commit = context.commit.as_deref();
+        self.repos
+            .checkout(
+                pzone,
+                &ProvisionLog::default(),
+                &cloned,
+                &context.params,
+                commit,
+                &self.secrets,
+            )
+            .await
+    }
+
+    pub async fn install_packages(&self, pzone: &PipelineZone, log: &ProvisionLog) -> Result<()> {
+        self.packages.install(pzone, log).await
+    }
+
+    /// Create the users the steps run as, those already there are kept
+    pub async fn create_users(&self, pzone: &PipelineZone, log: &ProvisionLog) -> Result<()> {
+        for user in self.steps.with_user(self.user.as_deref()).users() {
+            print!("Creating user {}...", user.yellow());
+            io::stdout().lock().flush().unwrap();
+            let status = log
+                .exec(pzone, crate::zones::create_user_command(&user))
+                .await?;
+            if !status.success() {
+                return Err(anyhow!("Couldn't create user {} in {}", user, pzone.name()));
+            }
+            println!("{}", "DONE".green());
+        }
+
+        Ok(())
     }
 
-    pub async fn install_packages(&self, pzone: &PipelineZone) -> Result<()> {
-        self.packages.install(&pzone).await
+    /// Boot the zone and bring every installed package up to date
+    pub async fn update_packages(&self, pzone: &PipelineZone, log: &ProvisionLog) -> Result<()> {
+        print!("Booting zone {}...", pzone.name().cyan());
+        io::stdout().lock().flush().unwrap();
+        log.push_output(&zone::Adm::new(pzone.name()).boot_blocking()?);
+        println!("{}", "DONE".green());
+
+        print!("Updating packages. This may take a while...");
+        io::stdout().lock().flush().unwrap();
+        // Exit status 4 means there was nothing to update
+        let status = log
+            .exec(
+                pzone,
+                "(pkg update -q || [ $? -eq 4 ]) && pkgin -y update && pkgin -y full-upgrade",
+            )
+            .await?;
+        if !status.success() {
+            return Err(anyhow!("Couldn't update packages in {}", pzone.name()));
+        }
+        println!("{}", "DONE".green());
+
+        Ok(())
     }
 
     pub fn path(&self) -> String {
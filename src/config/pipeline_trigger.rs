@@ -0,0 +1,215 @@
+use crate::config::{CronSchedule, Filter, Frame, Value, info_line};
+use anyhow::{Result, anyhow};
+use crate::color::Colorize;
+use serde::{Deserialize, Serialize};
+use std::{cell::RefCell, rc::Rc};
+
+/// What starts runs of a pipeline on its own, without anyone asking
+#[derive(Debug, PartialEq)]
+pub struct PipelineTriggers {
+    vec: Vec<Rc<RefCell<PipelineTrigger>>>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct ValidatedPipelineTriggers {
+    #[serde(default)]
+    #[serde(rename = "trigger")]
+    vec: Vec<ValidatedPipelineTrigger>,
+}
+
+impl Default for PipelineTriggers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PipelineTriggers {
+    pub fn new() -> PipelineTriggers {
+        Self { vec: Vec::new() }
+    }
+
+    pub fn add_empty(&mut self) -> Frame {
+        let t = Rc::new(RefCell::new(PipelineTrigger::default()));
+        self.vec.push(t.clone());
+        Frame::Trigger(t.clone())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Rc<RefCell<PipelineTrigger>>> {
+        self.vec.iter()
+    }
+
+    pub fn append(&mut self, other: PipelineTriggers) -> usize {
+        crate::filterable::append(&mut self.vec, other.vec)
+    }
+
+    pub fn select(&self, filters: &[Filter], index: Option<usize>) -> Result<Frame> {
+        crate::filterable::select(&self.vec, filters, index, PipelineTrigger::name).map(Frame::Trigger)
+    }
+
+    /// Append a copy of the element matching `filters`, optionally with a new
+    /// expression
+    pub fn copy(&mut self, filters: &[Filter], expr: Option<String>) -> Result<Frame> {
+        crate::filterable::copy(&mut self.vec, filters, PipelineTrigger::name, |t| {
+            if let Some(expr) = expr {
+                t.expr = Value::Set(expr);
+            }
+        })
+        .map(Frame::Trigger)
+    }
+
+    pub fn validate(&self) -> Result<ValidatedPipelineTriggers> {
+        let vtriggers = self
+            .vec
+            .iter()
+            .map(|t| t.borrow().validate())
+            .collect::<Result<Vec<ValidatedPipelineTrigger>>>()?;
+
+        Ok(ValidatedPipelineTriggers { vec: vtriggers })
+    }
+
+    pub fn validation_errors(&self) -> Vec<String> {
+        crate::filterable::validation_errors(&self.vec, PipelineTrigger::name, PipelineTrigger::validate)
+    }
+}
+
+impl Clone for PipelineTriggers {
+    fn clone(&self) -> Self {
+        Self { vec: crate::filterable::detach(&self.vec) }
+    }
+}
+
+impl From<Vec<ValidatedPipelineTrigger>> for ValidatedPipelineTriggers {
+    fn from(vec: Vec<ValidatedPipelineTrigger>) -> Self {
+        Self { vec }
+    }
+}
+
+impl ValidatedPipelineTriggers {
+    pub fn as_triggers(&self) -> PipelineTriggers {
+        let triggers = self
+            .vec
+            .iter()
+            .map(|t| Rc::new(RefCell::new(t.as_trigger())))
+            .collect();
+        PipelineTriggers { vec: triggers }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ValidatedPipelineTrigger> {
+        self.vec.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vec.is_empty()
+    }
+}
+
+/// Kind of a trigger, only cron schedules for now
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TriggerKind {
+    #[default]
+    Cron,
+}
+
+impl std::str::FromStr for TriggerKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "cron" => Ok(TriggerKind::Cron),
+            _ => Err(anyhow!("Unknown trigger type {}, use cron", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for TriggerKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TriggerKind::Cron => write!(f, "cron"),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PipelineTrigger {
+    pub kind: Value<String>,
+    pub expr: Value<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ValidatedPipelineTrigger {
+    #[serde(rename = "@type", default)]
+    pub kind: TriggerKind,
+    /// When the trigger fires, e.g. `0 3 * * *` for every night at 3:00
+    #[serde(rename = "@expr")]
+    pub expr: String,
+}
+
+impl PipelineTrigger {
+    pub const ATTRIBUTES: &[&str] = &["type", "expr"];
+
+    pub fn validate(&self) -> Result<ValidatedPipelineTrigger> {
+        let kind = match self.kind.option() {
+            Some(kind) => kind.parse()?,
+            None => TriggerKind::default(),
+        };
+        let expr = self.expr.require("expr")?;
+        expr.parse::<CronSchedule>()?;
+
+        Ok(ValidatedPipelineTrigger { kind, expr })
+    }
+
+    pub fn name(&self) -> String {
+        match &self.expr {
+            Value::Unset => "trigger".to_string(),
+            Value::Set(v) => format!("trigger({})", v.cyan()),
+        }
+    }
+
+    pub fn info(&self) -> String {
+        [
+            info_line("type", self.kind.option().unwrap_or(TriggerKind::default().to_string())),
+            info_line("expr", self.expr.display()),
+        ]
+        .join("\n")
+    }
+
+    pub fn unset(&mut self, key: String) -> Result<()> {
+        match key.as_str() {
+            "type" => self.kind = Value::Unset,
+            "expr" => self.expr = Value::Unset,
+            _ => return Err(anyhow!("Unknown attribute for trigger: {}", key)),
+        }
+        Ok(())
+    }
+
+    pub fn set(&mut self, key: String, value: String) -> Result<()> {
+        match key.as_str() {
+            "type" => {
+                value.parse::<TriggerKind>()?;
+                self.kind = Value::Set(value);
+                Ok(())
+            }
+            "expr" => {
+                value.parse::<CronSchedule>()?;
+                self.expr = Value::Set(value);
+                Ok(())
+            }
+            _ => Err(anyhow!("Unknown attribute for trigger: {}", key)),
+        }
+    }
+}
+
+impl ValidatedPipelineTrigger {
+    pub fn as_trigger(&self) -> PipelineTrigger {
+        PipelineTrigger {
+            kind: Value::Set(self.kind.to_string()),
+            expr: Value::Set(self.expr.clone()),
+        }
+    }
+
+    /// When the trigger fires
+    pub fn schedule(&self) -> Result<CronSchedule> {
+        self.expr.parse()
+    }
+}
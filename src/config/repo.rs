@@ -1,23 +1,29 @@
-use crate::config::{Filter, Frame, Value};
-use crate::filterable::Filterable;
+use crate::config::{CredentialKind, EnvVar, Filter, Frame, Param, ProvisionLog, RepoCredential, Secret, Value, info_line};
 use crate::zones::PipelineZone;
 use anyhow::{Result, anyhow};
-use owo_colors::OwoColorize;
+use crate::color::Colorize;
 use serde::{Deserialize, Serialize};
 use std::{cell::RefCell, rc::Rc};
 use std::io::{self, Write};
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Repos {
     vec: Vec<Rc<RefCell<Repo>>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
 pub struct ValidatedRepos {
+    #[serde(default)]
     #[serde(rename = "repo")]
     vec: Vec<ValidatedRepo>,
 }
 
+impl Default for Repos {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Repos {
     pub fn new() -> Repos {
         Self { vec: Vec::new() }
@@ -29,18 +35,26 @@ impl Repos {
         Frame::Repo(r.clone())
     }
 
-    pub fn select(&self, filter: &Option<Filter>) -> Result<Frame> {
-        let matching: Vec<_> = self
-            .vec
-            .iter()
-            .filter(|f| f.borrow().filter(filter))
-            .collect();
+    pub fn iter(&self) -> impl Iterator<Item = &Rc<RefCell<Repo>>> {
+        self.vec.iter()
+    }
 
-        match matching[..] {
-            [x] => Ok(Frame::Repo(x.clone())),
-            [] => Err(anyhow!("No element matched the filter")),
-            _ => Err(anyhow!("More than one element matched the filter")),
-        }
+    pub fn append(&mut self, other: Repos) -> usize {
+        crate::filterable::append(&mut self.vec, other.vec)
+    }
+
+    pub fn select(&self, filters: &[Filter], index: Option<usize>) -> Result<Frame> {
+        crate::filterable::select(&self.vec, filters, index, Repo::name).map(Frame::Repo)
+    }
+
+    /// Append a copy of the element matching `filters`, optionally with a new url
+    pub fn copy(&mut self, filters: &[Filter], url: Option<String>) -> Result<Frame> {
+        crate::filterable::copy(&mut self.vec, filters, Repo::name, |r| {
+            if let Some(url) = url {
+                r.url = Value::Set(url);
+            }
+        })
+        .map(Frame::Repo)
     }
 
     pub fn validate(&self) -> Result<ValidatedRepos> {
@@ -51,9 +65,41 @@ impl Repos {
             .collect::<Result<Vec<ValidatedRepo>>>()?;
         Ok(ValidatedRepos { vec: vrepos })
     }
+
+    pub fn validation_errors(&self) -> Vec<String> {
+        crate::filterable::validation_errors(&self.vec, Repo::name, Repo::validate)
+    }
+}
+
+impl Clone for Repos {
+    fn clone(&self) -> Self {
+        Self { vec: crate::filterable::detach(&self.vec) }
+    }
+}
+
+impl From<Vec<ValidatedRepo>> for ValidatedRepos {
+    fn from(vec: Vec<ValidatedRepo>) -> Self {
+        Self { vec }
+    }
 }
 
 impl ValidatedRepos {
+    /// These repos, except those `own` clones too, followed by `own`
+    pub fn extended_by(self, own: ValidatedRepos) -> ValidatedRepos {
+        let mut vec: Vec<_> = self
+            .vec
+            .into_iter()
+            .filter(|repo| own.vec.iter().all(|r| r.url != repo.url))
+            .collect();
+        vec.extend(own.vec);
+        ValidatedRepos { vec }
+    }
+
+    /// Where the first repo is cloned, if it names a `dest`
+    pub fn workdir(&self) -> Option<&str> {
+        self.vec.first().and_then(|repo| repo.dest.as_deref())
+    }
+
     pub fn as_repos(&self) -> Repos {
         let repos = self
             .vec
@@ -63,11 +109,75 @@ impl ValidatedRepos {
         Repos { vec: repos }
     }
 
-    pub async fn clone(&self, pzone: &PipelineZone) -> Result<()> {
+    /// Clone every repo into the zone, with the parameters in their urls
+    /// replaced by `params` and their credentials taken from `secrets`
+    pub async fn clone(
+        &self,
+        pzone: &PipelineZone,
+        log: &ProvisionLog,
+        params: &[EnvVar],
+        secrets: &[Secret],
+    ) -> Result<()> {
         for repo in self.vec.iter() {
-            print!("Cloning repo {}...", repo.url.yellow());
+            let url = Param::expand(&repo.url, params)?;
+            print!("Cloning repo {}...", url.yellow());
+            io::stdout().lock().flush().unwrap();
+
+            repo.with_credential(pzone, &url, secrets, async |env| repo.clone_with(pzone, log, &url, &env, params).await)
+                .await?;
+            println!("{}", "DONE".green());
+        }
+
+        Ok(())
+    }
+
+    /// Check out in a run zone the refs the parameters of the run resolve
+    /// to, for the repos where they differ from the ones resolved by the
+    /// `cloned` parameters the base zone was cloned with
+    ///
+    /// The `commit` of the run, if any, is checked out in the first repo
+    /// whatever its ref.
+    pub async fn checkout(
+        &self,
+        pzone: &PipelineZone,
+        log: &ProvisionLog,
+        cloned: &[EnvVar],
+        params: &[EnvVar],
+        commit: Option<&str>,
+        secrets: &[Secret],
+    ) -> Result<()> {
+        for (index, repo) in self.vec.iter().enumerate() {
+            let wanted = match (commit, &repo.reference) {
+                (Some(commit), _) if index == 0 => commit.to_string(),
+                (_, Some(reference)) => {
+                    let wanted = Param::expand(reference, params)?;
+                    if wanted == Param::expand(reference, cloned)? {
+                        continue;
+                    }
+                    wanted
+                }
+                (_, None) => continue,
+            };
+            validate_reference(&wanted)?;
+            let url = Param::expand(&repo.url, cloned)?;
+            let dir = repo.dir(&url);
+            print!("Checking out {} of {}...", wanted.yellow(), url.yellow());
             io::stdout().lock().flush().unwrap();
-            pzone.exec(format!("git clone {}", repo.url))?.wait().await?;
+
+            repo.with_credential(pzone, &url, secrets, async |env| {
+                let mut commands = vec![fetch_checkout_command(dir, &wanted, repo.depth)];
+                // The ref may point the submodules at other commits
+                if repo.submodules {
+                    commands.push(submodule_update_command(dir));
+                }
+                for command in commands {
+                    if !log.exec(pzone, format!("{}{}", env, command)).await?.success() {
+                        return Err(anyhow!("Couldn't check out {} of {}:\n{}", wanted, url, log.render()));
+                    }
+                }
+                Ok(())
+            })
+            .await?;
             println!("{}", "DONE".green());
         }
 
@@ -84,25 +194,151 @@ impl ValidatedRepos {
     }
 }
 
-#[derive(Debug, Default)]
+/// Directory `git clone` checks `url` out in, its last component without
+/// `.git`
+pub fn clone_dir(url: &str) -> &str {
+    let url = url.trim_end_matches('/');
+    let name = url.rsplit(['/', ':']).next().unwrap_or(url);
+    name.strip_suffix(".git").unwrap_or(name)
+}
+
+/// Command cloning `url` into `dir`, only its last `depth` commits if set,
+/// along with its submodules if `submodules`
+pub fn clone_command(url: &str, dir: &str, depth: Option<u32>, submodules: bool) -> String {
+    let mut command = "git clone".to_string();
+    if let Some(depth) = depth {
+        command.push_str(&format!(" --depth {}", depth));
+    }
+    if submodules {
+        command.push_str(" --recurse-submodules");
+    }
+    format!("{} {} {}", command, crate::config::step::quote(url), crate::config::step::quote(dir))
+}
+
+/// Command checking out the submodules of the clone in `dir` at the commit
+/// its superproject points to
+pub fn submodule_update_command(dir: &str) -> String {
+    format!(
+        "git -C {} submodule update --init --recursive",
+        crate::config::step::quote(dir)
+    )
+}
+
+/// Command checking out `reference` in the clone in `dir`
+///
+/// A shallow clone only has the default branch, so the ref is fetched with
+/// the same depth first.
+pub fn checkout_command(dir: &str, reference: &str, depth: Option<u32>) -> String {
+    match depth {
+        Some(_) => fetch_checkout_command(dir, reference, depth),
+        None => format!(
+            "git -C {} checkout --quiet {}",
+            crate::config::step::quote(dir),
+            crate::config::step::quote(reference)
+        ),
+    }
+}
+
+/// Command fetching `reference` into the clone in `dir` with `depth` and
+/// checking it out, for refs that may be newer than the clone
+pub fn fetch_checkout_command(dir: &str, reference: &str, depth: Option<u32>) -> String {
+    let dir = crate::config::step::quote(dir);
+    let reference = crate::config::step::quote(reference);
+    let depth = depth.map(|depth| format!(" --depth {}", depth)).unwrap_or_default();
+
+    format!("git -C {dir} fetch --quiet{depth} origin {reference} && git -C {dir} checkout --quiet FETCH_HEAD")
+}
+
+/// An absolute path in the zone to clone a repo into
+fn validate_dest(dest: &str) -> Result<()> {
+    if !dest.starts_with('/') || dest == "/" || dest.split('/').any(|c| c == "..") || dest.contains(char::is_control) {
+        Err(anyhow!("Invalid dest {}, use an absolute path below /", dest))
+    } else {
+        Ok(())
+    }
+}
+
+/// A branch, tag or commit, which git mustn't take for an option
+fn validate_reference(reference: &str) -> Result<()> {
+    if reference.is_empty() || reference.starts_with('-') || reference.contains(char::is_whitespace) {
+        Err(anyhow!("Invalid ref {}, use a branch, tag or commit", reference))
+    } else {
+        Ok(())
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Repo {
     pub url: Value<String>,
+    pub reference: Value<String>,
+    pub depth: Value<u32>,
+    pub credential: Value<String>,
+    pub submodules: Value<bool>,
+    pub dest: Value<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct ValidatedRepo {
     #[serde(rename = "@url")]
     pub url: String,
+    /// Branch, tag or commit checked out after cloning, the default branch
+    /// when unset
+    #[serde(rename = "@ref", default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    /// Commits of history cloned, the whole history when unset
+    #[serde(rename = "@depth", default, skip_serializing_if = "Option::is_none")]
+    pub depth: Option<u32>,
+    /// Secret of the pipeline the repo is cloned with, an SSH private key or
+    /// an HTTPS token
+    #[serde(rename = "@credential", default, skip_serializing_if = "Option::is_none")]
+    pub credential: Option<String>,
+    /// Whether the submodules of the repo are checked out too
+    #[serde(rename = "@submodules", default, skip_serializing_if = "is_false")]
+    pub submodules: bool,
+    /// Absolute path in the zone the repo is cloned into, the directory git
+    /// names after the url when unset
+    #[serde(rename = "@dest", default, skip_serializing_if = "Option::is_none")]
+    pub dest: Option<String>,
 }
 
 impl Repo {
+    pub const ATTRIBUTES: &[&str] = &["url", "ref", "depth", "credential", "submodules", "dest"];
+
     pub fn validate(&self) -> Result<ValidatedRepo> {
         let url = match &self.url {
             Value::Unset => Err(anyhow!("url is unset")),
             Value::Set(url) => Ok(url),
         }?;
+        let reference = self.reference.option();
+        if let Some(reference) = &reference {
+            validate_reference(reference)?;
+        }
+
+        if self.depth == Value::Set(0) {
+            return Err(anyhow!("depth must be a number above 0"));
+        }
+        let dest = self.dest.option();
+        if let Some(dest) = &dest {
+            validate_dest(dest)?;
+        }
+        let credential = self.credential.option();
+        if let Some(credential) = &credential {
+            EnvVar::validate_name(credential)?;
+            CredentialKind::for_url(url)?;
+        }
 
-        Ok(ValidatedRepo { url: url.clone() })
+        Ok(ValidatedRepo {
+            url: url.clone(),
+            reference,
+            depth: self.depth.option(),
+            credential,
+            submodules: self.submodules.option().unwrap_or(false),
+            dest,
+        })
     }
 
     pub fn name(&self) -> String {
@@ -112,21 +348,129 @@ impl Repo {
         }
     }
 
+    pub fn info(&self) -> String {
+        [
+            info_line("url", self.url.display()),
+            info_line("ref", self.reference.option().unwrap_or("default branch".to_string())),
+            info_line("depth", self.depth.option().map_or("full".to_string(), |d| d.to_string())),
+            info_line("credential", self.credential.display()),
+            info_line("submodules", self.submodules.option().unwrap_or(false).to_string()),
+            info_line("dest", self.dest.display()),
+        ]
+        .join("\n")
+    }
+
+    pub fn unset(&mut self, key: String) -> Result<()> {
+        match key.as_str() {
+            "url" => self.url = Value::Unset,
+            "ref" => self.reference = Value::Unset,
+            "depth" => self.depth = Value::Unset,
+            "credential" => self.credential = Value::Unset,
+            "submodules" => self.submodules = Value::Unset,
+            "dest" => self.dest = Value::Unset,
+            _ => return Err(anyhow!("Unknown attribute for repo: {}", key)),
+        }
+        Ok(())
+    }
+
     pub fn set(&mut self, key: String, value: String) -> Result<()> {
         match key.as_str() {
             "url" => {
                 self.url = Value::Set(value);
                 Ok(())
             }
+            "ref" => {
+                validate_reference(&value)?;
+                self.reference = Value::Set(value);
+                Ok(())
+            }
+            "depth" => {
+                let depth = value
+                    .parse()
+                    .ok()
+                    .filter(|depth| *depth > 0)
+                    .ok_or(anyhow!("depth must be a number above 0, got {}", value))?;
+                self.depth = Value::Set(depth);
+                Ok(())
+            }
+            "credential" => {
+                EnvVar::validate_name(&value)?;
+                self.credential = Value::Set(value);
+                Ok(())
+            }
+            "submodules" => {
+                let submodules = value
+                    .parse()
+                    .map_err(|_| anyhow!("submodules must be true or false, got {}", value))?;
+                self.submodules = Value::Set(submodules);
+                Ok(())
+            }
+            "dest" => {
+                validate_dest(&value)?;
+                self.dest = Value::Set(value);
+                Ok(())
+            }
             _ => Err(anyhow!("Unknown attribute for repo: {}", key)),
         }
     }
 }
 
 impl ValidatedRepo {
+    /// Clone the repo from `url` and check out its ref, git commands
+    /// prefixed by `env`
+    async fn clone_with(&self, pzone: &PipelineZone, log: &ProvisionLog, url: &str, env: &str, params: &[EnvVar]) -> Result<()> {
+        let dir = self.dir(url);
+        log.exec(pzone, format!("{}{}", env, clone_command(url, dir, self.depth, self.submodules))).await?;
+        if let Some(reference) = &self.reference {
+            let reference = Param::expand(reference, params)?;
+            validate_reference(&reference)?;
+            log.exec(pzone, format!("{}{}", env, checkout_command(dir, &reference, self.depth))).await?;
+            // The ref may point the submodules at other commits
+            if self.submodules {
+                log.exec(pzone, format!("{}{}", env, submodule_update_command(dir))).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Run `git` with the credential of the repo installed in the zone
+    /// meanwhile, passing it the variables git uses it through
+    async fn with_credential(
+        &self,
+        pzone: &PipelineZone,
+        url: &str,
+        secrets: &[Secret],
+        git: impl AsyncFnOnce(String) -> Result<()>,
+    ) -> Result<()> {
+        let Some(name) = &self.credential else {
+            return git(String::new()).await;
+        };
+        let secret = secrets
+            .iter()
+            .find(|s| s.name == *name)
+            .ok_or(anyhow!("No secret {} to clone {} with", name, url))?;
+        let credential = RepoCredential::new(url, secret)?;
+
+        credential.install(pzone).await?;
+        let result = git(format!("{} ", credential.env())).await;
+        let shredded = RepoCredential::shred(pzone).await;
+        result.and(shredded)
+    }
+
+    /// Directory the repo is cloned into from `url`
+    pub fn dir<'a>(&'a self, url: &'a str) -> &'a str {
+        self.dest.as_deref().unwrap_or(clone_dir(url))
+    }
+
     pub fn as_repo(&self) -> Repo {
         Repo {
             url: Value::Set(self.url.clone()),
+            reference: self.reference.clone().into(),
+            depth: self.depth.into(),
+            credential: self.credential.clone().into(),
+            // Left unset when off, like in the saved file
+            submodules: if self.submodules { Value::Set(true) } else { Value::Unset },
+            dest: self.dest.clone().into(),
         }
     }
 }
@@ -0,0 +1,77 @@
+//! Steps checked into the repository next to the code they build
+//!
+//! A `.renzokutai.xml` at the root of the checkout holds a `<steps>` section
+//! like the one of a saved pipeline. When a run starts its steps are merged
+//! over the ones configured on the host: a step with the name of a host step
+//! replaces it, the others are added after them, so changes to the pipeline
+//! are reviewed alongside the code.
+//!
+//! Anyone who can push writes the definition, so its steps can't name files
+//! on the host: a `secret_file` would copy any of them into the zone.
+
+use crate::config::ValidatedSteps;
+use crate::zones::PipelineZone;
+use anyhow::{Result, anyhow};
+use serde::Deserialize;
+use std::path::Path;
+
+/// Path of the definition, relative to the checkout
+pub const DEFINITION_FILE: &str = ".renzokutai.xml";
+
+#[derive(Debug, Default, Deserialize)]
+struct RepoDefinition {
+    #[serde(default)]
+    steps: ValidatedSteps,
+}
+
+/// `host` with the steps of the definition in `xml` merged over them,
+/// validated as if they were edited in the shell
+pub fn merge(xml: &str, host: &ValidatedSteps) -> Result<ValidatedSteps> {
+    let definition: RepoDefinition =
+        serde_xml_rs::from_str(xml).map_err(|e| anyhow!("Invalid {}: {}", DEFINITION_FILE, e))?;
+    if let Some(step) = definition.steps.iter().find(|step| !step.secret_files.is_empty()) {
+        return Err(anyhow!(
+            "Invalid {}: step {} has a secret_file, these are only configured on the host",
+            DEFINITION_FILE,
+            step.name
+        ));
+    }
+    let own = definition
+        .steps
+        .as_steps()
+        .validate(host)
+        .map_err(|e| anyhow!("Invalid {}: {}", DEFINITION_FILE, e))?;
+
+    Ok(host.clone().extended_by(own))
+}
+
+/// The steps to run in `pzone`, `host` merged with the definition of the
+/// checkout at `workdir` if it has one
+pub fn load(pzone: &PipelineZone, host: &ValidatedSteps, workdir: &str) -> Result<ValidatedSteps> {
+    match read(&pzone.host_path(""), workdir)? {
+        Some(xml) => {
+            println!("Using the steps of {}", DEFINITION_FILE);
+            merge(&xml, host)
+        }
+        None => Ok(host.clone()),
+    }
+}
+
+/// The definition of the checkout at `workdir` in the zone whose root is at
+/// `zone_root` on the host, if it has one
+pub fn read(zone_root: &Path, workdir: &str) -> Result<Option<String>> {
+    let path = zone_root.join(workdir.trim_start_matches('/')).join(DEFINITION_FILE);
+    let path = match path.canonicalize() {
+        Ok(path) => path,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(anyhow!("Couldn't read {}: {}", path.display(), err)),
+    };
+    // The checkout could link it anywhere on the host
+    if !path.starts_with(zone_root.canonicalize()?) {
+        return Err(anyhow!("{} points outside of the zone", DEFINITION_FILE));
+    }
+
+    std::fs::read_to_string(&path)
+        .map(Some)
+        .map_err(|e| anyhow!("Couldn't read {}: {}", path.display(), e))
+}
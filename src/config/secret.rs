@@ -0,0 +1,153 @@
+//! Secrets of a pipeline, exported to its steps like environment variables
+//!
+//! They are kept out of the pipeline XML, in `<name>.secrets` next to it and
+//! only readable by its owner, and masked in the output of the steps. Steps
+//! get them through a file only their user can read, which they remove once
+//! sourced, so the values never show up in the commands running them.
+
+use crate::config::EnvVar;
+use crate::config::step::quote;
+use anyhow::{Result, anyhow};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+
+/// What secret values are replaced with in step output
+pub const REDACTED: &str = "********";
+
+#[derive(Clone, PartialEq)]
+pub struct Secret {
+    pub name: String,
+    pub value: String,
+}
+
+/// Never shows the value, `print` dumps the pipeline with `Debug`
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Secret")
+            .field("name", &self.name)
+            .field("value", &REDACTED)
+            .finish()
+    }
+}
+
+impl Secret {
+    /// Set `name` in `secrets`, replacing its previous value
+    pub fn set(secrets: &mut Vec<Secret>, name: String, value: String) -> Result<()> {
+        EnvVar::validate_name(&name)?;
+
+        match secrets.iter_mut().find(|s| s.name == name) {
+            Some(secret) => secret.value = value,
+            None => secrets.push(Secret { name, value }),
+        }
+        Ok(())
+    }
+
+    pub fn unset(secrets: &mut Vec<Secret>, name: &str) -> Result<()> {
+        let before = secrets.len();
+        secrets.retain(|s| s.name != name);
+
+        if secrets.len() == before {
+            Err(anyhow!("No secret {}", name))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Environment variable the secret is exported as
+    pub fn as_env(&self) -> EnvVar {
+        EnvVar {
+            name: self.name.clone(),
+            value: self.value.clone(),
+        }
+    }
+
+    /// Where the secrets of `step` wait in the zone for its script, right in
+    /// /tmp so the step's user can remove them
+    pub fn zone_path(step: &str) -> String {
+        format!("/tmp/.renzokutai-secrets-{}", step)
+    }
+
+    /// Command writing its input to `path`, readable by `user` only, root
+    /// when there's none
+    pub fn install_command(path: &str, user: Option<&str>) -> String {
+        let path = quote(path);
+        let chown = user.map(|user| format!(" && chown {} {}", quote(user), path)).unwrap_or_default();
+        format!("umask 077 && rm -f {path} && cat > {path}{chown}", path = path, chown = chown)
+    }
+
+    /// Command exporting the secrets written to `path`, removing them right after
+    pub fn source_command(path: &str) -> String {
+        format!(". {path} && rm -f {path}", path = quote(path))
+    }
+
+    /// Script exporting `secrets`, to write to `zone_path`
+    pub fn exports(secrets: &[Secret]) -> String {
+        secrets.iter().map(|s| format!("{}\n", s.as_env().export_command())).collect()
+    }
+
+    /// One line per secret with its value masked, the ones set to another
+    /// value than in `before` marked as changed
+    pub fn masked_lines(secrets: &[Secret], before: &[Secret]) -> String {
+        secrets
+            .iter()
+            .map(|secret| {
+                let changed = before.iter().any(|b| b.name == secret.name && b.value != secret.value);
+                format!("secret {}={}{}\n", secret.name, REDACTED, if changed { " (changed)" } else { "" })
+            })
+            .collect()
+    }
+
+    /// Replace the value of every secret in `line` with `REDACTED`
+    pub fn redact(line: &str, secrets: &[Secret]) -> String {
+        let mut values: Vec<&str> = secrets.iter().map(|s| s.value.as_str()).filter(|v| !v.is_empty()).collect();
+        // Longest first so a secret containing another is masked whole
+        values.sort_by_key(|v| std::cmp::Reverse(v.len()));
+
+        values
+            .into_iter()
+            .fold(line.to_string(), |line, value| line.replace(value, REDACTED))
+    }
+
+    /// Secrets stored at `path`, none when the file doesn't exist
+    pub fn load(path: &Path) -> Result<Vec<Secret>> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(anyhow!("Couldn't read secrets {}: {}", path.display(), err)),
+        };
+        let secrets: BTreeMap<String, String> = serde_json::from_str(&contents)
+            .map_err(|e| anyhow!("Couldn't read secrets {}: {}", path.display(), e))?;
+
+        Ok(secrets
+            .into_iter()
+            .map(|(name, value)| Secret { name, value })
+            .collect())
+    }
+
+    /// Store `secrets` at `path` readable only by its owner, removing the
+    /// file when there are none
+    pub fn save(path: &Path, secrets: &[Secret]) -> Result<()> {
+        if secrets.is_empty() {
+            return match fs::remove_file(path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+                _ => Ok(()),
+            };
+        }
+
+        let secrets: BTreeMap<&str, &str> = secrets.iter().map(|s| (s.name.as_str(), s.value.as_str())).collect();
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?;
+        // The mode only applies to new files
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        file.write_all(serde_json::to_string_pretty(&secrets)?.as_bytes())?;
+
+        Ok(())
+    }
+}
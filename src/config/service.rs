@@ -0,0 +1,359 @@
+use crate::color::Colorize;
+use crate::config::{EnvVar, Filter, Frame, Param, Value, info_line};
+use crate::config::step::kill_contract_command;
+use crate::zones::PipelineZone;
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::Duration;
+use std::{cell::RefCell, rc::Rc};
+
+/// How long a service gets to pass its readiness check when it doesn't set
+/// `ready_timeout`
+const READY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Time between two readiness checks
+const READY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Daemons a run needs besides its steps, like a database the tests talk to
+#[derive(Debug, PartialEq)]
+pub struct Services {
+    vec: Vec<Rc<RefCell<Service>>>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct ValidatedServices {
+    #[serde(default)]
+    #[serde(rename = "service")]
+    vec: Vec<ValidatedService>,
+}
+
+impl Default for Services {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Services {
+    pub fn new() -> Services {
+        Self { vec: Vec::new() }
+    }
+
+    pub fn add_empty(&mut self) -> Frame {
+        let s = Rc::new(RefCell::new(Service::default()));
+        self.vec.push(s.clone());
+        Frame::Service(s.clone())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Rc<RefCell<Service>>> {
+        self.vec.iter()
+    }
+
+    pub fn append(&mut self, other: Services) -> usize {
+        crate::filterable::append(&mut self.vec, other.vec)
+    }
+
+    pub fn select(&self, filters: &[Filter], index: Option<usize>) -> Result<Frame> {
+        crate::filterable::select(&self.vec, filters, index, Service::name).map(Frame::Service)
+    }
+
+    /// Append a copy of the element matching `filters`, optionally with a new
+    /// name
+    pub fn copy(&mut self, filters: &[Filter], name: Option<String>) -> Result<Frame> {
+        crate::filterable::copy(&mut self.vec, filters, Service::name, |s| {
+            if let Some(name) = name {
+                s.name = Value::Set(name);
+            }
+        })
+        .map(Frame::Service)
+    }
+
+    pub fn validate(&self) -> Result<ValidatedServices> {
+        let vservices = self
+            .vec
+            .iter()
+            .map(|s| s.borrow().validate())
+            .collect::<Result<Vec<ValidatedService>>>()?;
+
+        let mut names = HashSet::new();
+        if let Some(service) = vservices.iter().find(|s| !names.insert(&s.name)) {
+            return Err(anyhow!("More than one service is named {}", service.name));
+        }
+        Ok(ValidatedServices { vec: vservices })
+    }
+
+    pub fn validation_errors(&self) -> Vec<String> {
+        crate::filterable::validation_errors(&self.vec, Service::name, Service::validate)
+    }
+}
+
+impl Clone for Services {
+    fn clone(&self) -> Self {
+        Self { vec: crate::filterable::detach(&self.vec) }
+    }
+}
+
+impl From<Vec<ValidatedService>> for ValidatedServices {
+    fn from(vec: Vec<ValidatedService>) -> Self {
+        Self { vec }
+    }
+}
+
+impl ValidatedServices {
+    pub fn as_services(&self) -> Services {
+        let services = self
+            .vec
+            .iter()
+            .map(|s| Rc::new(RefCell::new(s.as_service())))
+            .collect();
+        Services { vec: services }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ValidatedService> {
+        self.vec.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vec.is_empty()
+    }
+
+    /// Start every service in `pzone`, with the run parameters `params`, and
+    /// wait for each to be ready before starting the next
+    ///
+    /// Services already started are left running when one fails, `stop`
+    /// takes them down.
+    pub async fn start(&self, pzone: &PipelineZone, params: &[EnvVar]) -> Result<()> {
+        for service in &self.vec {
+            println!("Starting service {}", service.name.cyan());
+            let status = pzone.exec(service.start_command(params)?)?.wait().await?;
+            if !status.success() {
+                return Err(anyhow!("Couldn't start service {}", service.name));
+            }
+
+            if let Some(ready) = &service.ready {
+                let ready = ValidatedService::in_environment(ready, params)?;
+                service.wait_ready(pzone, &ready).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Kill the services started by `start` in `pzone`, in reverse order
+    pub async fn stop(&self, pzone: &PipelineZone) -> Result<()> {
+        for service in self.vec.iter().rev() {
+            println!("Stopping service {}", service.name.cyan());
+            pzone.exec(service.stop_command())?.wait().await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Service {
+    pub name: Value<String>,
+    pub script: Value<String>,
+    pub ready: Value<String>,
+    pub ready_timeout: Value<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ValidatedService {
+    #[serde(rename = "@name")]
+    pub name: String,
+    /// Command running the daemon in the foreground, e.g. `postgres -D /db`
+    #[serde(rename = "@script")]
+    pub script: String,
+    /// Command succeeding once the daemon accepts work, checked before the
+    /// first step runs
+    #[serde(rename = "@ready", default, skip_serializing_if = "Option::is_none")]
+    pub ready: Option<String>,
+    /// How long the readiness check may keep failing, 60s when unset
+    #[serde(rename = "@ready_timeout", default, skip_serializing_if = "Option::is_none")]
+    pub ready_timeout: Option<String>,
+}
+
+/// Service names end up in file names in the run zone
+fn validate_service_name(name: &str) -> Result<()> {
+    let valid_chars = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+    if name.is_empty() || !valid_chars {
+        Err(anyhow!("Invalid service name {}, use letters, digits, '_' and '-'", name))
+    } else {
+        Ok(())
+    }
+}
+
+impl Service {
+    pub const ATTRIBUTES: &[&str] = &["name", "script", "ready", "ready_timeout"];
+
+    pub fn validate(&self) -> Result<ValidatedService> {
+        let name = self.name.require("name")?;
+        validate_service_name(&name)?;
+        let script = self.script.require("script")?;
+        let ready_timeout = self.ready_timeout.option();
+        if let Some(timeout) = &ready_timeout {
+            crate::policy::parse_duration(timeout)?;
+        }
+
+        Ok(ValidatedService {
+            name,
+            script,
+            ready: self.ready.option(),
+            ready_timeout,
+        })
+    }
+
+    pub fn name(&self) -> String {
+        match &self.name {
+            Value::Unset => "service".to_string(),
+            Value::Set(v) => format!("service({})", v.cyan()),
+        }
+    }
+
+    pub fn info(&self) -> String {
+        [
+            info_line("name", self.name.display()),
+            info_line("script", self.script.display()),
+            info_line("ready", self.ready.display()),
+            info_line("ready_timeout", self.ready_timeout.option().unwrap_or("60s".to_string())),
+        ]
+        .join("\n")
+    }
+
+    pub fn unset(&mut self, key: String) -> Result<()> {
+        match key.as_str() {
+            "name" => self.name = Value::Unset,
+            "script" => self.script = Value::Unset,
+            "ready" => self.ready = Value::Unset,
+            "ready_timeout" => self.ready_timeout = Value::Unset,
+            _ => return Err(anyhow!("Unknown attribute for service: {}", key)),
+        }
+        Ok(())
+    }
+
+    pub fn set(&mut self, key: String, value: String) -> Result<()> {
+        match key.as_str() {
+            "name" => {
+                validate_service_name(&value)?;
+                self.name = Value::Set(value);
+                Ok(())
+            }
+            "script" => {
+                self.script = Value::Set(value);
+                Ok(())
+            }
+            "ready" => {
+                self.ready = Value::Set(value);
+                Ok(())
+            }
+            "ready_timeout" => {
+                crate::policy::parse_duration(&value)?;
+                self.ready_timeout = Value::Set(value);
+                Ok(())
+            }
+            _ => Err(anyhow!("Unknown attribute for service: {}", key)),
+        }
+    }
+}
+
+impl ValidatedService {
+    pub fn as_service(&self) -> Service {
+        Service {
+            name: Value::Set(self.name.clone()),
+            script: Value::Set(self.script.clone()),
+            ready: self.ready.clone().into(),
+            ready_timeout: self.ready_timeout.clone().into(),
+        }
+    }
+
+    /// File in the run zone the output of the service goes to
+    pub fn log_file(&self) -> String {
+        format!("/var/tmp/renzokutai-service-{}.log", self.name)
+    }
+
+    /// File in the run zone holding the id of the process contract of the
+    /// service
+    fn contract_file(&self) -> String {
+        format!("/var/tmp/renzokutai-service-{}.ctid", self.name)
+    }
+
+    /// `command` run after loading the profile of root, with the run
+    /// parameters `params` in the environment
+    ///
+    /// The references to parameters become references to their variables,
+    /// so their values are never parsed as shell.
+    pub fn in_environment(command: &str, params: &[EnvVar]) -> Result<String> {
+        let variables: Vec<EnvVar> = params
+            .iter()
+            .map(|param| EnvVar {
+                name: param.name.clone(),
+                value: format!("${{{}}}", Self::param_variable(&param.name)),
+            })
+            .collect();
+        let command = Param::expand(command, &variables)?;
+        let exports: String = params
+            .iter()
+            .map(|param| {
+                let var = EnvVar {
+                    name: Self::param_variable(&param.name),
+                    value: param.value.clone(),
+                };
+                format!("{} && ", var.export_command())
+            })
+            .collect();
+
+        Ok(format!(". ~/.profile && {}{}", exports, command))
+    }
+
+    /// Variable holding the value of the run parameter `name`
+    fn param_variable(name: &str) -> String {
+        format!("RENZOKUTAI_PARAM_{}", name)
+    }
+
+    /// Command running the script in the background, detached from the
+    /// zlogin that starts it but in its process contract
+    pub fn start_command(&self, params: &[EnvVar]) -> Result<String> {
+        let script = Self::in_environment(&self.script, params)?;
+        Ok(format!(
+            "ps -o ctid= -p $$ > {} && (nohup sh -c {} > {} 2>&1 < /dev/null &)",
+            self.contract_file(),
+            crate::config::step::quote(&script),
+            self.log_file()
+        ))
+    }
+
+    /// Command killing every process the service started, through its
+    /// process contract
+    pub fn stop_command(&self) -> String {
+        kill_contract_command(&self.contract_file())
+    }
+
+    fn timeout(&self) -> Result<Duration> {
+        match &self.ready_timeout {
+            Some(timeout) => crate::policy::parse_duration(timeout),
+            None => Ok(READY_TIMEOUT),
+        }
+    }
+
+    /// Run the `ready` command until it succeeds, failing once the timeout
+    /// of the service runs out
+    async fn wait_ready(&self, pzone: &PipelineZone, ready: &str) -> Result<()> {
+        let timeout = self.timeout()?;
+        let start = std::time::Instant::now();
+
+        loop {
+            if pzone.exec(ready)?.wait().await?.success() {
+                return Ok(());
+            }
+            if start.elapsed() >= timeout {
+                return Err(anyhow!(
+                    "Service {} wasn't ready within {}, its output is in {}",
+                    self.name,
+                    crate::policy::format_duration(timeout),
+                    self.log_file()
+                ));
+            }
+            tokio::time::sleep(READY_INTERVAL).await;
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use crate::config::{BuildCache, Frame, Package, PipelineTrigger, Repo, Service, Step};
+use crate::color::Colorize;
+
+pub const COMMANDS: &[&str] = &[
+    "add", "apply", "commit", "copy", "depends", "diff", "end", "env", "exit", "export",
+    "help", "history", "import", "info", "matrix", "move", "param", "print", "quit", "redo", "revert", "save",
+    "secret", "select", "set", "undo", "unset", "validate",
+];
+
+/// Usage and description of every command, shown by `help`
+const USAGE: &[(&str, &str)] = &[
+    ("add <type>", "add a new package, repo, step, cache, trigger or service and select it"),
+    ("apply", "validate and apply the pipeline to its base zone"),
+    ("commit", "validate, save and apply the pipeline"),
+    ("copy <type> [key=value] [name]", "duplicate an element under a new name and select it"),
+    ("depends add|remove <step>", "edit the dependencies of the selected step"),
+    ("diff", "show the unsaved changes against the saved pipeline"),
+    ("end", "go back to the enclosing scope"),
+    ("env set|unset <KEY>[=<value>]", "edit the environment of the pipeline or the selected step"),
+    ("exit", "leave the shell, asking first if there are unsaved changes"),
+    ("export [--format xml|json|toml|yaml] [path]", "print the pipeline or write it to path"),
+    ("help", "show this help"),
+    ("history", "list recently entered commands"),
+    ("import <pipeline> [type]", "append the elements of another saved pipeline"),
+    ("import --drone <path>", "append the steps of a Drone or Woodpecker YAML file"),
+    ("info", "show the attributes of the current scope"),
+    ("matrix set|unset <NAME>[=<value>,...]", "edit the axes the pipeline runs across, one run per combination"),
+    ("move step <name> before|after <other>", "reorder the steps of the pipeline"),
+    ("param set|unset <NAME>[=<default>]", "edit the parameters of the pipeline, overridden with run -e"),
+    ("print", "dump the current scope"),
+    ("quit", "same as exit"),
+    ("redo", "apply again the last undone change"),
+    ("revert", "discard unsaved changes and reload the pipeline"),
+    ("save", "validate and save the pipeline to disk"),
+    ("secret set|unset <NAME>[=<value>]", "edit the secrets of the pipeline, kept out of its file"),
+    ("select <type> [key=value]", "select an existing package, repo, step, cache, trigger or service"),
+    ("set <key>=<value> ...", "set one or more attributes, quote values containing spaces"),
+    ("undo", "roll back the last change to the pipeline"),
+    ("unset <key>", "clear an attribute"),
+    ("validate", "check the pipeline for errors"),
+];
+
+pub const TYPES: &[&str] = &["package", "repo", "step", "cache", "trigger", "service"];
+
+/// Help text listing the commands and the attributes of the current `frame`
+pub fn help(frame: &Frame) -> String {
+    let width = USAGE.iter().map(|(usage, _)| usage.len()).max().unwrap_or(0);
+    let mut lines = vec!["Commands:".bold().to_string()];
+
+    for (usage, description) in USAGE {
+        lines.push(format!("  {:<width$}  {}", usage, description));
+    }
+
+    lines.push(String::new());
+    lines.push(format!("{} {}", "Types:".bold(), TYPES.join(", ")));
+    lines.push(format!(
+        "{} {}",
+        format!("Attributes of {}:", frame.kind()).bold(),
+        frame.attributes().join(", ")
+    ));
+
+    lines.join("\n")
+}
+
+fn type_attributes(ty: &str) -> &'static [&'static str] {
+    match ty {
+        "package" => Package::ATTRIBUTES,
+        "repo" => Repo::ATTRIBUTES,
+        "cache" => BuildCache::ATTRIBUTES,
+        "trigger" => PipelineTrigger::ATTRIBUTES,
+        "service" => Service::ATTRIBUTES,
+        "step" => Step::ATTRIBUTES,
+        _ => &[],
+    }
+}
+
+/// Completion candidates for the word under the cursor at the end of `line`
+///
+/// Returns the byte offset where the word starts along with the candidates.
+pub fn complete(line: &str, attributes: &[&str]) -> (usize, Vec<String>) {
+    let start = line.rfind(char::is_whitespace).map(|i| i + 1).unwrap_or(0);
+    let word = &line[start..];
+    let previous: Vec<&str> = line[..start].split_whitespace().collect();
+
+    let keys = |attrs: &[&str]| attrs.iter().map(|a| format!("{}=", a)).collect::<Vec<_>>();
+    let options: Vec<String> = match previous[..] {
+        [] => COMMANDS.iter().map(|c| c.to_string()).collect(),
+        ["select"] | ["add"] | ["copy"] => TYPES.iter().map(|t| t.to_string()).collect(),
+        ["select" | "copy", ty, ..] => keys(type_attributes(ty)),
+        ["set", ..] => keys(attributes),
+        ["depends"] => ["add", "remove"].iter().map(|a| a.to_string()).collect(),
+        ["move"] => vec!["step".to_string()],
+        ["env" | "secret" | "matrix" | "param"] => ["set", "unset"].iter().map(|a| a.to_string()).collect(),
+        ["move", "step", _] => ["before", "after"].iter().map(|a| a.to_string()).collect(),
+        ["unset"] => attributes.iter().map(|a| a.to_string()).collect(),
+        _ => Vec::new(),
+    };
+
+    (
+        start,
+        options.into_iter().filter(|o| o.starts_with(word)).collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completes_commands_types_and_keys() {
+        assert_eq!(complete("se", &[]), (0, vec!["secret".to_string(), "select".to_string(), "set".to_string()]));
+        assert_eq!(complete("add r", &[]), (4, vec!["repo".to_string()]));
+        assert_eq!(
+            complete("select step sc", &[]),
+            (12, vec!["script=".to_string()])
+        );
+        assert_eq!(
+            complete("select step name=build sc", &[]),
+            (23, vec!["script=".to_string()])
+        );
+        assert_eq!(complete("set ", Repo::ATTRIBUTES), (4, vec!["url=".to_string(), "ref=".to_string(), "depth=".to_string(), "credential=".to_string(), "submodules=".to_string(), "dest=".to_string()]));
+        assert_eq!(complete("set url=foo r", Repo::ATTRIBUTES), (12, vec!["ref=".to_string()]));
+    }
+
+    #[test]
+    fn help_lists_every_command_and_frame_attributes() {
+        let step = Frame::Step(std::rc::Rc::new(std::cell::RefCell::new(Step::default())));
+        let help = help(&step);
+
+        for command in COMMANDS {
+            assert!(USAGE.iter().any(|(usage, _)| usage.split(' ').next() == Some(command)));
+        }
+        assert!(help.contains("name, script, depends"));
+    }
+}
@@ -0,0 +1,239 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Directory on the host collected artifacts are kept in, overridable for tests
+pub const ARTIFACTS_DIR_VAR: &str = "RENZOKUTAI_ARTIFACTS_DIR";
+
+/// Directory under the artifacts one where what steps produce is staged,
+/// which no run id can clash with as those are alphanumeric
+const PRODUCED_DIR: &str = "_produced";
+
+/// A file or directory produced by a dependency step that a step consumes
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ArtifactNeed {
+    #[serde(rename = "@step")]
+    pub step: String,
+    #[serde(rename = "@path")]
+    pub path: String,
+}
+
+impl ArtifactNeed {
+    /// Parse a comma separated list of `step:path` entries
+    pub fn parse_list(value: &str) -> Result<Vec<ArtifactNeed>> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| match entry.split_once(':') {
+                Some((step, path)) if !step.is_empty() && !path.is_empty() => Ok(ArtifactNeed {
+                    step: step.to_string(),
+                    path: path.to_string(),
+                }),
+                _ => Err(anyhow!("Expected step:path, got {}", entry)),
+            })
+            .collect()
+    }
+
+    /// Directory inside the zone where the artifacts of `step` are staged
+    pub fn staging_dir(step: &str) -> String {
+        format!("/tmp/renzokutai/{}/artifacts", step)
+    }
+}
+
+impl std::fmt::Display for ArtifactNeed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.step, self.path)
+    }
+}
+
+/// Files a step produces that are copied out of its zone once it completes,
+/// matched by a glob relative to the checkout, or to the zone root when
+/// absolute
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ArtifactPath {
+    #[serde(rename = "@path")]
+    pub path: String,
+}
+
+/// The `<artifacts>` section of a step
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Artifacts {
+    #[serde(default, rename = "artifact")]
+    pub paths: Vec<ArtifactPath>,
+}
+
+impl Artifacts {
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+}
+
+impl ArtifactPath {
+    /// Parse a comma separated list of globs
+    pub fn parse_list(value: &str) -> Result<Vec<ArtifactPath>> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|path| !path.is_empty())
+            .map(|path| {
+                Self::validate(path)?;
+                Ok(ArtifactPath { path: path.to_string() })
+            })
+            .collect()
+    }
+
+    /// Parse a comma separated list of the files or directories a step
+    /// produces or consumes, named as they are rather than matched
+    pub fn parse_exact_list(value: &str) -> Result<Vec<ArtifactPath>> {
+        let paths = Self::parse_list(value)?;
+        match paths.iter().find(|p| p.path.contains(['*', '?', '[', ']'])) {
+            Some(glob) => Err(anyhow!("Path {} can't be a glob", glob.path)),
+            None => Ok(paths),
+        }
+    }
+
+    /// Whether `path` is this path or inside it
+    pub fn covers(&self, path: &str) -> bool {
+        let own = self.path.trim_end_matches('/');
+        let path = path.trim_end_matches('/');
+        path.strip_prefix(own).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+
+    pub fn validate(path: &str) -> Result<()> {
+        if Path::new(path).components().any(|c| c == std::path::Component::ParentDir) {
+            return Err(anyhow!("Artifact path {} can't leave the zone with ..", path));
+        }
+        glob::Pattern::new(path).map_err(|e| anyhow!("Invalid artifact path {}: {}", path, e))?;
+        Ok(())
+    }
+
+    /// Directory on the host artifacts are collected into, one per run
+    pub fn collected_root() -> PathBuf {
+        match std::env::var(ARTIFACTS_DIR_VAR) {
+            Ok(path) if !path.is_empty() => PathBuf::from(path),
+            _ => PathBuf::from("/var/db/renzokutai/artifacts"),
+        }
+    }
+
+    /// Directory on the host the artifacts of `step` in `run` are collected into
+    pub fn collected_dir(run: &str, step: &str) -> PathBuf {
+        Self::collected_root().join(run).join(step)
+    }
+
+    /// Copy what the globs of `paths` match under the zone root at `zone_root`
+    /// into `target`, keeping their path relative to the zone root, and
+    /// return the copied paths
+    ///
+    /// Relative globs start from `workdir`, the directory of the step in the
+    /// zone.
+    pub fn collect(paths: &[ArtifactPath], zone_root: &Path, workdir: &str, target: &Path) -> Result<Vec<PathBuf>> {
+        let zone_root = zone_root.canonicalize()?;
+        let mut collected = Vec::new();
+
+        for artifact in paths {
+            let pattern = match artifact.path.strip_prefix('/') {
+                Some(absolute) => zone_root.join(absolute),
+                None => zone_root.join(workdir.trim_start_matches('/')).join(&artifact.path),
+            };
+            let matches = glob::glob(&pattern.to_string_lossy())
+                .map_err(|e| anyhow!("Invalid artifact path {}: {}", artifact.path, e))?;
+
+            for path in matches {
+                let path = path?.canonicalize()?;
+                // Symlinks in the zone could point anywhere on the host
+                let Ok(relative) = path.strip_prefix(&zone_root) else {
+                    return Err(anyhow!("Artifact {} points outside of the zone", artifact.path));
+                };
+                let destination = target.join(relative);
+                copy_recursively(&path, &destination)?;
+                collected.push(relative.to_path_buf());
+            }
+        }
+
+        Ok(collected)
+    }
+
+    /// Directory on the host what `step` produces in `run` is staged in, for
+    /// the steps consuming it to find it in whichever zone they run
+    pub fn produced_dir(run: &str, step: &str) -> PathBuf {
+        Self::collected_root().join(PRODUCED_DIR).join(run).join(step)
+    }
+
+    /// Copy what `step` produced in run `from` into the staging of run `to`
+    pub fn copy_produced(from: &str, to: &str, step: &str) -> Result<()> {
+        let source = Self::produced_dir(from, step);
+        match source.exists() {
+            true => copy_recursively(&source, &Self::produced_dir(to, step)),
+            false => Ok(()),
+        }
+    }
+
+    /// Copy what `step` collected in run `from` into the directory of run `to`
+    pub fn copy_collected(from: &str, to: &str, step: &str) -> Result<()> {
+        let source = Self::collected_dir(from, step);
+        match source.exists() {
+            true => copy_recursively(&source, &Self::collected_dir(to, step)),
+            false => Ok(()),
+        }
+    }
+
+    /// Copy artifacts collected into `collected` back to where they were
+    /// found under the zone root at `zone_root`
+    pub fn restore(collected: &Path, zone_root: &Path) -> Result<()> {
+        restore_recursively(collected, collected, zone_root)
+    }
+}
+
+fn copy_recursively(source: &Path, destination: &Path) -> Result<()> {
+    if let Some(parent) = destination.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if !source.is_dir() {
+        std::fs::copy(source, destination)?;
+        return Ok(());
+    }
+
+    std::fs::create_dir_all(destination)?;
+    for entry in std::fs::read_dir(source)? {
+        let entry = entry?;
+        // Links inside collected directories are skipped rather than followed
+        if entry.file_type()?.is_symlink() {
+            continue;
+        }
+        copy_recursively(&entry.path(), &destination.join(entry.file_name()))?;
+    }
+    Ok(())
+}
+
+fn restore_recursively(collected: &Path, source: &Path, zone_root: &Path) -> Result<()> {
+    if source.is_dir() {
+        for entry in std::fs::read_dir(source)? {
+            restore_recursively(collected, &entry?.path(), zone_root)?;
+        }
+        return Ok(());
+    }
+
+    // The zone may have replaced any directory on the way with a symlink
+    // pointing anywhere on the host
+    let relative = source.strip_prefix(collected)?;
+    let mut destination = zone_root.to_path_buf();
+    for component in relative.components() {
+        destination.push(component);
+        if destination.symlink_metadata().is_ok_and(|m| m.file_type().is_symlink()) {
+            return Err(anyhow!("Can't restore artifact {} through a symlink", relative.display()));
+        }
+    }
+
+    if let Some(parent) = destination.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::copy(source, &destination)?;
+    Ok(())
+}
+
+impl std::fmt::Display for ArtifactPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.path)
+    }
+}
@@ -0,0 +1,127 @@
+use crate::config::EnvVar;
+use crate::history::RunTag;
+use anyhow::{Result, anyhow};
+use nom::{
+    IResult, Parser,
+    branch::alt,
+    bytes::complete::{tag, take_till, take_while1},
+    character::complete::{char, multispace0},
+    combinator::{all_consuming, map, value},
+    multi::separated_list1,
+    sequence::{delimited, preceded},
+};
+
+/// What a run was started with, the `when` condition of its steps is
+/// checked against it
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RunContext {
+    pub branch: Option<String>,
+    pub commit: Option<String>,
+    pub actor: Option<String>,
+    /// Environment of the pipeline, with the values of the matrix combination
+    pub env: Vec<EnvVar>,
+    pub tags: Vec<RunTag>,
+    /// Value of every parameter of the pipeline for the run
+    pub params: Vec<EnvVar>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    Branch,
+    Commit,
+    Actor,
+    Env(String),
+    Tag(String),
+}
+
+impl Operand {
+    /// Value in `context`, missing ones compare like an empty string
+    fn resolve<'a>(&self, context: &'a RunContext) -> &'a str {
+        let value = match self {
+            Operand::Branch => context.branch.as_deref(),
+            Operand::Commit => context.commit.as_deref(),
+            Operand::Actor => context.actor.as_deref(),
+            Operand::Env(name) => context.env.iter().find(|e| e.name == *name).map(|e| e.value.as_str()),
+            Operand::Tag(key) => context.tags.iter().find(|t| t.key == *key).map(|t| t.value.as_str()),
+        };
+        value.unwrap_or("")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Comparison {
+    operand: Operand,
+    equal: bool,
+    value: String,
+}
+
+/// `when` condition of a step, e.g. `branch == 'main' && env.RUN_TESTS != '0'`
+///
+/// `&&` binds tighter than `||`, there are no parentheses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    /// Alternatives, each holding when all its comparisons do
+    any: Vec<Vec<Comparison>>,
+}
+
+impl Condition {
+    pub fn parse(expression: &str) -> Result<Self> {
+        all_consuming(delimited(multispace0, disjunction, multispace0))
+            .parse(expression)
+            .map(|(_, any)| Condition { any })
+            .map_err(|_| {
+                anyhow!(
+                    "Invalid condition {}, expected e.g. branch == 'main' or env.RUN_TESTS != '0'",
+                    expression
+                )
+            })
+    }
+
+    pub fn holds(&self, context: &RunContext) -> bool {
+        self.any.iter().any(|all| {
+            all.iter()
+                .all(|c| (c.operand.resolve(context) == c.value) == c.equal)
+        })
+    }
+}
+
+fn name(input: &str) -> IResult<&str, &str> {
+    take_while1(|c: char| c.is_alphanumeric() || c == '_' || c == '-' || c == '.').parse(input)
+}
+
+fn operand(input: &str) -> IResult<&str, Operand> {
+    alt((
+        map(preceded(tag("env."), name), |name| Operand::Env(name.to_string())),
+        map(preceded(tag("tag."), name), |key| Operand::Tag(key.to_string())),
+        value(Operand::Branch, tag("branch")),
+        value(Operand::Commit, tag("commit")),
+        value(Operand::Actor, tag("actor")),
+    ))
+    .parse(input)
+}
+
+fn comparison(input: &str) -> IResult<&str, Comparison> {
+    let operator = alt((value(true, tag("==")), value(false, tag("!="))));
+    let literal = alt((
+        delimited(char('\''), take_till(|c| c == '\''), char('\'')),
+        delimited(char('"'), take_till(|c| c == '"'), char('"')),
+    ));
+
+    map(
+        (operand, multispace0, operator, multispace0, literal),
+        |(operand, _, equal, _, value)| Comparison {
+            operand,
+            equal,
+            value: value.to_string(),
+        },
+    )
+    .parse(input)
+}
+
+fn conjunction(input: &str) -> IResult<&str, Vec<Comparison>> {
+    separated_list1(delimited(multispace0, tag("&&"), multispace0), comparison).parse(input)
+}
+
+fn disjunction(input: &str) -> IResult<&str, Vec<Vec<Comparison>>> {
+    separated_list1(delimited(multispace0, tag("||"), multispace0), conjunction).parse(input)
+}
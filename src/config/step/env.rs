@@ -0,0 +1,83 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+
+/// Environment variable exported to the script of a step
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct EnvVar {
+    #[serde(rename = "@name")]
+    pub name: String,
+    #[serde(rename = "@value")]
+    pub value: String,
+}
+
+impl EnvVar {
+    /// Check that `name` can be exported by a POSIX shell
+    pub fn validate_name(name: &str) -> Result<()> {
+        let valid_chars = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        let starts_alpha = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_');
+
+        if valid_chars && starts_alpha {
+            Ok(())
+        } else {
+            Err(anyhow!("Invalid environment variable name: {}", name))
+        }
+    }
+
+    /// Set `name` in `env`, replacing its previous value
+    pub fn set(env: &mut Vec<EnvVar>, name: String, value: String) -> Result<()> {
+        Self::validate_name(&name)?;
+
+        match env.iter_mut().find(|e| e.name == name) {
+            Some(var) => var.value = value,
+            None => env.push(EnvVar { name, value }),
+        }
+        Ok(())
+    }
+
+    pub fn unset(env: &mut Vec<EnvVar>, name: &str) -> Result<()> {
+        let before = env.len();
+        env.retain(|e| e.name != name);
+
+        if env.len() == before {
+            Err(anyhow!("No environment variable {}", name))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// `inherited` variables `own` doesn't override, followed by `own`
+    pub fn merge(inherited: &[EnvVar], own: &[EnvVar]) -> Vec<EnvVar> {
+        inherited
+            .iter()
+            .filter(|var| own.iter().all(|o| o.name != var.name))
+            .chain(own)
+            .cloned()
+            .collect()
+    }
+
+    /// Shell command exporting the variable, with the value single quoted
+    pub fn export_command(&self) -> String {
+        format!("export {}='{}'", self.name, self.value.replace('\'', r"'\''"))
+    }
+}
+
+/// Parses `NAME=value`, the value may be empty
+impl std::str::FromStr for EnvVar {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, value) = s.split_once('=').ok_or(anyhow!("Expected NAME=value, got {}", s))?;
+        Self::validate_name(name)?;
+
+        Ok(EnvVar {
+            name: name.to_string(),
+            value: value.to_string(),
+        })
+    }
+}
+
+impl std::fmt::Display for EnvVar {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}={}", self.name, self.value)
+    }
+}
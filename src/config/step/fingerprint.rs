@@ -0,0 +1,113 @@
+//! Hash of the inputs of a step, to reuse its outputs while they don't change
+//!
+//! A step declares its inputs as globs of files, relative to the checkout or
+//! to the zone root when absolute, and optionally a command whose output is
+//! hashed along with them, e.g. `git rev-parse HEAD:src`. Its script and
+//! variables are part of the fingerprint too. Anything else the step reads is
+//! not, a step reading undeclared inputs may be reused when it shouldn't.
+
+use crate::config::ValidatedStep;
+use anyhow::{Result, anyhow};
+use sha2::{Digest, Sha256};
+use std::path::{Component, Path, PathBuf};
+
+/// Parse a comma separated list of globs
+pub fn parse_inputs(value: &str) -> Result<Vec<String>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|input| !input.is_empty())
+        .map(|input| {
+            validate_input(input)?;
+            Ok(input.to_string())
+        })
+        .collect()
+}
+
+fn validate_input(input: &str) -> Result<()> {
+    if Path::new(input).components().any(|c| c == Component::ParentDir) {
+        return Err(anyhow!("Fingerprint input {} can't leave the zone with ..", input));
+    }
+    glob::Pattern::new(input).map_err(|e| anyhow!("Invalid fingerprint input {}: {}", input, e))?;
+    Ok(())
+}
+
+/// Hex encoded hash of the inputs of `step` in the zone rooted at
+/// `zone_root`, and of `command_output`, what its fingerprint command printed
+pub fn fingerprint(step: &ValidatedStep, zone_root: &Path, command_output: Option<&[u8]>) -> Result<String> {
+    let mut hasher = Sha256::new();
+    // Every field is prefixed with its length so they can't run into each other
+    let mut field = |bytes: &[u8]| {
+        hasher.update((bytes.len() as u64).to_le_bytes());
+        hasher.update(bytes);
+    };
+
+    field(step.script.as_bytes());
+    // Only when set, so fingerprints taken before shells existed still match
+    if let Some(shell) = &step.shell {
+        field(shell.as_bytes());
+    }
+    for env in step.env.iter() {
+        field(env.to_string().as_bytes());
+    }
+    for (relative, contents) in input_files(step, zone_root)? {
+        field(relative.to_string_lossy().as_bytes());
+        field(&contents);
+    }
+    if let Some(output) = command_output {
+        field(output);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Path relative to the zone root and contents of every file matched by the
+/// inputs of `step`, sorted by path
+fn input_files(step: &ValidatedStep, zone_root: &Path) -> Result<Vec<(PathBuf, Vec<u8>)>> {
+    let inputs = match &step.fingerprint {
+        Some(inputs) => parse_inputs(inputs)?,
+        None => return Ok(Vec::new()),
+    };
+    let zone_root = zone_root.canonicalize()?;
+    let mut files = Vec::new();
+
+    for input in inputs.iter() {
+        let pattern = match input.strip_prefix('/') {
+            Some(absolute) => zone_root.join(absolute),
+            None => zone_root.join(step.workdir().trim_start_matches('/')).join(input),
+        };
+        let matches = glob::glob(&pattern.to_string_lossy())
+            .map_err(|e| anyhow!("Invalid fingerprint input {}: {}", input, e))?;
+
+        for path in matches {
+            let path = path?.canonicalize()?;
+            // Symlinks in the zone could point anywhere on the host
+            if !path.starts_with(&zone_root) {
+                return Err(anyhow!("Fingerprint input {} points outside of the zone", input));
+            }
+            read_recursively(&path, &zone_root, &mut files)?;
+        }
+    }
+
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+fn read_recursively(path: &Path, zone_root: &Path, files: &mut Vec<(PathBuf, Vec<u8>)>) -> Result<()> {
+    if !path.is_dir() {
+        let relative = path.strip_prefix(zone_root)?.to_path_buf();
+        files.push((relative, std::fs::read(path)?));
+        return Ok(());
+    }
+
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        // Like collected artifacts, links inside directories are skipped
+        if entry.file_type()?.is_symlink() {
+            continue;
+        }
+        read_recursively(&entry.path(), zone_root, files)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,133 @@
+use crate::config::step::{StepShell, quote};
+use crate::zones::PipelineZone;
+use anyhow::{Result, anyhow};
+use crate::color::Colorize;
+use itertools::Itertools;
+use std::io::{self, Write};
+
+/// Environment variable with the git URL of the shared steps library
+pub const LIBRARY_VAR: &str = "RENZOKUTAI_STEPS_LIBRARY";
+
+/// Prefix of scripts taken from the library, e.g. `lib://rust/build.sh`
+pub const LIBRARY_SCHEME: &str = "lib://";
+
+/// Where the library is checked out inside the zone
+pub const LIBRARY_DIR: &str = "/opt/renzokutai/library";
+
+pub fn url() -> Option<String> {
+    std::env::var(LIBRARY_VAR).ok().filter(|u| !u.is_empty())
+}
+
+/// Path inside the library if `script` refers to it
+pub fn library_path(script: &str) -> Option<&str> {
+    script.strip_prefix(LIBRARY_SCHEME)
+}
+
+/// Check that a script is either a plain path or a library path that stays
+/// inside the library, followed by its arguments if any
+pub fn validate_script(script: &str) -> Result<()> {
+    let words = script_words(script)?;
+    let script = words[0].as_str();
+    match library_path(script) {
+        Some(path) if path.is_empty() || path.starts_with('/') => {
+            Err(anyhow!("Library script {} must be a path relative to the library", script))
+        }
+        Some(path) if path.split('/').any(|part| part == "..") => {
+            Err(anyhow!("Library script {} can't leave the library", script))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Split `script` into its path and arguments the way a shell would, with
+/// quotes and backslashes keeping spaces in, e.g. `'my build.sh' --release`
+pub fn script_words(script: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = script.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(anyhow!("Unterminated quote in script {}", script)),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => word.push(c),
+                            Some(c) => word.extend(['\\', c]),
+                            None => return Err(anyhow!("Unterminated quote in script {}", script)),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(anyhow!("Unterminated quote in script {}", script)),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => return Err(anyhow!("Trailing backslash in script {}", script)),
+            },
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+
+    if words.is_empty() {
+        return Err(anyhow!("Script is empty"));
+    }
+    Ok(words)
+}
+
+/// Shell snippet running `script` with `shell`, relative to the repo unless
+/// it's a library script
+///
+/// The path and arguments are quoted, so they reach the script as written.
+pub fn script_command(script: &str, shell: StepShell) -> Result<String> {
+    let words = script_words(script)?;
+    let path = match library_path(&words[0]) {
+        Some(path) => format!("{}/{}", LIBRARY_DIR, path),
+        None => format!("./{}", words[0]),
+    };
+
+    Ok(std::iter::once(shell.command().to_string())
+        .chain(std::iter::once(&path).chain(&words[1..]).map(|word| quote(word)))
+        .join(" "))
+}
+
+/// Fetch a fresh copy of the library into the zone
+pub async fn sync(pzone: &PipelineZone) -> Result<()> {
+    let url = url().ok_or(anyhow!(
+        "Steps use library scripts but no library is configured, set {}",
+        LIBRARY_VAR
+    ))?;
+
+    print!("Syncing steps library {}...", url.yellow());
+    io::stdout().lock().flush().unwrap();
+
+    let status = pzone
+        .exec(format!(
+            "rm -rf {dir} && git clone --depth 1 {url} {dir}",
+            dir = LIBRARY_DIR,
+            url = url
+        ))?
+        .wait()
+        .await?;
+
+    if !status.success() {
+        return Err(anyhow!("Couldn't clone steps library {}", url));
+    }
+
+    println!("{}", "DONE".green());
+    Ok(())
+}
@@ -23,36 +23,75 @@
 ///! STATUS TRANSITIONS:
 ///!
 ///!  Pending ──▶ Running ──▶ Finished
-///!                 │
-///!                 ▼
-///!               Failed
+///!     │  ▲        │
+///!     │  │        ▼
+///!     │  └ retry ─ Failed
+///!     ▼
+///!  Skipped, when its condition doesn't hold for the run
 ///!
-use crate::config::{Filter, Frame, Value};
-use crate::filterable::Filterable;
+///!  Pending ──▶ Finished, reused when its fingerprint matches a previous run
+///!
+///! Steps with `run=always` wait for every other step to finish or fail, and
+///! run even when some did, to upload what the run left or notify about it.
+///!
+use crate::config::{Filter, Frame, MovePosition, Param, Secret, Value, info_line};
 use anyhow::{Result, anyhow};
-use owo_colors::OwoColorize;
+use itertools::Itertools;
+use crate::color::Colorize;
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use std::iter::Iterator;
 use std::{cell::RefCell, rc::Rc, sync::Arc};
+use std::time::Duration;
 use tokio::sync::RwLock;
 
+mod artifacts;
+mod condition;
+mod env;
+pub mod fingerprint;
+pub mod library;
+mod output;
+mod processes;
+mod requirements;
+mod resources;
 mod runnable;
+mod secrets;
 
+pub use artifacts::*;
+pub use condition::*;
+pub use env::*;
+pub use output::*;
+pub use processes::*;
+pub use requirements::*;
+pub use resources::*;
 pub use runnable::*;
+pub use secrets::*;
+
+/// Most times a step may be retried
+pub const MAX_RETRIES: u32 = 10;
 
-#[derive(Debug)]
+/// Wait before the first retry of steps without a `retry_delay`
+pub const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+#[derive(Debug, PartialEq)]
 pub struct Steps {
     vec: Vec<Rc<RefCell<Step>>>,
 }
 
 /// Container of steps to run in a pipeline
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
 pub struct ValidatedSteps {
+    #[serde(default)]
     #[serde(rename = "step")]
     vec: Vec<ValidatedStep>,
 }
 
+impl Default for Steps {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Steps {
     pub fn new() -> Self {
         Self { vec: Vec::new() }
@@ -64,39 +103,541 @@ impl Steps {
         Frame::Step(s.clone())
     }
 
-    pub fn select(&self, filter: &Option<Filter>) -> Result<Frame> {
-        let matching: Vec<_> = self
-            .vec
-            .iter()
-            .filter(|f| f.borrow().filter(filter))
-            .collect();
+    pub fn push(&mut self, step: Step) {
+        self.vec.push(Rc::new(RefCell::new(step)));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Rc<RefCell<Step>>> {
+        self.vec.iter()
+    }
+
+    pub fn append(&mut self, other: Steps) -> usize {
+        crate::filterable::append(&mut self.vec, other.vec)
+    }
+
+    pub fn select(&self, filters: &[Filter], index: Option<usize>) -> Result<Frame> {
+        crate::filterable::select(&self.vec, filters, index, Step::name).map(Frame::Step)
+    }
 
-        match matching[..] {
-            [x] => Ok(Frame::Step(x.clone())),
-            [] => Err(anyhow!("No element matched the filter")),
-            _ => Err(anyhow!("More than one element matched the filter")),
+    /// Append a copy of the element matching `filters`, optionally with a new name
+    pub fn copy(&mut self, filters: &[Filter], name: Option<String>) -> Result<Frame> {
+        crate::filterable::copy(&mut self.vec, filters, Step::name, |s| {
+            if let Some(name) = name {
+                s.name = Value::Set(name);
+            }
+        })
+        .map(Frame::Step)
+    }
+
+    /// Move the step named `name` right before or after the step named `other`
+    pub fn move_step(&mut self, name: &str, position: MovePosition, other: &str) -> Result<()> {
+        if name == other {
+            return Err(anyhow!("Can't move step {} relative to itself", name));
         }
+
+        let index = |vec: &Vec<Rc<RefCell<Step>>>, name: &str| {
+            vec.iter()
+                .position(|s| s.borrow().name == Value::Set(name.to_string()))
+                .ok_or(anyhow!("No step named {}", name))
+        };
+        // Look both up first so a missing step leaves the order untouched
+        index(&self.vec, other)?;
+        let step = self.vec.remove(index(&self.vec, name)?);
+        let target = match position {
+            MovePosition::Before => index(&self.vec, other)?,
+            MovePosition::After => index(&self.vec, other)? + 1,
+        };
+        self.vec.insert(target, step);
+
+        Ok(())
     }
 
-    pub fn validate(&self) -> Result<ValidatedSteps> {
+    /// Validate the steps of a pipeline extending one with the `inherited`
+    /// steps, which they may depend on or redefine
+    pub fn validate(&self, inherited: &ValidatedSteps) -> Result<ValidatedSteps> {
         let step_names: HashSet<String> = self
             .vec
             .iter()
             .map(|s| s.borrow().name.clone().ensure())
             .collect::<Result<HashSet<String>>>()?;
+        let known: HashSet<String> = step_names.iter().cloned().chain(inherited.iter().map(|s| s.name.clone())).collect();
         let vsteps = self
             .vec
             .iter()
-            .map(|r| r.borrow().validate(&step_names))
+            .map(|r| r.borrow().validate(&known))
             .collect::<Result<Vec<ValidatedStep>>>()?;
+        let own = vsteps
+            .iter()
+            .map(|s| (s.name.clone(), s.depends.iter().map(|d| d.name.clone()).collect()));
+        if let Some(cycle) = find_cycle(&inherited.graph(&step_names, own)) {
+            return Err(cycle_error(&cycle));
+        }
+        let merged: Vec<ValidatedStep> = inherited
+            .iter()
+            .filter(|step| !step_names.contains(&step.name))
+            .chain(vsteps.iter())
+            .cloned()
+            .collect();
+        let merged = ValidatedSteps::from(merged);
+        merged.check_stage_order()?;
+        merged.check_always_order()?;
+        merged.check_consumes()?;
         Ok(ValidatedSteps { vec: vsteps })
     }
+
+    pub fn validation_errors(&self, inherited: &ValidatedSteps) -> Vec<String> {
+        let step_names: HashSet<String> = self
+            .vec
+            .iter()
+            .filter_map(|s| s.borrow().name.ensure().ok())
+            .collect();
+        let known: HashSet<String> = step_names.iter().cloned().chain(inherited.iter().map(|s| s.name.clone())).collect();
+
+        let own = self.vec.iter().filter_map(|s| {
+            let s = s.borrow();
+            let depends = s.depends.iter().filter_map(|d| d.name.option()).collect();
+            s.name.option().map(|name| (name, depends))
+        });
+        let graph = inherited.graph(&step_names, own);
+
+        self.vec
+            .iter()
+            .filter_map(|s| {
+                let s = s.borrow();
+                s.validate(&known)
+                    .err()
+                    .map(|e| format!("{}: {}", s.name(), e))
+            })
+            .chain(find_cycle(&graph).map(|cycle| cycle_error(&cycle).to_string()))
+            .collect()
+    }
+}
+
+/// When a step runs
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RunCondition {
+    /// Once the steps it depends on succeed
+    #[default]
+    OnSuccess,
+    /// After every other step, whether they succeeded or not
+    Always,
+}
+
+impl RunCondition {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RunCondition::OnSuccess => "on_success",
+            RunCondition::Always => "always",
+        }
+    }
+}
+
+impl std::str::FromStr for RunCondition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "on_success" => Ok(RunCondition::OnSuccess),
+            "always" => Ok(RunCondition::Always),
+            _ => Err(anyhow!("Unknown run condition {}, expected on_success or always", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for RunCondition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What runs the script of a step
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum StepShell {
+    /// `/usr/bin/sh -x`, tracing the commands it runs
+    #[default]
+    Sh,
+    /// `/usr/bin/bash -x`
+    Bash,
+    /// `/usr/bin/ksh -x`
+    Ksh,
+    /// The script itself, which has to be executable, e.g. with a shebang
+    Exec,
+}
+
+impl StepShell {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StepShell::Sh => "sh",
+            StepShell::Bash => "bash",
+            StepShell::Ksh => "ksh",
+            StepShell::Exec => "exec",
+        }
+    }
+
+    /// Command running the script, followed by its path and arguments
+    pub fn command(&self) -> &'static str {
+        match self {
+            StepShell::Sh => "/usr/bin/sh -x",
+            StepShell::Bash => "/usr/bin/bash -x",
+            StepShell::Ksh => "/usr/bin/ksh -x",
+            StepShell::Exec => "exec",
+        }
+    }
+}
+
+impl std::str::FromStr for StepShell {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sh" => Ok(StepShell::Sh),
+            "bash" => Ok(StepShell::Bash),
+            "ksh" => Ok(StepShell::Ksh),
+            "exec" => Ok(StepShell::Exec),
+            _ => Err(anyhow!("Unknown shell {}, expected sh, bash, ksh or exec", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for StepShell {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Check that a stage name can be shown in the output as is
+fn validate_stage(stage: &str) -> Result<()> {
+    let valid_chars = stage.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+    if stage.is_empty() || !valid_chars {
+        Err(anyhow!("Invalid stage {}, use letters, digits, '_' and '-'", stage))
+    } else {
+        Ok(())
+    }
+}
+
+/// Names of steps depending on each other in a cycle, the first repeated at
+/// the end, among `graph`'s steps and the names of their dependencies
+///
+/// Dependencies on steps missing from `graph` are left to the validation of
+/// the steps.
+pub fn find_cycle(graph: &[(String, Vec<String>)]) -> Option<Vec<String>> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum Mark {
+        Unvisited,
+        InPath,
+        Done,
+    }
+
+    fn visit(index: usize, graph: &[(String, Vec<String>)], marks: &mut [Mark], path: &mut Vec<usize>) -> Option<Vec<String>> {
+        marks[index] = Mark::InPath;
+        path.push(index);
+
+        for dependency in graph[index].1.iter() {
+            let Some(next) = graph.iter().position(|(name, _)| name == dependency) else {
+                continue;
+            };
+            match marks[next] {
+                Mark::InPath => {
+                    let start = path.iter().position(|i| *i == next).unwrap();
+                    let mut cycle: Vec<String> = path[start..].iter().map(|i| graph[*i].0.clone()).collect();
+                    cycle.push(graph[next].0.clone());
+                    return Some(cycle);
+                }
+                Mark::Unvisited => {
+                    if let Some(cycle) = visit(next, graph, marks, path) {
+                        return Some(cycle);
+                    }
+                }
+                Mark::Done => {}
+            }
+        }
+
+        path.pop();
+        marks[index] = Mark::Done;
+        None
+    }
+
+    let mut marks = vec![Mark::Unvisited; graph.len()];
+    (0..graph.len()).find_map(|index| match marks[index] {
+        Mark::Unvisited => visit(index, graph, &mut marks, &mut Vec::new()),
+        _ => None,
+    })
+}
+
+fn cycle_error(cycle: &[String]) -> anyhow::Error {
+    anyhow!("Steps depend on each other in a cycle: {}", cycle.join(" -> "))
+}
+
+impl Clone for Steps {
+    fn clone(&self) -> Self {
+        Self { vec: crate::filterable::detach(&self.vec) }
+    }
+}
+
+impl From<Vec<ValidatedStep>> for ValidatedSteps {
+    fn from(vec: Vec<ValidatedStep>) -> Self {
+        Self { vec }
+    }
 }
 
 impl ValidatedSteps {
+    pub fn iter(&self) -> impl Iterator<Item = &ValidatedStep> {
+        self.vec.iter()
+    }
+
+    /// Steps with `env` exported before their own environment
+    pub fn with_env(&self, env: &[EnvVar]) -> ValidatedSteps {
+        let vec = self
+            .vec
+            .iter()
+            .map(|step| ValidatedStep {
+                env: EnvVar::merge(env, &step.env),
+                ..step.clone()
+            })
+            .collect();
+        ValidatedSteps { vec }
+    }
+
+    /// Steps exporting `secrets` and masking them in their output
+    pub fn with_secrets(&self, secrets: &[Secret]) -> ValidatedSteps {
+        let vec = self
+            .vec
+            .iter()
+            .map(|step| ValidatedStep {
+                secrets: secrets.to_vec(),
+                ..step.clone()
+            })
+            .collect();
+        ValidatedSteps { vec }
+    }
+
+    /// Names of the stages of the steps, in the order of their first step
+    pub fn stages(&self) -> Vec<String> {
+        self.vec.iter().filter_map(|s| s.stage.clone()).unique().collect()
+    }
+
+    /// Position of the stage of the step named `name` among `stages`, steps
+    /// without a stage run in the first one
+    pub fn stage_index(&self, name: &str) -> usize {
+        let stage = self.vec.iter().find(|s| s.name == name).and_then(|s| s.stage.as_ref());
+        stage.and_then(|stage| self.stages().iter().position(|s| s == stage)).unwrap_or(0)
+    }
+
+    /// Fail when a step depends on a step of a later stage, which would
+    /// never run before it
+    pub fn check_stage_order(&self) -> Result<()> {
+        for step in self.vec.iter() {
+            let stage = self.stage_index(&step.name);
+            if let Some(later) = step.depends.iter().find(|d| self.stage_index(&d.name) > stage) {
+                return Err(anyhow!(
+                    "Step {} depends on {}, which is in a later stage",
+                    step.name,
+                    later.name
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fail on what `Steps::validate` rejects across all of these steps,
+    /// e.g. once merged with the steps of an extended pipeline
+    pub fn check(&self) -> Result<()> {
+        let names: HashSet<&str> = self.vec.iter().map(|s| s.name.as_str()).collect();
+        for step in self.vec.iter() {
+            if let Some(missing) = step.depends.iter().find(|d| !names.contains(d.name.as_str())) {
+                return Err(anyhow!("Step {} depends on a non-existing step: {}", step.name, missing.name));
+            }
+        }
+        if let Some(cycle) = find_cycle(&self.graph(&HashSet::new(), std::iter::empty())) {
+            return Err(cycle_error(&cycle));
+        }
+        self.check_stage_order()?;
+        self.check_always_order()?;
+        self.check_consumes()
+    }
+
+    /// Fail when a step depends on a step that always runs, which only
+    /// starts once every other step is over
+    pub fn check_always_order(&self) -> Result<()> {
+        let always: HashSet<&str> = self.vec.iter().filter(|s| s.always_runs()).map(|s| s.name.as_str()).collect();
+        for step in self.vec.iter().filter(|s| !s.always_runs()) {
+            if let Some(finalizer) = step.depends.iter().find(|d| always.contains(d.name.as_str())) {
+                return Err(anyhow!(
+                    "Step {} depends on {}, which always runs after the other steps",
+                    step.name,
+                    finalizer.name
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Steps `name` depends on, directly or not
+    pub fn dependency_closure(&self, name: &str) -> HashSet<String> {
+        let mut closure = HashSet::new();
+        let mut pending = vec![name.to_string()];
+
+        while let Some(name) = pending.pop() {
+            let Some(step) = self.vec.iter().find(|s| s.name == name) else {
+                continue;
+            };
+            for dependency in step.depends.iter() {
+                if closure.insert(dependency.name.clone()) {
+                    pending.push(dependency.name.clone());
+                }
+            }
+        }
+        closure
+    }
+
+    /// Steps among the dependencies of `step` producing what it consumes,
+    /// or the first consumed path none of them produces
+    fn producers_of(&self, step: &ValidatedStep) -> std::result::Result<Vec<String>, String> {
+        let closure = self.dependency_closure(&step.name);
+        let mut producers = Vec::new();
+
+        for consumed in step.consumes.iter() {
+            let producer = self
+                .vec
+                .iter()
+                .filter(|s| closure.contains(&s.name))
+                .find(|s| s.produces.iter().any(|p| p.covers(&consumed.path)));
+            match producer {
+                Some(producer) if !producers.contains(&producer.name) => producers.push(producer.name.clone()),
+                Some(_) => (),
+                None => return Err(consumed.path.clone()),
+            }
+        }
+        Ok(producers)
+    }
+
+    /// Fail when a step consumes a path no step it depends on produces
+    pub fn check_consumes(&self) -> Result<()> {
+        for step in self.vec.iter() {
+            if let Err(path) = self.producers_of(step) {
+                return Err(anyhow!(
+                    "Step {} consumes {}, which none of the steps it depends on produces",
+                    step.name,
+                    path
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Steps knowing which of their dependencies produce what they consume
+    pub fn with_producers(&self) -> ValidatedSteps {
+        let vec = self
+            .vec
+            .iter()
+            .map(|step| ValidatedStep {
+                producers: self.producers_of(step).unwrap_or_default(),
+                ..step.clone()
+            })
+            .collect();
+        ValidatedSteps { vec }
+    }
+
+    /// These steps, except those redefined by `own`, followed by `own`
+    pub fn extended_by(self, own: ValidatedSteps) -> ValidatedSteps {
+        let mut vec: Vec<_> = self
+            .vec
+            .into_iter()
+            .filter(|step| own.vec.iter().all(|s| s.name != step.name))
+            .collect();
+        vec.extend(own.vec);
+        ValidatedSteps { vec }
+    }
+
+    /// Dependency graph of these steps, except those named in `redefined`,
+    /// followed by `own`
+    fn graph(
+        &self,
+        redefined: &HashSet<String>,
+        own: impl Iterator<Item = (String, Vec<String>)>,
+    ) -> Vec<(String, Vec<String>)> {
+        self.vec
+            .iter()
+            .filter(|step| !redefined.contains(&step.name))
+            .map(|step| (step.name.clone(), step.depends.iter().map(|d| d.name.clone()).collect()))
+            .chain(own)
+            .collect()
+    }
+
+    /// Steps with the parameters in their scripts and environment replaced
+    /// by `params`
+    pub fn with_params(&self, params: &[EnvVar]) -> Result<ValidatedSteps> {
+        let vec = self
+            .vec
+            .iter()
+            .map(|step| {
+                let script = Param::expand(&step.script, params)?;
+                library::validate_script(&script)?;
+                Ok(ValidatedStep {
+                    script,
+                    env: Param::expand_env(&step.env, params)?,
+                    ..step.clone()
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ValidatedSteps { vec })
+    }
+
+    /// Steps running as `user` unless they name their own
+    pub fn with_user(&self, user: Option<&str>) -> ValidatedSteps {
+        let vec = self
+            .vec
+            .iter()
+            .map(|step| ValidatedStep {
+                user: step.user.clone().or(user.map(str::to_string)),
+                ..step.clone()
+            })
+            .collect();
+        ValidatedSteps { vec }
+    }
+
+    /// Steps running in `workdir`, the checkout when `None`
+    pub fn with_workdir(&self, workdir: Option<&str>) -> ValidatedSteps {
+        let vec = self
+            .vec
+            .iter()
+            .map(|step| ValidatedStep {
+                workdir: workdir.map(str::to_string),
+                ..step.clone()
+            })
+            .collect();
+        ValidatedSteps { vec }
+    }
+
+    /// Users the steps run as other than root, each once
+    pub fn users(&self) -> Vec<String> {
+        self.vec
+            .iter()
+            .filter_map(|s| s.user.clone())
+            .sorted()
+            .dedup()
+            .collect()
+    }
+
+    /// Whether any step installs packages through pkgin
+    pub fn uses_pkgin(&self) -> bool {
+        self.vec
+            .iter()
+            .flat_map(|s| s.requires.iter())
+            .any(|r| r.provider == "pkgsrc")
+    }
+
+    /// Whether any step runs a script from the steps library
+    pub fn uses_library(&self) -> bool {
+        self.vec.iter().any(|s| library::library_path(&s.script).is_some())
+    }
+
     pub fn as_runnable(&self) -> RunnableSteps {
         RunnableSteps {
             steps: self.vec.iter().map(|s| s.as_runnable()).collect(),
+            stages: self.stages(),
+            output: Arc::new(LogRouter::new(self.vec.iter().map(|s| s.name.as_str()))),
         }
     }
 
@@ -110,7 +651,7 @@ impl ValidatedSteps {
     }
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Dependency {
     pub name: Value<String>,
 }
@@ -129,6 +670,9 @@ impl Dependency {
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
 pub struct ValidatedDependency {
+    /// An attribute, as text nodes lose their surrounding whitespace.
+    /// Definitions written before still name it in a `<name>` element
+    #[serde(rename = "@name", alias = "name")]
     pub name: String,
 }
 
@@ -140,40 +684,297 @@ impl ValidatedDependency {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Step {
     pub name: Value<String>,
     pub script: Value<String>,
     pub depends: Vec<Dependency>,
+    pub needs_artifacts: Vec<ArtifactNeed>,
+    pub artifacts: Vec<ArtifactPath>,
+    pub produces: Vec<ArtifactPath>,
+    pub consumes: Vec<ArtifactPath>,
+    pub secret_files: Vec<SecretFile>,
+    pub requires: Vec<PackageRequirement>,
+    pub env: Vec<EnvVar>,
+    pub output_limit: Value<String>,
+    pub on_output_limit: Value<String>,
+    pub memory_limit: Value<String>,
+    pub cpu_limit: Value<String>,
+    pub timeout: Value<String>,
+    pub retries: Value<u32>,
+    pub retry_delay: Value<String>,
+    pub when: Value<String>,
+    pub fingerprint: Value<String>,
+    pub fingerprint_command: Value<String>,
+    pub user: Value<String>,
+    pub stage: Value<String>,
+    pub run: Value<String>,
+    pub shell: Value<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
 pub struct ValidatedStep {
     #[serde(rename = "@name")]
     pub name: String,
     #[serde(rename = "@script")]
     pub script: String,
+    /// Output kept from the step, e.g. `1M`, defaults to `DEFAULT_OUTPUT_LIMIT`
+    #[serde(rename = "@output_limit", default, skip_serializing_if = "Option::is_none")]
+    pub output_limit: Option<String>,
+    /// `truncate` or `fail` once the step prints more than its output limit
+    #[serde(rename = "@on_output_limit", default, skip_serializing_if = "Option::is_none")]
+    pub on_output_limit: Option<String>,
+    /// Address space of each process of the step, e.g. `4G`
+    #[serde(rename = "@memory_limit", default, skip_serializing_if = "Option::is_none")]
+    pub memory_limit: Option<String>,
+    /// CPUs the step may use, e.g. `2` or `0.5`
+    #[serde(rename = "@cpu_limit", default, skip_serializing_if = "Option::is_none")]
+    pub cpu_limit: Option<String>,
+    /// Longest the step may run, e.g. `10m`, overrides the pipeline's step timeout
+    #[serde(rename = "@timeout", default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<String>,
+    /// Times the step runs again after failing
+    #[serde(rename = "@retries", default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+    /// Wait before the first retry, doubled for every later one
+    #[serde(rename = "@retry_delay", default, skip_serializing_if = "Option::is_none")]
+    pub retry_delay: Option<String>,
+    /// Condition on the run for the step to run, e.g. `branch == 'main'`
+    #[serde(rename = "@when", default, skip_serializing_if = "Option::is_none")]
+    pub when: Option<String>,
+    /// Globs of the files the step reads, to reuse its outputs from a
+    /// previous run while they don't change
+    #[serde(rename = "@fingerprint", default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+    /// Command whose output is part of the fingerprint, e.g. `git rev-parse HEAD:src`
+    #[serde(rename = "@fingerprint_command", default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint_command: Option<String>,
+    /// User the script runs as instead of root, overrides the pipeline's
+    #[serde(rename = "@user", default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// Stage of the step, e.g. `test`, stages run one after the other
+    #[serde(rename = "@stage", default, skip_serializing_if = "Option::is_none")]
+    pub stage: Option<String>,
+    /// `always` to run after the other steps even when they fail, e.g. to
+    /// upload artifacts or notify, `on_success` when unset
+    #[serde(rename = "@run", default, skip_serializing_if = "Option::is_none")]
+    pub run: Option<String>,
+    /// `sh`, `bash`, `ksh` or `exec` to run the script as is, `sh` when unset
+    #[serde(rename = "@shell", default, skip_serializing_if = "Option::is_none")]
+    pub shell: Option<String>,
     #[serde(default)]
     #[serde(rename = "depend")]
     pub depends: Vec<ValidatedDependency>,
+    #[serde(default)]
+    #[serde(rename = "needs")]
+    pub needs_artifacts: Vec<ArtifactNeed>,
+    /// Files copied out of the zone once the step completes
+    #[serde(default, skip_serializing_if = "Artifacts::is_empty")]
+    pub artifacts: Artifacts,
+    /// Files or directories the step leaves for the steps depending on it
+    #[serde(default, rename = "produces", skip_serializing_if = "Vec::is_empty")]
+    pub produces: Vec<ArtifactPath>,
+    /// Files or directories the step takes from the steps it depends on,
+    /// directly or not
+    #[serde(default, rename = "consumes", skip_serializing_if = "Vec::is_empty")]
+    pub consumes: Vec<ArtifactPath>,
+    #[serde(default)]
+    #[serde(rename = "secret_file")]
+    pub secret_files: Vec<SecretFile>,
+    #[serde(default)]
+    #[serde(rename = "requires")]
+    pub requires: Vec<PackageRequirement>,
+    #[serde(default)]
+    #[serde(rename = "env")]
+    pub env: Vec<EnvVar>,
+    /// Secrets of the pipeline, handed to the step only for a run
+    #[serde(skip)]
+    pub secrets: Vec<Secret>,
+    /// Directory the step runs in, set for a run from where the pipeline's
+    /// first repo is cloned
+    #[serde(skip)]
+    pub workdir: Option<String>,
+    /// Steps producing what the step consumes, resolved for a run
+    #[serde(skip)]
+    pub producers: Vec<String>,
+}
+
+/// Step names end up in paths and commands in the run zone
+fn validate_step_name(name: &str) -> Result<()> {
+    let valid_chars = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    let starts_alnum = name.chars().next().is_some_and(|c| c.is_ascii_alphanumeric());
+
+    if !valid_chars || !starts_alnum {
+        Err(anyhow!(
+            "Invalid step name {}, use letters, digits, '_' and '-' starting with a letter or digit",
+            name
+        ))
+    } else {
+        Ok(())
+    }
 }
 
 impl Step {
+    pub const ATTRIBUTES: &[&str] = &[
+        "name",
+        "script",
+        "depends",
+        "needs_artifacts",
+        "artifacts",
+        "produces",
+        "consumes",
+        "secret_file",
+        "requires",
+        "output_limit",
+        "on_output_limit",
+        "memory_limit",
+        "cpu_limit",
+        "timeout",
+        "retries",
+        "retry_delay",
+        "when",
+        "fingerprint",
+        "fingerprint_command",
+        "user",
+        "stage",
+        "run",
+        "shell",
+    ];
+
     pub fn validate(&self, step_names: &HashSet<String>) -> Result<ValidatedStep> {
-        let name = self.name.ensure()?;
-        let script = self.script.ensure()?;
+        let name = self.name.require("name")?;
+        validate_step_name(&name)?;
+        let script = self.script.require("script")?;
+        library::validate_script(&script)?;
+        let depends = self
+            .depends
+            .iter()
+            .map(|d| d.validate(step_names))
+            .collect::<Result<Vec<ValidatedDependency>>>()?;
+
+        if let Some(need) = self
+            .needs_artifacts
+            .iter()
+            .find(|n| !depends.iter().any(|d| d.name == n.step))
+        {
+            return Err(anyhow!(
+                "Step needs artifacts from {} which is not one of its dependencies",
+                need.step
+            ));
+        }
+        if let Some(limit) = self.output_limit.option() {
+            crate::zfs::validate_size(&limit)?;
+        }
+        if let Some(policy) = self.on_output_limit.option() {
+            policy.parse::<OutputOverflow>()?;
+        }
+        if let Some(limit) = self.memory_limit.option() {
+            crate::zfs::validate_size(&limit)?;
+        }
+        if let Some(limit) = self.cpu_limit.option() {
+            Resources::parse_cpu_limit(&limit)?;
+        }
+        if let Some(timeout) = self.timeout.option() {
+            crate::policy::parse_duration(&timeout)?;
+        }
+        // Pipelines loaded from their files never went through `set`
+        if let Some(retries) = self.retries.option()
+            && retries > MAX_RETRIES
+        {
+            return Err(anyhow!("retries must be a number up to {}, got {}", MAX_RETRIES, retries));
+        }
+        if let Some(delay) = self.retry_delay.option() {
+            crate::policy::parse_duration(&delay)?;
+        }
+        if let Some(when) = self.when.option() {
+            Condition::parse(&when)?;
+        }
+        if let Some(inputs) = self.fingerprint.option() {
+            fingerprint::parse_inputs(&inputs)?;
+        }
+        if self.fingerprint == Value::Unset && self.fingerprint_command != Value::Unset {
+            return Err(anyhow!("fingerprint_command needs fingerprint to be set"));
+        }
+        if let Some(user) = self.user.option() {
+            crate::zones::validate_user_name(&user)?;
+        }
+        if let Some(stage) = self.stage.option() {
+            validate_stage(&stage)?;
+        }
+        if let Some(run) = self.run.option() {
+            run.parse::<RunCondition>()?;
+        }
+        if let Some(shell) = self.shell.option() {
+            shell.parse::<StepShell>()?;
+        }
 
         Ok(ValidatedStep {
             name: name.clone(),
             script: script.clone(),
-            depends: self
-                .depends
-                .iter()
-                .map(|d| d.validate(&step_names))
-                .collect::<Result<Vec<ValidatedDependency>>>()?,
+            depends,
+            needs_artifacts: self.needs_artifacts.clone(),
+            artifacts: Artifacts {
+                paths: self.artifacts.clone(),
+            },
+            produces: self.produces.clone(),
+            consumes: self.consumes.clone(),
+            secret_files: self.secret_files.clone(),
+            requires: self.requires.clone(),
+            env: self.env.clone(),
+            output_limit: self.output_limit.option(),
+            on_output_limit: self.on_output_limit.option(),
+            memory_limit: self.memory_limit.option(),
+            cpu_limit: self.cpu_limit.option(),
+            timeout: self.timeout.option(),
+            retries: self.retries.option(),
+            retry_delay: self.retry_delay.option(),
+            when: self.when.option(),
+            fingerprint: self.fingerprint.option(),
+            fingerprint_command: self.fingerprint_command.option(),
+            user: self.user.option(),
+            stage: self.stage.option(),
+            run: self.run.option(),
+            shell: self.shell.option(),
+            secrets: Vec::new(),
+            workdir: None,
+            producers: Vec::new(),
         })
     }
 
+    pub fn add_dependency(&mut self, name: String) -> Result<()> {
+        if self.name == Value::Set(name.clone()) {
+            return Err(anyhow!("A step can't depend on itself"));
+        }
+        if self.depends.iter().any(|d| d.name == Value::Set(name.clone())) {
+            return Err(anyhow!("Already depends on {}", name));
+        }
+
+        self.depends.push(Dependency {
+            name: Value::Set(name),
+        });
+        Ok(())
+    }
+
+    pub fn remove_dependency(&mut self, name: &str) -> Result<()> {
+        let before = self.depends.len();
+        self.depends.retain(|d| d.name != Value::Set(name.to_string()));
+
+        if self.depends.len() == before {
+            Err(anyhow!("Doesn't depend on {}", name))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Set an environment variable of the step, replacing its previous value
+    pub fn set_env(&mut self, name: String, value: String) -> Result<()> {
+        EnvVar::set(&mut self.env, name, value)
+    }
+
+    pub fn unset_env(&mut self, name: &str) -> Result<()> {
+        EnvVar::unset(&mut self.env, name)
+    }
+
     pub fn name(&self) -> String {
         match &self.name {
             Value::Unset => "step".to_string(),
@@ -181,19 +982,190 @@ impl Step {
         }
     }
 
+    pub fn info(&self) -> String {
+        let depends = if self.depends.is_empty() {
+            "none".to_string()
+        } else {
+            self.depends.iter().map(|d| d.name.display()).join(", ")
+        };
+
+        [
+            info_line("name", self.name.display()),
+            info_line("script", self.script.display()),
+            info_line("depends", depends),
+            info_line("needs_artifacts", self.needs_artifacts.iter().join(", ")),
+            info_line("artifacts", self.artifacts.iter().join(", ")),
+            info_line("produces", self.produces.iter().join(", ")),
+            info_line("consumes", self.consumes.iter().join(", ")),
+            info_line("secret_file", self.secret_files.iter().join(", ")),
+            info_line("requires", self.requires.iter().join(", ")),
+            info_line("env", self.env.iter().join(", ")),
+            info_line("output_limit", self.output_limit.display()),
+            info_line("on_output_limit", self.on_output_limit.display()),
+            info_line("memory_limit", self.memory_limit.display()),
+            info_line("cpu_limit", self.cpu_limit.display()),
+            info_line("timeout", self.timeout.display()),
+            info_line("retries", self.retries.option().unwrap_or(0).to_string()),
+            info_line("retry_delay", self.retry_delay.display()),
+            info_line("when", self.when.option().unwrap_or("always".to_string())),
+            info_line("fingerprint", self.fingerprint.display()),
+            info_line("fingerprint_command", self.fingerprint_command.display()),
+            info_line("user", self.user.option().unwrap_or("root".to_string())),
+            info_line("stage", self.stage.display()),
+            info_line("run", self.run.option().unwrap_or(RunCondition::OnSuccess.to_string())),
+            info_line("shell", self.shell.option().unwrap_or(StepShell::Sh.to_string())),
+        ]
+        .join("\n")
+    }
+
+    pub fn unset(&mut self, key: String) -> Result<()> {
+        match key.as_str() {
+            "name" => self.name = Value::Unset,
+            "script" => self.script = Value::Unset,
+            "depends" => self.depends.clear(),
+            "needs_artifacts" => self.needs_artifacts.clear(),
+            "artifacts" => self.artifacts.clear(),
+            "produces" => self.produces.clear(),
+            "consumes" => self.consumes.clear(),
+            "secret_file" => self.secret_files.clear(),
+            "requires" => self.requires.clear(),
+            "output_limit" => self.output_limit = Value::Unset,
+            "on_output_limit" => self.on_output_limit = Value::Unset,
+            "memory_limit" => self.memory_limit = Value::Unset,
+            "cpu_limit" => self.cpu_limit = Value::Unset,
+            "timeout" => self.timeout = Value::Unset,
+            "retries" => self.retries = Value::Unset,
+            "retry_delay" => self.retry_delay = Value::Unset,
+            "when" => self.when = Value::Unset,
+            "fingerprint" => self.fingerprint = Value::Unset,
+            "fingerprint_command" => self.fingerprint_command = Value::Unset,
+            "user" => self.user = Value::Unset,
+            "stage" => self.stage = Value::Unset,
+            "run" => self.run = Value::Unset,
+            "shell" => self.shell = Value::Unset,
+            _ => return Err(anyhow!("Unknown attribute for step: {}", key)),
+        }
+        Ok(())
+    }
+
     pub fn set(&mut self, key: String, value: String) -> Result<()> {
         match key.as_str() {
             "name" => {
+                validate_step_name(&value)?;
                 self.name = Value::Set(value);
                 Ok(())
             }
             "script" => {
+                library::validate_script(&value)?;
                 self.script = Value::Set(value);
                 Ok(())
             }
-            // TODO
             "depends" => {
-                self.depends = Vec::new();
+                // Keep the previous dependencies when any of the new ones is rejected
+                let previous = std::mem::take(&mut self.depends);
+                for name in value.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+                    if let Err(err) = self.add_dependency(name.to_string()) {
+                        self.depends = previous;
+                        return Err(err);
+                    }
+                }
+                Ok(())
+            }
+            "needs_artifacts" => {
+                self.needs_artifacts = ArtifactNeed::parse_list(&value)?;
+                Ok(())
+            }
+            "artifacts" => {
+                self.artifacts = ArtifactPath::parse_list(&value)?;
+                Ok(())
+            }
+            "produces" => {
+                self.produces = ArtifactPath::parse_exact_list(&value)?;
+                Ok(())
+            }
+            "consumes" => {
+                self.consumes = ArtifactPath::parse_exact_list(&value)?;
+                Ok(())
+            }
+            "secret_file" => {
+                self.secret_files = SecretFile::parse_list(&value)?;
+                Ok(())
+            }
+            "requires" => {
+                self.requires = PackageRequirement::parse_list(&value)?;
+                Ok(())
+            }
+            "output_limit" => {
+                crate::zfs::validate_size(&value)?;
+                self.output_limit = Value::Set(value);
+                Ok(())
+            }
+            "on_output_limit" => {
+                value.parse::<OutputOverflow>()?;
+                self.on_output_limit = Value::Set(value);
+                Ok(())
+            }
+            "memory_limit" => {
+                crate::zfs::validate_size(&value)?;
+                self.memory_limit = Value::Set(value);
+                Ok(())
+            }
+            "cpu_limit" => {
+                Resources::parse_cpu_limit(&value)?;
+                self.cpu_limit = Value::Set(value);
+                Ok(())
+            }
+            "timeout" => {
+                crate::policy::parse_duration(&value)?;
+                self.timeout = Value::Set(value);
+                Ok(())
+            }
+            "retries" => {
+                let retries = value
+                    .parse()
+                    .ok()
+                    .filter(|retries| *retries <= MAX_RETRIES)
+                    .ok_or(anyhow!("retries must be a number up to {}, got {}", MAX_RETRIES, value))?;
+                self.retries = Value::Set(retries);
+                Ok(())
+            }
+            "retry_delay" => {
+                crate::policy::parse_duration(&value)?;
+                self.retry_delay = Value::Set(value);
+                Ok(())
+            }
+            "when" => {
+                Condition::parse(&value)?;
+                self.when = Value::Set(value);
+                Ok(())
+            }
+            "fingerprint" => {
+                fingerprint::parse_inputs(&value)?;
+                self.fingerprint = Value::Set(value);
+                Ok(())
+            }
+            "fingerprint_command" => {
+                self.fingerprint_command = Value::Set(value);
+                Ok(())
+            }
+            "user" => {
+                crate::zones::validate_user_name(&value)?;
+                self.user = Value::Set(value);
+                Ok(())
+            }
+            "stage" => {
+                validate_stage(&value)?;
+                self.stage = Value::Set(value);
+                Ok(())
+            }
+            "run" => {
+                value.parse::<RunCondition>()?;
+                self.run = Value::Set(value);
+                Ok(())
+            }
+            "shell" => {
+                value.parse::<StepShell>()?;
+                self.shell = Value::Set(value);
                 Ok(())
             }
             _ => Err(anyhow!("Unknown attribute for package: {}", key)),
@@ -206,6 +1178,76 @@ impl ValidatedStep {
         Ok(())
     }
 
+    /// Bytes of output kept from the step
+    pub fn output_limit(&self) -> Result<u64> {
+        crate::zfs::size_bytes(self.output_limit.as_deref().unwrap_or(DEFAULT_OUTPUT_LIMIT))
+    }
+
+    /// Limits the step runs under
+    pub fn resources(&self) -> Result<Resources> {
+        Ok(Resources {
+            memory: self.memory_limit.as_deref().map(crate::zfs::size_bytes).transpose()?,
+            cpu_cap: self.cpu_limit.as_deref().map(Resources::parse_cpu_limit).transpose()?,
+        })
+    }
+
+    /// Longest the step may run, its own timeout or else `inherited`
+    pub fn timeout(&self, inherited: Option<Duration>) -> Result<Option<Duration>> {
+        match &self.timeout {
+            Some(timeout) => crate::policy::parse_duration(timeout).map(Some),
+            None => Ok(inherited),
+        }
+    }
+
+    /// Whether the step runs in `context`, steps without a condition always do
+    pub fn runs_in(&self, context: &RunContext) -> Result<bool> {
+        match &self.when {
+            Some(when) => Ok(Condition::parse(when)?.holds(context)),
+            None => Ok(true),
+        }
+    }
+
+    /// How long to wait before running the step again after `attempt`
+    /// failed, counting from 1
+    pub fn retry_delay(&self, attempt: u32) -> Result<Duration> {
+        let delay = match &self.retry_delay {
+            Some(delay) => crate::policy::parse_duration(delay)?,
+            None => DEFAULT_RETRY_DELAY,
+        };
+
+        Ok(delay.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1))))
+    }
+
+    pub fn on_output_limit(&self) -> Result<OutputOverflow> {
+        self.on_output_limit
+            .as_deref()
+            .map(str::parse)
+            .unwrap_or(Ok(OutputOverflow::default()))
+    }
+
+    pub fn shell(&self) -> Result<StepShell> {
+        self.shell.as_deref().map(str::parse).unwrap_or(Ok(StepShell::default()))
+    }
+
+    /// Whether the step runs after the others even when they fail
+    pub fn always_runs(&self) -> bool {
+        self.run.as_deref().and_then(|run| run.parse().ok()) == Some(RunCondition::Always)
+    }
+
+    /// Directory the script runs in and relative paths start from
+    pub fn workdir(&self) -> &str {
+        self.workdir.as_deref().unwrap_or(crate::zones::CHECKOUT_DIR)
+    }
+
+    /// `command` run as the step's user through a login shell, or as root
+    /// after loading its profile
+    pub fn as_user(&self, command: String) -> String {
+        match &self.user {
+            Some(user) => format!("su - {} -c {}", user, resources::quote(&command)),
+            None => format!(". ~/.profile && {}", command),
+        }
+    }
+
     pub fn as_runnable(&self) -> RunnableStep {
         Arc::new(RwLock::new(InnerRunnableStep {
             step: self.clone(),
@@ -224,6 +1266,27 @@ impl ValidatedStep {
             name: Value::Set(self.name.clone()),
             script: Value::Set(self.script.clone()),
             depends: self.depends.iter().map(|s| s.as_dependency()).collect(),
+            needs_artifacts: self.needs_artifacts.clone(),
+            artifacts: self.artifacts.paths.clone(),
+            produces: self.produces.clone(),
+            consumes: self.consumes.clone(),
+            secret_files: self.secret_files.clone(),
+            requires: self.requires.clone(),
+            env: self.env.clone(),
+            output_limit: self.output_limit.clone().into(),
+            on_output_limit: self.on_output_limit.clone().into(),
+            memory_limit: self.memory_limit.clone().into(),
+            cpu_limit: self.cpu_limit.clone().into(),
+            timeout: self.timeout.clone().into(),
+            retries: self.retries.into(),
+            retry_delay: self.retry_delay.clone().into(),
+            when: self.when.clone().into(),
+            fingerprint: self.fingerprint.clone().into(),
+            fingerprint_command: self.fingerprint_command.clone().into(),
+            user: self.user.clone().into(),
+            stage: self.stage.clone().into(),
+            run: self.run.clone().into(),
+            shell: self.shell.clone().into(),
         }
     }
 }
@@ -232,99 +1295,31 @@ impl ValidatedStep {
 mod tests {
     use super::*;
 
+    fn step(name: &str, depends: &[&str]) -> ValidatedStep {
+        ValidatedStep {
+            name: name.to_string(),
+            script: format!("{}.sh", name),
+            depends: depends
+                .iter()
+                .map(|name| ValidatedDependency { name: name.to_string() })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
     #[test]
     fn steps_without_dependencies() {
-        let mut steps_by_name = HashMap::new();
-        steps_by_name.insert(
-            "build".to_string(),
-            ValidatedStep {
-                name: "build".to_string(),
-                script: "build.sh".to_string(),
-                depends: Vec::new(),
-            },
-        );
-
-        steps_by_name.insert(
-            "lint".to_string(),
-            ValidatedStep {
-                name: "lint".to_string(),
-                script: "lint.sh".to_string(),
-                depends: Vec::new(),
-            },
-        );
-
-        let steps = ValidatedSteps { steps_by_name };
-        let mut rsteps = steps.as_runnable();
-        let mut iter = rsteps.iter_mut();
-
-        if let Some(Some(steps)) = iter.next() {
-            assert_eq!(steps.len(), 2);
-            for step in steps.iter() {
-                step.borrow_mut().status = Status::Running;
-            }
-
-            assert_eq!(iter.next(), Some(None));
+        let finished = HashSet::new();
 
-            for step in steps.iter() {
-                step.borrow_mut().status = Status::Finished;
-            }
-
-            assert_eq!(iter.next(), None);
-        } else {
-            panic!("Should have returned some steps");
-        }
+        assert!(step("build", &[]).is_available(&finished));
+        assert!(step("lint", &[]).is_available(&finished));
     }
 
     #[test]
     fn steps_with_dependencies() {
-        let mut steps_by_name = HashMap::new();
-        steps_by_name.insert(
-            "build".to_string(),
-            ValidatedStep {
-                name: "build".to_string(),
-                script: "build.sh".to_string(),
-                depends: Vec::new(),
-            },
-        );
-
-        steps_by_name.insert(
-            "test".to_string(),
-            ValidatedStep {
-                name: "test".to_string(),
-                script: "test.sh".to_string(),
-                depends: vec![ValidatedDependency {
-                    name: "build".to_string(),
-                }],
-            },
-        );
-
-        let steps = ValidatedSteps { steps_by_name };
-        let mut rsteps = steps.as_runnable();
-        let mut iter = rsteps.iter_mut();
-
-        if let Some(Some(steps)) = iter.next() {
-            assert_eq!(steps.len(), 1);
-            for step in steps.iter() {
-                step.borrow_mut().status = Status::Running;
-            }
-
-            assert_eq!(iter.next(), Some(None));
-
-            for step in steps.iter() {
-                step.borrow_mut().status = Status::Finished;
-            }
-
-            if let Some(Some(steps)) = iter.next() {
-                assert_eq!(steps.len(), 1);
+        let test = step("test", &["build"]);
 
-                for step in steps.iter() {
-                    step.borrow_mut().status = Status::Finished;
-                }
-
-                assert_eq!(iter.next(), None);
-            }
-        } else {
-            panic!("Should have returned some steps");
-        }
+        assert!(!test.is_available(&HashSet::new()));
+        assert!(test.is_available(&HashSet::from(["build".to_string()])));
     }
 }
@@ -0,0 +1,350 @@
+use crate::color::Colorize;
+use crate::zones::PipelineZone;
+use anyhow::{Result, anyhow};
+use std::collections::{HashMap, VecDeque};
+use std::ffi::OsStr;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+/// Output a step may print before it gets truncated, unless it sets `output_limit`
+pub const DEFAULT_OUTPUT_LIMIT: &str = "10M";
+
+/// Directory holding the log files of the steps, overridable for tests
+pub const LOGS_DIR_VAR: &str = "RENZOKUTAI_LOGS_DIR";
+
+/// What happens to a step printing more than its output limit
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutputOverflow {
+    /// Keep the start and the end of the output, dropping the middle
+    #[default]
+    Truncate,
+    /// Stop the step and fail it
+    Fail,
+}
+
+impl OutputOverflow {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutputOverflow::Truncate => "truncate",
+            OutputOverflow::Fail => "fail",
+        }
+    }
+}
+
+impl std::str::FromStr for OutputOverflow {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "truncate" => Ok(OutputOverflow::Truncate),
+            "fail" => Ok(OutputOverflow::Fail),
+            _ => Err(anyhow!("Unknown output overflow policy {}, expected truncate or fail", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for OutputOverflow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Output of a step kept within `limit` bytes
+///
+/// The first half of the limit holds the first lines printed and the second
+/// half the latest ones, whatever falls in between is dropped.
+#[derive(Debug, Default, PartialEq)]
+pub struct StepLog {
+    limit: u64,
+    head: Vec<String>,
+    head_bytes: u64,
+    tail: VecDeque<String>,
+    tail_bytes: u64,
+    dropped_lines: u64,
+    dropped_bytes: u64,
+}
+
+impl StepLog {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            ..Default::default()
+        }
+    }
+
+    pub fn push(&mut self, line: String) {
+        let len = line.len() as u64 + 1;
+
+        if self.tail.is_empty() && self.head_bytes + len <= self.limit / 2 {
+            self.head_bytes += len;
+            self.head.push(line);
+            return;
+        }
+
+        self.tail_bytes += len;
+        self.tail.push_back(line);
+        while self.tail_bytes > self.limit - self.limit / 2 {
+            let Some(dropped) = self.tail.pop_front() else { break };
+            self.tail_bytes -= dropped.len() as u64 + 1;
+            self.dropped_lines += 1;
+            self.dropped_bytes += dropped.len() as u64 + 1;
+        }
+    }
+
+    /// Whether the step printed more than the limit
+    pub fn exceeded(&self) -> bool {
+        self.dropped_bytes > 0
+    }
+
+    /// Lines printed, dropped ones included
+    pub fn lines(&self) -> u64 {
+        (self.head.len() + self.tail.len()) as u64 + self.dropped_lines
+    }
+
+    /// Bytes dropped from the middle of the output
+    pub fn truncated_bytes(&self) -> u64 {
+        self.dropped_bytes
+    }
+
+    /// Output kept, with a marker where lines were dropped
+    pub fn render(&self) -> String {
+        let mut lines: Vec<String> = self.head.clone();
+        if self.exceeded() {
+            lines.push(format!(
+                "[... {} lines ({} bytes) truncated ...]",
+                self.dropped_lines, self.dropped_bytes
+            ));
+        }
+        lines.extend(self.tail.iter().cloned());
+
+        lines.iter().map(|l| format!("{}\n", l)).collect()
+    }
+}
+
+/// Whole output of a step written to a file on the host, each line tagged with
+/// the stream it was printed on
+///
+/// Unlike [`StepLog`] nothing is dropped, so failures can be looked into once
+/// the run is over. Retries append to the same file, and the runs older than
+/// the log retention are pruned along with their logs in the history.
+#[derive(Debug)]
+pub struct StepLogFile {
+    path: PathBuf,
+    file: Mutex<File>,
+    /// Whether a write failed, the file is left alone after the first error
+    failed: AtomicBool,
+}
+
+impl StepLogFile {
+    pub fn root() -> PathBuf {
+        match std::env::var(LOGS_DIR_VAR) {
+            Ok(path) if !path.is_empty() => PathBuf::from(path),
+            _ => PathBuf::from("/var/log/renzokutai"),
+        }
+    }
+
+    /// File holding the output of `step` in `run` of `pipeline`
+    pub fn path(pipeline: &str, run: &str, step: &str) -> PathBuf {
+        Self::root().join(pipeline).join(run).join(format!("{}.log", step))
+    }
+
+    pub fn open(path: &Path) -> Result<Self> {
+        let open = || -> std::io::Result<File> {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            OpenOptions::new().create(true).append(true).open(path)
+        };
+        let file = open().map_err(|e| anyhow!("Couldn't open log file {}: {}", path.display(), e))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+            failed: AtomicBool::new(false),
+        })
+    }
+
+    /// Append `line`, printed on `stream`, e.g. `stdout`
+    ///
+    /// A failed write is reported once and the file skipped afterwards, the
+    /// step keeps running and its output keeps being read.
+    pub fn push(&self, stream: &str, line: &str) {
+        if self.failed.load(Ordering::Relaxed) {
+            return;
+        }
+        if let Err(err) = writeln!(self.file.lock().unwrap(), "[{}] {}", stream, line) {
+            self.failed.store(true, Ordering::Relaxed);
+            eprintln!("Couldn't write to log file {}: {}", self.path.display(), err);
+        }
+    }
+
+    /// Remove the logs of the runs of `pipeline` last written to before
+    /// `before`, returning how many runs were removed
+    pub fn prune(pipeline: &str, before: SystemTime) -> Result<usize> {
+        let dir = Self::root().join(pipeline);
+        let runs = match std::fs::read_dir(&dir) {
+            Ok(runs) => runs,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(anyhow!("Couldn't list log directory {}: {}", dir.display(), err)),
+        };
+
+        let mut removed = 0;
+        for run in runs {
+            let run = run?;
+            if !run.file_type()?.is_dir() {
+                continue;
+            }
+            // Appending to a log doesn't touch the directory of its run
+            let mut written = run.metadata()?.modified()?;
+            for log in std::fs::read_dir(run.path())? {
+                written = written.max(log?.metadata()?.modified()?);
+            }
+            if written < before {
+                std::fs::remove_dir_all(run.path())?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// Longest prefix given to the output of a step, longer names overflow it
+const MAX_PREFIX_WIDTH: usize = 20;
+
+/// Stream a step printed a line on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// Prints the output of steps running side by side
+///
+/// Lines are prefixed with the name of their step, padded to the same width
+/// and in a color of its own that stays the same for the whole run. Grouped,
+/// the output of a step is held back and printed in one block once the step
+/// is over instead.
+#[derive(Debug, Default)]
+pub struct LogRouter {
+    /// Position of every step, which picks its color
+    colors: HashMap<String, usize>,
+    width: usize,
+    group: bool,
+    held: Mutex<HashMap<String, Vec<String>>>,
+}
+
+impl LogRouter {
+    pub fn new<'a>(steps: impl IntoIterator<Item = &'a str>) -> Self {
+        let colors: HashMap<String, usize> = steps.into_iter().enumerate().map(|(i, step)| (step.to_string(), i)).collect();
+        let width = colors.keys().map(|step| step.chars().count()).max().unwrap_or(0).min(MAX_PREFIX_WIDTH);
+
+        Self {
+            colors,
+            width,
+            ..Default::default()
+        }
+    }
+
+    /// The same router, holding the output of each step until it's over if
+    /// `group`
+    pub fn grouped(&self, group: bool) -> Self {
+        Self {
+            colors: self.colors.clone(),
+            width: self.width,
+            group,
+            held: Mutex::default(),
+        }
+    }
+
+    /// `line` with the prefix of `step`, lines printed on stderr stand out
+    pub fn format(&self, step: &str, stream: OutputStream, line: &str) -> String {
+        let color = self.colors.get(step).copied().unwrap_or(self.colors.len());
+        let prefix = format!("{:<width$} |", step.palette(color), width = self.width);
+        match stream {
+            OutputStream::Stdout => format!("{} {}", prefix, line),
+            OutputStream::Stderr => format!("{} {}", prefix, line.yellow()),
+        }
+    }
+
+    /// Print `line`, or hold it until `flush` when grouped
+    pub fn line(&self, step: &str, stream: OutputStream, line: &str) {
+        let line = self.format(step, stream, line);
+        match self.group {
+            true => self.held.lock().unwrap().entry(step.to_string()).or_default().push(line),
+            false => println!("{}", line),
+        }
+    }
+
+    /// Lines of `step` held back so far, forgetting them
+    pub fn take(&self, step: &str) -> Vec<String> {
+        self.held.lock().unwrap().remove(step).unwrap_or_default()
+    }
+
+    /// Print what `step` printed while it ran, in one block
+    pub fn flush(&self, step: &str) {
+        let lines = self.take(step);
+        if !lines.is_empty() {
+            println!("{}", lines.join("\n"));
+        }
+    }
+}
+
+/// Output of the commands run while provisioning a zone, kept within the
+/// default output limit like the output of a step
+#[derive(Debug)]
+pub struct ProvisionLog {
+    log: Mutex<StepLog>,
+}
+
+impl Default for ProvisionLog {
+    fn default() -> Self {
+        let limit = crate::zfs::size_bytes(DEFAULT_OUTPUT_LIMIT).expect("valid default output limit");
+        Self {
+            log: Mutex::new(StepLog::new(limit)),
+        }
+    }
+}
+
+impl ProvisionLog {
+    /// Keep the output of a command that already finished
+    pub fn push_output(&self, output: &str) {
+        let mut log = self.log.lock().unwrap();
+        for line in output.lines() {
+            log.push(line.to_string());
+        }
+    }
+
+    /// Run `command` in the zone, keeping what it prints
+    pub async fn exec(&self, pzone: &PipelineZone, command: impl AsRef<OsStr>) -> Result<ExitStatus> {
+        let mut child = pzone.exec(command)?;
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+
+        let (stdout, stderr) = tokio::join!(self.read(stdout), self.read(stderr));
+        stdout?;
+        stderr?;
+
+        Ok(child.wait().await?)
+    }
+
+    async fn read(&self, stream: impl AsyncRead + Unpin) -> Result<()> {
+        let mut lines = BufReader::new(stream).lines();
+        while let Some(line) = lines.next_line().await? {
+            self.log.lock().unwrap().push(line);
+        }
+        Ok(())
+    }
+
+    pub fn render(&self) -> String {
+        self.log.lock().unwrap().render()
+    }
+
+    pub fn truncated_bytes(&self) -> u64 {
+        self.log.lock().unwrap().truncated_bytes()
+    }
+}
@@ -0,0 +1,82 @@
+use crate::zones::PipelineZone;
+use anyhow::Result;
+use rand::distributions::Alphanumeric;
+use rand::{Rng, thread_rng};
+use std::time::Duration;
+
+/// Time the processes of a step get to exit on SIGTERM before they're sent
+/// SIGKILL
+pub const KILL_GRACE: Duration = Duration::from_secs(10);
+
+/// Command sending SIGTERM to every process of the contract whose id is in
+/// `contract_file`, and SIGKILL to those still there after `KILL_GRACE`
+pub fn kill_contract_command(contract_file: &str) -> String {
+    format!(
+        "ctid=$(cat {file}) && pkill -TERM -c $ctid; \
+         i=0; while [ $i -lt {grace} ] && pgrep -c $ctid > /dev/null; do sleep 1; i=$((i + 1)); done; \
+         pkill -KILL -c $ctid; rm -f {file}",
+        file = contract_file,
+        grace = KILL_GRACE.as_secs()
+    )
+}
+
+/// Processes a step started in its zone, tracked by the process contract
+/// zlogin runs the step in
+///
+/// Killing zlogin on the host leaves the processes in the zone running, they
+/// are stopped through their contract instead when the step times out. When
+/// dropped while the step still runs, e.g. once the run is cancelled, they are
+/// stopped in the background.
+#[derive(Debug)]
+pub struct StepProcesses<'a> {
+    pzone: &'a PipelineZone,
+    /// File in the zone holding the contract id, unique to this execution
+    contract_file: String,
+    running: bool,
+}
+
+impl<'a> StepProcesses<'a> {
+    pub fn new(pzone: &'a PipelineZone) -> Self {
+        let id: String = thread_rng().sample_iter(&Alphanumeric).take(8).map(char::from).collect();
+        Self {
+            pzone,
+            contract_file: format!("/var/tmp/renzokutai-step-{}.ctid", id),
+            running: true,
+        }
+    }
+
+    /// Command recording the contract of the step, to run before its script
+    pub fn track_command(&self) -> String {
+        format!("ps -o ctid= -p $$ > {}", self.contract_file)
+    }
+
+    /// Command sending SIGTERM to every process of the step, and SIGKILL to
+    /// those still there after the grace period
+    pub fn kill_command(&self) -> String {
+        kill_contract_command(&self.contract_file)
+    }
+
+    /// Stop the processes of the step and wait until they're gone
+    pub async fn kill(&mut self) -> Result<()> {
+        self.running = false;
+        self.pzone.exec(self.kill_command())?.wait().await?;
+        Ok(())
+    }
+
+    /// The step exited on its own, there's nothing left to stop
+    pub async fn finished(&mut self) -> Result<()> {
+        self.running = false;
+        self.pzone.exec(format!("rm -f {}", self.contract_file))?.wait().await?;
+        Ok(())
+    }
+}
+
+impl Drop for StepProcesses<'_> {
+    fn drop(&mut self) {
+        if self.running
+            && let Err(err) = self.pzone.exec_detached(self.kill_command())
+        {
+            eprintln!("Couldn't stop the processes of a cancelled step: {}", err);
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use anyhow::{Result, anyhow};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+/// Providers a step can install extra packages from
+pub const PROVIDERS: &[&str] = &["pkg", "pkgsrc"];
+
+/// Where pkgin keeps downloaded packages inside a pkgsrc zone
+pub const PKGIN_CACHE_DIR: &str = "/opt/local/var/db/pkgin/cache";
+
+/// A package installed into the run zone right before the step executes
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PackageRequirement {
+    #[serde(rename = "@provider")]
+    pub provider: String,
+    #[serde(rename = "@name")]
+    pub name: String,
+}
+
+impl PackageRequirement {
+    /// Parse a comma separated list of `provider:name` entries
+    pub fn parse_list(value: &str) -> Result<Vec<PackageRequirement>> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| match entry.split_once(':') {
+                Some((provider, name)) if PROVIDERS.contains(&provider) && !name.is_empty() => {
+                    Ok(PackageRequirement {
+                        provider: provider.to_string(),
+                        name: name.to_string(),
+                    })
+                }
+                Some((provider, _)) if !PROVIDERS.contains(&provider) => Err(anyhow!(
+                    "Unknown provider {}, expected one of {}",
+                    provider,
+                    PROVIDERS.join(", ")
+                )),
+                _ => Err(anyhow!("Expected provider:name, got {}", entry)),
+            })
+            .collect()
+    }
+
+    /// Commands installing `requirements`, one per provider
+    pub fn install_commands(requirements: &[PackageRequirement]) -> Vec<String> {
+        PROVIDERS
+            .iter()
+            .filter_map(|provider| {
+                let names = requirements
+                    .iter()
+                    .filter(|r| r.provider == *provider)
+                    .map(|r| crate::config::step::quote(&r.name))
+                    .join(" ");
+                if names.is_empty() {
+                    return None;
+                }
+
+                Some(match *provider {
+                    // Exit status 4 means everything was already installed
+                    "pkg" => format!("pkg install -q {} || [ $? -eq 4 ]", names),
+                    _ => format!("pkgin -y install {}", names),
+                })
+            })
+            .collect()
+    }
+}
+
+impl std::fmt::Display for PackageRequirement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.provider, self.name)
+    }
+}
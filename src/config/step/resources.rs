@@ -0,0 +1,63 @@
+use anyhow::{Result, anyhow};
+
+/// Resource controls a step runs under inside the zone
+///
+/// The memory limit caps the address space of every process of the step,
+/// which fails their allocations instead of letting the zone run out of
+/// memory and take parallel steps down with it. The CPU limit caps the
+/// step's project at that many CPUs.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Resources {
+    /// Bytes of address space of each process
+    pub memory: Option<u64>,
+    /// `project.cpu-cap`, 100 per CPU
+    pub cpu_cap: Option<u64>,
+}
+
+impl Resources {
+    /// Parse a CPU limit in CPUs like `2` or `0.5` into a `project.cpu-cap`
+    pub fn parse_cpu_limit(value: &str) -> Result<u64> {
+        match value.parse::<f64>() {
+            Ok(cpus) if cpus.is_finite() && cpus >= 0.01 => Ok((cpus * 100.0).round() as u64),
+            _ => Err(anyhow!("Invalid CPU limit {}, expected a number of CPUs like 2 or 0.5", value)),
+        }
+    }
+
+    /// Wrap `command` so it runs within the limits, `step` names the
+    /// project capping its CPU
+    pub fn wrap(&self, step: &str, command: String) -> String {
+        let command = match self.memory {
+            Some(bytes) => format!(
+                "prctl -n process.max-address-space -t privileged -v {} -e deny -i process $$ && {}",
+                bytes, command
+            ),
+            None => command,
+        };
+
+        match self.cpu_cap {
+            Some(cap) => {
+                let project = project_name(step);
+                let control = format!("'project.cpu-cap=(privileged,{},deny)'", cap);
+                format!(
+                    "(projmod -s -K {control} {project} 2>/dev/null || projadd -K {control} {project}) \
+                     && newtask -p {project} sh -c {}",
+                    quote(&command)
+                )
+            }
+            None => command,
+        }
+    }
+}
+
+/// Project of a step, project names only take letters, digits, `_` and `-`
+fn project_name(step: &str) -> String {
+    let step: String = step
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' })
+        .collect();
+    format!("renzokutai_{}", step)
+}
+
+pub(crate) fn quote(command: &str) -> String {
+    format!("'{}'", command.replace('\'', r"'\''"))
+}
@@ -1,16 +1,34 @@
-use crate::config::ValidatedStep;
-use anyhow::Result;
-use futures::stream::{self, StreamExt};
-use owo_colors::OwoColorize;
-use std::collections::HashSet;
+use crate::config::{ArtifactNeed, ArtifactPath, LogRouter, OutputOverflow, OutputStream, PackageRequirement, RunContext, Secret, StepLog, StepLogFile, StepProcesses, ValidatedStep, fingerprint, library};
+use crate::config::step::quote;
+use crate::history::{History, RunTag, StepResultRecord, StepState, StepTransition};
+use crate::zones::ZoneType;
+use anyhow::{Result, anyhow};
+use crate::color::Colorize;
+use std::collections::{HashMap, HashSet};
 use std::io::{Stderr, Stdout};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::sync::RwLock;
+use tokio::sync::{RwLock, Semaphore};
+
+/// Steps tag their run by printing `::tag key=value` on stdout
+pub const TAG_COMMAND: &str = "::tag ";
 
 #[derive(Debug, Default)]
 pub struct StepResult {
     status: Status,
+    /// Tags output by the step
+    tags: Vec<RunTag>,
+    /// Output printed by the step, within its output limit
+    log: StepLog,
+    /// States the step went through, to replay its run
+    transitions: Vec<StepTransition>,
+    /// Error of every attempt, `None` for the one that succeeded
+    attempts: Vec<Option<String>>,
+    /// Base run and fingerprint of the inputs of the step, when it declares any
+    fingerprint: Option<(String, String)>,
+    /// Exit code of the script in the last attempt, when it exited on its own
+    exit_code: Option<i32>,
     stdout: Option<BufReader<Stdout>>,
     stderr: Option<BufReader<Stderr>>,
 }
@@ -21,13 +39,15 @@ impl PartialEq for StepResult {
     }
 }
 
-#[derive(Debug, PartialEq, Default)]
+#[derive(Debug, Clone, PartialEq, Default)]
 pub enum Status {
     #[default]
     Pending,
     Running,
     Failed,
     Finished,
+    /// Not run, its condition or that of a step it depends on didn't hold
+    Skipped,
 }
 
 #[derive(Debug, PartialEq)]
@@ -39,44 +59,412 @@ pub struct InnerRunnableStep {
 pub type RunnableStep = Arc<RwLock<InnerRunnableStep>>;
 
 impl InnerRunnableStep {
-    pub fn is_available(&self, finished_steps: &HashSet<String>) -> bool {
-        self.step.is_available(finished_steps) && self.result.status == Status::Pending
+    /// Copy the artifacts this step needs from its dependencies into its
+    /// staging directory, which its user is let into
+    pub async fn stage_artifacts(&self, pzone: &crate::zones::PipelineZone) -> Result<()> {
+        for need in self.step.needs_artifacts.iter() {
+            let target = quote(&format!("{}/{}", ArtifactNeed::staging_dir(&self.step.name), need.step));
+            let source = quote(&format!("{}/{}", self.step.workdir(), need.path));
+            let status = pzone
+                .exec(format!("mkdir -p {target} && cp -R {source} {target}/", target = target, source = source))?
+                .wait()
+                .await?;
+
+            if !status.success() {
+                return Err(anyhow!("Couldn't stage artifact {} for step {}", need, self.step.name));
+            }
+        }
+
+        if self.step.user.is_some() && !self.step.needs_artifacts.is_empty() {
+            let staging_dir = ArtifactNeed::staging_dir(&self.step.name);
+            let status = pzone.exec(crate::zones::share_command(&[&staging_dir]))?.wait().await?;
+            if !status.success() {
+                return Err(anyhow!("Couldn't share the artifacts staged for step {}", self.step.name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fingerprint of the inputs of the step in the zone it runs in
+    pub async fn fingerprint(&self, pzone: &crate::zones::PipelineZone) -> Result<String> {
+        let output = match &self.step.fingerprint_command {
+            Some(command) => {
+                let output = pzone
+                    .exec(self.step.as_user(format!("cd {} && {}", quote(self.step.workdir()), command)))?
+                    .wait_with_output()
+                    .await?;
+                if !output.status.success() {
+                    return Err(anyhow!("Fingerprint command of step {} failed", self.step.name));
+                }
+                Some(output.stdout)
+            }
+            None => None,
+        };
+
+        fingerprint::fingerprint(&self.step, &pzone.host_path(""), output.as_deref())
+    }
+
+    /// Install the packages this step requires into the zone it runs in
+    pub async fn install_requirements(&self, pzone: &crate::zones::PipelineZone) -> Result<()> {
+        for command in PackageRequirement::install_commands(&self.step.requires) {
+            println!("Step {} installing packages: {}", self.step.name.cyan(), command.yellow());
+            let status = pzone.exec(command)?.wait().await?;
+
+            if !status.success() {
+                return Err(anyhow!("Couldn't install packages required by step {}", self.step.name));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run the step, stopping it once it ran longer than its own timeout or
+    /// else `timeout`, its output printed through `output`
+    pub async fn run(&mut self, pzone: &crate::zones::PipelineZone, timeout: Option<Duration>, output: &LogRouter) -> Result<()> {
+        self.result.status = Status::Running;
+        let timeout = self.step.timeout(timeout)?;
+        self.stage_artifacts(pzone).await?;
+        self.restore_consumed(pzone)?;
+        self.install_requirements(pzone).await?;
+
+        let mut installed = 0;
+        let mut result = Ok(());
+        for secret in self.step.secret_files.iter() {
+            // One that failed may still have been partly written
+            installed += 1;
+            result = secret.install(pzone).await;
+            if result.is_err() {
+                break;
+            }
+        }
+
+        if result.is_ok() {
+            result = self.execute(pzone, timeout, output).await;
+        }
+
+        // Every secret written is shredded, whatever happened to the others
+        for secret in self.step.secret_files.iter().take(installed) {
+            let shredded = secret.shred(pzone).await;
+            result = result.and(shredded);
+        }
+
+        result
+    }
+
+    /// Run the step, marking it as failed when it couldn't complete
+    pub async fn run_or_fail(
+        &mut self,
+        pzone: &crate::zones::PipelineZone,
+        timeout: Option<Duration>,
+        output: &LogRouter,
+    ) -> Result<()> {
+        self.transition(StepState::Running);
+        let result = self.run(pzone, timeout, output).await;
+        output.flush(&self.step.name);
+        if result.is_err() {
+            self.result.status = Status::Failed;
+        }
+        self.transition(if result.is_ok() { StepState::Succeeded } else { StepState::Failed });
+        self.result.attempts.push(result.as_ref().err().map(|e| e.to_string()));
+        result
+    }
+
+    /// Run the step, running it again after a delay while it fails and has
+    /// retries left
+    pub async fn run_with_retries(
+        &mut self,
+        pzone: &crate::zones::PipelineZone,
+        timeout: Option<Duration>,
+        output: &LogRouter,
+    ) -> Result<()> {
+        let retries = self.step.retries.unwrap_or(0);
+
+        loop {
+            let result = self.run_or_fail(pzone, timeout, output).await;
+            let attempt = self.result.attempts.len() as u32;
+            match result {
+                Err(err) if attempt <= retries => {
+                    let delay = self.step.retry_delay(attempt)?;
+                    println!(
+                        "Step {} {} ({}), retrying in {} ({}/{})",
+                        self.step.name.cyan(),
+                        "FAILED".red(),
+                        err,
+                        crate::policy::format_duration(delay),
+                        attempt,
+                        retries
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                result => {
+                    let collected = self.collect_artifacts(pzone);
+                    return match result {
+                        Ok(_) => self.stage_produced(pzone).and(collected),
+                        Err(err) => Err(err),
+                    };
+                }
+            }
+        }
+    }
+
+    /// Copy the artifacts of the step out of its run zone into the directory
+    /// of the run on the host
+    fn collect_artifacts(&self, pzone: &crate::zones::PipelineZone) -> Result<()> {
+        let ZoneType::Run(run) = &pzone.zone_type else {
+            return Ok(());
+        };
+        if self.step.artifacts.is_empty() {
+            return Ok(());
+        }
+
+        let target = ArtifactPath::collected_dir(run, &self.step.name);
+        let collected = ArtifactPath::collect(
+            &self.step.artifacts.paths,
+            &pzone.host_path(""),
+            self.step.workdir(),
+            &target,
+        )
+            .map_err(|e| anyhow!("Couldn't collect the artifacts of step {}: {}", self.step.name, e))?;
+        match collected.len() {
+            0 => println!("Step {} {}: no artifacts matched", self.step.name.cyan(), "WARNING".yellow()),
+            count => println!("Step {} collected {} artifacts into {}", self.step.name.cyan(), count, target.display()),
+        }
+        Ok(())
+    }
+
+    /// Copy what the step produces out of its run zone into the staging of
+    /// the run on the host, failing when any of it is missing
+    fn stage_produced(&self, pzone: &crate::zones::PipelineZone) -> Result<()> {
+        let ZoneType::Run(run) = &pzone.zone_type else {
+            return Ok(());
+        };
+
+        let target = ArtifactPath::produced_dir(run, &self.step.name);
+        for produced in self.step.produces.iter() {
+            let staged = ArtifactPath::collect(
+                std::slice::from_ref(produced),
+                &pzone.host_path(""),
+                self.step.workdir(),
+                &target,
+            )
+            .map_err(|e| anyhow!("Couldn't stage what step {} produces: {}", self.step.name, e))?;
+            if staged.is_empty() {
+                return Err(anyhow!("Step {} didn't produce {}", self.step.name, produced));
+            }
+        }
+        Ok(())
     }
 
-    pub async fn run(&mut self, pzone: &crate::zones::PipelineZone) -> Result<()> {
-        self.result.status = Status::Pending;
-        let mut child = pzone.exec(format!(
-            ". ~/.profile && cd ./renzokutai/ && /usr/bin/sh -x ./{}",
-            self.step.script
-        ))?;
+    /// Copy what the steps this one consumes from produced into its zone
+    fn restore_consumed(&self, pzone: &crate::zones::PipelineZone) -> Result<()> {
+        let ZoneType::Run(run) = &pzone.zone_type else {
+            return Ok(());
+        };
+
+        for producer in self.step.producers.iter() {
+            let staged = ArtifactPath::produced_dir(run, producer);
+            if staged.exists() {
+                ArtifactPath::restore(&staged, &pzone.host_path(""))
+                    .map_err(|e| anyhow!("Couldn't restore what step {} produced: {}", producer, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Error of every attempt at running the step, `None` for the one that
+    /// succeeded
+    pub fn attempts(&self) -> &[Option<String>] {
+        &self.result.attempts
+    }
+
+    pub fn status(&self) -> &Status {
+        &self.result.status
+    }
+
+    /// Exit code of the script in the last attempt, when it exited on its own
+    pub fn exit_code(&self) -> Option<i32> {
+        self.result.exit_code
+    }
+
+    fn transition(&mut self, state: StepState) {
+        let log_lines = self
+            .result
+            .transitions
+            .iter()
+            .map(|t| t.log_lines as u64)
+            .fold(self.result.log.lines(), u64::max);
+        self.result
+            .transitions
+            .push(StepTransition::new(&self.step.name, state, log_lines));
+    }
+
+    async fn execute(&mut self, pzone: &crate::zones::PipelineZone, timeout: Option<Duration>, output: &LogRouter) -> Result<()> {
+        let overflow = self.step.on_output_limit()?;
+        let log = Mutex::new(StepLog::new(self.step.output_limit()?));
+        self.result.exit_code = None;
+        let log_file = match &pzone.zone_type {
+            ZoneType::Run(run) => Some(StepLogFile::open(&StepLogFile::path(&pzone.pipeline, run, &self.step.name))?),
+            ZoneType::Base | ZoneType::Candidate => None,
+        };
+        // Stop reading once over the limit when that fails the step
+        let keep_reading = || overflow == OutputOverflow::Truncate || !log.lock().unwrap().exceeded();
+        let transitions = Mutex::new(Vec::new());
+        // One transition a second is enough to replay the pace of the output
+        let progress = || {
+            let transition = StepTransition::new(&self.step.name, StepState::Output, log.lock().unwrap().lines());
+            let mut transitions = transitions.lock().unwrap();
+            if transitions.last().is_none_or(|last: &StepTransition| last.at / 1000 < transition.at / 1000) {
+                transitions.push(transition);
+            }
+        };
+
+        let mut exports: String = self
+            .step
+            .env
+            .iter()
+            .map(|e| format!("{} && ", e.export_command()))
+            .collect();
+        if !self.step.secrets.is_empty() {
+            let path = Secret::zone_path(&self.step.name);
+            let install = Secret::install_command(&path, self.step.user.as_deref());
+            pzone.exec_with_input(install, Secret::exports(&self.step.secrets).as_bytes()).await?;
+            exports.push_str(&format!("{} && ", Secret::source_command(&path)));
+        }
+        let command = self.step.as_user(format!(
+            "export RENZOKUTAI_ARTIFACTS={} && {}cd {} && {}",
+            quote(&ArtifactNeed::staging_dir(&self.step.name)),
+            exports,
+            quote(self.step.workdir()),
+            library::script_command(&self.step.script, self.step.shell()?)?
+        ));
+        let mut processes = StepProcesses::new(pzone);
+        let command = self.step.resources()?.wrap(&self.step.name, command);
+        let mut child = pzone.exec(format!("{} && {}", processes.track_command(), command))?;
 
         let stdout = child.stdout.take().unwrap();
         let stderr = child.stderr.take().unwrap();
 
         let mut stdout_reader = BufReader::new(stdout).lines();
         let mut stderr_reader = BufReader::new(stderr).lines();
+        let tags = Mutex::new(Vec::new());
+        let mut timed_out = false;
+        let mut exit_status = None;
+        let deadline = timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+        // Wakes the wait below once a reader stops over the output limit
+        let exceeded = tokio::sync::Notify::new();
 
-        // TODO(Marce): Save into the DB
-        tokio::select! {
-            _result = async {
-                while let Some(line) = stdout_reader.next_line().await? {
-                    println!("stdout({}): {}", self.step.name.cyan(), line);
+        let read_stdout = async {
+            while let Some(line) = stdout_reader.next_line().await? {
+                let line = Secret::redact(&line, &self.step.secrets);
+                output.line(&self.step.name, OutputStream::Stdout, &line);
+                if let Some(file) = &log_file {
+                    file.push("stdout", &line);
+                }
+                if let Some(tag) = line.strip_prefix(TAG_COMMAND) {
+                    match tag.parse::<RunTag>() {
+                        Ok(tag) => tags.lock().unwrap().push(tag),
+                        Err(err) => output.line(&self.step.name, OutputStream::Stderr, &err.to_string()),
+                    }
+                }
+                log.lock().unwrap().push(line);
+                progress();
+                if !keep_reading() {
+                    exceeded.notify_one();
+                    break;
                 }
-                Ok::<(), Box<dyn std::error::Error>>(())
-            } => { },
+            }
+            Ok::<(), std::io::Error>(())
+        };
+        let read_stderr = async {
+            while let Some(line) = stderr_reader.next_line().await? {
+                let line = Secret::redact(&line, &self.step.secrets);
+                output.line(&self.step.name, OutputStream::Stderr, &line);
+                if let Some(file) = &log_file {
+                    file.push("stderr", &line);
+                }
+                log.lock().unwrap().push(line);
+                progress();
+                if !keep_reading() {
+                    exceeded.notify_one();
+                    break;
+                }
+            }
+            Ok::<(), std::io::Error>(())
+        };
+        // Either stream can close before the other, the step is over once both
+        // are drained and the script exited
+        let finished = async {
+            let (stdout, stderr) = tokio::join!(read_stdout, read_stderr);
+            stdout.and(stderr)?;
+            child.wait().await
+        };
+
+        tokio::select! {
+            status = finished => {
+                let status = status.map_err(|e| anyhow!("Couldn't read the output of step {}: {}", self.step.name, e))?;
+                exit_status = Some(status);
+            }
+
+            _ = exceeded.notified() => {}
 
-            _result = async {
-                while let Some(line) = stderr_reader.next_line().await? {
-                    println!("stderr({}): {}", self.step.name.cyan(), line.yellow());
+            _ = async {
+                match deadline {
+                    Some(deadline) => tokio::time::sleep_until(deadline).await,
+                    None => std::future::pending().await,
                 }
-                Ok::<(), Box<dyn std::error::Error>>(())
-            } => { },
+            } => {
+                timed_out = true;
+            }
+        }
+
+        self.result.transitions.extend(transitions.into_inner().unwrap());
 
-            _status = child.wait() => {
-                println!("Step {} {}", self.step.name, "DONE".green());
+        if let (true, Some(timeout)) = (timed_out, timeout) {
+            // zlogin is killed even when the processes in the zone couldn't be
+            let killed = processes.kill().await;
+            child.start_kill()?;
+            killed?;
+            return Err(anyhow!(
+                "Step {} timed out after {}",
+                self.step.name,
+                crate::policy::format_duration(timeout)
+            ));
+        }
+
+        self.result.tags = tags.into_inner().unwrap();
+        self.result.log = log.into_inner().unwrap();
+
+        if self.result.log.exceeded() {
+            println!(
+                "Step {} {}: output over {}, {} bytes truncated",
+                self.step.name.cyan(),
+                "WARNING".yellow(),
+                self.step.output_limit.as_deref().unwrap_or(crate::config::DEFAULT_OUTPUT_LIMIT),
+                self.result.log.truncated_bytes()
+            );
+            if overflow == OutputOverflow::Fail {
+                let killed = processes.kill().await;
+                child.start_kill()?;
+                killed?;
+                return Err(anyhow!("Step {} exceeded its output limit", self.step.name));
             }
         }
 
+        let status = match exit_status {
+            Some(status) => status,
+            None => child.wait().await?,
+        };
+        processes.finished().await?;
+        self.result.exit_code = status.code();
+        if !status.success() {
+            self.result.status = Status::Failed;
+            return Err(match status.code() {
+                Some(code) => anyhow!("Step {} exited with code {}", self.step.name, code),
+                None => anyhow!("Step {} was killed by a signal", self.step.name),
+            });
+        }
+
+        println!("Step {} {}", self.step.name, "DONE".green());
         self.result.status = Status::Finished;
         Ok(())
     }
@@ -84,72 +472,387 @@ impl InnerRunnableStep {
 
 pub struct RunnableSteps {
     pub steps: Vec<RunnableStep>,
+    /// Stages of the steps in the order they run, empty without stages
+    pub stages: Vec<String>,
+    /// Where the steps print their output
+    pub output: Arc<LogRouter>,
 }
 
 impl RunnableSteps {
-    /// Run available steps until completion of the Step Set
-    pub async fn run(&mut self, pzone: &crate::zones::PipelineZone) -> Result<()> {
-        let mut set = tokio::task::JoinSet::new();
+    /// Print the output of each step in one block once it's over if `group`,
+    /// rather than as it comes
+    pub fn with_grouped_output(mut self, group: bool) -> Self {
+        self.output = Arc::new(self.output.grouped(group));
+        self
+    }
+
+    /// Mark the steps whose `when` condition doesn't hold in `context` as
+    /// skipped, along with every step depending on a skipped one
+    pub async fn skip(&mut self, context: &RunContext) -> Result<()> {
+        let mut skipped = HashSet::new();
+        for step in self.steps.iter() {
+            let step = step.read().await;
+            if !step.step.runs_in(context)? {
+                skipped.insert(step.step.name.clone());
+            }
+        }
 
         loop {
-            match self.unblocked_steps().await {
-                Some(mut steps) => {
-                    for step in steps.drain(..) {
-                        let cloned_pzone = pzone.clone();
-                        set.spawn(async move { step.write().await.run(&cloned_pzone).await });
-                    }
+            let mut dependents = Vec::new();
+            for step in self.steps.iter() {
+                let step = step.read().await;
+                if !skipped.contains(&step.step.name) && step.step.depends.iter().any(|d| skipped.contains(&d.name)) {
+                    dependents.push(step.step.name.clone());
                 }
-                None => (),
             }
+            if dependents.is_empty() {
+                break;
+            }
+            skipped.extend(dependents);
+        }
 
-            match set.join_next().await {
-                Some(res) => println!("Step {}", "DONE".green()),
-                None => break,
+        for step in self.steps.iter() {
+            let mut step = step.write().await;
+            if skipped.contains(&step.step.name) {
+                println!("Step {} {}", step.step.name.cyan(), "SKIPPED".yellow());
+                step.result.status = Status::Skipped;
+                step.transition(StepState::Skipped);
             }
         }
 
         Ok(())
     }
 
-    async fn unblocked_steps(&mut self) -> Option<Vec<RunnableStep>> {
-        let remaining: Vec<_> = stream::iter(&self.steps)
-            .filter_map(async |s| {
-                let inner = s.read().await;
-                match inner.result.status {
-                    Status::Pending | Status::Running => Some(s.clone()),
-                    _ => None,
+    /// Fingerprint the steps declaring their inputs, and instead of running
+    /// again those whose fingerprint matches a run of `pipeline` where they
+    /// succeeded on the same `base`, restore the artifacts of that run. A step
+    /// is only reused along with every step it depends on, as those could
+    /// change its inputs otherwise
+    pub async fn reuse(
+        &mut self,
+        pzone: &crate::zones::PipelineZone,
+        history: &History,
+        pipeline: &str,
+        base: &str,
+    ) -> Result<()> {
+        let ZoneType::Run(run) = &pzone.zone_type else {
+            return Ok(());
+        };
+
+        let mut reusable = HashMap::new();
+        for step in self.steps.iter() {
+            let mut step = step.write().await;
+            if step.result.status != Status::Pending || step.step.fingerprint.is_none() {
+                continue;
+            }
+
+            let fingerprint = step.fingerprint(pzone).await?;
+            let previous = history
+                .matching_fingerprint(pipeline, &step.step.name, base, &fingerprint)
+                .await?;
+            step.result.fingerprint = Some((base.to_string(), fingerprint));
+            // Artifacts of the previous run may have been cleaned up since
+            let collected = |run: &String| ArtifactPath::collected_dir(run, &step.step.name).exists();
+            let produced = |run: &String| ArtifactPath::produced_dir(run, &step.step.name).exists();
+            let kept = |run: &String| {
+                (step.step.artifacts.is_empty() || collected(run)) && (step.step.produces.is_empty() || produced(run))
+            };
+            if let Some(previous) = previous.filter(kept) {
+                reusable.insert(step.step.name.clone(), previous);
+            }
+        }
+
+        loop {
+            let mut rerun = Vec::new();
+            for step in self.steps.iter() {
+                let step = step.read().await;
+                if reusable.contains_key(&step.step.name)
+                    && step.step.depends.iter().any(|d| !reusable.contains_key(&d.name))
+                {
+                    rerun.push(step.step.name.clone());
                 }
-            })
-            .collect()
-            .await;
-
-        if remaining.is_empty() {
-            None
-        } else {
-            let completed = stream::iter(&self.steps)
-                .filter_map(async |s| {
-                    let s = s.read().await;
-                    match s.result.status {
-                        Status::Finished => Some(s.step.name.clone()),
-                        _ => None,
-                    }
-                })
-                .collect()
-                .await;
+            }
+            if rerun.is_empty() {
+                break;
+            }
+            for name in rerun {
+                reusable.remove(&name);
+            }
+        }
 
-            let available: Vec<_> = stream::iter(remaining)
-                .filter_map(async |s| match s.read().await.is_available(&completed) {
-                    true => Some(s.clone()),
-                    false => None,
-                })
-                .collect()
-                .await;
+        for step in self.steps.iter() {
+            let mut step = step.write().await;
+            let Some(previous) = reusable.get(&step.step.name) else {
+                continue;
+            };
 
-            if available.is_empty() {
-                None
-            } else {
-                Some(available)
+            let collected = ArtifactPath::collected_dir(previous, &step.step.name);
+            if collected.exists() {
+                ArtifactPath::restore(&collected, &pzone.host_path(""))
+                    .map_err(|e| anyhow!("Couldn't restore the artifacts of step {}: {}", step.step.name, e))?;
+                ArtifactPath::copy_collected(previous, run, &step.step.name)?;
             }
+            ArtifactPath::copy_produced(previous, run, &step.step.name)?;
+            println!("Step {} {} from run {}", step.step.name.cyan(), "REUSED".green(), previous);
+            step.result.status = Status::Finished;
+            step.transition(StepState::Reused);
         }
+
+        Ok(())
+    }
+
+    /// Position of the stage of `step` in `stages`, steps without a stage run
+    /// in the first one
+    fn stage_index(&self, step: &ValidatedStep) -> usize {
+        step.stage
+            .as_ref()
+            .and_then(|stage| self.stages.iter().position(|s| s == stage))
+            .unwrap_or(0)
+    }
+
+    /// Run available steps until completion of the Step Set, at most
+    /// `max_parallel` of them at the same time, and a stage only once every
+    /// step of the previous ones finished or was skipped
+    ///
+    /// Steps that always run are left for `run_always`. Once a step fails,
+    /// the steps that don't depend on it keep running, unless `fail_fast`
+    /// where those in flight are stopped and no other starts.
+    pub async fn run(
+        &mut self,
+        pzone: &crate::zones::PipelineZone,
+        step_timeout: Option<Duration>,
+        max_parallel: Option<u32>,
+        fail_fast: bool,
+    ) -> Result<()> {
+        self.run_phase(pzone, step_timeout, max_parallel, false, fail_fast).await
+    }
+
+    /// Run the steps that always run, once `run` is over whether it
+    /// succeeded or not, their dependencies on the other steps being met
+    /// whatever became of those
+    pub async fn run_always(
+        &mut self,
+        pzone: &crate::zones::PipelineZone,
+        step_timeout: Option<Duration>,
+        max_parallel: Option<u32>,
+    ) -> Result<()> {
+        self.run_phase(pzone, step_timeout, max_parallel, true, false).await
+    }
+
+    /// Run the steps that always run if `always`, the others otherwise
+    async fn run_phase(
+        &mut self,
+        pzone: &crate::zones::PipelineZone,
+        step_timeout: Option<Duration>,
+        max_parallel: Option<u32>,
+        always: bool,
+        fail_fast: bool,
+    ) -> Result<()> {
+        let mut set = tokio::task::JoinSet::new();
+        let mut tasks = HashMap::new();
+        let mut failure = None;
+        let permits = max_parallel.map_or(Semaphore::MAX_PERMITS, |max| max as usize);
+        let semaphore = Arc::new(Semaphore::new(permits));
+        let mut started_stage = None;
+
+        // A running step holds its lock until it ends, so what the scheduler
+        // knows of each step is kept here rather than read from it
+        let mut scheduled = Vec::new();
+        for step in self.steps.iter() {
+            let step = step.read().await;
+            scheduled.push((step.step.clone(), step.result.status.clone()));
+        }
+
+        loop {
+            for index in self.unblocked_steps(always, &scheduled) {
+                // Steps left out wait for a running one to finish
+                let Ok(permit) = semaphore.clone().try_acquire_owned() else {
+                    break;
+                };
+                // Not picked again while it runs
+                scheduled[index].1 = Status::Running;
+                let step = self.steps[index].clone();
+                step.write().await.result.status = Status::Running;
+                let stage = self.stage_index(&scheduled[index].0);
+                if !self.stages.is_empty() && started_stage != Some(stage) {
+                    println!("Stage {}", self.stages[stage].bold());
+                    started_stage = Some(stage);
+                }
+                let cloned_pzone = pzone.clone();
+                let output = self.output.clone();
+                let task = set.spawn(async move {
+                    let _permit = permit;
+                    step.write().await.run_with_retries(&cloned_pzone, step_timeout, &output).await
+                });
+                tasks.insert(task.id(), index);
+            }
+
+            let joined = set.join_next_with_id().await;
+            let id = match &joined {
+                Some(Ok((id, _))) => *id,
+                Some(Err(err)) => err.id(),
+                None => break,
+            };
+            // The step is over, its lock free again
+            if let Some(index) = tasks.remove(&id) {
+                scheduled[index].1 = self.steps[index].read().await.result.status.clone();
+            }
+
+            match joined {
+                Some(Ok((_, Ok(_)))) => println!("Step {}", "DONE".green()),
+                Some(Ok((_, Err(err)))) => {
+                    println!("Step {}: {}", "FAILED".red(), err);
+                    failure.get_or_insert(err);
+                }
+                Some(Err(err)) => {
+                    failure.get_or_insert(anyhow!(err));
+                }
+                None => break,
+            }
+
+            if fail_fast && failure.is_some() {
+                // Dropping the steps in flight stops their processes
+                set.abort_all();
+                while set.join_next().await.is_some() {}
+                self.fail_stopped(always).await;
+                break;
+            }
+        }
+
+        if failure.is_some() {
+            let not_run = self.pending(always).await;
+            if !not_run.is_empty() {
+                println!("Steps not run after a failure: {}", not_run.join(", ").yellow());
+            }
+        }
+
+        match failure {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Mark the steps of a phase stopped as another one failed, those that
+    /// always run if `always`, as failed
+    async fn fail_stopped(&mut self, always: bool) {
+        for step in self.steps.iter() {
+            let mut step = step.write().await;
+            if step.step.always_runs() == always && step.result.status == Status::Running {
+                // Its output was held back until it ended, grouped
+                self.output.flush(&step.step.name);
+                println!("Step {} {}: stopped as another step failed", step.step.name.cyan(), "FAILED".red());
+                step.result.status = Status::Failed;
+                step.transition(StepState::Failed);
+            }
+        }
+    }
+
+    /// Names of the steps of a phase that didn't start, those that always run
+    /// if `always`
+    async fn pending(&self, always: bool) -> Vec<String> {
+        let mut pending = Vec::new();
+        for step in self.steps.iter() {
+            let step = step.read().await;
+            if step.step.always_runs() == always && step.result.status == Status::Pending {
+                pending.push(step.step.name.clone());
+            }
+        }
+        pending
+    }
+
+    /// Mark the steps of a phase cut short by a timeout or Ctrl-C as failed,
+    /// those that always run if `always`, both the ones running and those left
+    /// waiting
+    ///
+    /// Only an interrupted phase leaves steps running, one that ran to its end
+    /// is kept as is.
+    pub async fn fail_interrupted(&mut self, always: bool) {
+        let mut interrupted = false;
+        for step in self.steps.iter() {
+            let step = step.read().await;
+            interrupted |= step.step.always_runs() == always && step.result.status == Status::Running;
+        }
+        if !interrupted {
+            return;
+        }
+
+        for step in self.steps.iter() {
+            let mut step = step.write().await;
+            if step.step.always_runs() == always && matches!(step.result.status, Status::Pending | Status::Running) {
+                self.output.flush(&step.step.name);
+                println!("Step {} {}: interrupted", step.step.name.cyan(), "FAILED".red());
+                step.result.status = Status::Failed;
+                step.transition(StepState::Failed);
+            }
+        }
+    }
+
+    /// Save the tags, output and transitions of every step that ran into the
+    /// history of `run_id`
+    pub async fn record(&self, history: &History, run_id: &str) -> Result<()> {
+        history.add_tags(run_id, &self.tags().await).await?;
+        for step in self.steps.iter() {
+            let step = step.read().await;
+            if step.result.status == Status::Pending {
+                continue;
+            }
+
+            if step.result.status != Status::Skipped {
+                let log = &step.result.log;
+                history
+                    .add_step_log(run_id, &step.step.name, &log.render(), log.truncated_bytes())
+                    .await?;
+            }
+            history.add_transitions(run_id, &step.result.transitions).await?;
+            let result =
+                StepResultRecord::from_transitions(run_id, &step.step.name, &step.result.transitions, step.result.exit_code);
+            if let Some(result) = result {
+                history.add_step_result(&result).await?;
+            }
+            if let (Status::Finished, Some((base, fingerprint))) = (&step.result.status, &step.result.fingerprint) {
+                history.add_fingerprint(run_id, &step.step.name, base, fingerprint).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Tags output by every step that ran
+    pub async fn tags(&self) -> Vec<RunTag> {
+        let mut tags = Vec::new();
+        for step in self.steps.iter() {
+            tags.extend(step.read().await.result.tags.iter().cloned());
+        }
+        tags
+    }
+
+    /// Indices of the steps of the phase that can start, those that always
+    /// run if `always`, going by the steps and statuses in `scheduled`
+    fn unblocked_steps(&self, always: bool, scheduled: &[(ValidatedStep, Status)]) -> Vec<usize> {
+        let completed: HashSet<String> = scheduled
+            .iter()
+            // Steps that always run don't wait on the others to succeed
+            .filter(|(step, status)| *status == Status::Finished || (always && !step.always_runs()))
+            .map(|(step, _)| step.name.clone())
+            .collect();
+
+        // Steps of later stages wait for the earliest one with steps left,
+        // failed steps included
+        let current_stage = scheduled
+            .iter()
+            .filter(|(step, status)| step.always_runs() == always && !matches!(status, Status::Finished | Status::Skipped))
+            .map(|(step, _)| self.stage_index(step))
+            .min();
+
+        scheduled
+            .iter()
+            .enumerate()
+            .filter(|(_, (step, status))| {
+                step.always_runs() == always
+                    && *status == Status::Pending
+                    && step.is_available(&completed)
+                    && Some(self.stage_index(step)) == current_stage
+            })
+            .map(|(index, _)| index)
+            .collect()
     }
 }
@@ -0,0 +1,103 @@
+use crate::config::step::quote;
+use crate::zones::PipelineZone;
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+const DEFAULT_MODE: &str = "0600";
+
+/// A secret on the host that is written into the zone only while a step runs
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SecretFile {
+    /// Path of the secret on the host
+    #[serde(rename = "@source")]
+    pub source: String,
+    /// Absolute path inside the zone
+    #[serde(rename = "@target")]
+    pub target: String,
+    /// Octal file mode
+    #[serde(rename = "@mode")]
+    pub mode: String,
+}
+
+impl SecretFile {
+    /// Parse a comma separated list of `source:target[:mode]` entries
+    pub fn parse_list(value: &str) -> Result<Vec<SecretFile>> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let secret = match entry.split(':').collect::<Vec<_>>()[..] {
+                    [source, target] => SecretFile {
+                        source: source.to_string(),
+                        target: target.to_string(),
+                        mode: DEFAULT_MODE.to_string(),
+                    },
+                    [source, target, mode] => SecretFile {
+                        source: source.to_string(),
+                        target: target.to_string(),
+                        mode: mode.to_string(),
+                    },
+                    _ => return Err(anyhow!("Expected source:target[:mode], got {}", entry)),
+                };
+                secret.validate()?;
+                Ok(secret)
+            })
+            .collect()
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !self.target.starts_with('/') {
+            return Err(anyhow!("Secret target must be an absolute path: {}", self.target));
+        }
+        self.mode()?;
+        Ok(())
+    }
+
+    fn mode(&self) -> Result<u32> {
+        u32::from_str_radix(&self.mode, 8).map_err(|_| anyhow!("Invalid file mode: {}", self.mode))
+    }
+
+    /// Copy the secret into the zone, through a command run in the zone so
+    /// links in its filesystem can't point the write at the host
+    pub async fn install(&self, pzone: &PipelineZone) -> Result<()> {
+        let contents = fs::read(&self.source)
+            .map_err(|e| anyhow!("Couldn't read secret {}: {}", self.source, e))?;
+        pzone.exec_with_input(self.install_command()?, &contents).await
+    }
+
+    /// Overwrite the secret with zeros before removing it
+    pub async fn shred(&self, pzone: &PipelineZone) -> Result<()> {
+        pzone.exec_with_input(self.shred_command(), &[]).await
+    }
+
+    /// Command writing its input to the target, in place of whatever was
+    /// there, with the mode of the secret
+    pub fn install_command(&self) -> Result<String> {
+        let dir = match self.target.rsplit_once('/') {
+            Some((dir, _)) if !dir.is_empty() => dir,
+            _ => "/",
+        };
+        Ok(format!(
+            "umask 077 && mkdir -p {dir} && rm -f {path} && cat > {path} && chmod {mode:o} {path}",
+            dir = quote(dir),
+            path = quote(&self.target),
+            mode = self.mode()?
+        ))
+    }
+
+    /// Command zeroing the target before removing it, if it's there
+    pub fn shred_command(&self) -> String {
+        format!(
+            "[ ! -f {path} ] || {{ dd if=/dev/zero of={path} bs=1 count=$(wc -c < {path}) conv=notrunc 2>/dev/null; rm -f {path}; }}",
+            path = quote(&self.target)
+        )
+    }
+}
+
+impl std::fmt::Display for SecretFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.source, self.target, self.mode)
+    }
+}
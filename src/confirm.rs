@@ -0,0 +1,47 @@
+//! Confirmation before halting or destroying what this invocation didn't create
+//!
+//! Interactive sessions are asked on the terminal, automation passes `--yes`.
+
+use anyhow::{Result, anyhow};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::sync::OnceLock;
+
+static ASSUME_YES: OnceLock<bool> = OnceLock::new();
+
+/// Answer yes to every confirmation, only the first call has any effect
+pub fn init(assume_yes: bool) {
+    let _ = ASSUME_YES.set(assume_yes);
+}
+
+pub fn assume_yes() -> bool {
+    *ASSUME_YES.get_or_init(|| false)
+}
+
+/// Ask whether to go ahead with what `message` describes, failing unless the
+/// user agrees
+///
+/// Without a terminal to ask on, only `--yes` lets it go ahead.
+pub fn destructive(message: &str) -> Result<()> {
+    if assume_yes() {
+        return Ok(());
+    }
+    if !io::stdin().is_terminal() {
+        return Err(anyhow!("{} Pass --yes to go ahead without asking", message));
+    }
+
+    if ask(&format!("{} Continue? [y/N] ", message))? {
+        Ok(())
+    } else {
+        Err(anyhow!("Aborted"))
+    }
+}
+
+/// Ask a yes/no question, anything but `y`/`yes` counts as no
+pub fn ask(prompt: &str) -> Result<bool> {
+    print!("{}", prompt);
+    io::stdout().lock().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use std::process::Stdio;
 
 pub async fn ensure_nic_exists(name: &String) -> Result<()> {
@@ -17,6 +17,20 @@ pub async fn ensure_nic_exists(name: &String) -> Result<()> {
     }
 }
 
+pub async fn delete_nic(name: &String) -> Result<()> {
+    let status = tokio::process::Command::new("dladm")
+        .arg("delete-vnic")
+        .arg(name)
+        .stdout(Stdio::null())
+        .status()
+        .await?;
+
+    if !status.success() {
+        return Err(anyhow!("Couldn't delete VNIC {}", name));
+    }
+    Ok(())
+}
+
 pub async fn nic_exists(name: &String) -> Result<bool> {
     let status = tokio::process::Command::new("dladm")
         .arg("show-vnic")
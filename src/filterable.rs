@@ -1,11 +1,12 @@
 use crate::config::{Filter, Value};
+use anyhow::{Result, anyhow};
+use itertools::Itertools;
+use std::{cell::RefCell, rc::Rc};
 
 pub trait Filterable {
-    fn filter(&self, filter: &Option<Filter>) -> bool {
-        match filter {
-            None => true,
-            Some(filter) => self.inner_filter(filter),
-        }
+    /// Whether every one of `filters` matches, an empty list matches anything
+    fn filter(&self, filters: &[Filter]) -> bool {
+        filters.iter().all(|filter| self.inner_filter(filter))
     }
 
     fn inner_filter(&self, filter: &Filter) -> bool;
@@ -15,6 +16,11 @@ impl Filterable for crate::config::Repo {
     fn inner_filter(&self, filter: &Filter) -> bool {
         match filter.key.as_str() {
             "url" => self.url == Value::Set(filter.value.clone()),
+            "ref" => self.reference == Value::Set(filter.value.clone()),
+            "depth" => self.depth.option().is_some_and(|d| d.to_string() == filter.value),
+            "credential" => self.credential == Value::Set(filter.value.clone()),
+            "submodules" => self.submodules.option().is_some_and(|s| s.to_string() == filter.value),
+            "dest" => self.dest == Value::Set(filter.value.clone()),
             _ => false,
         }
     }
@@ -25,6 +31,38 @@ impl Filterable for crate::config::Package {
         match filter.key.as_str() {
             "name" => self.name == Value::Set(filter.value.clone()),
             "provider" => self.provider == Value::Set(filter.value.clone()),
+            "version" => self.version == Value::Set(filter.value.clone()),
+            _ => false,
+        }
+    }
+}
+
+impl Filterable for crate::config::PipelineTrigger {
+    fn inner_filter(&self, filter: &Filter) -> bool {
+        match filter.key.as_str() {
+            "type" => self.kind == Value::Set(filter.value.clone()),
+            "expr" => self.expr == Value::Set(filter.value.clone()),
+            _ => false,
+        }
+    }
+}
+
+impl Filterable for crate::config::Service {
+    fn inner_filter(&self, filter: &Filter) -> bool {
+        match filter.key.as_str() {
+            "name" => self.name == Value::Set(filter.value.clone()),
+            "script" => self.script == Value::Set(filter.value.clone()),
+            "ready" => self.ready == Value::Set(filter.value.clone()),
+            _ => false,
+        }
+    }
+}
+
+impl Filterable for crate::config::BuildCache {
+    fn inner_filter(&self, filter: &Filter) -> bool {
+        match filter.key.as_str() {
+            "path" => self.path == Value::Set(filter.value.clone()),
+            "key" => self.key == Value::Set(filter.value.clone()),
             _ => false,
         }
     }
@@ -40,3 +78,87 @@ impl Filterable for crate::config::Step {
         }
     }
 }
+
+/// Pick a single element, either by its 1-based `index` or as the only one
+/// matching `filters`
+///
+/// When several elements match, the error lists them with their index so
+/// one can be picked with `select <type> <index>`.
+pub fn select<T: Filterable>(
+    elements: &[Rc<RefCell<T>>],
+    filters: &[Filter],
+    index: Option<usize>,
+    label: impl Fn(&T) -> String,
+) -> Result<Rc<RefCell<T>>> {
+    if let Some(index) = index {
+        return index
+            .checked_sub(1)
+            .and_then(|i| elements.get(i))
+            .cloned()
+            .ok_or(anyhow!("No element at index {}, there are {}", index, elements.len()));
+    }
+
+    let matching: Vec<_> = elements
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| e.borrow().filter(filters))
+        .collect();
+
+    match matching[..] {
+        [(_, x)] => Ok(x.clone()),
+        [] => Err(anyhow!("No element matched the filter")),
+        _ => Err(anyhow!(
+            "More than one element matched the filter, select one by index:\n{}",
+            matching
+                .iter()
+                .map(|(i, e)| format!("  {}: {}", i + 1, label(&e.borrow())))
+                .join("\n")
+        )),
+    }
+}
+
+/// Move every one of `other` to the end of `elements`, returning how many
+/// were moved
+pub fn append<T>(elements: &mut Vec<Rc<RefCell<T>>>, mut other: Vec<Rc<RefCell<T>>>) -> usize {
+    let count = other.len();
+    elements.append(&mut other);
+    count
+}
+
+/// Append a copy of the element matching `filters`, changed by `edit` so it
+/// can be told apart from the original
+pub fn copy<T: Filterable + Clone>(
+    elements: &mut Vec<Rc<RefCell<T>>>,
+    filters: &[Filter],
+    label: impl Fn(&T) -> String,
+    edit: impl FnOnce(&mut T),
+) -> Result<Rc<RefCell<T>>> {
+    let original = select(elements, filters, None, label)?;
+    let mut copy = original.borrow().clone();
+    edit(&mut copy);
+
+    let copy = Rc::new(RefCell::new(copy));
+    elements.push(copy.clone());
+    Ok(copy)
+}
+
+/// Copies of `elements` that can be edited without changing the originals,
+/// which cloning the `Rc`s would share
+pub fn detach<T: Clone>(elements: &[Rc<RefCell<T>>]) -> Vec<Rc<RefCell<T>>> {
+    elements.iter().map(|e| Rc::new(RefCell::new(e.borrow().clone()))).collect()
+}
+
+/// Why each of `elements` doesn't validate, prefixed with its `label`
+pub fn validation_errors<T, V>(
+    elements: &[Rc<RefCell<T>>],
+    label: impl Fn(&T) -> String,
+    validate: impl Fn(&T) -> Result<V>,
+) -> Vec<String> {
+    elements
+        .iter()
+        .filter_map(|e| {
+            let e = e.borrow();
+            validate(&e).err().map(|err| format!("{}: {}", label(&e), err))
+        })
+        .collect()
+}
@@ -0,0 +1,68 @@
+//! Health and readiness reporting for the web server
+
+use crate::history::History;
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use std::process::Stdio;
+
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Check {
+    fn new<T>(name: &'static str, result: &Result<T>) -> Self {
+        Self {
+            name,
+            ok: result.is_ok(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    /// Runs currently in progress according to the history database
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub running_runs: Option<i64>,
+    pub checks: Vec<Check>,
+}
+
+/// Check everything needed to run pipelines
+pub async fn readiness() -> Readiness {
+    let running = match History::open().await {
+        Ok(history) => history.count_running().await,
+        Err(err) => Err(err),
+    };
+
+    let checks = vec![
+        Check::new("history_db", &running),
+        Check::new("zfs", &command_works("zfs", &["list", "-H", "-o", "name", "-d", "0"]).await),
+        Check::new("zoneadm", &command_works("zoneadm", &["list"]).await),
+    ];
+
+    Readiness {
+        ready: checks.iter().all(|c| c.ok),
+        running_runs: running.ok(),
+        checks,
+    }
+}
+
+async fn command_works(program: &str, args: &[&str]) -> Result<()> {
+    let status = tokio::process::Command::new(program)
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("{} exited with {}", program, status))
+    }
+}
@@ -0,0 +1,802 @@
+//! Run history stored in a sqlite database
+
+use anyhow::{Result, anyhow};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const HISTORY_DB_VAR: &str = "RENZOKUTAI_HISTORY_DB";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunStatus {
+    Running,
+    Succeeded,
+    Failed,
+    /// Cut short with Ctrl-C
+    Cancelled,
+}
+
+impl RunStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RunStatus::Running => "running",
+            RunStatus::Succeeded => "succeeded",
+            RunStatus::Failed => "failed",
+            RunStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// Regular pipeline runs and applies of the base zone share the history
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunKind {
+    Run,
+    Apply,
+    /// Scheduled rebuild of a pipeline's base zone
+    Refresh,
+}
+
+impl RunKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RunKind::Run => "run",
+            RunKind::Apply => "apply",
+            RunKind::Refresh => "refresh",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, sqlx::FromRow)]
+pub struct RunRecord {
+    pub id: String,
+    pub pipeline_name: String,
+    pub kind: String,
+    pub branch: Option<String>,
+    pub commit_id: Option<String>,
+    pub status: String,
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    /// VNIC the run zone was given
+    pub vnic: Option<String>,
+    /// Who or what triggered the run
+    pub actor: Option<String>,
+}
+
+impl RunRecord {
+    /// Human friendly age of the run, e.g. `5 minutes ago`
+    pub fn started_ago(&self) -> String {
+        let secs = (now() - self.started_at).max(0);
+
+        match secs {
+            0..60 => format!("{} seconds ago", secs),
+            60..3600 => format!("{} minutes ago", secs / 60),
+            3600..86400 => format!("{} hours ago", secs / 3600),
+            _ => format!("{} days ago", secs / 86400),
+        }
+    }
+}
+
+/// Timed phase of a run, e.g. `install_packages` during an apply
+#[derive(Debug, Clone, PartialEq, serde::Serialize, sqlx::FromRow)]
+pub struct RunEvent {
+    pub id: i64,
+    pub run_id: String,
+    pub name: String,
+    pub started_at: i64,
+    pub finished_at: i64,
+    pub error: Option<String>,
+}
+
+impl RunEvent {
+    pub fn duration(&self) -> i64 {
+        self.finished_at - self.started_at
+    }
+}
+
+/// `key=value` label on a run, e.g. `release=1.2`, set when triggering it or
+/// by its steps
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, sqlx::FromRow)]
+pub struct RunTag {
+    pub key: String,
+    pub value: String,
+}
+
+impl RunTag {
+    /// Parse a comma separated list of `key=value` tags
+    pub fn parse_list(value: &str) -> Result<Vec<RunTag>> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(str::parse)
+            .collect()
+    }
+}
+
+impl std::str::FromStr for RunTag {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => Ok(RunTag {
+                key: key.trim().to_string(),
+                value: value.trim().to_string(),
+            }),
+            _ => Err(anyhow!("Expected key=value, got {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for RunTag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}={}", self.key, self.value)
+    }
+}
+
+/// Logs of steps have this kind
+pub const STEP_LOG: &str = "step";
+/// Logs of the phases provisioning a zone, e.g. `install_zone`, have this kind
+pub const PROVISION_LOG: &str = "provision";
+
+/// Output of a step in a run, with the middle cut out past its output limit
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct StepLogRecord {
+    /// `STEP_LOG` or `PROVISION_LOG`
+    pub kind: String,
+    /// Name of the step or provisioning phase
+    pub step_name: String,
+    pub body: String,
+    pub truncated_bytes: i64,
+}
+
+/// States a step goes through during a run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepState {
+    Running,
+    /// The step printed more lines, recorded at most once a second
+    Output,
+    Succeeded,
+    Failed,
+    /// The `when` condition of the step, or of a step it depends on, didn't
+    /// hold for the run
+    Skipped,
+    /// The inputs of the step didn't change since a previous run, whose
+    /// outputs were restored instead
+    Reused,
+}
+
+impl StepState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StepState::Running => "running",
+            StepState::Output => "output",
+            StepState::Succeeded => "succeeded",
+            StepState::Failed => "failed",
+            StepState::Skipped => "skipped",
+            StepState::Reused => "reused",
+        }
+    }
+}
+
+/// Point of the timeline of a run where a step changed state
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, sqlx::FromRow)]
+pub struct StepTransition {
+    pub step_name: String,
+    /// `StepState` the step entered
+    pub state: String,
+    /// Milliseconds since the epoch
+    pub at: i64,
+    /// Lines the step had printed by then, an offset into its output
+    pub log_lines: i64,
+}
+
+impl StepTransition {
+    pub fn new(step_name: &str, state: StepState, log_lines: u64) -> Self {
+        Self {
+            step_name: step_name.to_string(),
+            state: state.as_str().to_string(),
+            at: now_millis(),
+            log_lines: log_lines as i64,
+        }
+    }
+}
+
+/// Outcome of a step in a run, its output is kept apart in the step logs
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, sqlx::FromRow)]
+pub struct StepResultRecord {
+    pub run_id: String,
+    pub step_name: String,
+    /// `StepState` the step ended in, `running` if it never finished
+    pub status: String,
+    /// Milliseconds since the epoch
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    /// Exit code of the script, `None` when it didn't exit on its own, e.g.
+    /// it was skipped or killed
+    pub exit_code: Option<i64>,
+}
+
+impl StepResultRecord {
+    /// Outcome of `step` in `run_id` out of the transitions it went through,
+    /// `None` if it never started
+    pub fn from_transitions(run_id: &str, step: &str, transitions: &[StepTransition], exit_code: Option<i32>) -> Option<Self> {
+        let started_at = transitions.first()?.at;
+        let last = transitions.iter().rev().find(|t| t.state != StepState::Output.as_str())?;
+        let finished = last.state != StepState::Running.as_str();
+
+        Some(Self {
+            run_id: run_id.to_string(),
+            step_name: step.to_string(),
+            status: last.state.clone(),
+            started_at,
+            finished_at: finished.then_some(last.at),
+            exit_code: exit_code.map(i64::from),
+        })
+    }
+}
+
+/// Time a step spent running within a run, out of its transitions
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct StepSpan {
+    pub step_name: String,
+    /// Milliseconds from the first step starting to this one starting
+    pub offset: i64,
+    pub duration: i64,
+    /// State the step ended in, `running` if it never finished
+    pub state: String,
+    pub log_lines: i64,
+}
+
+impl StepSpan {
+    /// Span of every step in `timeline`, in the order they started
+    pub fn from_timeline(timeline: &[StepTransition]) -> Vec<StepSpan> {
+        let Some(origin) = timeline.iter().map(|t| t.at).min() else {
+            return Vec::new();
+        };
+        let mut spans: Vec<(i64, StepSpan)> = Vec::new();
+
+        for transition in timeline {
+            match spans.iter_mut().find(|(_, span)| span.step_name == transition.step_name) {
+                Some((started, span)) => {
+                    span.duration = transition.at - *started;
+                    span.log_lines = span.log_lines.max(transition.log_lines);
+                    if transition.state != StepState::Output.as_str() {
+                        span.state = transition.state.clone();
+                    }
+                }
+                None => spans.push((
+                    transition.at,
+                    StepSpan {
+                        step_name: transition.step_name.clone(),
+                        offset: transition.at - origin,
+                        duration: 0,
+                        state: transition.state.clone(),
+                        log_lines: transition.log_lines,
+                    },
+                )),
+            }
+        }
+
+        spans.into_iter().map(|(_, span)| span).collect()
+    }
+
+    /// Percentages of `total` milliseconds the span starts at and lasts, to
+    /// draw it as a bar
+    pub fn bar(&self, total: i64) -> (i64, i64) {
+        let total = total.max(1);
+        let left = self.offset * 100 / total;
+        (left, (self.duration * 100 / total).clamp(1, 100 - left.min(99)))
+    }
+}
+
+/// Free-text note attached to a run by an operator
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct RunNote {
+    pub id: i64,
+    pub run_id: String,
+    pub body: String,
+    pub created_at: i64,
+}
+
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+pub fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+pub struct History {
+    pool: SqlitePool,
+}
+
+impl History {
+    /// Database location, overridable through `RENZOKUTAI_HISTORY_DB`
+    pub fn path() -> PathBuf {
+        match std::env::var(HISTORY_DB_VAR) {
+            Ok(path) if !path.is_empty() => PathBuf::from(path),
+            _ => PathBuf::from("/var/db/renzokutai/history.sqlite"),
+        }
+    }
+
+    pub async fn open() -> Result<Self> {
+        Self::open_at(&Self::path()).await
+    }
+
+    pub async fn open_at(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true);
+        let pool = SqlitePool::connect_with(options)
+            .await
+            .map_err(|e| anyhow!("Couldn't open history at {}: {}", path.display(), e))?;
+        // The run tables in migrations/ predate the history and never applied,
+        // databases that went through them only lack them in the source
+        let mut migrator = sqlx::migrate!("./migrations/history");
+        migrator.set_ignore_missing(true);
+        migrator.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    pub async fn start_run(
+        &self,
+        id: &str,
+        pipeline: &str,
+        branch: Option<&str>,
+        commit: Option<&str>,
+    ) -> Result<()> {
+        self.start(id, RunKind::Run, pipeline, branch, commit).await
+    }
+
+    pub async fn start_apply(&self, id: &str, pipeline: &str) -> Result<()> {
+        self.start(id, RunKind::Apply, pipeline, None, None).await
+    }
+
+    pub async fn start_refresh(&self, id: &str, pipeline: &str) -> Result<()> {
+        self.start(id, RunKind::Refresh, pipeline, None, None).await
+    }
+
+    async fn start(
+        &self,
+        id: &str,
+        kind: RunKind,
+        pipeline: &str,
+        branch: Option<&str>,
+        commit: Option<&str>,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO runs (id, kind, pipeline_name, branch, commit_id, status, started_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(id)
+        .bind(kind.as_str())
+        .bind(pipeline)
+        .bind(branch)
+        .bind(commit)
+        .bind(RunStatus::Running.as_str())
+        .bind(now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_vnic(&self, id: &str, vnic: &str) -> Result<()> {
+        sqlx::query("UPDATE runs SET vnic = ? WHERE id = ?")
+            .bind(vnic)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_actor(&self, id: &str, actor: &str) -> Result<()> {
+        sqlx::query("UPDATE runs SET actor = ? WHERE id = ?")
+            .bind(actor)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn finish_run(&self, id: &str, status: RunStatus) -> Result<()> {
+        sqlx::query("UPDATE runs SET status = ?, finished_at = ? WHERE id = ?")
+            .bind(status.as_str())
+            .bind(now())
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn run(&self, id: &str) -> Result<Option<RunRecord>> {
+        Ok(sqlx::query_as("SELECT * FROM runs WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?)
+    }
+
+    /// Number of runs that haven't finished yet
+    pub async fn count_running(&self) -> Result<i64> {
+        Ok(sqlx::query_scalar("SELECT COUNT(*) FROM runs WHERE status = ?")
+            .bind(RunStatus::Running.as_str())
+            .fetch_one(&self.pool)
+            .await?)
+    }
+
+    /// Most recent runs of a pipeline, newest first
+    pub async fn runs(&self, pipeline: &str, limit: i64) -> Result<Vec<RunRecord>> {
+        Ok(sqlx::query_as(
+            "SELECT * FROM runs WHERE pipeline_name = ? ORDER BY started_at DESC, rowid DESC LIMIT ?",
+        )
+        .bind(pipeline)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    /// Most recent base refresh of a pipeline, whatever its outcome
+    pub async fn last_refresh(&self, pipeline: &str) -> Result<Option<RunRecord>> {
+        Ok(sqlx::query_as(
+            "SELECT * FROM runs WHERE pipeline_name = ? AND kind = ?
+             ORDER BY started_at DESC, rowid DESC LIMIT 1",
+        )
+        .bind(pipeline)
+        .bind(RunKind::Refresh.as_str())
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    /// Most recent successful run of `kind` of a pipeline
+    pub async fn last_succeeded(&self, pipeline: &str, kind: RunKind) -> Result<Option<RunRecord>> {
+        Ok(sqlx::query_as(
+            "SELECT * FROM runs WHERE pipeline_name = ? AND kind = ? AND status = ?
+             ORDER BY started_at DESC, rowid DESC LIMIT 1",
+        )
+        .bind(pipeline)
+        .bind(kind.as_str())
+        .bind(RunStatus::Succeeded.as_str())
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    /// Latest run of every branch of a pipeline, most recently run branch first
+    pub async fn latest_per_branch(&self, pipeline: &str) -> Result<Vec<RunRecord>> {
+        Ok(sqlx::query_as(
+            "SELECT * FROM runs r
+             WHERE pipeline_name = ? AND kind = ?
+               AND rowid = (SELECT rowid FROM runs
+                            WHERE pipeline_name = r.pipeline_name AND kind = r.kind
+                              AND branch IS r.branch
+                            ORDER BY started_at DESC, rowid DESC LIMIT 1)
+             ORDER BY started_at DESC, rowid DESC",
+        )
+        .bind(pipeline)
+        .bind(RunKind::Run.as_str())
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    /// Record a finished phase of a run
+    pub async fn record_event(
+        &self,
+        run_id: &str,
+        name: &str,
+        started_at: i64,
+        error: Option<&str>,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO run_events (run_id, name, started_at, finished_at, error)
+             VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(run_id)
+        .bind(name)
+        .bind(started_at)
+        .bind(now())
+        .bind(error)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Run `phase` and record how long it took and whether it failed
+    pub async fn timed(
+        &self,
+        run_id: &str,
+        name: &str,
+        phase: impl Future<Output = Result<()>>,
+    ) -> Result<()> {
+        let started_at = now();
+        let result = phase.await;
+        let error = result.as_ref().err().map(|e| format!("{:?}", e));
+
+        self.record_event(run_id, name, started_at, error.as_deref()).await?;
+        result
+    }
+
+    /// Phases of a run in the order they happened
+    pub async fn events(&self, run_id: &str) -> Result<Vec<RunEvent>> {
+        Ok(sqlx::query_as("SELECT * FROM run_events WHERE run_id = ? ORDER BY id")
+            .bind(run_id)
+            .fetch_all(&self.pool)
+            .await?)
+    }
+
+    /// Attach a note to an existing run
+    pub async fn add_note(&self, run_id: &str, body: &str) -> Result<()> {
+        if self.run(run_id).await?.is_none() {
+            return Err(anyhow!("Unknown run {}", run_id));
+        }
+
+        let body = body.trim();
+        if body.is_empty() {
+            return Err(anyhow!("Note is empty"));
+        }
+
+        sqlx::query("INSERT INTO run_notes (run_id, body, created_at) VALUES (?, ?, ?)")
+            .bind(run_id)
+            .bind(body)
+            .bind(now())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Tag a run, replacing the previous value of the same key
+    pub async fn add_tag(&self, run_id: &str, tag: &RunTag) -> Result<()> {
+        if self.run(run_id).await?.is_none() {
+            return Err(anyhow!("Unknown run {}", run_id));
+        }
+
+        sqlx::query("INSERT OR REPLACE INTO run_tags (run_id, key, value) VALUES (?, ?, ?)")
+            .bind(run_id)
+            .bind(&tag.key)
+            .bind(&tag.value)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn add_tags(&self, run_id: &str, tags: &[RunTag]) -> Result<()> {
+        for tag in tags {
+            self.add_tag(run_id, tag).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn tags(&self, run_id: &str) -> Result<Vec<RunTag>> {
+        Ok(sqlx::query_as("SELECT key, value FROM run_tags WHERE run_id = ? ORDER BY key")
+            .bind(run_id)
+            .fetch_all(&self.pool)
+            .await?)
+    }
+
+    /// Most recent runs carrying every one of `tags`, newest first, of
+    /// `pipeline` or of any pipeline
+    pub async fn tagged_runs(
+        &self,
+        pipeline: Option<&str>,
+        tags: &[RunTag],
+        limit: i64,
+    ) -> Result<Vec<RunRecord>> {
+        let mut sql = "SELECT * FROM runs r WHERE (? IS NULL OR pipeline_name = ?)".to_string();
+        for _ in tags {
+            sql.push_str(
+                " AND EXISTS (SELECT 1 FROM run_tags t WHERE t.run_id = r.id AND t.key = ? AND t.value = ?)",
+            );
+        }
+        sql.push_str(" ORDER BY started_at DESC, rowid DESC LIMIT ?");
+
+        let mut query = sqlx::query_as(&sql).bind(pipeline).bind(pipeline);
+        for tag in tags {
+            query = query.bind(&tag.key).bind(&tag.value);
+        }
+
+        Ok(query.bind(limit).fetch_all(&self.pool).await?)
+    }
+
+    /// Save the output of a step, replacing any previous one
+    pub async fn add_step_log(&self, run_id: &str, step: &str, body: &str, truncated_bytes: u64) -> Result<()> {
+        self.add_log(run_id, STEP_LOG, step, body, truncated_bytes).await
+    }
+
+    /// Keep the output of a provisioning phase along the step logs
+    pub async fn add_provision_log(&self, run_id: &str, phase: &str, body: &str, truncated_bytes: u64) -> Result<()> {
+        self.add_log(run_id, PROVISION_LOG, phase, body, truncated_bytes).await
+    }
+
+    async fn add_log(&self, run_id: &str, kind: &str, name: &str, body: &str, truncated_bytes: u64) -> Result<()> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO step_logs (run_id, kind, step_name, body, truncated_bytes)
+             VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(run_id)
+        .bind(kind)
+        .bind(name)
+        .bind(body)
+        .bind(truncated_bytes as i64)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Output of a run, provisioning phases in the order they ran followed
+    /// by the steps by name
+    pub async fn step_logs(&self, run_id: &str) -> Result<Vec<StepLogRecord>> {
+        Ok(sqlx::query_as(
+            "SELECT kind, step_name, body, truncated_bytes FROM step_logs WHERE run_id = ?
+             ORDER BY kind = ?, CASE WHEN kind = ? THEN step_name END, rowid",
+        )
+        .bind(run_id)
+        .bind(STEP_LOG)
+        .bind(STEP_LOG)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    /// Drop the step output of the runs of `pipeline` started before
+    /// `before`, returning how many logs were removed
+    pub async fn prune_step_logs(&self, pipeline: &str, before: i64) -> Result<u64> {
+        let result = sqlx::query(
+            "DELETE FROM step_logs WHERE run_id IN \
+             (SELECT id FROM runs WHERE pipeline_name = ? AND started_at < ?)",
+        )
+        .bind(pipeline)
+        .bind(before)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Record the transitions of a step of a run
+    pub async fn add_transitions(&self, run_id: &str, transitions: &[StepTransition]) -> Result<()> {
+        for transition in transitions {
+            sqlx::query(
+                "INSERT INTO step_transitions (run_id, step_name, state, at, log_lines)
+                 VALUES (?, ?, ?, ?, ?)",
+            )
+            .bind(run_id)
+            .bind(&transition.step_name)
+            .bind(&transition.state)
+            .bind(transition.at)
+            .bind(transition.log_lines)
+            .execute(&self.pool)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Save the outcome of a step, replacing any previous one
+    pub async fn add_step_result(&self, result: &StepResultRecord) -> Result<()> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO step_results (run_id, step_name, status, started_at, finished_at, exit_code)
+             VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&result.run_id)
+        .bind(&result.step_name)
+        .bind(&result.status)
+        .bind(result.started_at)
+        .bind(result.finished_at)
+        .bind(result.exit_code)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Outcome of the steps of a run in the order they started
+    pub async fn step_results(&self, run_id: &str) -> Result<Vec<StepResultRecord>> {
+        Ok(sqlx::query_as("SELECT * FROM step_results WHERE run_id = ? ORDER BY started_at, rowid")
+            .bind(run_id)
+            .fetch_all(&self.pool)
+            .await?)
+    }
+
+    /// Outcome of `step` in the latest `limit` runs of `pipeline` it ran in,
+    /// newest first
+    pub async fn step_history(&self, pipeline: &str, step: &str, limit: i64) -> Result<Vec<StepResultRecord>> {
+        Ok(sqlx::query_as(
+            "SELECT s.* FROM step_results s JOIN runs r ON r.id = s.run_id
+             WHERE r.pipeline_name = ? AND s.step_name = ?
+             ORDER BY s.started_at DESC, s.rowid DESC LIMIT ?",
+        )
+        .bind(pipeline)
+        .bind(step)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    /// Remember that `step` succeeded in `run_id` with inputs hashing to
+    /// `fingerprint` on the base built by run `base`
+    pub async fn add_fingerprint(&self, run_id: &str, step: &str, base: &str, fingerprint: &str) -> Result<()> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO step_fingerprints (run_id, step_name, base, fingerprint)
+             VALUES (?, ?, ?, ?)",
+        )
+        .bind(run_id)
+        .bind(step)
+        .bind(base)
+        .bind(fingerprint)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Latest run of `pipeline` where `step` succeeded with the same
+    /// `fingerprint` on the same `base`
+    pub async fn matching_fingerprint(
+        &self,
+        pipeline: &str,
+        step: &str,
+        base: &str,
+        fingerprint: &str,
+    ) -> Result<Option<String>> {
+        Ok(sqlx::query_scalar(
+            "SELECT f.run_id FROM step_fingerprints f JOIN runs r ON r.id = f.run_id
+             WHERE r.pipeline_name = ? AND f.step_name = ? AND f.base = ? AND f.fingerprint = ?
+             ORDER BY r.started_at DESC, r.rowid DESC LIMIT 1",
+        )
+        .bind(pipeline)
+        .bind(step)
+        .bind(base)
+        .bind(fingerprint)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    /// Latest successful apply or refresh of `pipeline`, which built the
+    /// base zone its runs are cloned from
+    pub async fn base_build(&self, pipeline: &str) -> Result<Option<RunRecord>> {
+        Ok(sqlx::query_as(
+            "SELECT * FROM runs WHERE pipeline_name = ? AND kind IN (?, ?) AND status = ?
+             ORDER BY started_at DESC, rowid DESC LIMIT 1",
+        )
+        .bind(pipeline)
+        .bind(RunKind::Apply.as_str())
+        .bind(RunKind::Refresh.as_str())
+        .bind(RunStatus::Succeeded.as_str())
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    /// Transitions of the steps of a run in the order they happened
+    pub async fn timeline(&self, run_id: &str) -> Result<Vec<StepTransition>> {
+        Ok(sqlx::query_as(
+            "SELECT step_name, state, at, log_lines FROM step_transitions
+             WHERE run_id = ? ORDER BY at, id",
+        )
+        .bind(run_id)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    /// Notes of a run, oldest first
+    pub async fn notes(&self, run_id: &str) -> Result<Vec<RunNote>> {
+        Ok(sqlx::query_as("SELECT * FROM run_notes WHERE run_id = ? ORDER BY created_at, id")
+            .bind(run_id)
+            .fetch_all(&self.pool)
+            .await?)
+    }
+}
@@ -0,0 +1,97 @@
+//! Cancellation of runs, e.g. on Ctrl-C
+//!
+//! A cancelled run stops its steps and cleans up its zone like a run past
+//! its timeout. Each run is given its own `Cancellation`, and only the
+//! commands running pipelines cancel it on Ctrl-C, where a second Ctrl-C
+//! exits right away leaving whatever was being cleaned up behind.
+
+use anyhow::Result;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::Notify;
+
+/// Error of the work cut short by a cancellation
+#[derive(Debug)]
+pub struct Interrupted;
+
+impl std::fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Run cancelled")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// Whether `err` comes from work cut short by a cancellation
+pub fn is_interrupted(err: &anyhow::Error) -> bool {
+    err.is::<Interrupted>()
+}
+
+/// Cancels the work it's shared with, clones cancel together
+#[derive(Debug, Clone, Default)]
+pub struct Cancellation {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl Cancellation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        self.inner.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Wait until cancelled, right away if it already is
+    pub async fn cancelled(&self) {
+        loop {
+            let notified = self.inner.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Result of `future`, or `Interrupted` if cancelled first, in which case
+    /// `future` is dropped along with what it spawned
+    pub async fn bound<T>(&self, future: impl Future<Output = Result<T>>) -> Result<T> {
+        if self.is_cancelled() {
+            return Err(Interrupted.into());
+        }
+
+        tokio::select! {
+            result = future => result,
+            _ = self.cancelled() => Err(Interrupted.into()),
+        }
+    }
+
+    /// Cancel on Ctrl-C from now on, instead of exiting right away
+    pub fn on_ctrl_c(&self) {
+        let cancellation = self.clone();
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_err() {
+                return;
+            }
+            eprintln!("Cancelling, press Ctrl-C again to exit without cleaning up");
+            cancellation.cancel();
+
+            if tokio::signal::ctrl_c().await.is_ok() {
+                std::process::exit(130);
+            }
+        });
+    }
+}
@@ -0,0 +1,209 @@
+//! What renzokutai created on the host: the zones, VNICs and datasets of
+//! its pipelines, each attributed to the pipeline and run owning it
+//!
+//! Everything is found by name, zones and VNICs start with `ci_` and
+//! datasets live under `DATASET_ROOT`.
+
+use crate::history::{History, RunStatus};
+use crate::zones::vnic_name;
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+
+/// Dataset every pipeline zone is created under
+pub const DATASET_ROOT: &str = "rpool/zones/ci";
+
+/// Id of the base zone of a pipeline, run zones are named after their run
+const BASE_ID: &str = "base";
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Resource {
+    pub name: String,
+    /// Pipeline the resource belongs to, unknown for hashed VNIC names of
+    /// pipelines that no longer exist
+    pub pipeline: Option<String>,
+    /// Run of a run zone, `None` for the base zone and shared datasets
+    pub run: Option<String>,
+    /// Zone or link state, or space used by a dataset
+    pub state: String,
+    /// Status of the owning run in the history, `None` when it isn't there
+    pub run_status: Option<String>,
+}
+
+impl Resource {
+    fn new(name: &str, owner: Option<(String, Option<String>)>, state: &str) -> Self {
+        let (pipeline, run) = match owner {
+            Some((pipeline, run)) => (Some(pipeline), run),
+            None => (None, None),
+        };
+
+        Self {
+            name: name.to_string(),
+            pipeline,
+            run,
+            state: state.to_string(),
+            run_status: None,
+        }
+    }
+
+    /// Whether the resource outlived its run or pipeline and is only taking
+    /// up space
+    pub fn is_leftover(&self, pipelines: &[String]) -> bool {
+        let orphaned = self.pipeline.as_ref().is_none_or(|p| !pipelines.contains(p));
+        let finished = self.run.is_some() && self.run_status.as_deref() != Some(RunStatus::Running.as_str());
+
+        orphaned || finished
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Inventory {
+    pub zones: Vec<Resource>,
+    pub vnics: Vec<Resource>,
+    pub datasets: Vec<Resource>,
+    /// Saved pipelines, resources of any other are leftovers
+    pub pipelines: Vec<String>,
+}
+
+impl Inventory {
+    /// Look up the zones, VNICs and datasets of the host and who owns them
+    pub async fn discover(history: &History) -> Result<Self> {
+        let pipelines: Vec<String> = crate::config::ValidatedPipeline::list()?
+            .into_iter()
+            .map(|p| p.name)
+            .collect();
+
+        let zones = command_lines("zoneadm", &["list", "-cp"]).await?;
+        let links = command_lines("dladm", &["show-link", "-p", "-o", "link,class,state"]).await?;
+        // Nothing was ever applied on this host when the root dataset is missing
+        let datasets = command_lines("zfs", &["list", "-H", "-p", "-o", "name,used", "-r", DATASET_ROOT])
+            .await
+            .unwrap_or_default();
+
+        let mut inventory = Self::parse(&zones, &links, &datasets, &pipelines);
+        inventory.pipelines = pipelines;
+        inventory.attribute(history).await?;
+
+        Ok(inventory)
+    }
+
+    /// Inventory out of the output of `zoneadm list -cp`, `dladm show-link
+    /// -p -o link,class,state` and `zfs list -H -p -o name,used`
+    pub fn parse(zones: &[String], links: &[String], datasets: &[String], pipelines: &[String]) -> Self {
+        let zones = zones
+            .iter()
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.split(':').collect();
+                match fields[..] {
+                    [_, name, state, ..] if name.starts_with("ci_") => {
+                        Some(Resource::new(name, parse_zone_name(name), state))
+                    }
+                    _ => None,
+                }
+            })
+            .collect();
+
+        let vnics = links
+            .iter()
+            .filter_map(|line| match line.split(':').collect::<Vec<_>>()[..] {
+                [link, "vnic", state] if link.starts_with("ci_") => {
+                    Some(Resource::new(link, parse_vnic_name(link, pipelines), state))
+                }
+                _ => None,
+            })
+            .collect();
+
+        let datasets = datasets
+            .iter()
+            .filter_map(|line| {
+                let (name, used) = line.split_once('\t')?;
+                let owner = parse_dataset_name(name)?;
+                Some(Resource::new(name, Some(owner), &format!("{} bytes used", used)))
+            })
+            .collect();
+
+        Self {
+            zones,
+            vnics,
+            datasets,
+            pipelines: Vec::new(),
+        }
+    }
+
+    /// Fill in the status of the run owning each resource
+    pub async fn attribute(&mut self, history: &History) -> Result<()> {
+        for resource in self.zones.iter_mut().chain(self.vnics.iter_mut()).chain(self.datasets.iter_mut()) {
+            if let Some(run) = &resource.run {
+                resource.run_status = history.run(run).await?.map(|r| r.status);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resources by kind, with the title they are listed under
+    pub fn sections(&self) -> [(&'static str, &[Resource]); 3] {
+        [("zones", &self.zones), ("vnics", &self.vnics), ("datasets", &self.datasets)]
+    }
+}
+
+/// Pipeline and run of a zone named `ci_<pipeline>_<id>`
+pub fn parse_zone_name(name: &str) -> Option<(String, Option<String>)> {
+    let (pipeline, id) = name.strip_prefix("ci_")?.rsplit_once('_')?;
+    owner(pipeline, id)
+}
+
+/// Pipeline and run of a VNIC, the hashed names are only recognized for
+/// `pipelines`
+pub fn parse_vnic_name(name: &str, pipelines: &[String]) -> Option<(String, Option<String>)> {
+    let (rest, _) = name.strip_prefix("ci_")?.rsplit_once('_')?;
+    let (pipeline, id) = rest.rsplit_once('_')?;
+
+    match pipelines.iter().find(|p| vnic_name(p, id) == name) {
+        Some(pipeline) => owner(pipeline, id),
+        None if vnic_name(pipeline, id) == name => owner(pipeline, id),
+        None => None,
+    }
+}
+
+/// Pipeline and run of a dataset under `DATASET_ROOT`: the dataset of a
+/// pipeline, of one of its zones or their swap volumes, its pkgin cache or
+/// its build caches
+pub fn parse_dataset_name(name: &str) -> Option<(String, Option<String>)> {
+    let path = name.strip_prefix(DATASET_ROOT)?.strip_prefix('/')?;
+
+    match path.split('/').collect::<Vec<_>>()[..] {
+        [pipeline] | [pipeline, "pkgin-cache"] | [pipeline, "caches"] | [pipeline, "caches", _] => {
+            owner(pipeline, BASE_ID)
+        }
+        [pipeline, zone] => owner(pipeline, zone.strip_suffix("_swap").unwrap_or(zone)),
+        // What the zones hold is theirs, not the pipeline's
+        _ => None,
+    }
+}
+
+fn owner(pipeline: &str, id: &str) -> Option<(String, Option<String>)> {
+    if pipeline.is_empty() || id.is_empty() {
+        return None;
+    }
+    let run = match id {
+        BASE_ID => None,
+        id => Some(id.to_string()),
+    };
+
+    Some((pipeline.to_string(), run))
+}
+
+async fn command_lines(program: &str, args: &[&str]) -> Result<Vec<String>> {
+    let output = tokio::process::Command::new(program).args(args).output().await?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).lines().map(str::to_string).collect())
+    } else {
+        Err(anyhow!(
+            "{} exited with {}: {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
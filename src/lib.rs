@@ -1,5 +1,18 @@
+pub mod bootstrap;
+pub mod cache;
+pub mod color;
 pub mod config;
+pub mod confirm;
 pub mod dladm;
 pub mod filterable;
+pub mod health;
+pub mod history;
+pub mod interrupt;
+pub mod inventory;
+pub mod network;
+pub mod policy;
+#[cfg(feature = "daemon")]
+pub mod trigger;
 pub mod zfs;
 pub mod zones;
+pub mod version;
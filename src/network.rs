@@ -0,0 +1,258 @@
+//! Host side of the build network
+//!
+//! Every pipeline gets its own subnet out of the build network pool on VNICs
+//! over the `internal0` etherstub. The host holds the first address of each
+//! subnet as the gateway and NATs the whole pool out of its external interface.
+
+use anyhow::{Result, anyhow};
+use crate::color::Colorize;
+use std::fmt;
+use std::io::{self, Write};
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::process::Stdio;
+use std::str::FromStr;
+
+/// Subnet of pipelines saved before subnets were configurable
+pub const DEFAULT_SUBNET: &str = "10.0.0.0/24";
+/// Pool subnets are allocated from, overridable through `RENZOKUTAI_SUBNET_POOL`
+pub const DEFAULT_POOL: &str = "10.0.0.0/16";
+pub const SUBNET_POOL_VAR: &str = "RENZOKUTAI_SUBNET_POOL";
+/// Prefix length of automatically allocated subnets
+pub const ALLOCATED_PREFIX: u8 = 24;
+pub const ETHERSTUB: &str = "internal0";
+pub const GATEWAY_VNIC: &str = "renzokutai0";
+
+const IPNAT_CONF: &str = "/etc/ipf/ipnat.conf";
+
+/// IPv4 subnet in CIDR notation, e.g. `10.0.5.0/24`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Subnet {
+    network: Ipv4Addr,
+    prefix: u8,
+}
+
+impl Subnet {
+    fn mask(&self) -> u32 {
+        u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0)
+    }
+
+    fn first(&self) -> u32 {
+        u32::from(self.network)
+    }
+
+    fn last(&self) -> u32 {
+        self.first() | !self.mask()
+    }
+
+    fn host(&self, n: u32) -> Ipv4Addr {
+        Ipv4Addr::from(self.first() + n)
+    }
+
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    /// Address of the host side of the subnet
+    pub fn gateway(&self) -> Ipv4Addr {
+        self.host(1)
+    }
+
+    /// Address given to the pipeline zones
+    pub fn zone_address(&self) -> Ipv4Addr {
+        self.host(100)
+    }
+
+    pub fn overlaps(&self, other: &Subnet) -> bool {
+        self.first() <= other.last() && other.first() <= self.last()
+    }
+
+    pub fn contains(&self, other: &Subnet) -> bool {
+        self.first() <= other.first() && other.last() <= self.last()
+    }
+
+    /// Every subnet with the given prefix length inside this one
+    pub fn subnets(&self, prefix: u8) -> impl Iterator<Item = Subnet> {
+        let step = 1u64 << (32 - prefix as u32);
+        let count = if prefix < self.prefix { 0 } else { 1u64 << (prefix - self.prefix) };
+        let first = self.first() as u64;
+
+        (0..count).map(move |i| Subnet {
+            network: Ipv4Addr::from((first + i * step) as u32),
+            prefix,
+        })
+    }
+}
+
+impl FromStr for Subnet {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (addr, prefix) = s
+            .split_once('/')
+            .ok_or(anyhow!("Invalid subnet {}, expected CIDR notation like 10.0.5.0/24", s))?;
+        let addr: Ipv4Addr = addr.parse().map_err(|_| anyhow!("Invalid address in subnet {}", s))?;
+        let prefix: u8 = prefix.parse().map_err(|_| anyhow!("Invalid prefix length in subnet {}", s))?;
+
+        // Leave room for the gateway and the zone addresses
+        if !(8..=24).contains(&prefix) {
+            return Err(anyhow!("Prefix length of subnet {} must be between 8 and 24", s));
+        }
+
+        let subnet = Subnet { network: addr, prefix };
+        if u32::from(addr) & !subnet.mask() != 0 {
+            return Err(anyhow!("{} has host bits set", s));
+        }
+
+        Ok(subnet)
+    }
+}
+
+impl fmt::Display for Subnet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+pub fn default_subnet() -> Subnet {
+    DEFAULT_SUBNET.parse().unwrap()
+}
+
+pub fn pool() -> Result<Subnet> {
+    match std::env::var(SUBNET_POOL_VAR) {
+        Ok(pool) if !pool.is_empty() => pool.parse(),
+        _ => DEFAULT_POOL.parse(),
+    }
+}
+
+/// First subnet of `pool` that doesn't overlap any of `used`
+pub fn allocate(pool: &Subnet, used: &[Subnet]) -> Option<Subnet> {
+    pool.subnets(ALLOCATED_PREFIX.max(pool.prefix))
+        .find(|candidate| used.iter().all(|u| !u.overlaps(candidate)))
+}
+
+pub async fn setup(external: &str) -> Result<()> {
+    let pool = pool()?;
+
+    print!("Creating etherstub {}...", ETHERSTUB.cyan());
+    io::stdout().lock().flush().unwrap();
+    ensure_etherstub().await?;
+    println!("{}", "DONE".green());
+
+    let subnet = default_subnet();
+    print!("Configuring gateway {} on {}...", subnet.gateway().cyan(), GATEWAY_VNIC.cyan());
+    io::stdout().lock().flush().unwrap();
+    ensure_gateway(&subnet).await?;
+    println!("{}", "DONE".green());
+
+    print!("Enabling IPv4 forwarding...");
+    io::stdout().lock().flush().unwrap();
+    run("routeadm", &["-u", "-e", "ipv4-forwarding"]).await?;
+    println!("{}", "DONE".green());
+
+    print!("Adding NAT rules for {}...", external.cyan());
+    io::stdout().lock().flush().unwrap();
+    add_nat_rules(Path::new(IPNAT_CONF), external, &pool)?;
+    run("svcadm", &["enable", "network/ipfilter"]).await?;
+    run("ipnat", &["-CF", "-f", IPNAT_CONF]).await?;
+    println!("{}", "DONE".green());
+
+    Ok(())
+}
+
+/// NAT rules sending the build network out through `external`
+pub fn nat_rules(external: &str, pool: &Subnet) -> Vec<String> {
+    vec![
+        format!("map {} {} -> 0/32 portmap tcp/udp auto", external, pool),
+        format!("map {} {} -> 0/32", external, pool),
+    ]
+}
+
+/// Append the rules missing from `conf`, leaving any existing rules alone
+fn add_nat_rules(conf: &Path, external: &str, pool: &Subnet) -> Result<()> {
+    let mut content = match std::fs::read_to_string(conf) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err.into()),
+    };
+
+    let missing: Vec<String> = nat_rules(external, pool)
+        .into_iter()
+        .filter(|rule| !content.lines().any(|line| line.trim() == rule))
+        .collect();
+
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    for rule in missing {
+        content.push_str(&rule);
+        content.push('\n');
+    }
+
+    if let Some(parent) = conf.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(conf, content)?;
+
+    Ok(())
+}
+
+/// Create the build network etherstub unless it exists, returning whether
+/// it had to
+pub async fn ensure_etherstub() -> Result<bool> {
+    if succeeds("dladm", &["show-etherstub", ETHERSTUB]).await? {
+        return Ok(false);
+    }
+
+    run("dladm", &["create-etherstub", ETHERSTUB]).await?;
+    Ok(true)
+}
+
+/// Make sure the host holds the gateway address of `subnet`
+pub async fn ensure_gateway(subnet: &Subnet) -> Result<()> {
+    if !crate::dladm::nic_exists(&GATEWAY_VNIC.to_string()).await? {
+        run("dladm", &["create-vnic", "-l", ETHERSTUB, GATEWAY_VNIC]).await?;
+    }
+
+    if !succeeds("ipadm", &["show-if", GATEWAY_VNIC]).await? {
+        run("ipadm", &["create-ip", GATEWAY_VNIC]).await?;
+    }
+
+    // One address object per subnet, e.g. renzokutai0/gw0a000500
+    let addr = format!("{}/gw{:08x}", GATEWAY_VNIC, subnet.first());
+    if !succeeds("ipadm", &["show-addr", &addr]).await? {
+        let gateway = format!("{}/{}", subnet.gateway(), subnet.prefix());
+        run("ipadm", &["create-addr", "-T", "static", "-a", &gateway, &addr]).await?;
+    }
+
+    Ok(())
+}
+
+async fn succeeds(program: &str, args: &[&str]) -> Result<bool> {
+    let status = tokio::process::Command::new(program)
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await?;
+
+    Ok(status.success())
+}
+
+async fn run(program: &str, args: &[&str]) -> Result<()> {
+    let status = tokio::process::Command::new(program)
+        .args(args)
+        .stdout(Stdio::null())
+        .status()
+        .await?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("{} {} failed", program, args.join(" ")))
+    }
+}
@@ -0,0 +1,174 @@
+//! Time bounds applied to every pipeline on the host
+//!
+//! The host defaults come from environment variables, each pipeline can
+//! override them with the attribute of the same name.
+
+use anyhow::{Result, anyhow};
+use std::fmt;
+use std::time::Duration;
+use tokio::time::Instant;
+
+pub const STEP_TIMEOUT_VAR: &str = "RENZOKUTAI_STEP_TIMEOUT";
+pub const RUN_TIMEOUT_VAR: &str = "RENZOKUTAI_RUN_TIMEOUT";
+pub const TOTAL_TIMEOUT_VAR: &str = "RENZOKUTAI_TOTAL_TIMEOUT";
+pub const BOOT_TIMEOUT_VAR: &str = "RENZOKUTAI_BOOT_TIMEOUT";
+pub const LOG_RETENTION_VAR: &str = "RENZOKUTAI_LOG_RETENTION";
+
+/// How long a zone may take to come up when the host doesn't say
+pub const DEFAULT_BOOT_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Limits of a pipeline, unset ones don't apply
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Policy {
+    /// Longest a single step may run
+    pub step_timeout: Option<Duration>,
+    /// Longest the steps of a run may take altogether
+    pub run_timeout: Option<Duration>,
+    /// Longest a whole run may take, from creating its zone to its last step
+    pub total_timeout: Option<Duration>,
+    /// Longest a zone may take to reach the multi-user milestone
+    pub boot_timeout: Duration,
+    /// How long step output is kept in the history
+    pub log_retention: Option<Duration>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            step_timeout: None,
+            run_timeout: None,
+            total_timeout: None,
+            boot_timeout: DEFAULT_BOOT_TIMEOUT,
+            log_retention: None,
+        }
+    }
+}
+
+impl Policy {
+    /// Host policy from the `RENZOKUTAI_*` variables
+    pub fn host() -> Result<Self> {
+        let var = |name: &str| -> Result<Option<Duration>> {
+            match std::env::var(name) {
+                Ok(value) if !value.is_empty() => parse_duration(&value)
+                    .map(Some)
+                    .map_err(|e| anyhow!("{}: {}", name, e)),
+                _ => Ok(None),
+            }
+        };
+
+        Ok(Self {
+            step_timeout: var(STEP_TIMEOUT_VAR)?,
+            run_timeout: var(RUN_TIMEOUT_VAR)?,
+            total_timeout: var(TOTAL_TIMEOUT_VAR)?,
+            boot_timeout: var(BOOT_TIMEOUT_VAR)?.unwrap_or(DEFAULT_BOOT_TIMEOUT),
+            log_retention: var(LOG_RETENTION_VAR)?,
+        })
+    }
+
+    /// This policy with the limits a pipeline sets replacing the inherited ones
+    pub fn with_overrides(&self, overrides: &PolicyOverrides) -> Result<Self> {
+        let pick = |value: &Option<String>, inherited: Option<Duration>| -> Result<Option<Duration>> {
+            match value {
+                Some(value) => parse_duration(value).map(Some),
+                None => Ok(inherited),
+            }
+        };
+
+        Ok(Self {
+            step_timeout: pick(&overrides.step_timeout, self.step_timeout)?,
+            run_timeout: pick(&overrides.run_timeout, self.run_timeout)?,
+            total_timeout: pick(&overrides.total_timeout, self.total_timeout)?,
+            boot_timeout: pick(&overrides.boot_timeout, Some(self.boot_timeout))?.unwrap_or(DEFAULT_BOOT_TIMEOUT),
+            log_retention: pick(&overrides.log_retention, self.log_retention)?,
+        })
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |d: Option<Duration>| d.map(format_duration).unwrap_or("none".to_string());
+
+        writeln!(f, "step_timeout: {}", show(self.step_timeout))?;
+        writeln!(f, "run_timeout: {}", show(self.run_timeout))?;
+        writeln!(f, "total_timeout: {}", show(self.total_timeout))?;
+        writeln!(f, "boot_timeout: {}", format_duration(self.boot_timeout))?;
+        write!(f, "log_retention: {}", show(self.log_retention))
+    }
+}
+
+/// Limits set on a pipeline, as written in its configuration
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PolicyOverrides {
+    pub step_timeout: Option<String>,
+    pub run_timeout: Option<String>,
+    pub total_timeout: Option<String>,
+    pub boot_timeout: Option<String>,
+    pub log_retention: Option<String>,
+}
+
+/// When a run started with a timeout has to be over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deadline {
+    at: Instant,
+    timeout: Duration,
+}
+
+impl Deadline {
+    /// Deadline of a run starting now, none without a `timeout`
+    pub fn after(timeout: Option<Duration>) -> Option<Self> {
+        timeout.map(|timeout| Self {
+            at: Instant::now() + timeout,
+            timeout,
+        })
+    }
+
+    pub fn passed(&self) -> bool {
+        Instant::now() >= self.at
+    }
+
+    pub fn error(&self) -> anyhow::Error {
+        anyhow!("Run didn't finish within the total timeout of {}", format_duration(self.timeout))
+    }
+
+    /// Result of `future`, or the error of `deadline` if it passes first, in
+    /// which case `future` is dropped along with what it spawned
+    pub async fn bound<T>(deadline: Option<Deadline>, future: impl Future<Output = Result<T>>) -> Result<T> {
+        match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline.at, future)
+                .await
+                .unwrap_or_else(|_| Err(deadline.error())),
+            None => future.await,
+        }
+    }
+}
+
+/// Parse a duration like `90s`, `30m`, `2h` or `7d`
+pub fn parse_duration(value: &str) -> Result<Duration> {
+    let digits = value.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let unit = match &value[digits.len()..] {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(anyhow!("Invalid duration {}, expected e.g. 90s, 30m, 2h or 7d", value)),
+    };
+
+    digits
+        .parse::<u64>()
+        .ok()
+        .filter(|amount| *amount > 0)
+        .and_then(|amount| amount.checked_mul(unit))
+        .map(Duration::from_secs)
+        .ok_or(anyhow!("Invalid duration {}, expected e.g. 90s, 30m, 2h or 7d", value))
+}
+
+/// Write a duration with the largest unit dividing it
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+
+    [(24 * 60 * 60, "d"), (60 * 60, "h"), (60, "m")]
+        .iter()
+        .find(|(unit, _)| secs > 0 && secs.is_multiple_of(*unit))
+        .map(|(unit, suffix)| format!("{}{}", secs / unit, suffix))
+        .unwrap_or(format!("{}s", secs))
+}
@@ -0,0 +1,90 @@
+use super::{RunRequest, RunSender, Trigger};
+use crate::config::ValidatedPipeline;
+use anyhow::Result;
+use futures::future::BoxFuture;
+use std::time::Duration;
+
+/// Runs pipelines when the cron expressions of their triggers fire
+///
+/// Pipelines are loaded again every minute, so triggers added or changed
+/// since the scheduler started are picked up.
+#[derive(Default)]
+pub struct CronTrigger;
+
+impl CronTrigger {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Requests for the pipelines whose triggers fire on the minute of `now`,
+    /// in seconds since the epoch, evaluated in the timezone of each pipeline
+    /// and holding off on the days its calendar excludes
+    ///
+    /// Pipelines whose triggers can't be evaluated are reported and left out.
+    pub fn due(pipelines: &[ValidatedPipeline], now: i64) -> Vec<RunRequest> {
+        pipelines
+            .iter()
+            .filter(|vp| vp.enabled && !vp.triggers.is_empty())
+            .filter(|vp| match Self::fires(vp, now) {
+                Ok(fired) => fired,
+                Err(err) => {
+                    eprintln!("Couldn't evaluate the cron triggers of {}: {:?}", vp.name, err);
+                    false
+                }
+            })
+            .map(|vp| RunRequest::new(&vp.name, "cron"))
+            .collect()
+    }
+
+    /// Whether a trigger of `vp` fires on the minute of `now`
+    fn fires(vp: &ValidatedPipeline, now: i64) -> Result<bool> {
+        let calendar = vp.schedule_calendar()?;
+        if calendar.exclusion(now)?.is_some() {
+            return Ok(false);
+        }
+
+        let time = calendar.datetime(now)?;
+        let mut fired = false;
+        for trigger in vp.triggers.iter() {
+            fired |= trigger.schedule()?.matches(time);
+        }
+        Ok(fired)
+    }
+}
+
+impl Trigger for CronTrigger {
+    fn name(&self) -> &str {
+        "cron"
+    }
+
+    fn listen(self: Box<Self>, requests: RunSender) -> BoxFuture<'static, Result<()>> {
+        Box::pin(async move {
+            let mut last_minute = None;
+            loop {
+                // Wake up right after the start of every minute
+                let now = crate::history::now();
+                tokio::time::sleep(Duration::from_secs((60 - now.rem_euclid(60)) as u64)).await;
+
+                let now = crate::history::now();
+                // The clock may have gone back or not moved on yet
+                if last_minute.is_some_and(|last| now / 60 <= last) {
+                    continue;
+                }
+                last_minute = Some(now / 60);
+
+                let pipelines = match ValidatedPipeline::list() {
+                    Ok(pipelines) => pipelines,
+                    Err(err) => {
+                        eprintln!("Couldn't load the pipelines for their cron triggers: {:?}", err);
+                        continue;
+                    }
+                };
+                for request in Self::due(&pipelines, now) {
+                    if requests.send(request).await.is_err() {
+                        return Ok(());
+                    }
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,207 @@
+//! Sources of pipeline runs
+//!
+//! Every source, be it an operator asking through the web UI, a schedule, a
+//! webhook or a repository being polled, implements [`Trigger`] and hands
+//! [`RunRequest`]s to the [`Scheduler`], which checks and starts them.
+
+use crate::config::ValidatedPipeline;
+use crate::history::RunTag;
+use anyhow::{Result, anyhow};
+use futures::future::BoxFuture;
+use std::path::PathBuf;
+use tokio::sync::{mpsc, oneshot};
+
+mod cron;
+mod watch;
+
+pub use cron::CronTrigger;
+pub use watch::WatchTrigger;
+
+/// Requests waiting for the scheduler before sources have to wait
+const QUEUE_SIZE: usize = 64;
+
+/// A run asked for by a trigger
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunRequest {
+    pub pipeline: String,
+    pub branch: Option<String>,
+    pub commit: Option<String>,
+    pub tags: Vec<RunTag>,
+    /// Local working tree to run against instead of the zone's checkout
+    pub local_src: Option<PathBuf>,
+    /// Who or what asked for the run, e.g. `web` or `cron`
+    pub actor: String,
+}
+
+impl RunRequest {
+    pub fn new(pipeline: &str, actor: &str) -> Self {
+        Self {
+            pipeline: pipeline.to_string(),
+            branch: None,
+            commit: None,
+            tags: Vec::new(),
+            local_src: None,
+            actor: actor.to_string(),
+        }
+    }
+
+    /// Fail unless the pipeline exists and is enabled
+    pub fn check(&self) -> Result<()> {
+        ValidatedPipeline::load(&self.pipeline)?
+            .ok_or(anyhow!("Unknown pipeline {}", self.pipeline))?
+            .ensure_enabled()
+    }
+
+    /// Arguments of the `pipelineadm` invocation starting the run
+    pub fn args(&self) -> Vec<String> {
+        let mut args = vec!["-p".to_string(), self.pipeline.clone(), "run".to_string()];
+
+        if let Some(branch) = &self.branch {
+            args.extend(["--branch".to_string(), branch.clone()]);
+        }
+        if let Some(commit) = &self.commit {
+            args.extend(["--commit".to_string(), commit.clone()]);
+        }
+        for tag in &self.tags {
+            args.extend(["--tag".to_string(), tag.to_string()]);
+        }
+        if let Some(path) = &self.local_src {
+            args.extend(["--local-src".to_string(), path.display().to_string()]);
+        }
+        args.extend(["--actor".to_string(), self.actor.clone()]);
+
+        args
+    }
+}
+
+pub type RunSender = mpsc::Sender<RunRequest>;
+
+/// Hands requests to the scheduler and waits until their run started, for
+/// sources answering whoever asked, e.g. the web UI
+#[derive(Clone)]
+pub struct RunSubmitter {
+    sender: mpsc::Sender<(RunRequest, oneshot::Sender<Result<()>>)>,
+}
+
+impl RunSubmitter {
+    /// Whether the run of `request` could be started
+    pub async fn submit(&self, request: RunRequest) -> Result<()> {
+        let (started, result) = oneshot::channel();
+        self.sender
+            .send((request, started))
+            .await
+            .map_err(|_| anyhow!("The scheduler stopped"))?;
+        result.await.map_err(|_| anyhow!("The scheduler stopped"))?
+    }
+}
+
+/// Source of run requests
+pub trait Trigger: Send + 'static {
+    /// Name the source is logged under
+    fn name(&self) -> &str;
+
+    /// Send requests until the source runs dry or the scheduler goes away
+    fn listen(self: Box<Self>, requests: RunSender) -> BoxFuture<'static, Result<()>>;
+}
+
+/// Starts the runs the triggers ask for
+pub struct Scheduler {
+    sender: RunSender,
+    receiver: mpsc::Receiver<RunRequest>,
+    submitter: RunSubmitter,
+    submissions: mpsc::Receiver<(RunRequest, oneshot::Sender<Result<()>>)>,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+        let (submitted, submissions) = mpsc::channel(QUEUE_SIZE);
+        Self {
+            sender,
+            receiver,
+            submitter: RunSubmitter { sender: submitted },
+            submissions,
+        }
+    }
+
+    /// Sender for sources living outside a [`Trigger`], e.g. request handlers
+    pub fn sender(&self) -> RunSender {
+        self.sender.clone()
+    }
+
+    /// Submitter for sources waiting on their runs to start
+    pub fn submitter(&self) -> RunSubmitter {
+        self.submitter.clone()
+    }
+
+    /// Start listening to a source in the background
+    pub fn add(&self, trigger: Box<dyn Trigger>) {
+        let sender = self.sender();
+
+        tokio::spawn(async move {
+            let name = trigger.name().to_string();
+            if let Err(err) = trigger.listen(sender).await {
+                eprintln!("Trigger {} stopped: {:?}", name, err);
+            }
+        });
+    }
+
+    /// Next request, `None` once every source is gone
+    pub async fn next(&mut self) -> Option<RunRequest> {
+        self.receiver.recv().await
+    }
+
+    /// Start the requested runs until every source is gone
+    pub async fn run(mut self) {
+        // Only the sources should keep the queues open
+        drop(self.sender);
+        drop(self.submitter);
+
+        loop {
+            tokio::select! {
+                Some(request) = self.receiver.recv() => {
+                    if let Err(err) = start(&request) {
+                        eprintln!("Couldn't start run of {}: {:?}", request.pipeline, err);
+                    }
+                }
+                Some((request, started)) = self.submissions.recv() => {
+                    let _ = started.send(start(&request));
+                }
+                else => break,
+            }
+        }
+    }
+}
+
+/// Start the run in its own `pipelineadm` process
+fn start(request: &RunRequest) -> Result<()> {
+    request.check()?;
+
+    println!(
+        "Triggering run of {} at commit {} for {}",
+        request.pipeline,
+        request.commit.as_deref().unwrap_or("-"),
+        request.actor
+    );
+    let mut child = tokio::process::Command::new("pipelineadm")
+        .args(request.args())
+        .spawn()?;
+
+    // Reap the run once it's over
+    let pipeline = request.pipeline.clone();
+    tokio::spawn(async move {
+        match child.wait().await {
+            Ok(status) if !status.success() => eprintln!("Run of {} exited with {}", pipeline, status),
+            Ok(_) => (),
+            Err(err) => eprintln!("Couldn't wait for the run of {}: {:?}", pipeline, err),
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use super::{RunRequest, RunSender, Trigger};
+use anyhow::{Result, anyhow};
+use futures::future::BoxFuture;
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Runs a pipeline against a local working tree whenever it changes
+pub struct WatchTrigger {
+    path: PathBuf,
+    request: RunRequest,
+    /// How long the tree has to stay untouched before a run starts
+    debounce: Duration,
+}
+
+impl WatchTrigger {
+    /// Watch `path`, running `pipeline` with it as `--local-src`
+    pub fn new(pipeline: &str, path: &Path, debounce: Duration) -> Self {
+        let mut request = RunRequest::new(pipeline, "watch");
+        request.local_src = Some(path.to_path_buf());
+
+        Self {
+            path: path.to_path_buf(),
+            request,
+            debounce,
+        }
+    }
+}
+
+/// Whether an event is worth a run, reads and git bookkeeping aren't
+fn is_change(event: &Event) -> bool {
+    !matches!(event.kind, EventKind::Access(_))
+        && event
+            .paths
+            .iter()
+            .any(|path| !path.components().any(|c| c == Component::Normal(".git".as_ref())))
+}
+
+impl Trigger for WatchTrigger {
+    fn name(&self) -> &str {
+        "watch"
+    }
+
+    fn listen(self: Box<Self>, requests: RunSender) -> BoxFuture<'static, Result<()>> {
+        Box::pin(async move {
+            let (sender, mut changes) = mpsc::unbounded_channel();
+            let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+                if event.as_ref().is_ok_and(is_change) {
+                    let _ = sender.send(());
+                }
+            })?;
+            watcher
+                .watch(&self.path, RecursiveMode::Recursive)
+                .map_err(|e| anyhow!("Couldn't watch {}: {}", self.path.display(), e))?;
+
+            while changes.recv().await.is_some() {
+                // Wait for the burst of changes of a save or checkout to end
+                while let Ok(Some(())) = tokio::time::timeout(self.debounce, changes.recv()).await {}
+
+                if requests.send(self.request.clone()).await.is_err() {
+                    break;
+                }
+            }
+
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,113 @@
+use crate::config::migration::SCHEMA_VERSION;
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::fmt;
+use std::process::Stdio;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT_COMMIT: &str = env!("RENZOKUTAI_GIT_COMMIT");
+
+/// Environment variable pointing at a URL that returns the latest released version
+pub const UPDATE_URL_VAR: &str = "RENZOKUTAI_UPDATE_URL";
+
+#[derive(Debug, Serialize)]
+pub struct VersionInfo {
+    pub version: String,
+    pub schema_version: u32,
+    pub git_commit: String,
+}
+
+impl VersionInfo {
+    pub fn current() -> Self {
+        Self {
+            version: VERSION.to_string(),
+            schema_version: SCHEMA_VERSION,
+            git_commit: GIT_COMMIT.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for VersionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "renzokutai {} (schema {}, commit {})",
+            self.version, self.schema_version, self.git_commit
+        )
+    }
+}
+
+pub fn update_url() -> Option<String> {
+    std::env::var(UPDATE_URL_VAR).ok().filter(|u| !u.is_empty())
+}
+
+/// Fetch the latest released version published at `url`
+///
+/// The URL is expected to return the bare version string, e.g. `0.2.0`.
+pub async fn latest_release(url: &str) -> Result<String> {
+    let output = tokio::process::Command::new("curl")
+        .arg("-sSfL")
+        .arg(url)
+        .stderr(Stdio::null())
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(anyhow!("Couldn't fetch latest release from {}", url));
+    }
+
+    let latest = String::from_utf8(output.stdout)?.trim().to_string();
+    if latest.is_empty() {
+        Err(anyhow!("Empty response from {}", url))
+    } else {
+        Ok(latest)
+    }
+}
+
+/// Whether `latest` is a strictly newer dotted version than `current`
+///
+/// Missing parts count as 0, so `1.0` and `1.0.0` are the same version, and
+/// a pre-release like `1.2.0-rc1` comes before its release. Build metadata
+/// after a `+` is ignored.
+pub fn is_newer(latest: &str, current: &str) -> bool {
+    compare_versions(latest, current) == Ordering::Greater
+}
+
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let parse = |v: &str| {
+        let v = v.trim_start_matches('v');
+        let v = v.split_once('+').map_or(v, |(v, _)| v);
+        let (numbers, pre) = match v.split_once('-') {
+            Some((numbers, pre)) => (numbers, Some(pre.to_string())),
+            None => (v, None),
+        };
+        let numbers: Vec<u64> = numbers.split('.').map(|p| p.parse().unwrap_or(0)).collect();
+        (numbers, pre)
+    };
+    let (a_numbers, a_pre) = parse(a);
+    let (b_numbers, b_pre) = parse(b);
+
+    let len = a_numbers.len().max(b_numbers.len());
+    let padded = |numbers: &[u64]| (0..len).map(|i| numbers.get(i).copied().unwrap_or(0)).collect::<Vec<_>>();
+
+    padded(&a_numbers).cmp(&padded(&b_numbers)).then_with(|| match (a_pre, b_pre) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+        (Some(a), Some(b)) => compare_pre_releases(&a, &b),
+    })
+}
+
+/// Order of two pre-releases, comparing their dot separated parts numerically
+/// when both are numbers and as text otherwise, numbers coming first
+fn compare_pre_releases(a: &str, b: &str) -> Ordering {
+    let parts = |pre: &str| {
+        pre.split('.')
+            .map(|part| part.parse::<u64>().map_err(|_| part.to_string()))
+            .collect::<Vec<_>>()
+    };
+
+    // Ok before Err puts numbers before text
+    parts(a).cmp(&parts(b))
+}
@@ -32,3 +32,169 @@ pub async fn create_dataset(name: &String) -> Result<()> {
         Err(anyhow!("Couldn't create dataset"))
     }
 }
+
+pub async fn create_volume(name: &str, size: &str) -> Result<()> {
+    let status = tokio::process::Command::new("zfs")
+        .arg("create")
+        .arg("-V")
+        .arg(size)
+        .arg(name)
+        .stderr(Stdio::null())
+        .status()
+        .await?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("Couldn't create volume {}", name))
+    }
+}
+
+pub async fn destroy(name: &String) -> Result<()> {
+    let status = tokio::process::Command::new("zfs")
+        .arg("destroy")
+        .arg(name.as_str())
+        .stderr(Stdio::null())
+        .status()
+        .await?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("Couldn't destroy {}", name))
+    }
+}
+
+pub async fn snapshot(name: &String) -> Result<()> {
+    let status = tokio::process::Command::new("zfs")
+        .arg("snapshot")
+        .arg(name.as_str())
+        .stderr(Stdio::null())
+        .status()
+        .await?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("Couldn't snapshot {}", name))
+    }
+}
+
+/// Destroy `name` along with its children and snapshots, failing while
+/// clones of them are left
+pub async fn destroy_recursive(name: &str) -> Result<()> {
+    let status = tokio::process::Command::new("zfs")
+        .arg("destroy")
+        .arg("-r")
+        .arg(name)
+        .stderr(Stdio::null())
+        .status()
+        .await?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("Couldn't destroy {}", name))
+    }
+}
+
+pub async fn rename(from: &str, to: &str) -> Result<()> {
+    let status = tokio::process::Command::new("zfs")
+        .arg("rename")
+        .arg(from)
+        .arg(to)
+        .stderr(Stdio::null())
+        .status()
+        .await?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("Couldn't rename {} to {}", from, to))
+    }
+}
+
+/// Make the clone `name` independent of the dataset it was cloned from
+pub async fn promote(name: &str) -> Result<()> {
+    let status = tokio::process::Command::new("zfs")
+        .arg("promote")
+        .arg(name)
+        .stderr(Stdio::null())
+        .status()
+        .await?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("Couldn't promote {}", name))
+    }
+}
+
+pub async fn set_quota(name: &str, quota: &str) -> Result<()> {
+    let status = tokio::process::Command::new("zfs")
+        .arg("set")
+        .arg(format!("quota={}", quota))
+        .arg(name)
+        .stderr(Stdio::null())
+        .status()
+        .await?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("Couldn't set quota on {}", name))
+    }
+}
+
+/// Whether the dataset has used up all of its quota
+pub async fn quota_exceeded(name: &str) -> Result<bool> {
+    let output = tokio::process::Command::new("zfs")
+        .arg("get")
+        .arg("-Hp")
+        .arg("-o")
+        .arg("value")
+        .arg("used,quota")
+        .arg(name)
+        .stderr(Stdio::null())
+        .output()
+        .await?;
+
+    let values: Vec<u64> = String::from_utf8(output.stdout)?
+        .lines()
+        .map(|l| l.trim().parse().unwrap_or(0))
+        .collect();
+
+    Ok(match values[..] {
+        [used, quota] => quota > 0 && used >= quota,
+        _ => false,
+    })
+}
+
+/// Check that a size is something zfs understands, e.g. `512M` or `20G`
+pub fn validate_size(size: &str) -> Result<()> {
+    let digits = size.trim_end_matches(|c: char| "KMGTkmgt".contains(c));
+
+    if !digits.is_empty() && size.len() - digits.len() <= 1 && digits.chars().all(|c| c.is_ascii_digit()) {
+        Ok(())
+    } else {
+        Err(anyhow!("Invalid size: {}", size))
+    }
+}
+
+/// Number of bytes in a size written like zfs does, e.g. `512M`
+pub fn size_bytes(size: &str) -> Result<u64> {
+    validate_size(size)?;
+    let digits = size.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let shift = match size[digits.len()..].to_ascii_uppercase().as_str() {
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => 0,
+    };
+
+    digits
+        .parse::<u64>()?
+        .checked_mul(1 << shift)
+        .ok_or(anyhow!("Size too large: {}", size))
+}
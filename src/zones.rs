@@ -1,12 +1,119 @@
-use anyhow::Result;
-use owo_colors::OwoColorize;
+use anyhow::{Result, anyhow};
+use crate::network::Subnet;
+use crate::color::Colorize;
+use sha2::{Digest, Sha256};
 use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::io;
 use std::io::Write;
+use std::time::Duration;
+
+/// Longest datalink name dladm accepts
+pub const MAX_LINK_NAME: usize = 31;
+/// Longest zone name zonecfg accepts
+pub const MAX_ZONE_NAME: usize = 64;
+/// Longest zone id, the run ids are 4 characters and `base` as long
+const MAX_ZONE_ID: usize = 4;
+
+/// Longest pipeline name that still yields valid zone names
+pub const MAX_PIPELINE_NAME: usize = MAX_ZONE_NAME - "ci__".len() - MAX_ZONE_ID;
+
+/// Check that `name` can be used in zone names and datasets
+pub fn validate_pipeline_name(name: &str) -> Result<()> {
+    let valid_chars = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    let starts_alnum = name.chars().next().is_some_and(|c| c.is_ascii_alphanumeric());
+
+    if !valid_chars || !starts_alnum {
+        Err(anyhow!(
+            "Invalid pipeline name {}, use letters, digits, '_' and '-' starting with a letter or digit",
+            name
+        ))
+    } else if name.len() > MAX_PIPELINE_NAME {
+        Err(anyhow!(
+            "Pipeline name {} is longer than {} characters",
+            name,
+            MAX_PIPELINE_NAME
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Longest user name useradd accepts without warning
+pub const MAX_USER_NAME: usize = 32;
+
+/// Group of the users steps run as, owning the checkout
+pub const RUN_GROUP: &str = "renzokutai";
+
+/// Check that `name` can be created with useradd and passed to `su`
+pub fn validate_user_name(name: &str) -> Result<()> {
+    let valid_chars = name
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-');
+    let starts_lower = name.chars().next().is_some_and(|c| c.is_ascii_lowercase());
+
+    if !valid_chars || !starts_lower {
+        Err(anyhow!(
+            "Invalid user name {}, use lowercase letters, digits, '_' and '-' starting with a letter",
+            name
+        ))
+    } else if name.len() > MAX_USER_NAME {
+        Err(anyhow!("User name {} is longer than {} characters", name, MAX_USER_NAME))
+    } else {
+        Ok(())
+    }
+}
+
+/// Command creating `user` in the zone unless it exists, in `RUN_GROUP`
+pub fn create_user_command(user: &str) -> String {
+    format!(
+        "(getent group {group} >/dev/null || groupadd {group}) \
+         && (getent passwd {user} >/dev/null || useradd -m -d /export/home/{user} -g {group} -s /bin/sh {user})",
+        group = RUN_GROUP,
+        user = user
+    )
+}
+
+/// Command letting the users in `RUN_GROUP` write to `paths`, which root
+/// creates, e.g. the checkout and the build caches
+pub fn share_command(paths: &[&str]) -> String {
+    let paths: Vec<String> = paths.iter().map(|path| crate::config::step::quote(path)).collect();
+    format!(
+        "mkdir -p {paths} && chgrp -R {group} {paths} && chmod -R g+rwX {paths}",
+        paths = paths.join(" "),
+        group = RUN_GROUP
+    )
+}
+
+/// VNIC name of a pipeline zone
+///
+/// Uses the readable `ci_<pipeline>_<id>_internal0` when it fits in
+/// `MAX_LINK_NAME`, otherwise replaces the pipeline with a hash of it. Link
+/// names must end in a digit, which both forms do.
+pub fn vnic_name(pipeline: &str, id: &str) -> String {
+    let readable = format!("ci_{}_{}_internal0", pipeline, id);
+    let readable_ok = readable.len() <= MAX_LINK_NAME
+        && readable.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+    if readable_ok {
+        readable
+    } else {
+        // Stable across builds unlike `DefaultHasher`
+        let hash = format!("{:x}", Sha256::digest(pipeline.as_bytes()));
+        format!("ci_{}_{}_0", &hash[..8], id)
+    }
+}
+
+/// Checkout the steps run in, out of the home of root so the step users
+/// can reach it
+pub const CHECKOUT_DIR: &str = "/opt/renzokutai/checkout";
 
 #[derive(Debug, Clone)]
 pub enum ZoneType {
     Base,
+    /// Refreshed base being built, swapped in once it passes a smoke run
+    Candidate,
     Run(String),
 }
 
@@ -14,18 +121,54 @@ impl ZoneType {
     pub fn id(&self) -> String {
         match self {
             ZoneType::Base => "base".to_string(),
+            ZoneType::Candidate => "next".to_string(),
             ZoneType::Run(id) => id.clone(),
         }
     }
 }
 
+/// Starts the commands meant for a zone
+///
+/// Runs go through zlogin, tests swap it for one running the commands they
+/// expect on the host.
+pub trait Executor: std::fmt::Debug + Send + Sync {
+    /// Command running `command` in the zone named `zone`
+    fn command(&self, zone: &str, command: &OsStr) -> std::process::Command;
+}
+
+/// Runs the commands in the zone through zlogin
+#[derive(Debug)]
+pub struct ZloginExecutor;
+
+impl Executor for ZloginExecutor {
+    fn command(&self, zone: &str, command: &OsStr) -> std::process::Command {
+        zone::Zlogin::new(zone).as_command(command)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct PipelineZone {
     pub pipeline: String,
     pub zone_type: ZoneType,
+    /// What starts the commands of `exec`, shared with the run zones
+    pub executor: Arc<dyn Executor>,
 }
 
 impl PipelineZone {
+    pub fn new(pipeline: String, zone_type: ZoneType) -> Self {
+        Self {
+            pipeline,
+            zone_type,
+            executor: Arc::new(ZloginExecutor),
+        }
+    }
+
+    /// This zone, its commands started by `executor` instead of zlogin
+    pub fn with_executor(mut self, executor: Arc<dyn Executor>) -> Self {
+        self.executor = executor;
+        self
+    }
+
     pub fn root_path(&self) -> String {
         format!("/zones/ci/{}", self.pipeline)
     }
@@ -38,14 +181,47 @@ impl PipelineZone {
         format!("ci_{}_{}", self.pipeline, self.zone_type.id())
     }
 
+    pub fn dataset(&self) -> String {
+        format!("rpool{}", self.path())
+    }
+
+    /// Host path of `path` inside the zone's root filesystem
+    pub fn host_path(&self, path: &str) -> PathBuf {
+        PathBuf::from(format!("{}/root{}", self.path(), path))
+    }
+
+    /// Dataset shared by the pipeline's run zones to cache pkgin downloads
+    pub fn pkgin_cache_dataset(&self) -> String {
+        format!("rpool{}/pkgin-cache", self.root_path())
+    }
+
+    /// Parent of the datasets backing the build caches of the pipeline
+    pub fn caches_dataset(&self) -> String {
+        format!("rpool{}/caches", self.root_path())
+    }
+
+    /// ZFS volume backing the zone's dedicated swap device
+    pub fn swap_volume(&self) -> String {
+        format!("{}_swap", self.dataset())
+    }
+
     pub fn vnic_name(&self) -> String {
-        format!("{}_internal0", self.name())
+        vnic_name(&self.pipeline, &self.zone_type.id())
+    }
+
+    /// Zone the refreshed base of the pipeline is built in
+    pub fn candidate_pzone(&self) -> Self {
+        PipelineZone {
+            zone_type: ZoneType::Candidate,
+            ..self.clone()
+        }
     }
 
     pub fn get_run_pzone(&self, run_id: &String) -> Self {
         PipelineZone {
             pipeline: self.pipeline.clone(),
             zone_type: ZoneType::Run(run_id.clone()),
+            executor: self.executor.clone(),
         }
     }
 
@@ -55,14 +231,67 @@ impl PipelineZone {
     }
 
     pub fn exec(&self, command: impl AsRef<OsStr>) -> Result<tokio::process::Child> {
-        let command = zone::Zlogin::new(self.name()).as_command(command);
+        let command = self.executor.command(&self.name(), command.as_ref());
 
+        // Commands dropped before they exit, e.g. past a timeout, kill zlogin
+        // but not what it started in the zone. Steps stop their processes
+        // themselves, the rest goes away with the run zone.
         Ok(tokio::process::Command::from(command)
             .stdout(std::process::Stdio::piped())
             .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true)
             .spawn()?)
     }
 
+    /// Start `command` in the zone without waiting for it nor killing it if
+    /// this process goes away first
+    pub fn exec_detached(&self, command: impl AsRef<OsStr>) -> Result<()> {
+        let command = self.executor.command(&self.name(), command.as_ref());
+
+        tokio::process::Command::from(command)
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn()?;
+        Ok(())
+    }
+
+    /// Run `command` in the zone with `input` on its stdin, which keeps
+    /// `input` out of the command line and has the zone resolve its own paths
+    pub async fn exec_with_input(&self, command: impl AsRef<OsStr>, input: &[u8]) -> Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        let mut child = tokio::process::Command::from(self.executor.command(&self.name(), command.as_ref()))
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        // Failing before reading its input shows in the exit status
+        if let Some(mut stdin) = child.stdin.take()
+            && let Err(err) = stdin.write_all(input).await
+            && err.kind() != io::ErrorKind::BrokenPipe
+        {
+            return Err(err.into());
+        }
+
+        let output = child.wait_with_output().await?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "{} failed in zone {}: {}",
+                command.as_ref().to_string_lossy(),
+                self.name(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether the zone is configured on the host, whatever its state
+    pub fn exists(&self) -> Result<bool> {
+        Ok(get_zone_state(self)?.is_some())
+    }
+
     pub fn cleanup(&self) -> Result<()> {
         if let Some(mut state) = get_zone_state(&self)? {
             print!(
@@ -102,6 +331,35 @@ impl PipelineZone {
     }
 }
 
+/// Replace the zone's checkout at `workdir` with a copy of the local working
+/// tree at `src`
+pub async fn sync_local_source(pzone: &PipelineZone, src: &Path, workdir: &str) -> Result<()> {
+    if !src.is_dir() {
+        return Err(anyhow!("{} is not a directory", src.display()));
+    }
+
+    let target = pzone.host_path(workdir);
+    print!("Copying local source {}...", src.display().cyan());
+    io::stdout().lock().flush().unwrap();
+
+    std::fs::create_dir_all(&target)?;
+    // Trailing slashes make rsync copy the contents rather than the directory
+    let status = tokio::process::Command::new("rsync")
+        .arg("-a")
+        .arg("--delete")
+        .arg(format!("{}/", src.display()))
+        .arg(format!("{}/", target.display()))
+        .status()
+        .await?;
+
+    if !status.success() {
+        return Err(anyhow!("Couldn't copy {} into {}", src.display(), pzone.name()));
+    }
+
+    println!("{}", "DONE".green());
+    Ok(())
+}
+
 fn get_zone_state(pzone: &PipelineZone) -> Result<Option<zone::State>> {
     Ok(match get_zone(pzone)? {
         Some(z) => Some(z.state),
@@ -117,7 +375,14 @@ fn list() -> Result<Vec<zone::Zone>> {
     Ok(zone::Adm::list_blocking()?)
 }
 
-pub async fn create_zone_from_base(target_pzone: &PipelineZone, base_pzone: &PipelineZone) -> Result<()> {
+pub async fn create_zone_from_base(
+    target_pzone: &PipelineZone,
+    base_pzone: &PipelineZone,
+    swap: Option<&String>,
+    pkgin_cache: bool,
+    caches: &crate::config::ValidatedBuildCaches,
+    boot_timeout: Duration,
+) -> Result<()> {
     print!("Creating VNIC {}...", target_pzone.vnic_name().cyan());
     io::stdout().lock().flush().unwrap();
     crate::dladm::ensure_nic_exists(&target_pzone.vnic_name()).await?;
@@ -133,15 +398,162 @@ pub async fn create_zone_from_base(target_pzone: &PipelineZone, base_pzone: &Pip
     zone::Adm::new(target_pzone.name()).clone_blocking(base_pzone.name())?;
     println!("{}", "DONE".green());
 
+    if pkgin_cache {
+        print!("Mounting pkgin cache {}...", target_pzone.pkgin_cache_dataset().cyan());
+        io::stdout().lock().flush().unwrap();
+        add_pkgin_cache(target_pzone).await?;
+        println!("{}", "DONE".green());
+    }
+
+    for cache in caches.iter() {
+        print!("Mounting cache {} on {}...", cache.key.cyan(), cache.path.cyan());
+        io::stdout().lock().flush().unwrap();
+        add_build_cache(target_pzone, cache).await?;
+        println!("{}", "DONE".green());
+    }
+
+    if let Some(size) = swap {
+        print!("Creating {} swap volume...", size.cyan());
+        io::stdout().lock().flush().unwrap();
+        add_swap_device(target_pzone, size).await?;
+        println!("{}", "DONE".green());
+    }
+
     print!("Booting zone {}...", target_pzone.name().cyan());
     io::stdout().lock().flush().unwrap();
     zone::Adm::new(target_pzone.name()).boot_blocking()?;
+    wait_for_boot(target_pzone, boot_timeout).await?;
+    println!("{}", "DONE".green());
+
+    if swap.is_some() {
+        let status = target_pzone
+            .exec(format!("swap -a /dev/zvol/dsk/{}", target_pzone.swap_volume()))?
+            .wait()
+            .await?;
+        if !status.success() {
+            return Err(anyhow!("Couldn't enable swap in zone {}", target_pzone.name()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Clone the halted `base` into `candidate`, which borrows the VNIC of the
+/// base so the network configured inside keeps working
+pub async fn clone_candidate(candidate: &PipelineZone, base: &PipelineZone) -> Result<()> {
+    print!("Cloning {} into {}...", base.name().cyan(), candidate.name().cyan());
+    io::stdout().lock().flush().unwrap();
+    configure_zone_on(candidate, &base.vnic_name()).await?;
+    zone::Adm::new(candidate.name()).clone_blocking(base.name())?;
     println!("{}", "DONE".green());
 
     Ok(())
 }
 
+/// Forget the halted zone `pzone` while keeping its datasets
+pub async fn detach(pzone: &PipelineZone) -> Result<()> {
+    let status = tokio::process::Command::new("zoneadm")
+        .arg("-z")
+        .arg(pzone.name())
+        .arg("detach")
+        .stderr(std::process::Stdio::null())
+        .status()
+        .await?;
+    if !status.success() {
+        return Err(anyhow!("Couldn't detach zone {}", pzone.name()));
+    }
+
+    zone::Config::new(pzone.name()).delete(true).run_blocking()?;
+    // Only read when attaching the zone again
+    match std::fs::remove_file(Path::new(&pzone.path()).join("SUNWdetached.xml")) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Wait for the zone to reach the multi-user milestone, failing after `timeout`
+pub async fn wait_for_boot(pzone: &PipelineZone, timeout: Duration) -> Result<()> {
+    let started = std::time::Instant::now();
+
+    loop {
+        let output = pzone
+            .exec("svcs -H -o state svc:/milestone/multi-user:default")?
+            .wait_with_output()
+            .await?;
+        if String::from_utf8_lossy(&output.stdout).trim() == "online" {
+            return Ok(());
+        }
+        if started.elapsed() >= timeout {
+            return Err(anyhow!(
+                "Zone {} didn't boot within {}",
+                pzone.name(),
+                crate::policy::format_duration(timeout)
+            ));
+        }
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+    }
+}
+
+/// Create a dedicated swap volume for the zone and hand it the device
+async fn add_swap_device(pzone: &PipelineZone, size: &str) -> Result<()> {
+    crate::zfs::create_volume(&pzone.swap_volume(), size).await?;
+
+    let mut cfg = zone::Config::new(pzone.name());
+    cfg.add_device(&zone::Device {
+        name: format!("/dev/zvol/dsk/{}", pzone.swap_volume()),
+    });
+    cfg.run_blocking()?;
+
+    Ok(())
+}
+
+/// Mount the pipeline's pkgin cache dataset over the zone's pkgin cache so
+/// packages required by steps are only downloaded once
+async fn add_pkgin_cache(pzone: &PipelineZone) -> Result<()> {
+    let dataset = pzone.pkgin_cache_dataset();
+    if !crate::zfs::base_dataset_exists(&dataset).await? {
+        crate::zfs::create_dataset(&dataset).await?;
+    }
+
+    let mut cfg = zone::Config::new(pzone.name());
+    cfg.add_fs(&zone::Fs {
+        ty: "lofs".to_string(),
+        dir: crate::config::PKGIN_CACHE_DIR.to_string(),
+        special: format!("{}/pkgin-cache", pzone.root_path()),
+        ..Default::default()
+    });
+    cfg.run_blocking()?;
+
+    Ok(())
+}
+
+/// Mount the dataset backing `cache` on its path in the zone, creating it
+/// on first use, so what runs leave there is found by the next ones
+async fn add_build_cache(pzone: &PipelineZone, cache: &crate::config::ValidatedBuildCache) -> Result<()> {
+    let dataset = cache.dataset(pzone);
+    if !crate::zfs::base_dataset_exists(&dataset).await? {
+        crate::zfs::create_dataset(&dataset).await?;
+    }
+
+    let mut cfg = zone::Config::new(pzone.name());
+    cfg.add_fs(&zone::Fs {
+        ty: "lofs".to_string(),
+        dir: cache.path.clone(),
+        special: cache.mountpoint(pzone),
+        ..Default::default()
+    });
+    cfg.run_blocking()?;
+
+    Ok(())
+}
+
 pub async fn configure_zone_with_default_config(pzone: &PipelineZone) -> Result<()> {
+    configure_zone_on(pzone, &pzone.vnic_name()).await
+}
+
+/// Configure `pzone` with the default config, networked through `vnic`
+async fn configure_zone_on(pzone: &PipelineZone, vnic: &str) -> Result<()> {
     let mut cfg = zone::Config::create(pzone.name(), true, zone::CreationOptions::Default);
 
     cfg.get_global()
@@ -150,7 +562,7 @@ pub async fn configure_zone_with_default_config(pzone: &PipelineZone) -> Result<
         .set_autoboot(false);
 
     cfg.add_net(&zone::Net {
-        physical: pzone.vnic_name(),
+        physical: vnic.to_string(),
         ..Default::default()
     });
 
@@ -164,8 +576,8 @@ pub async fn configure_zone_with_default_config(pzone: &PipelineZone) -> Result<
     Ok(())
 }
 
-pub async fn configure_zone_networking(pzone: &PipelineZone) -> Result<()> {
-    let ip = "10.0.0.100";
+pub async fn configure_zone_networking(pzone: &PipelineZone, subnet: &Subnet) -> Result<()> {
+    let ip = subnet.zone_address();
     let command = format!("ipadm create-ip {}", pzone.vnic_name());
     let _status = tokio::process::Command::new("zlogin")
         .arg(pzone.name())
@@ -173,8 +585,9 @@ pub async fn configure_zone_networking(pzone: &PipelineZone) -> Result<()> {
         .status()
         .await?;
     let command = format!(
-        "ipadm create-addr -T static -a {} {}/v4",
+        "ipadm create-addr -T static -a {}/{} {}/v4",
         ip,
+        subnet.prefix(),
         pzone.vnic_name()
     );
     let _status = tokio::process::Command::new("zlogin")
@@ -182,7 +595,7 @@ pub async fn configure_zone_networking(pzone: &PipelineZone) -> Result<()> {
         .arg(command)
         .status()
         .await?;
-    let command = "route -p add default 10.0.0.1";
+    let command = format!("route -p add default {}", subnet.gateway());
     let _status = tokio::process::Command::new("zlogin")
         .arg(pzone.name())
         .arg(command)
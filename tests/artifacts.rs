@@ -0,0 +1,117 @@
+use renzokutai::config::ArtifactPath;
+use renzokutai::zones::CHECKOUT_DIR;
+use std::path::{Path, PathBuf};
+
+fn zone(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("renzokutai-artifacts-{}-{}", name, std::process::id()));
+    let checkout = root.join("zone/opt/renzokutai/checkout/dist");
+    std::fs::create_dir_all(checkout.join("docs")).unwrap();
+    std::fs::create_dir_all(root.join("zone/tmp")).unwrap();
+    std::fs::write(checkout.join("app.tgz"), "app").unwrap();
+    std::fs::write(checkout.join("app.sig"), "sig").unwrap();
+    std::fs::write(checkout.join("docs/index.html"), "docs").unwrap();
+    std::fs::write(root.join("zone/tmp/test.log"), "log").unwrap();
+    root
+}
+
+fn read(path: &Path) -> String {
+    std::fs::read_to_string(path).unwrap()
+}
+
+#[test]
+fn parses_and_validates_globs() {
+    let paths = ArtifactPath::parse_list("dist/*.tgz, /tmp/*.log,").unwrap();
+    assert_eq!(paths.iter().map(|p| p.to_string()).collect::<Vec<_>>(), ["dist/*.tgz", "/tmp/*.log"]);
+
+    assert!(ArtifactPath::parse_list("../etc/passwd").is_err());
+    assert!(ArtifactPath::parse_list("dist/[").is_err());
+}
+
+#[test]
+fn copies_matching_files_out_of_the_zone() {
+    let root = zone("copy");
+    let target = root.join("collected");
+    let paths = ArtifactPath::parse_list("dist/*.tgz,dist/docs,/tmp/*.log,missing/*").unwrap();
+
+    let collected = ArtifactPath::collect(&paths, &root.join("zone"), CHECKOUT_DIR, &target).unwrap();
+
+    assert_eq!(
+        collected,
+        ["opt/renzokutai/checkout/dist/app.tgz", "opt/renzokutai/checkout/dist/docs", "tmp/test.log"].map(PathBuf::from)
+    );
+    assert_eq!(read(&target.join("opt/renzokutai/checkout/dist/app.tgz")), "app");
+    assert_eq!(read(&target.join("opt/renzokutai/checkout/dist/docs/index.html")), "docs");
+    assert_eq!(read(&target.join("tmp/test.log")), "log");
+    assert!(!target.join("opt/renzokutai/checkout/dist/app.sig").exists());
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn matches_relative_paths_from_the_workdir() {
+    let root = zone("workdir");
+    let target = root.join("collected");
+    let paths = ArtifactPath::parse_list("*.log").unwrap();
+
+    let collected = ArtifactPath::collect(&paths, &root.join("zone"), "/tmp", &target).unwrap();
+
+    assert_eq!(collected, [PathBuf::from("tmp/test.log")]);
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn names_produced_paths_exactly() {
+    assert!(ArtifactPath::parse_exact_list("dist/*.tgz").is_err());
+    let produced = ArtifactPath::parse_exact_list("dist/, app.tgz").unwrap();
+
+    assert!(produced[0].covers("dist"));
+    assert!(produced[0].covers("dist/docs/index.html"));
+    assert!(!produced[0].covers("distribution"));
+    assert!(produced[1].covers("app.tgz"));
+    assert!(!produced[1].covers("app"));
+}
+
+#[test]
+fn refuses_links_out_of_the_zone() {
+    let root = zone("link");
+    std::os::unix::fs::symlink(&root, root.join("zone/opt/renzokutai/checkout/dist/escape")).unwrap();
+    let paths = ArtifactPath::parse_list("dist/escape").unwrap();
+
+    assert!(ArtifactPath::collect(&paths, &root.join("zone"), CHECKOUT_DIR, &root.join("collected")).is_err());
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn restores_collected_files_into_the_zone() {
+    let root = zone("restore");
+    let collected = root.join("collected");
+    let paths = ArtifactPath::parse_list("dist/*.tgz,dist/docs").unwrap();
+    ArtifactPath::collect(&paths, &root.join("zone"), CHECKOUT_DIR, &collected).unwrap();
+    std::fs::remove_dir_all(root.join("zone/opt/renzokutai/checkout/dist")).unwrap();
+
+    ArtifactPath::restore(&collected, &root.join("zone")).unwrap();
+
+    assert_eq!(read(&root.join("zone/opt/renzokutai/checkout/dist/app.tgz")), "app");
+    assert_eq!(read(&root.join("zone/opt/renzokutai/checkout/dist/docs/index.html")), "docs");
+    assert!(!root.join("zone/opt/renzokutai/checkout/dist/app.sig").exists());
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn refuses_to_restore_through_links() {
+    let root = zone("restore-link");
+    let collected = root.join("collected");
+    let paths = ArtifactPath::parse_list("dist/*.tgz").unwrap();
+    ArtifactPath::collect(&paths, &root.join("zone"), CHECKOUT_DIR, &collected).unwrap();
+    std::fs::remove_dir_all(root.join("zone/opt/renzokutai/checkout/dist")).unwrap();
+    std::fs::create_dir_all(root.join("outside")).unwrap();
+    std::os::unix::fs::symlink(root.join("outside"), root.join("zone/opt/renzokutai/checkout/dist")).unwrap();
+
+    assert!(ArtifactPath::restore(&collected, &root.join("zone")).is_err());
+    assert!(!root.join("outside/app.tgz").exists());
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
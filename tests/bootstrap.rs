@@ -0,0 +1,27 @@
+use renzokutai::bootstrap::Requirement;
+use std::os::unix::fs::PermissionsExt;
+
+#[tokio::test]
+async fn converges_directories_and_the_history_db() {
+    let root = std::env::temp_dir().join(format!("renzokutai-bootstrap-{}", std::process::id()));
+    let dir = Requirement::Directory {
+        path: root.join("db"),
+        mode: 0o700,
+    };
+
+    assert!(dir.converge().await.unwrap());
+    assert!(!dir.converge().await.unwrap());
+    let mode = |path: &std::path::Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+    assert_eq!(mode(&root.join("db")), 0o700);
+
+    std::fs::set_permissions(root.join("db"), std::fs::Permissions::from_mode(0o755)).unwrap();
+    assert!(dir.converge().await.unwrap());
+    assert_eq!(mode(&root.join("db")), 0o700);
+
+    let db = Requirement::HistoryDb(root.join("db").join("history.sqlite"));
+    assert!(db.converge().await.unwrap());
+    assert!(!db.converge().await.unwrap());
+    assert_eq!(db.to_string(), format!("history database {}", root.join("db/history.sqlite").display()));
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,1358 @@
+#![cfg(feature = "integration")]
+
+use renzokutai::config::{
+    CfgState, EnvVar, MatrixAxis, PIPELINES_DIR_VAR, Param, ProvisionLog, RepoCredential, RunOptions, RunnableSteps, Secret, Status,
+    ValidatedPipeline, find_cycle,
+};
+use renzokutai::zones::{CHECKOUT_DIR, Executor, PipelineZone};
+use std::ffi::OsStr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, Once};
+use std::time::Duration;
+
+static INIT: Once = Once::new();
+
+fn pipelines_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("renzokutai-it-{}", std::process::id()));
+
+    INIT.call_once(|| {
+        std::fs::create_dir_all(&dir).unwrap();
+        // SAFETY: set once before any test reads it
+        unsafe { std::env::set_var(PIPELINES_DIR_VAR, &dir) };
+    });
+
+    dir
+}
+
+async fn run(state: &mut CfgState, lines: &[&str]) {
+    for line in lines {
+        state.execute_line(line).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn configures_and_persists_pipeline() {
+    let dir = pipelines_dir();
+    let name = "katarineko".to_string();
+    let mut state = CfgState::new(&name).unwrap();
+
+    run(
+        &mut state,
+        &[
+            "add package",
+            "set name=rust",
+            "set provider=pkgsrc",
+            "end",
+            "add repo",
+            "set url=https://github.com/MarceColl/katarineko",
+            "end",
+            "add step",
+            "set name=build",
+            "set script=build.sh",
+            "end",
+        ],
+    )
+    .await;
+
+    state.execute_line("save").await.unwrap();
+
+    let xml = std::fs::read_to_string(dir.join("katarineko.xml")).unwrap();
+    assert!(xml.contains(r#"name="katarineko""#));
+    assert!(xml.contains(r#"provider="pkgsrc""#));
+    assert!(xml.contains(r#"url="https://github.com/MarceColl/katarineko""#));
+    assert!(xml.contains(r#"script="build.sh""#));
+
+    let loaded = ValidatedPipeline::load(&name).unwrap().unwrap();
+    assert_eq!(loaded.name, name);
+
+    // Reopening the shell picks up the saved pipeline
+    let mut state = CfgState::new(&name).unwrap();
+    run(&mut state, &["select step name=build", "set script=ci.sh", "end", "save"]).await;
+
+    let xml = std::fs::read_to_string(dir.join("katarineko.xml")).unwrap();
+    assert!(xml.contains(r#"script="ci.sh""#));
+    assert!(!xml.contains(r#"script="build.sh""#));
+}
+
+#[tokio::test]
+async fn rejects_incomplete_pipeline() {
+    pipelines_dir();
+    let mut state = CfgState::new(&"incomplete".to_string()).unwrap();
+
+    run(&mut state, &["add step", "set name=build", "end"]).await;
+
+    assert!(state.validate().is_err());
+}
+
+#[tokio::test]
+async fn rejects_unknown_commands_and_attributes() {
+    pipelines_dir();
+    let mut state = CfgState::new(&"unknown".to_string()).unwrap();
+
+    assert!(state.execute_line("frobnicate").await.is_err());
+    assert!(state.execute_line("set colour=blue").await.is_err());
+    assert!(state.execute_line("unset colour").await.is_err());
+}
+
+#[tokio::test]
+async fn unset_clears_attributes() {
+    pipelines_dir();
+    let mut state = CfgState::new(&"unsetting".to_string()).unwrap();
+
+    run(
+        &mut state,
+        &["add repo", "set url=https://example.com/repo", "end"],
+    )
+    .await;
+    assert!(state.validate().is_ok());
+
+    run(&mut state, &["select repo", "unset url", "end"]).await;
+    assert!(state.validate().is_err());
+}
+
+#[tokio::test]
+async fn describes_who_owns_the_pipeline() {
+    pipelines_dir();
+    let mut state = CfgState::new(&"owned".to_string()).unwrap();
+
+    run(&mut state, &["set description=\"Builds the web frontend\" owner=web@example.org labels=team-web,nightly"]).await;
+    let vp = state.validate().unwrap();
+    assert_eq!(vp.description.as_deref(), Some("Builds the web frontend"));
+    assert_eq!(vp.owner.as_deref(), Some("web@example.org"));
+    assert_eq!(vp.labels, vec!["team-web", "nightly"]);
+
+    assert!(state.execute_line("set labels=\"team web\"").await.is_err());
+    run(&mut state, &["unset labels"]).await;
+    assert!(state.validate().unwrap().labels.is_empty());
+}
+
+#[tokio::test]
+async fn revert_discards_unsaved_changes() {
+    pipelines_dir();
+    let name = "reverting".to_string();
+    let mut state = CfgState::new(&name).unwrap();
+
+    run(
+        &mut state,
+        &["add repo", "set url=https://example.com/repo", "end", "save"],
+    )
+    .await;
+
+    run(&mut state, &["add step", "set name=build", "revert"]).await;
+    let vp = state.validate().unwrap();
+    assert_eq!(vp.repos.iter().count(), 1);
+    assert_eq!(vp.steps, Vec::new().into());
+    assert_eq!(state.prompt(), CfgState::new(&name).unwrap().prompt());
+}
+
+#[tokio::test]
+async fn rejects_overlapping_subnets() {
+    pipelines_dir();
+
+    let mut first = CfgState::new(&"subnet_a".to_string()).unwrap();
+    run(&mut first, &["set subnet=10.200.0.0/24", "save"]).await;
+
+    let mut second = CfgState::new(&"subnet_b".to_string()).unwrap();
+    run(&mut second, &["set subnet=10.200.0.0/16"]).await;
+    // Only saving and applying look at the other pipelines
+    assert!(second.validate().is_ok());
+    let err = second.claimed().unwrap_err().to_string();
+    assert!(err.contains("subnet_a"), "{}", err);
+
+    run(&mut second, &["unset subnet"]).await;
+    assert_eq!(second.validate().unwrap().subnet, None);
+    let allocated = second.claimed().unwrap().subnet.unwrap();
+    assert_ne!(allocated, "10.200.0.0/24");
+
+    run(&mut second, &["save"]).await;
+    assert!(!second.is_dirty());
+    assert_eq!(second.diff().unwrap(), "");
+}
+
+#[tokio::test]
+async fn selects_by_index_when_ambiguous() {
+    pipelines_dir();
+    let mut state = CfgState::new(&"ambiguous".to_string()).unwrap();
+
+    run(
+        &mut state,
+        &[
+            "add step", "set name=build", "set script=a.sh", "end",
+            "add step", "set name=build", "set script=b.sh", "end",
+        ],
+    )
+    .await;
+
+    let err = state.execute_line("select step name=build").await.unwrap_err().to_string();
+    assert!(err.contains("  1: ") && err.contains("  2: "), "{}", err);
+
+    run(&mut state, &["select step 2", "unset script", "end"]).await;
+    let errors = state.validate().unwrap_err().to_string();
+    assert!(errors.contains("script"), "{}", errors);
+
+    assert!(state.execute_line("select step 3").await.is_err());
+}
+
+#[tokio::test]
+async fn copies_elements() {
+    pipelines_dir();
+    let mut state = CfgState::new(&"copying".to_string()).unwrap();
+
+    run(
+        &mut state,
+        &[
+            "add step", "set name=build", "set script=build.sh", "end",
+            "copy step name=build build_release", "set script=release.sh", "end",
+        ],
+    )
+    .await;
+
+    let vp = state.validate().unwrap();
+    let steps: Vec<(String, String)> = vp
+        .steps
+        .iter()
+        .map(|s| (s.name.clone(), s.script.clone()))
+        .collect();
+    assert_eq!(
+        steps,
+        vec![
+            ("build".to_string(), "build.sh".to_string()),
+            ("build_release".to_string(), "release.sh".to_string()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn exports_pipeline() {
+    let dir = pipelines_dir().join("exports");
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut state = CfgState::new(&"exporting".to_string()).unwrap();
+    let xml = dir.join("exported.xml");
+    let json = dir.join("exported.json");
+
+    run(
+        &mut state,
+        &[
+            "add repo",
+            "set url=https://example.com/repo",
+            "end",
+            &format!("export {}", xml.display()),
+            &format!("export --format json {}", json.display()),
+        ],
+    )
+    .await;
+
+    let vp = state.validate().unwrap();
+    let from_xml: ValidatedPipeline =
+        serde_xml_rs::from_str(&std::fs::read_to_string(&xml).unwrap()).unwrap();
+    assert_eq!(from_xml, vp);
+
+    let json = std::fs::read_to_string(&json).unwrap();
+    assert!(json.contains("https://example.com/repo"), "{}", json);
+}
+
+#[tokio::test]
+async fn imports_elements_from_other_pipelines() {
+    pipelines_dir();
+
+    let mut toolchain = CfgState::new(&"toolchain".to_string()).unwrap();
+    run(
+        &mut toolchain,
+        &[
+            "add package", "set name=rust", "set provider=pkg", "end",
+            "add step", "set name=fmt", "set script=fmt.sh", "end",
+            "save",
+        ],
+    )
+    .await;
+
+    let mut state = CfgState::new(&"importing".to_string()).unwrap();
+    run(&mut state, &["import toolchain package"]).await;
+    let vp = state.validate().unwrap();
+    assert_eq!(vp.packages.iter().count(), 1);
+    assert_eq!(vp.steps.iter().count(), 0);
+
+    run(&mut state, &["import toolchain"]).await;
+    let vp = state.validate().unwrap_or_else(|e| panic!("{:?}", e));
+    assert_eq!(vp.packages.iter().count(), 2);
+    assert_eq!(vp.steps.iter().count(), 1);
+
+    assert!(state.execute_line("import missing").await.is_err());
+}
+
+#[tokio::test]
+async fn edits_step_dependencies() {
+    pipelines_dir();
+    let mut state = CfgState::new(&"depending".to_string()).unwrap();
+
+    run(
+        &mut state,
+        &[
+            "add step", "set name=build", "set script=build.sh", "end",
+            "add step", "set name=test", "set script=test.sh", "depends add build", "end",
+        ],
+    )
+    .await;
+
+    let vp = state.validate().unwrap();
+    let test = vp.steps.iter().find(|s| s.name == "test").unwrap();
+    assert_eq!(test.depends.iter().map(|d| d.name.as_str()).collect::<Vec<_>>(), vec!["build"]);
+
+    run(&mut state, &["select step name=test"]).await;
+    assert!(state.execute_line("depends add build").await.is_err());
+    assert!(state.execute_line("depends add test").await.is_err());
+    run(&mut state, &["depends add lint", "end"]).await;
+    assert!(state.validate().is_err());
+
+    run(&mut state, &["select step name=test", "depends remove lint", "end"]).await;
+    assert!(state.validate().is_ok());
+
+    assert!(state.execute_line("depends add build").await.is_err());
+}
+
+#[tokio::test]
+async fn sets_step_dependencies_from_a_list() {
+    pipelines_dir();
+    let mut state = CfgState::new(&"listed".to_string()).unwrap();
+
+    run(
+        &mut state,
+        &[
+            "add step", "set name=build script=build.sh", "end",
+            "add step", "set name=lint script=lint.sh", "end",
+            "add step", "set name=test script=test.sh", "set depends=build,lint", "end",
+        ],
+    )
+    .await;
+
+    let depends = |state: &CfgState| {
+        let vp = state.validate().unwrap();
+        let test = vp.steps.iter().find(|s| s.name == "test").unwrap();
+        test.depends.iter().map(|d| d.name.clone()).collect::<Vec<_>>()
+    };
+    assert_eq!(depends(&state), vec!["build", "lint"]);
+
+    // Rejected lists leave the dependencies as they were
+    run(&mut state, &["select step name=test"]).await;
+    assert!(state.execute_line("set depends=build,build").await.is_err());
+    assert!(state.execute_line("set depends=lint,test").await.is_err());
+    run(&mut state, &["end"]).await;
+    assert_eq!(depends(&state), vec!["build", "lint"]);
+
+    run(&mut state, &["select step name=test", "set depends=\"lint, build\"", "end"]).await;
+    assert_eq!(depends(&state), vec!["lint", "build"]);
+
+    run(&mut state, &["select step name=test", "set depends=deploy", "end"]).await;
+    assert!(state.validate().is_err());
+}
+
+#[tokio::test]
+async fn rejects_dependency_cycles() {
+    pipelines_dir();
+    let mut state = CfgState::new(&"cyclic".to_string()).unwrap();
+
+    run(
+        &mut state,
+        &[
+            "add step", "set name=fetch script=fetch.sh", "end",
+            "add step", "set name=build script=build.sh depends=fetch,test", "end",
+            "add step", "set name=test script=test.sh depends=build", "end",
+        ],
+    )
+    .await;
+
+    let err = state.validate().unwrap_err().to_string();
+    assert!(err.contains("build -> test -> build"), "{}", err);
+
+    run(&mut state, &["select step name=build", "set depends=fetch", "end"]).await;
+    assert!(state.validate().is_ok());
+}
+
+#[tokio::test]
+async fn diffs_unsaved_changes() {
+    pipelines_dir();
+    let mut state = CfgState::new(&"diffing".to_string()).unwrap();
+
+    run(
+        &mut state,
+        &["add repo", "set url=https://example.com/repo", "end", "save"],
+    )
+    .await;
+    assert_eq!(state.diff().unwrap(), "");
+
+    run(&mut state, &["select repo", "set url=https://example.com/fork", "end"]).await;
+    let diff = state.diff().unwrap();
+    assert!(diff.contains("-    <repo url=\"https://example.com/repo\" />"), "{}", diff);
+    assert!(diff.contains("+    <repo url=\"https://example.com/fork\" />"), "{}", diff);
+
+    run(&mut state, &["save", "secret set TOKEN=hunter2"]).await;
+    let diff = state.diff().unwrap();
+    assert!(diff.contains("+secret TOKEN=********"), "{}", diff);
+    run(&mut state, &["save", "secret set TOKEN=hunter3"]).await;
+    let diff = state.diff().unwrap();
+    assert!(diff.contains("+secret TOKEN=******** (changed)"), "{}", diff);
+    assert!(!diff.contains("hunter"), "{}", diff);
+}
+
+#[tokio::test]
+async fn undoes_and_redoes_changes() {
+    pipelines_dir();
+    let mut state = CfgState::new(&"undoing".to_string()).unwrap();
+
+    run(&mut state, &["add repo", "set url=https://example.com/repo", "end"]).await;
+    assert_eq!(state.validate().unwrap().repos.iter().count(), 1);
+
+    // Rolls back the url, then the repo itself
+    run(&mut state, &["undo"]).await;
+    assert!(state.validate().is_err());
+    assert!(state.prompt().contains("repo"), "{}", state.prompt());
+    run(&mut state, &["undo"]).await;
+    assert_eq!(state.validate().unwrap().repos.iter().count(), 0);
+    assert!(state.execute_line("undo").await.is_err());
+
+    run(&mut state, &["redo", "redo"]).await;
+    let vp = state.validate().unwrap();
+    assert_eq!(vp.repos.iter().next().unwrap().url, "https://example.com/repo");
+    assert!(state.execute_line("redo").await.is_err());
+
+    // A new change drops what could still be redone
+    run(&mut state, &["undo", "end", "set swap=1G"]).await;
+    assert!(state.execute_line("redo").await.is_err());
+}
+
+#[tokio::test]
+async fn tracks_unsaved_changes() {
+    pipelines_dir();
+    let mut state = CfgState::new(&"dirty".to_string()).unwrap();
+    assert!(!state.is_dirty());
+
+    run(&mut state, &["set swap=1G"]).await;
+    assert!(state.is_dirty());
+    assert!(state.prompt().contains('*'), "{}", state.prompt());
+
+    run(&mut state, &["undo"]).await;
+    assert!(!state.is_dirty());
+
+    run(&mut state, &["add repo", "set url=https://example.com/repo", "end", "save"]).await;
+    assert!(!state.is_dirty());
+    assert!(!state.prompt().contains('*'), "{}", state.prompt());
+
+    run(&mut state, &["select repo", "set url=https://example.com/fork"]).await;
+    assert!(state.is_dirty());
+    run(&mut state, &["revert"]).await;
+    assert!(!state.is_dirty());
+}
+
+#[tokio::test]
+async fn disables_pipelines() {
+    pipelines_dir();
+    let name = "retired".to_string();
+    let mut state = CfgState::new(&name).unwrap();
+
+    assert!(state.execute_line("set enabled=maybe").await.is_err());
+    run(&mut state, &["set enabled=false", "save"]).await;
+
+    let vp = ValidatedPipeline::load(&name).unwrap().unwrap();
+    assert!(!vp.enabled);
+    let err = vp.run(&Default::default()).await.unwrap_err();
+    assert!(err.to_string().contains("disabled"), "{}", err);
+
+    run(&mut state, &["unset enabled", "save"]).await;
+    assert!(ValidatedPipeline::load(&name).unwrap().unwrap().enabled);
+}
+
+#[tokio::test]
+async fn saves_the_limit_of_steps_running_at_once() {
+    pipelines_dir();
+    let name = "throttled".to_string();
+    let mut state = CfgState::new(&name).unwrap();
+
+    for value in ["0", "-1", "two"] {
+        assert!(state.execute_line(&format!("set max_parallel={}", value)).await.is_err(), "{}", value);
+    }
+    run(&mut state, &["set max_parallel=2", "save"]).await;
+    assert_eq!(ValidatedPipeline::load(&name).unwrap().unwrap().max_parallel, Some(2));
+
+    run(&mut state, &["unset max_parallel", "save"]).await;
+    assert_eq!(ValidatedPipeline::load(&name).unwrap().unwrap().max_parallel, None);
+}
+
+#[tokio::test]
+async fn runs_steps_as_the_configured_user() {
+    pipelines_dir();
+    let name = "unprivileged".to_string();
+    let mut state = CfgState::new(&name).unwrap();
+
+    assert!(state.execute_line("set user=Root").await.is_err());
+    run(
+        &mut state,
+        &[
+            "set user=builder",
+            "add step",
+            "set name=build script=build.sh",
+            "end",
+            "add step",
+            "set name=publish script=publish.sh",
+            "set user=deployer",
+            "end",
+            "save",
+        ],
+    )
+    .await;
+
+    let vp = ValidatedPipeline::load(&name).unwrap().unwrap();
+    assert_eq!(vp.user.as_deref(), Some("builder"));
+    let steps = vp.steps.with_user(vp.user.as_deref());
+    let users: Vec<_> = steps.iter().map(|s| s.user.as_deref()).collect();
+    assert_eq!(users, vec![Some("builder"), Some("deployer")]);
+    assert_eq!(steps.users(), vec!["builder".to_string(), "deployer".to_string()]);
+
+    let publish = steps.iter().nth(1).unwrap();
+    assert_eq!(publish.as_user("cd /tmp && ls".to_string()), "su - deployer -c 'cd /tmp && ls'");
+}
+
+#[tokio::test]
+async fn expands_run_parameters() {
+    pipelines_dir();
+    let name = "parameterized".to_string();
+    let mut state = CfgState::new(&name).unwrap();
+
+    run(
+        &mut state,
+        &[
+            "param set VERSION=dev",
+            "env set RELEASE=v${param.VERSION}",
+            "add step",
+            "set name=build script=build-${param.VERSION}.sh",
+            "env set OUT=dist/${param.VERSION}",
+            "end",
+        ],
+    )
+    .await;
+    let vp = state.validate().unwrap();
+    assert_eq!(vp.params, vec![Param { name: "VERSION".to_string(), default: "dev".to_string() }]);
+
+    let options = RunOptions {
+        params: vec!["VERSION=1.2".parse().unwrap()],
+        ..RunOptions::default()
+    };
+    let context = vp.run_context(&options, &[]).unwrap();
+    assert_eq!(context.env, vec![EnvVar { name: "RELEASE".to_string(), value: "v1.2".to_string() }]);
+    let steps = vp.steps.with_params(&context.params).unwrap();
+    let build = steps.iter().next().unwrap();
+    assert_eq!(build.script, "build-1.2.sh");
+    assert_eq!(build.env[0].value, "dist/1.2");
+
+    let unknown = RunOptions {
+        params: vec!["TARGET=x86_64".parse().unwrap()],
+        ..RunOptions::default()
+    };
+    assert!(vp.run_context(&unknown, &[]).is_err());
+
+    run(&mut state, &["param unset VERSION"]).await;
+    assert!(state.validate().is_err());
+    assert!(state.execute_line("select step").await.is_ok());
+    assert!(state.execute_line("param set VERSION=dev").await.is_err());
+}
+
+#[tokio::test]
+async fn inherits_from_the_extended_pipeline() {
+    pipelines_dir();
+    let mut common = CfgState::new(&"common".to_string()).unwrap();
+    run(
+        &mut common,
+        &[
+            "add package", "set name=rust provider=pkgsrc", "end",
+            "add repo", "set url=https://example.com/tools.git", "end",
+            "add step", "set name=setup script=setup.sh", "end",
+            "add step", "set name=lint script=lint.sh depends=setup", "end",
+            "param set SHA=main", "env set CC=gcc", "env set LANG=C", "secret set TOKEN=hunter2",
+            "save",
+        ],
+    )
+    .await;
+
+    let name = "extending".to_string();
+    let mut state = CfgState::new(&name).unwrap();
+    assert!(state.execute_line("set extends=extending").await.is_ok());
+    assert!(state.validate().is_err());
+    run(
+        &mut state,
+        &[
+            "set extends=common",
+            "add package", "set name=rust provider=pkgsrc", "end",
+            "add step", "set name=lint script=strict-lint.sh depends=setup", "end",
+            "add step", "set name=build script=build.sh depends=setup", "end",
+            "env set CC=clang",
+            "save",
+        ],
+    )
+    .await;
+
+    let own = ValidatedPipeline::load_own(&name).unwrap().unwrap();
+    assert_eq!(own.steps.iter().count(), 2);
+
+    let vp = ValidatedPipeline::load(&name).unwrap().unwrap();
+    assert_eq!(vp.packages.iter().count(), 1);
+    assert_eq!(vp.repos.iter().map(|r| r.url.as_str()).collect::<Vec<_>>(), vec!["https://example.com/tools.git"]);
+    let steps: Vec<_> = vp.steps.iter().map(|s| (s.name.as_str(), s.script.as_str())).collect();
+    assert_eq!(steps, vec![("setup", "setup.sh"), ("lint", "strict-lint.sh"), ("build", "build.sh")]);
+    assert_eq!(vp.params.iter().map(|p| p.name.as_str()).collect::<Vec<_>>(), vec!["SHA"]);
+    let env: Vec<_> = vp.env.iter().map(|e| (e.name.as_str(), e.value.as_str())).collect();
+    assert_eq!(env, vec![("LANG", "C"), ("CC", "clang")]);
+    assert_eq!(vp.secrets.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(), vec!["TOKEN"]);
+
+    // Renaming a step of the base leaves the steps depending on it dangling
+    run(&mut common, &["select step name=setup", "set name=prepare", "end", "select step name=lint", "set depends=prepare", "end", "save"]).await;
+    let err = ValidatedPipeline::load(&name).unwrap_err();
+    assert!(err.to_string().contains("non-existing step: setup"), "{}", err);
+
+    // The base can't extend what extends it
+    assert!(common.execute_line("set extends=extending").await.is_ok());
+    assert!(common.validate().is_err());
+}
+
+#[tokio::test]
+async fn orders_steps_by_stage() {
+    pipelines_dir();
+    let mut state = CfgState::new(&"staged".to_string()).unwrap();
+
+    assert!(state.execute_line("add step").await.is_ok());
+    assert!(state.execute_line("set stage=\"has space\"").await.is_err());
+    run(
+        &mut state,
+        &[
+            "set name=compile script=compile.sh stage=build", "end",
+            "add step", "set name=unit script=unit.sh stage=test", "end",
+            "add step", "set name=fetch script=fetch.sh", "end",
+            "add step", "set name=docs script=docs.sh stage=build", "end",
+        ],
+    )
+    .await;
+
+    let steps = state.validate().unwrap().steps;
+    assert_eq!(steps.stages(), vec!["build", "test"]);
+    let indices: Vec<_> = ["compile", "unit", "fetch", "docs"].iter().map(|s| steps.stage_index(s)).collect();
+    assert_eq!(indices, vec![0, 1, 0, 0]);
+
+    // A step can't wait for one that only runs in a later stage
+    run(&mut state, &["select step name=docs", "set depends=unit", "end"]).await;
+    assert!(state.validate().is_err());
+    run(&mut state, &["select step name=unit", "set depends=docs", "end", "select step name=docs", "unset depends", "end"]).await;
+    assert!(state.validate().is_ok());
+}
+
+#[tokio::test]
+async fn runs_finalizer_steps_after_the_others() {
+    pipelines_dir();
+    let mut state = CfgState::new(&"finalized".to_string()).unwrap();
+
+    assert!(state.execute_line("add step").await.is_ok());
+    assert!(state.execute_line("set run=sometimes").await.is_err());
+    run(
+        &mut state,
+        &[
+            "set name=build script=build.sh", "end",
+            "add step", "set name=upload script=upload.sh run=always depends=build", "end",
+        ],
+    )
+    .await;
+
+    let steps = state.validate().unwrap().steps;
+    let always: Vec<_> = steps.iter().map(|s| s.always_runs()).collect();
+    assert_eq!(always, vec![false, true]);
+
+    // Steps that always run start once the others are over
+    run(&mut state, &["add step", "set name=test script=test.sh depends=upload", "end"]).await;
+    assert!(state.validate().is_err());
+}
+
+#[tokio::test]
+async fn consumes_what_a_dependency_produces() {
+    pipelines_dir();
+    let mut state = CfgState::new(&"handoff".to_string()).unwrap();
+
+    assert!(state.execute_line("add step").await.is_ok());
+    assert!(state.execute_line("set produces=dist/*").await.is_err());
+    run(
+        &mut state,
+        &[
+            "set name=build script=build.sh produces=dist", "end",
+            "add step", "set name=test script=test.sh depends=build", "end",
+            "add step", "set name=package script=package.sh depends=test consumes=dist/app.tgz", "end",
+        ],
+    )
+    .await;
+
+    let steps = state.validate().unwrap().steps.with_producers();
+    let producers: Vec<_> = steps.iter().map(|s| s.producers.clone()).collect();
+    assert_eq!(producers, vec![vec![], vec![], vec!["build".to_string()]]);
+
+    // Only steps it waits for can produce what a step consumes
+    run(&mut state, &["select step name=package", "set depends=", "end"]).await;
+    assert!(state.validate().is_err());
+    run(&mut state, &["select step name=package", "set consumes=docs depends=build", "end"]).await;
+    assert!(state.validate().is_err());
+}
+
+#[tokio::test]
+async fn sets_several_attributes_at_once() {
+    pipelines_dir();
+    let mut state = CfgState::new(&"multiset".to_string()).unwrap();
+
+    run(&mut state, &["add step", "set name=build script=build.sh", "end"]).await;
+    let vp = state.validate().unwrap();
+    let step = vp.steps.iter().next().unwrap();
+    assert_eq!((step.name.as_str(), step.script.as_str()), ("build", "build.sh"));
+
+    // Nothing is applied when one of the pairs is rejected
+    run(&mut state, &["select step"]).await;
+    assert!(state.execute_line("set name=test bogus=1").await.is_err());
+    assert!(state.execute_line("set name=test retires=3").await.is_err());
+    run(&mut state, &["end"]).await;
+    assert_eq!(state.validate().unwrap().steps.iter().next().unwrap().name, "build");
+}
+
+#[tokio::test]
+async fn writes_drone_scripts_on_save() {
+    let root = pipelines_dir().join("drone-repo");
+    std::fs::create_dir_all(&root).unwrap();
+    let drone = root.join(".drone.yml");
+    std::fs::write(&drone, "kind: pipeline\nsteps:\n- name: build\n  commands: [make]\n").unwrap();
+    let import = format!("import --drone {}", drone.display());
+    let script = root.join("ci/build.sh");
+    let mut state = CfgState::new(&"drone".to_string()).unwrap();
+
+    run(&mut state, &[&import, "undo"]).await;
+    run(&mut state, &["save"]).await;
+    assert!(!script.exists());
+
+    run(&mut state, &[&import]).await;
+    assert!(!script.exists());
+    run(&mut state, &["save"]).await;
+    assert_eq!(std::fs::read_to_string(&script).unwrap(), "set -e\nmake\n");
+}
+
+#[tokio::test]
+async fn reorders_steps() {
+    pipelines_dir();
+    let mut state = CfgState::new(&"reordering".to_string()).unwrap();
+    let order = |state: &CfgState| -> Vec<String> {
+        state.validate().unwrap().steps.iter().map(|s| s.name.clone()).collect()
+    };
+
+    run(
+        &mut state,
+        &[
+            "add step",
+            "set name=build script=build.sh",
+            "end",
+            "add step",
+            "set name=test script=test.sh",
+            "end",
+            "add step",
+            "set name=lint script=lint.sh",
+            "end",
+        ],
+    )
+    .await;
+
+    run(&mut state, &["move step lint before build"]).await;
+    assert_eq!(order(&state), ["lint", "build", "test"]);
+    run(&mut state, &["move step lint after test"]).await;
+    assert_eq!(order(&state), ["build", "test", "lint"]);
+    run(&mut state, &["undo"]).await;
+    assert_eq!(order(&state), ["lint", "build", "test"]);
+
+    assert!(state.execute_line("move step lint after lint").await.is_err());
+    assert!(state.execute_line("move step deploy after lint").await.is_err());
+    assert!(state.execute_line("move step lint after deploy").await.is_err());
+    assert_eq!(order(&state), ["lint", "build", "test"]);
+}
+
+#[tokio::test]
+async fn edits_step_environment() {
+    pipelines_dir();
+    let mut state = CfgState::new(&"environment".to_string()).unwrap();
+
+    run(
+        &mut state,
+        &[
+            "add step",
+            "set name=build script=build.sh",
+            "env set RUST_LOG=debug",
+            "env set CFLAGS=\"-O2 -g\"",
+            "env set RUST_LOG=info",
+            "env unset CFLAGS",
+        ],
+    )
+    .await;
+    assert!(state.execute_line("env unset CFLAGS").await.is_err());
+    assert!(state.execute_line("env set 1X=y").await.is_err());
+    run(&mut state, &["end", "env set RUST_LOG=warn", "env set TZ=UTC", "add repo"]).await;
+    assert!(state.execute_line("env set A=b").await.is_err());
+    run(&mut state, &["set url=https://example.com/repo.git", "end", "save"]).await;
+
+    let vp = ValidatedPipeline::load(&"environment".to_string()).unwrap().unwrap();
+    let names = |env: &[EnvVar]| env.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+    assert_eq!(names(&vp.env), ["RUST_LOG=warn", "TZ=UTC"]);
+    let step = vp.steps.iter().next().unwrap();
+    assert_eq!(names(&step.env), ["RUST_LOG=info"]);
+    let steps = vp.steps.with_env(&vp.env);
+    assert_eq!(names(&steps.iter().next().unwrap().env), ["TZ=UTC", "RUST_LOG=info"]);
+}
+
+#[tokio::test]
+async fn keeps_secrets_out_of_the_pipeline_file() {
+    let dir = pipelines_dir();
+    let mut state = CfgState::new(&"secretive".to_string()).unwrap();
+
+    run(&mut state, &["secret set DEPLOY_TOKEN=hunter2", "add step", "set name=deploy script=deploy.sh"]).await;
+    assert!(state.execute_line("secret set OTHER=x").await.is_err());
+    run(&mut state, &["end", "save"]).await;
+
+    let xml = std::fs::read_to_string(dir.join("secretive.xml")).unwrap();
+    assert!(!xml.contains("hunter2"));
+    let vp = ValidatedPipeline::load(&"secretive".to_string()).unwrap().unwrap();
+    assert_eq!(vp.secrets.len(), 1);
+    assert_eq!(vp.secrets[0].value, "hunter2");
+
+    run(&mut state, &["secret unset DEPLOY_TOKEN", "save"]).await;
+    assert!(!ValidatedPipeline::secrets_path(&"secretive".to_string()).exists());
+}
+
+#[tokio::test]
+async fn saves_the_matrix_of_the_pipeline() {
+    let mut state = CfgState::new(&"matrixed".to_string()).unwrap();
+
+    run(&mut state, &["matrix set RUST=1.80,1.81", "matrix set PKGSRC=\"2024Q4, 2025Q1\"", "add step"]).await;
+    assert!(state.execute_line("matrix set OS=x").await.is_err());
+    run(&mut state, &["set name=build script=build.sh", "end"]).await;
+    assert!(state.execute_line("matrix set OS=").await.is_err());
+    run(&mut state, &["matrix set RUST=1.82", "save"]).await;
+
+    let vp = ValidatedPipeline::load(&"matrixed".to_string()).unwrap().unwrap();
+    let axes: Vec<String> = vp.matrix.iter().map(|a| a.to_string()).collect();
+    assert_eq!(axes, ["RUST=1.82", "PKGSRC=2024Q4,2025Q1"]);
+    assert_eq!(MatrixAxis::combinations(&vp.matrix).len(), 2);
+}
+
+#[tokio::test]
+async fn saves_the_caches_of_the_pipeline() {
+    pipelines_dir();
+    let mut state = CfgState::new(&"cached".to_string()).unwrap();
+
+    run(&mut state, &["add cache"]).await;
+    assert!(state.execute_line("set path=relative/dir").await.is_err());
+    assert!(state.execute_line("set key=../escape").await.is_err());
+    run(&mut state, &["set path=/root/.cargo/registry key=cargo-registry", "end"]).await;
+    run(&mut state, &["copy cache key=cargo-registry target", "set path=/opt/renzokutai/checkout/target", "end"]).await;
+    run(&mut state, &["add step", "set name=build script=build.sh", "end", "save"]).await;
+
+    let vp = ValidatedPipeline::load(&"cached".to_string()).unwrap().unwrap();
+    let caches: Vec<(&str, &str)> = vp.caches.iter().map(|c| (c.key.as_str(), c.path.as_str())).collect();
+    assert_eq!(caches, [("cargo-registry", "/root/.cargo/registry"), ("target", "/opt/renzokutai/checkout/target")]);
+
+    run(&mut state, &["select cache key=target", "set path=/root/.cargo/registry", "end"]).await;
+    assert!(state.validate().is_err());
+}
+
+#[tokio::test]
+async fn saves_the_triggers_of_the_pipeline() {
+    pipelines_dir();
+    let mut state = CfgState::new(&"nightly".to_string()).unwrap();
+
+    run(&mut state, &["add trigger"]).await;
+    assert!(state.execute_line("set expr=\"0 25 * * *\"").await.is_err());
+    assert!(state.execute_line("set type=webhook").await.is_err());
+    run(&mut state, &["set expr=\"0 3 * * *\"", "end"]).await;
+    run(&mut state, &["add trigger", "set type=cron expr=@weekly", "end"]).await;
+    run(&mut state, &["add step", "set name=build script=build.sh", "end", "save"]).await;
+
+    let vp = ValidatedPipeline::load(&"nightly".to_string()).unwrap().unwrap();
+    let exprs: Vec<&str> = vp.triggers.iter().map(|t| t.expr.as_str()).collect();
+    assert_eq!(exprs, ["0 3 * * *", "@weekly"]);
+
+    run(&mut state, &["select trigger expr=@weekly", "unset expr", "end"]).await;
+    assert!(state.validate().is_err());
+}
+
+#[tokio::test]
+async fn saves_the_services_of_the_pipeline() {
+    pipelines_dir();
+    let mut state = CfgState::new(&"integration".to_string()).unwrap();
+
+    run(&mut state, &["add service"]).await;
+    assert!(state.execute_line("set name=\"my db\"").await.is_err());
+    assert!(state.execute_line("set ready_timeout=soon").await.is_err());
+    run(&mut state, &["set name=db script=\"postgres -D /db\" ready=pg_isready ready_timeout=2m", "end"]).await;
+    run(&mut state, &["add service", "set name=cache script=redis-server", "end"]).await;
+    run(&mut state, &["add step", "set name=test script=test.sh", "end", "save"]).await;
+
+    let vp = ValidatedPipeline::load(&"integration".to_string()).unwrap().unwrap();
+    let services: Vec<(&str, Option<&str>)> = vp.services.iter().map(|s| (s.name.as_str(), s.ready.as_deref())).collect();
+    assert_eq!(services, [("db", Some("pg_isready")), ("cache", None)]);
+
+    run(&mut state, &["select service name=cache", "set name=db", "end"]).await;
+    assert!(state.validate().is_err());
+}
+
+#[tokio::test]
+async fn hands_parameters_to_services_through_the_environment() {
+    pipelines_dir();
+    let executor = Arc::new(MockExecutor::default());
+    let mut state = CfgState::new(&"service_params".to_string()).unwrap();
+    run(
+        &mut state,
+        &[
+            "param set PORT=5432",
+            "add service",
+            "set name=db script=\"postgres -p ${param.PORT}\" ready=\"pg_isready -p ${param.PORT}\"",
+            "end",
+            "add step",
+            "set name=test script=test.sh",
+            "end",
+        ],
+    )
+    .await;
+    let vp = state.validate().unwrap();
+    let pzone = vp.base_pzone().with_executor(executor.clone());
+    let params = [EnvVar { name: "PORT".to_string(), value: "1; touch /pwned".to_string() }];
+
+    vp.services.start(&pzone, &params).await.unwrap();
+    vp.services.stop(&pzone).await.unwrap();
+
+    assert!(executor.ran("postgres -p ${RENZOKUTAI_PARAM_PORT}"));
+    assert!(executor.ran(". ~/.profile && export RENZOKUTAI_PARAM_PORT='1; touch /pwned' && pg_isready -p ${RENZOKUTAI_PARAM_PORT}"));
+    assert!(!executor.ran("-p 1; touch"));
+    assert!(executor.ran("pkill -TERM -c $ctid"));
+}
+
+#[tokio::test]
+async fn keeps_the_format_pipelines_are_saved_in() {
+    let dir = pipelines_dir();
+    std::fs::write(
+        dir.join("handwritten.toml"),
+        "\"@name\" = \"handwritten\"\n\n[[steps.step]]\n\"@name\" = \"build\"\n\"@script\" = \"build.sh\"\n",
+    )
+    .unwrap();
+
+    let vp = ValidatedPipeline::load(&"handwritten".to_string()).unwrap().unwrap();
+    assert_eq!(vp.steps.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(), ["build"]);
+    assert!(ValidatedPipeline::list().unwrap().iter().any(|p| p.name == "handwritten"));
+
+    let mut state = CfgState::new(&"handwritten".to_string()).unwrap();
+    run(&mut state, &["add step", "set name=test script=test.sh", "end", "save"]).await;
+    assert!(!dir.join("handwritten.xml").exists());
+    let saved = std::fs::read_to_string(dir.join("handwritten.toml")).unwrap();
+    assert!(saved.contains("test.sh"));
+}
+
+#[test]
+fn upgrades_pipelines_saved_in_an_older_schema() {
+    let dir = pipelines_dir();
+    std::fs::write(
+        dir.join("legacy.xml"),
+        r#"<pipeline name="legacy" subnet="10.0.99.0/24"><steps><step name="build" script="build.sh"/><step name="test" script="test.sh" depends="build"/></steps></pipeline>"#,
+    )
+    .unwrap();
+
+    let vp = ValidatedPipeline::load(&"legacy".to_string()).unwrap().unwrap();
+    let test = vp.steps.iter().find(|s| s.name == "test").unwrap();
+    assert_eq!(test.depends.iter().map(|d| d.name.as_str()).collect::<Vec<_>>(), ["build"]);
+
+    let saved = std::fs::read_to_string(dir.join("legacy.xml")).unwrap();
+    assert!(saved.contains(r#"version="2""#), "{}", saved);
+    assert!(!saved.contains("depends="), "{}", saved);
+}
+
+#[test]
+fn bounds_the_retries_of_loaded_pipelines() {
+    let dir = pipelines_dir();
+    std::fs::write(
+        dir.join("flaky.xml"),
+        r#"<pipeline version="2" name="flaky"><steps><step name="build" script="build.sh" retries="50"/></steps></pipeline>"#,
+    )
+    .unwrap();
+
+    let err = CfgState::new(&"flaky".to_string()).unwrap().validate().unwrap_err();
+    assert!(err.to_string().contains("retries"), "{}", err);
+}
+
+#[tokio::test]
+async fn clones_private_repos_with_a_secret() {
+    pipelines_dir();
+    let mut state = CfgState::new(&"private".to_string()).unwrap();
+
+    run(&mut state, &["add repo", "set url=/srv/git/app"]).await;
+    assert!(state.execute_line("set credential=DEPLOY_KEY").await.is_ok());
+    assert!(state.validate().is_err());
+    run(&mut state, &["set url=git@github.com:org/app.git", "end"]).await;
+    run(&mut state, &["add step", "set name=build script=build.sh", "end"]).await;
+    assert!(state.validate().is_err());
+
+    run(&mut state, &["secret set DEPLOY_KEY=key", "save"]).await;
+    let vp = ValidatedPipeline::load(&"private".to_string()).unwrap().unwrap();
+    let repo = vp.repos.iter().next().unwrap();
+    assert_eq!(repo.credential.as_deref(), Some("DEPLOY_KEY"));
+}
+
+#[tokio::test]
+async fn runs_the_steps_in_the_dest_of_the_first_repo() {
+    pipelines_dir();
+    let mut state = CfgState::new(&"dest".to_string()).unwrap();
+
+    run(&mut state, &["add step", "set name=build script=build.sh", "end"]).await;
+    assert_eq!(state.validate().unwrap().workdir(), CHECKOUT_DIR);
+
+    run(&mut state, &["add repo", "set url=https://example.com/app.git dest=/srv/app", "end"]).await;
+    run(&mut state, &["add repo", "set url=https://example.com/tools.git dest=/srv/tools", "end"]).await;
+    assert_eq!(state.validate().unwrap().workdir(), "/srv/app");
+}
+
+#[tokio::test]
+async fn quotes_the_dest_steps_run_in() {
+    let executor = Arc::new(MockExecutor::default());
+    let lines = [
+        "add repo", "set url=https://example.com/app.git dest=\"/opt/my src\"", "end",
+        "add step", "set name=build script=build.sh", "end",
+    ];
+    let mut state = CfgState::new(&"quoteddest".to_string()).unwrap();
+    run(&mut state, &lines).await;
+    let vp = state.validate().unwrap();
+
+    let mut steps = vp.steps.with_workdir(Some(vp.workdir())).as_runnable();
+    steps.run(&vp.base_pzone().with_executor(executor.clone()), None, None, false).await.unwrap();
+    assert!(executor.ran("cd '/opt/my src' && "));
+}
+
+#[test]
+fn finds_the_steps_of_a_cycle() {
+    let graph = |edges: &[(&str, &[&str])]| {
+        edges
+            .iter()
+            .map(|(name, depends)| (name.to_string(), depends.iter().map(|d| d.to_string()).collect()))
+            .collect::<Vec<(String, Vec<String>)>>()
+    };
+
+    assert_eq!(find_cycle(&graph(&[("a", &[]), ("b", &["a"]), ("c", &["a", "b"])])), None);
+    assert_eq!(find_cycle(&graph(&[("a", &["missing"])])), None);
+    assert_eq!(find_cycle(&graph(&[("a", &["a"])])), Some(vec!["a".to_string(), "a".to_string()]));
+    assert_eq!(
+        find_cycle(&graph(&[("a", &[]), ("b", &["a", "d"]), ("c", &["b"]), ("d", &["c"])])),
+        Some(vec!["b", "d", "c", "b"].into_iter().map(String::from).collect())
+    );
+}
+
+/// Runs on the host the script of the first rule whose pattern the zone
+/// command contains, `true` when none does, so steps run without zones
+#[derive(Debug, Default)]
+struct MockExecutor {
+    rules: Vec<(String, String)>,
+    commands: Mutex<Vec<String>>,
+}
+
+impl MockExecutor {
+    fn on(mut self, pattern: &str, script: &str) -> Self {
+        self.rules.push((pattern.to_string(), script.to_string()));
+        self
+    }
+
+    fn ran(&self, pattern: &str) -> bool {
+        self.commands.lock().unwrap().iter().any(|c| c.contains(pattern))
+    }
+}
+
+impl Executor for MockExecutor {
+    fn command(&self, _zone: &str, command: &OsStr) -> std::process::Command {
+        let command = command.to_string_lossy().into_owned();
+        let script = self
+            .rules
+            .iter()
+            .find(|(pattern, _)| command.contains(pattern.as_str()))
+            .map_or("true", |(_, script)| script.as_str())
+            .to_string();
+        self.commands.lock().unwrap().push(command);
+
+        let mut command = std::process::Command::new("sh");
+        command.arg("-c").arg(script);
+        command
+    }
+}
+
+/// Steps of the pipeline configured by `lines`, along with its base zone
+/// running commands through `executor`
+async fn runnable(name: &str, lines: &[&str], executor: Arc<MockExecutor>) -> (RunnableSteps, PipelineZone) {
+    pipelines_dir();
+    let mut state = CfgState::new(&name.to_string()).unwrap();
+    run(&mut state, lines).await;
+    let vp = state.validate().unwrap();
+    (vp.steps.as_runnable(), vp.base_pzone().with_executor(executor))
+}
+
+async fn status(steps: &RunnableSteps, name: &str) -> (Status, Option<i32>) {
+    for step in steps.steps.iter() {
+        let step = step.read().await;
+        if step.step.name == name {
+            return (step.status().clone(), step.exit_code());
+        }
+    }
+    panic!("No step named {}", name);
+}
+
+#[tokio::test]
+async fn fails_steps_exiting_with_an_error() {
+    let executor = Arc::new(MockExecutor::default().on("build.sh", "echo building; exit 3"));
+    let lines = [
+        "add step", "set name=build script=build.sh", "end",
+        "add step", "set name=test script=test.sh depends=build", "end",
+    ];
+    let (mut steps, pzone) = runnable("exits", &lines, executor.clone()).await;
+
+    let err = steps.run(&pzone, None, None, false).await.unwrap_err();
+    assert!(err.to_string().contains("exited with code 3"), "{}", err);
+    assert_eq!(status(&steps, "build").await, (Status::Failed, Some(3)));
+    // Steps depending on a failed one don't start
+    assert_eq!(status(&steps, "test").await, (Status::Pending, None));
+    assert!(!executor.ran("test.sh"));
+}
+
+#[tokio::test]
+async fn finishes_steps_exiting_successfully() {
+    let executor = Arc::new(MockExecutor::default().on("build.sh", "echo out; echo err >&2"));
+    let lines = ["add step", "set name=build script=build.sh", "end"];
+    let (mut steps, pzone) = runnable("succeeds", &lines, executor).await;
+
+    steps.run(&pzone, None, None, false).await.unwrap();
+    assert_eq!(status(&steps, "build").await, (Status::Finished, Some(0)));
+}
+
+#[tokio::test]
+async fn stops_other_steps_on_failure_with_fail_fast() {
+    let executor = Arc::new(MockExecutor::default().on("fail.sh", "exit 1").on("slow.sh", "sleep 30"));
+    let lines = [
+        "add step", "set name=fail script=fail.sh", "end",
+        "add step", "set name=slow script=slow.sh", "end",
+    ];
+    let (mut steps, pzone) = runnable("failfast", &lines, executor).await;
+
+    let started = std::time::Instant::now();
+    assert!(steps.run(&pzone, None, None, true).await.is_err());
+    assert!(started.elapsed() < Duration::from_secs(20));
+    assert_eq!(status(&steps, "fail").await.0, Status::Failed);
+    assert_eq!(status(&steps, "slow").await.0, Status::Failed);
+}
+
+#[tokio::test]
+async fn kills_steps_running_past_their_timeout() {
+    let executor = Arc::new(MockExecutor::default().on("slow.sh", "sleep 30"));
+    let lines = ["add step", "set name=slow script=slow.sh timeout=1s", "end"];
+    let (mut steps, pzone) = runnable("timeout", &lines, executor.clone()).await;
+
+    let err = steps.run(&pzone, None, None, false).await.unwrap_err();
+    assert!(err.to_string().contains("timed out after 1s"), "{}", err);
+    assert_eq!(status(&steps, "slow").await, (Status::Failed, None));
+    // The processes of the step in the zone are stopped along with zlogin
+    assert!(executor.ran("pkill -TERM"));
+}
+
+#[tokio::test]
+async fn reads_the_output_until_both_streams_close() {
+    let script = "exec 2>&-; sleep 1; echo '::tag late=yes'; exit 4";
+    let executor = Arc::new(MockExecutor::default().on("late.sh", script));
+    let lines = ["add step", "set name=late script=late.sh", "end"];
+    let (mut steps, pzone) = runnable("streams", &lines, executor).await;
+
+    assert!(steps.run(&pzone, None, None, false).await.is_err());
+    assert_eq!(status(&steps, "late").await, (Status::Failed, Some(4)));
+    // Printed on stdout after stderr closed
+    let tags = steps.tags().await;
+    assert!(tags.iter().any(|t| t.key == "late" && t.value == "yes"), "{:?}", tags);
+}
+
+#[tokio::test]
+async fn prints_the_grouped_output_of_stopped_steps() {
+    let executor = Arc::new(MockExecutor::default().on("fail.sh", "sleep 1; exit 1").on("slow.sh", "echo started; sleep 30"));
+    let lines = [
+        "add step", "set name=fail script=fail.sh", "end",
+        "add step", "set name=slow script=slow.sh", "end",
+    ];
+    let (steps, pzone) = runnable("grouped", &lines, executor).await;
+    let mut steps = steps.with_grouped_output(true);
+
+    assert!(steps.run(&pzone, None, None, true).await.is_err());
+    assert_eq!(status(&steps, "slow").await.0, Status::Failed);
+    // Printed when the step was stopped rather than held back for good
+    assert!(steps.output.take("slow").is_empty());
+}
+
+#[tokio::test]
+async fn starts_a_step_as_soon_as_a_slot_frees_up() {
+    let file = pipelines_dir().join("refilled");
+    let executor = Arc::new(
+        MockExecutor::default()
+            .on("quick.sh", "sleep 1")
+            .on("slow.sh", "sleep 4")
+            .on("next.sh", &format!("touch {}", file.display())),
+    );
+    let lines = [
+        "add step", "set name=quick script=quick.sh", "end",
+        "add step", "set name=slow script=slow.sh", "end",
+        "add step", "set name=next script=next.sh", "end",
+    ];
+    let (mut steps, pzone) = runnable("refilled", &lines, executor).await;
+
+    let started = std::time::SystemTime::now();
+    steps.run(&pzone, None, Some(2), false).await.unwrap();
+    // Started once quick finished, not after slow
+    let next_started = std::fs::metadata(&file).unwrap().modified().unwrap();
+    assert!(next_started.duration_since(started).unwrap() < Duration::from_secs(3));
+}
+
+#[tokio::test]
+async fn quotes_the_paths_of_needed_artifacts() {
+    let executor = Arc::new(MockExecutor::default());
+    let lines = [
+        "add step", "set name=build script=build.sh", "end",
+        "add step", "set name=test script=test.sh depends=build needs_artifacts=\"build:out dir/app;reboot\"", "end",
+    ];
+    let (mut steps, pzone) = runnable("neededpaths", &lines, executor.clone()).await;
+
+    steps.run(&pzone, None, None, false).await.unwrap();
+    assert!(executor.ran(&format!(
+        "mkdir -p '/tmp/renzokutai/test/artifacts/build' && cp -R '{}/out dir/app;reboot' '/tmp/renzokutai/test/artifacts/build'/",
+        CHECKOUT_DIR
+    )));
+}
+
+#[tokio::test]
+async fn rejects_step_names_unfit_for_paths() {
+    pipelines_dir();
+    let mut state = CfgState::new(&"stepnames".to_string()).unwrap();
+
+    run(&mut state, &["add step"]).await;
+    for name in ["\"my step\"", "../x", "-x", "a;b", "\"\""] {
+        assert!(state.execute_line(&format!("set name={}", name)).await.is_err(), "{}", name);
+    }
+    run(&mut state, &["set name=unit-tests_2 script=test.sh", "end"]).await;
+    assert!(state.validate().is_ok());
+}
+
+#[tokio::test]
+async fn installs_credentials_through_the_zone() {
+    let file = pipelines_dir().join("credential");
+    let script = format!("cat > {}", file.display());
+    let executor = Arc::new(MockExecutor::default().on("cat >", &script));
+    let lines = ["add step", "set name=build script=build.sh", "end"];
+    let (_, pzone) = runnable("credentials", &lines, executor.clone()).await;
+
+    let secret = Secret {
+        name: "TOKEN".to_string(),
+        value: "ghp_abc".to_string(),
+    };
+    let credential = RepoCredential::new("https://github.com/org/app.git", &secret).unwrap();
+    credential.install(&pzone).await.unwrap();
+    RepoCredential::shred(&pzone).await.unwrap();
+
+    // The secret goes over stdin, never on a command line
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), credential.contents().unwrap());
+    assert!(!executor.commands.lock().unwrap().iter().any(|c| c.contains("ghp_abc")));
+    assert!(executor.ran("dd if=/dev/zero"));
+}
+
+#[tokio::test]
+async fn hands_secrets_to_steps_through_a_file() {
+    let file = pipelines_dir().join("step-secrets");
+    let script = format!("cat > {}", file.display());
+    let executor = Arc::new(MockExecutor::default().on("cat > '/tmp/.renzokutai-secrets", &script));
+    let mut state = CfgState::new(&"stepsecrets".to_string()).unwrap();
+    run(&mut state, &["add step", "set name=deploy script=deploy.sh", "end"]).await;
+    let vp = state.validate().unwrap();
+
+    let secret = Secret {
+        name: "TOKEN".to_string(),
+        value: "hunter2".to_string(),
+    };
+    let mut steps = vp.steps.with_secrets(&[secret]).as_runnable();
+    steps.run(&vp.base_pzone().with_executor(executor.clone()), None, None, false).await.unwrap();
+
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "export TOKEN='hunter2'\n");
+    assert!(!executor.commands.lock().unwrap().iter().any(|c| c.contains("hunter2")));
+    assert!(executor.ran(". '/tmp/.renzokutai-secrets-deploy' && rm -f '/tmp/.renzokutai-secrets-deploy'"));
+}
+
+#[tokio::test]
+async fn installs_secret_files_through_the_zone() {
+    let dir = pipelines_dir();
+    let source = dir.join("secret-file-source");
+    std::fs::write(&source, "s3cr3t").unwrap();
+    let file = dir.join("secret-file");
+    let script = format!("cat > {}", file.display());
+    let executor = Arc::new(MockExecutor::default().on("cat > '/root/.netrc'", &script).on("cat > '/root/.other'", "exit 1"));
+    let secrets = format!("set secret_file={src}:/root/.netrc,{src}:/root/.other", src = source.display());
+    let lines = ["add step", "set name=deploy script=deploy.sh", &secrets, "end"];
+    let (mut steps, pzone) = runnable("secretfiles", &lines, executor.clone()).await;
+
+    assert!(steps.run(&pzone, None, None, false).await.is_err());
+    // The secret goes over stdin, never on a command line
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "s3cr3t");
+    assert!(!executor.commands.lock().unwrap().iter().any(|c| c.contains("s3cr3t")));
+    // The second one failed, the step didn't run and both were shredded
+    assert!(!executor.ran("deploy.sh"));
+    assert!(executor.ran("dd if=/dev/zero of='/root/.netrc'"));
+    assert!(executor.ran("dd if=/dev/zero of='/root/.other'"));
+}
+
+#[tokio::test]
+async fn checks_out_the_ref_of_the_run() {
+    pipelines_dir();
+    let executor = Arc::new(MockExecutor::default());
+    let mut state = CfgState::new(&"runref".to_string()).unwrap();
+    run(&mut state, &["param set SHA=main", "add repo", "set url=https://example.com/app.git ref=${param.SHA}", "end"]).await;
+    let vp = state.validate().unwrap();
+    let pzone = vp.base_pzone().with_executor(executor.clone());
+
+    let cloned = Param::resolve(&vp.params, &[]).unwrap();
+    vp.repos.checkout(&pzone, &ProvisionLog::default(), &cloned, &cloned, None, &[]).await.unwrap();
+    // The base zone already has the ref checked out
+    assert!(!executor.ran("git"));
+
+    let params = Param::resolve(&vp.params, &["SHA=abc123".parse().unwrap()]).unwrap();
+    vp.repos.checkout(&pzone, &ProvisionLog::default(), &cloned, &params, None, &[]).await.unwrap();
+    assert!(executor.ran("git -C 'app' fetch --quiet origin 'abc123' && git -C 'app' checkout --quiet FETCH_HEAD"));
+}
+
+#[tokio::test]
+async fn checks_out_the_commit_of_the_run() {
+    pipelines_dir();
+    let executor = Arc::new(MockExecutor::default());
+    let mut state = CfgState::new(&"runcommit".to_string()).unwrap();
+    let lines = [
+        "add repo", "set url=https://example.com/app.git ref=main", "end",
+        "add repo", "set url=https://example.com/lib.git", "end",
+    ];
+    run(&mut state, &lines).await;
+    let vp = state.validate().unwrap();
+    let pzone = vp.base_pzone().with_executor(executor.clone());
+    let params = Param::resolve(&vp.params, &[]).unwrap();
+
+    vp.repos.checkout(&pzone, &ProvisionLog::default(), &params, &params, Some("abc123"), &[]).await.unwrap();
+    // Only the first repo, over its ref
+    assert!(executor.ran("git -C 'app' fetch --quiet origin 'abc123' && git -C 'app' checkout --quiet FETCH_HEAD"));
+    assert!(!executor.ran("'lib'"));
+
+    let option = Some("--upload-pack=touch /tmp/x");
+    assert!(vp.repos.checkout(&pzone, &ProvisionLog::default(), &params, &params, option, &[]).await.is_err());
+}
+
+#[tokio::test]
+async fn lists_pipelines_past_broken_files() {
+    let dir = pipelines_dir();
+    let mut state = CfgState::new(&"listed".to_string()).unwrap();
+    run(&mut state, &["save"]).await;
+    std::fs::write(dir.join("broken.xml"), "<ValidatedPipeline").unwrap();
+
+    let names: Vec<String> = ValidatedPipeline::list().unwrap().into_iter().map(|p| p.name).collect();
+    assert!(names.contains(&"listed".to_string()), "{:?}", names);
+    assert!(!names.contains(&"broken".to_string()));
+}
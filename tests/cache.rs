@@ -0,0 +1,21 @@
+use renzokutai::cache::{BOOTSTRAP_URL_VAR, CACHE_DIR_VAR, bootstrap_path, install_options};
+use std::ffi::OsString;
+
+#[test]
+fn installs_from_the_cached_bootstrap() {
+    let dir = std::env::temp_dir().join(format!("renzokutai-cache-{}", std::process::id()));
+    let url = "https://example.com/bootstrap/bootstrap-2024Q4-x86_64.tar.gz";
+    // SAFETY: the only test of this binary reading them
+    unsafe {
+        std::env::set_var(CACHE_DIR_VAR, &dir);
+        std::env::set_var(BOOTSTRAP_URL_VAR, url);
+    }
+
+    let path = bootstrap_path(url);
+    assert_eq!(path, dir.join("pkgsrc").join("bootstrap-2024Q4-x86_64.tar.gz"));
+    assert!(install_options().is_empty());
+
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(&path, b"bootstrap").unwrap();
+    assert_eq!(install_options(), vec![OsString::from("-s"), path.into_os_string()]);
+}
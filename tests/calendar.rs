@@ -0,0 +1,38 @@
+use renzokutai::config::{Exclusion, ScheduleCalendar};
+
+// 2026-10-17 22:30 UTC, a Saturday, already Sunday in Tokyo
+const SATURDAY_NIGHT: i64 = 1_792_276_200;
+
+fn exclusions(value: &str) -> Vec<String> {
+    Exclusion::parse_list(value).unwrap().iter().map(|e| e.to_string()).collect()
+}
+
+#[test]
+fn parses_weekdays_and_dates() {
+    assert_eq!(exclusions("weekends, 12-25,2026-01-06,fri"), ["sat", "sun", "12-25", "2026-01-06", "fri"]);
+    assert_eq!(exclusions("02-29"), ["02-29"]);
+
+    for value in ["saturday", "13-01", "2026-02-30", "12-25-2026", "1-5"] {
+        assert!(Exclusion::parse_list(value).is_err(), "{}", value);
+    }
+}
+
+#[test]
+fn excludes_days_in_the_pipeline_timezone() {
+    let utc = ScheduleCalendar::new(Some("UTC"), Some("sun,10-19")).unwrap();
+    assert_eq!(utc.date(SATURDAY_NIGHT).unwrap().to_string(), "2026-10-17");
+    assert_eq!(utc.exclusion(SATURDAY_NIGHT).unwrap(), None);
+
+    let tokyo = ScheduleCalendar::new(Some("Asia/Tokyo"), Some("sun,10-19")).unwrap();
+    assert_eq!(tokyo.exclusion(SATURDAY_NIGHT).unwrap().map(|e| e.to_string()), Some("sun".to_string()));
+    assert_eq!(
+        tokyo.exclusion(SATURDAY_NIGHT + 24 * 60 * 60).unwrap().map(|e| e.to_string()),
+        Some("10-19".to_string())
+    );
+}
+
+#[test]
+fn rejects_unknown_timezones() {
+    assert!(ScheduleCalendar::new(Some("Mars/Olympus_Mons"), None).is_err());
+    assert!(ScheduleCalendar::new(None, None).unwrap().exclusions.is_empty());
+}
@@ -0,0 +1,10 @@
+use renzokutai::color::{self, ColorMode, Colorize};
+
+#[test]
+fn plain_mode_leaves_text_alone() {
+    color::init(ColorMode::Never);
+
+    assert_eq!("DONE".green().to_string(), "DONE");
+    assert_eq!(format!("{:<6}|", "run".cyan()), "run   |");
+    assert_eq!(format!("{:?}", "run".red()), "\"run\"");
+}
@@ -0,0 +1,49 @@
+use renzokutai::config::{Condition, EnvVar, RunContext};
+use renzokutai::history::RunTag;
+
+fn context() -> RunContext {
+    RunContext {
+        branch: Some("main".to_string()),
+        commit: Some("abc123".to_string()),
+        actor: None,
+        env: vec![EnvVar {
+            name: "RUN_TESTS".to_string(),
+            value: "1".to_string(),
+        }],
+        tags: vec![RunTag {
+            key: "RUST".to_string(),
+            value: "1.80".to_string(),
+        }],
+        params: Vec::new(),
+    }
+}
+
+fn holds(expression: &str) -> bool {
+    Condition::parse(expression).unwrap().holds(&context())
+}
+
+#[test]
+fn compares_the_run_metadata() {
+    assert!(holds("branch == 'main'"));
+    assert!(!holds("branch != 'main'"));
+    assert!(holds("commit == \"abc123\""));
+    assert!(holds("env.RUN_TESTS == '1'"));
+    assert!(holds("tag.RUST == '1.80'"));
+    // Missing values compare like an empty string
+    assert!(holds("actor == ''"));
+    assert!(holds("env.MISSING != '1'"));
+}
+
+#[test]
+fn and_binds_tighter_than_or() {
+    assert!(holds("branch == 'dev' && env.RUN_TESTS == '1' || tag.RUST == '1.80'"));
+    assert!(!holds("branch == 'dev' && env.RUN_TESTS == '1' || tag.RUST == '1.81'"));
+    assert!(holds("  branch=='main'&&env.RUN_TESTS=='1'  "));
+}
+
+#[test]
+fn rejects_invalid_conditions() {
+    for expression in ["", "branch", "branch == main", "branch = 'main'", "os == 'illumos'", "branch == 'main' &&"] {
+        assert!(Condition::parse(expression).is_err(), "{}", expression);
+    }
+}
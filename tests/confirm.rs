@@ -0,0 +1,8 @@
+use renzokutai::confirm;
+
+#[test]
+fn yes_skips_confirmation() {
+    confirm::init(true);
+    assert!(confirm::assume_yes());
+    assert!(confirm::destructive("Zone ci_test_base will be halted.").is_ok());
+}
@@ -0,0 +1,55 @@
+use jiff::civil::{DateTime, date};
+use renzokutai::config::CronSchedule;
+
+fn at(month: i8, day: i8, hour: i8, minute: i8) -> DateTime {
+    date(2026, month, day).at(hour, minute, 0, 0)
+}
+
+fn schedule(expr: &str) -> CronSchedule {
+    expr.parse().unwrap()
+}
+
+#[test]
+fn matches_values_ranges_lists_and_steps() {
+    let nightly = schedule("30 3 * * *");
+    assert!(nightly.matches(at(10, 18, 3, 30)));
+    assert!(!nightly.matches(at(10, 18, 3, 31)));
+    assert!(!nightly.matches(at(10, 18, 15, 30)));
+
+    let office = schedule("*/15 9-17 * * mon-fri");
+    assert!(office.matches(at(10, 16, 9, 45)));
+    assert!(!office.matches(at(10, 16, 9, 50)));
+    assert!(!office.matches(at(10, 16, 18, 0)));
+    // Saturday
+    assert!(!office.matches(at(10, 17, 10, 0)));
+
+    let quarterly = schedule("0 0 1 jan,apr,jul,oct *");
+    assert!(quarterly.matches(at(10, 1, 0, 0)));
+    assert!(!quarterly.matches(at(11, 1, 0, 0)));
+
+    assert!(schedule("5/20 * * * *").matches(at(10, 18, 0, 45)));
+    assert!(!schedule("5/20 * * * *").matches(at(10, 18, 0, 0)));
+}
+
+#[test]
+fn takes_either_day_when_both_are_restricted() {
+    // The 13th of every month and every Friday
+    let schedule = schedule("0 12 13 * fri");
+    assert!(schedule.matches(at(10, 13, 12, 0)));
+    assert!(schedule.matches(at(10, 16, 12, 0)));
+    assert!(!schedule.matches(at(10, 14, 12, 0)));
+}
+
+#[test]
+fn expands_nicknames_and_sunday_as_seven() {
+    assert_eq!(schedule("@daily"), schedule("0 0 * * *"));
+    assert_eq!(schedule("@weekly"), schedule("0 0 * * 7"));
+    assert!(schedule("@weekly").matches(at(10, 18, 0, 0)));
+}
+
+#[test]
+fn rejects_invalid_expressions() {
+    for expr in ["", "* * * *", "60 * * * *", "* 24 * * *", "* * 0 * *", "* * * 13 *", "5-1 * * * *", "*/0 * * * *", "@often", "* * * * funday"] {
+        assert!(expr.parse::<CronSchedule>().is_err(), "{}", expr);
+    }
+}
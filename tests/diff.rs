@@ -0,0 +1,22 @@
+use renzokutai::config::diff::unified;
+
+#[test]
+fn identical_inputs_have_no_diff() {
+    assert_eq!(unified("a\nb\n", "a\nb\n", "old", "new", 3), "");
+}
+
+#[test]
+fn renders_hunks_with_context() {
+    let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+    let new = "1\n2\nthree\n4\n5\n6\n7\n8\n9\nten\n";
+
+    assert_eq!(
+        unified(old, new, "old", "new", 1),
+        "--- old\n+++ new\n@@ -2,3 +2,3 @@\n 2\n-3\n+three\n 4\n@@ -9 +9,2 @@\n 9\n+ten\n"
+    );
+}
+
+#[test]
+fn diffs_against_nothing() {
+    assert_eq!(unified("", "a\nb\n", "old", "new", 3), "--- old\n+++ new\n@@ -0,0 +1,2 @@\n+a\n+b\n");
+}
@@ -0,0 +1,113 @@
+use renzokutai::config::drone::{DroneImport, write_scripts};
+use renzokutai::config::{Step, Value};
+
+const DRONE: &str = r#"
+kind: pipeline
+type: docker
+name: default
+
+steps:
+- name: build
+  image: rust:1.80
+  commands:
+  - cargo build
+  - cargo test
+  environment:
+    RUST_BACKTRACE: 1
+    TOKEN:
+      from_secret: token
+- name: publish docs
+  commands: make docs
+
+---
+kind: secret
+name: token
+"#;
+
+const WOODPECKER: &str = r#"
+steps:
+  build:
+    commands:
+      - make
+  lint:
+    commands:
+      - make lint
+  package:
+    commands:
+      - make package
+    depends_on: [build, lint]
+    when:
+      branch: main
+"#;
+
+fn steps(import: &DroneImport) -> Vec<Step> {
+    import.steps.iter().map(|s| s.borrow().clone()).collect()
+}
+
+fn depends(step: &Step) -> Vec<String> {
+    step.depends.iter().filter_map(|d| d.name.option()).collect()
+}
+
+#[test]
+fn runs_drone_steps_one_after_the_other() {
+    let import = DroneImport::parse(DRONE).unwrap();
+    let steps = steps(&import);
+
+    assert_eq!(steps.len(), 2);
+    assert_eq!(steps[0].name, Value::Set("build".to_string()));
+    assert_eq!(steps[0].script, Value::Set("ci/build.sh".to_string()));
+    assert!(depends(&steps[0]).is_empty());
+    assert_eq!(steps[0].env.iter().map(|e| e.to_string()).collect::<Vec<_>>(), ["RUST_BACKTRACE=1"]);
+    // Step names can't hold spaces
+    assert_eq!(steps[1].name, Value::Set("publish_docs".to_string()));
+    assert_eq!(steps[1].script, Value::Set("ci/publish_docs.sh".to_string()));
+    assert_eq!(depends(&steps[1]), ["build"]);
+
+    assert_eq!(import.scripts[0], ("ci/build.sh".to_string(), "set -e\ncargo build\ncargo test\n".to_string()));
+    assert_eq!(import.warnings.len(), 3, "{:?}", import.warnings);
+    assert!(import.warnings.iter().any(|w| w.contains("image")));
+    assert!(import.warnings.iter().any(|w| w.contains("TOKEN")));
+    assert!(import.warnings.iter().any(|w| w.contains("renamed to publish_docs")));
+}
+
+#[test]
+fn keeps_the_dependencies_of_woodpecker_steps() {
+    let import = DroneImport::parse(WOODPECKER).unwrap();
+    let steps = steps(&import);
+
+    assert_eq!(
+        steps.iter().filter_map(|s| s.name.option()).collect::<Vec<_>>(),
+        ["build", "lint", "package"]
+    );
+    assert!(depends(&steps[1]).is_empty());
+    assert_eq!(depends(&steps[2]), ["build", "lint"]);
+    assert!(import.warnings.iter().any(|w| w.contains("when")));
+}
+
+#[test]
+fn gives_clashing_step_names_their_own_script() {
+    let yaml = "steps:\n- name: Run tests\n  commands: [a]\n- name: run-tests\n  commands: [b]\n- name: run tests\n  commands: [c]\n";
+    let import = DroneImport::parse(yaml).unwrap();
+    let paths: Vec<_> = import.scripts.iter().map(|(path, _)| path.as_str()).collect();
+
+    assert_eq!(paths, ["ci/run_tests.sh", "ci/run-tests.sh", "ci/run_tests_2.sh"]);
+}
+
+#[test]
+fn rejects_files_without_steps() {
+    assert!(DroneImport::parse("kind: pipeline\nname: default\n").is_err());
+    assert!(DroneImport::parse("steps: 3\n").is_err());
+    assert!(DroneImport::parse("steps:\n- commands: [make]\n").is_err());
+}
+
+#[test]
+fn writes_the_scripts_without_overwriting() {
+    let root = std::env::temp_dir().join(format!("renzokutai-drone-{}", std::process::id()));
+    let scripts = DroneImport::parse(DRONE).unwrap().scripts_under(&root);
+
+    write_scripts(&scripts).unwrap();
+    assert_eq!(std::fs::read_to_string(root.join("ci/publish_docs.sh")).unwrap(), "set -e\nmake docs\n");
+    assert!(write_scripts(&scripts).is_err());
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,32 @@
+use renzokutai::config::EnvVar;
+
+#[test]
+fn exports_single_quoted_values() {
+    let var = |value: &str| EnvVar {
+        name: "GREETING".to_string(),
+        value: value.to_string(),
+    };
+
+    assert_eq!(var("hello world").export_command(), "export GREETING='hello world'");
+    assert_eq!(var("$HOME").export_command(), "export GREETING='$HOME'");
+    assert_eq!(var("it's").export_command(), r"export GREETING='it'\''s'");
+}
+
+#[test]
+fn validates_variable_names() {
+    for name in ["RUST_LOG", "_private", "CC2"] {
+        assert!(EnvVar::validate_name(name).is_ok(), "{}", name);
+    }
+    for name in ["", "2FAST", "WITH-DASH", "ÑAME"] {
+        assert!(EnvVar::validate_name(name).is_err(), "{}", name);
+    }
+}
+
+#[test]
+fn parses_name_value_pairs() {
+    let var: EnvVar = "VERSION=1.2=rc".parse().unwrap();
+    assert_eq!((var.name.as_str(), var.value.as_str()), ("VERSION", "1.2=rc"));
+    assert_eq!("EMPTY=".parse::<EnvVar>().unwrap().value, "");
+    assert!("VERSION".parse::<EnvVar>().is_err());
+    assert!("2FAST=1".parse::<EnvVar>().is_err());
+}
@@ -0,0 +1,77 @@
+use renzokutai::config::ValidatedStep;
+use renzokutai::config::fingerprint::{fingerprint, parse_inputs};
+use std::path::PathBuf;
+
+fn zone(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("renzokutai-fingerprint-{}-{}", name, std::process::id()));
+    let checkout = root.join("opt/renzokutai/checkout");
+    std::fs::create_dir_all(checkout.join("src/bin")).unwrap();
+    std::fs::write(checkout.join("Cargo.toml"), "[package]").unwrap();
+    std::fs::write(checkout.join("src/lib.rs"), "fn lib() {}").unwrap();
+    std::fs::write(checkout.join("src/bin/main.rs"), "fn main() {}").unwrap();
+    std::fs::write(checkout.join("README"), "docs").unwrap();
+    root
+}
+
+fn step(inputs: &str) -> ValidatedStep {
+    ValidatedStep {
+        name: "build".to_string(),
+        script: "build.sh".to_string(),
+        fingerprint: Some(inputs.to_string()),
+        ..ValidatedStep::default()
+    }
+}
+
+#[test]
+fn parses_and_validates_inputs() {
+    assert_eq!(parse_inputs("src, Cargo.*,").unwrap(), ["src", "Cargo.*"]);
+    assert!(parse_inputs("../secrets").is_err());
+    assert!(parse_inputs("src/[").is_err());
+}
+
+#[test]
+fn changes_only_with_the_inputs() {
+    let root = zone("inputs");
+    let step = step("src,Cargo.toml");
+    let first = fingerprint(&step, &root, None).unwrap();
+
+    assert_eq!(fingerprint(&step, &root, None).unwrap(), first);
+    std::fs::write(root.join("opt/renzokutai/checkout/README"), "more docs").unwrap();
+    assert_eq!(fingerprint(&step, &root, None).unwrap(), first);
+
+    std::fs::write(root.join("opt/renzokutai/checkout/src/bin/main.rs"), "fn main() { }").unwrap();
+    assert_ne!(fingerprint(&step, &root, None).unwrap(), first);
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn covers_the_script_variables_and_command_output() {
+    let root = zone("command");
+    let step = step("src");
+    let first = fingerprint(&step, &root, Some(b"abc")).unwrap();
+
+    assert_ne!(fingerprint(&step, &root, Some(b"abd")).unwrap(), first);
+    assert_ne!(fingerprint(&step, &root, None).unwrap(), first);
+
+    let other_script = ValidatedStep {
+        script: "test.sh".to_string(),
+        ..step.clone()
+    };
+    assert_ne!(fingerprint(&other_script, &root, Some(b"abc")).unwrap(), first);
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn refuses_links_out_of_the_zone() {
+    let root = zone("link");
+    let outside = root.with_extension("outside");
+    std::fs::create_dir_all(&outside).unwrap();
+    std::os::unix::fs::symlink(&outside, root.join("opt/renzokutai/checkout/escape")).unwrap();
+
+    assert!(fingerprint(&step("escape"), &root, None).is_err());
+
+    std::fs::remove_dir_all(&root).unwrap();
+    std::fs::remove_dir_all(&outside).unwrap();
+}
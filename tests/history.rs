@@ -0,0 +1,335 @@
+use renzokutai::config::{RefreshSchedule, ValidatedPipeline};
+use renzokutai::history::{
+    History, PROVISION_LOG, RunRecord, RunStatus, RunTag, STEP_LOG, StepResultRecord, StepSpan, StepState, StepTransition,
+    now,
+};
+
+async fn history() -> History {
+    let path = std::env::temp_dir()
+        .join(format!("renzokutai-history-{}", std::process::id()))
+        .join(format!("{}.sqlite", unique_suffix()));
+
+    History::open_at(&path).await.unwrap()
+}
+
+fn unique_suffix() -> String {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    NEXT.fetch_add(1, Ordering::SeqCst).to_string()
+}
+
+#[tokio::test]
+async fn records_run_lifecycle() {
+    let history = history().await;
+
+    history.start_run("r1", "katarineko", Some("main"), Some("abc123")).await.unwrap();
+    let run = history.run("r1").await.unwrap().unwrap();
+    assert_eq!(run.status, RunStatus::Running.as_str());
+    assert_eq!(run.finished_at, None);
+    assert_eq!(history.count_running().await.unwrap(), 1);
+
+    history.set_actor("r1", "web").await.unwrap();
+    assert_eq!(history.run("r1").await.unwrap().unwrap().actor.as_deref(), Some("web"));
+
+    history.finish_run("r1", RunStatus::Failed).await.unwrap();
+    let run = history.run("r1").await.unwrap().unwrap();
+    assert_eq!(run.status, RunStatus::Failed.as_str());
+    assert!(run.finished_at.is_some());
+    assert_eq!(history.count_running().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn keeps_latest_run_per_branch() {
+    let history = history().await;
+
+    history.start_run("a", "katarineko", Some("main"), Some("c1")).await.unwrap();
+    history.start_run("b", "katarineko", Some("feature"), Some("c2")).await.unwrap();
+    history.start_run("c", "katarineko", Some("main"), Some("c3")).await.unwrap();
+    history.start_run("d", "other", Some("main"), Some("c4")).await.unwrap();
+
+    let latest: Vec<String> = history
+        .latest_per_branch("katarineko")
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.id)
+        .collect();
+
+    assert_eq!(latest, vec!["c".to_string(), "b".to_string()]);
+}
+
+#[tokio::test]
+async fn attaches_notes_to_runs() {
+    let history = history().await;
+
+    history.start_run("n1", "katarineko", Some("main"), None).await.unwrap();
+    history.add_note("n1", "failed due to upstream outage, ignore").await.unwrap();
+    history.add_note("n1", "  retried  ").await.unwrap();
+
+    let notes: Vec<String> = history.notes("n1").await.unwrap().into_iter().map(|n| n.body).collect();
+    assert_eq!(notes, vec!["failed due to upstream outage, ignore".to_string(), "retried".to_string()]);
+
+    assert!(history.add_note("missing", "note").await.is_err());
+    assert!(history.add_note("n1", "   ").await.is_err());
+}
+
+#[tokio::test]
+async fn records_apply_phases() {
+    let history = history().await;
+
+    history.start_apply("ap", "katarineko").await.unwrap();
+    history.timed("ap", "create_dataset", async { Ok(()) }).await.unwrap();
+    let failed = history
+        .timed("ap", "install_zone", async { Err(anyhow::anyhow!("zoneadm failed")) })
+        .await;
+    assert!(failed.is_err());
+
+    let events = history.events("ap").await.unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].name, "create_dataset");
+    assert_eq!(events[0].error, None);
+    assert!(events[1].error.as_deref().unwrap().contains("zoneadm failed"));
+
+    let run = history.run("ap").await.unwrap().unwrap();
+    assert_eq!(run.kind, "apply");
+    assert!(history.latest_per_branch("katarineko").await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn schedules_base_refreshes() {
+    let history = history().await;
+    let weekly: RefreshSchedule = "weekly".parse().unwrap();
+
+    history.start_run("run", "katarineko", Some("main"), None).await.unwrap();
+    let last = history.last_refresh("katarineko").await.unwrap();
+    assert_eq!(last, None);
+    assert!(weekly.is_due(last.as_ref(), now()));
+
+    history.start_refresh("refresh", "katarineko").await.unwrap();
+    let last = history.last_refresh("katarineko").await.unwrap().unwrap();
+    assert_eq!(last.kind, "refresh");
+    assert!(!weekly.is_due(Some(&last), now()));
+    assert!(weekly.is_due(Some(&last), now() + weekly.interval()));
+
+    assert!("hourly".parse::<RefreshSchedule>().is_err());
+}
+
+#[tokio::test]
+async fn filters_runs_by_tag() {
+    let history = history().await;
+    let release: RunTag = "release=1.2".parse().unwrap();
+    let ticket: RunTag = "ticket=CI-7".parse().unwrap();
+
+    history.start_run("a", "katarineko", Some("main"), None).await.unwrap();
+    history.start_run("b", "katarineko", Some("main"), None).await.unwrap();
+    history.start_run("c", "other", Some("main"), None).await.unwrap();
+    history.add_tags("a", &[release.clone(), ticket.clone()]).await.unwrap();
+    history.add_tag("b", &release).await.unwrap();
+    history.add_tag("c", &release).await.unwrap();
+
+    let ids = |runs: Vec<RunRecord>| runs.into_iter().map(|r| r.id).collect::<Vec<_>>();
+    assert_eq!(ids(history.tagged_runs(None, std::slice::from_ref(&release), 10).await.unwrap()).len(), 3);
+    assert_eq!(
+        ids(history.tagged_runs(Some("katarineko"), std::slice::from_ref(&release), 10).await.unwrap()).len(),
+        2
+    );
+    assert_eq!(
+        ids(history.tagged_runs(None, &[release, ticket.clone()], 10).await.unwrap()),
+        vec!["a"]
+    );
+
+    // Retagging replaces the value
+    history.add_tag("a", &"ticket=CI-8".parse().unwrap()).await.unwrap();
+    assert!(history.tagged_runs(None, &[ticket], 10).await.unwrap().is_empty());
+    assert_eq!(history.tags("a").await.unwrap().len(), 2);
+
+    assert!(history.add_tag("missing", &"a=b".parse().unwrap()).await.is_err());
+    assert!("novalue".parse::<RunTag>().is_err());
+    assert_eq!(RunTag::parse_list("a=1, b=2").unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn stores_step_logs() {
+    let history = history().await;
+
+    history.start_run("r1", "katarineko", None, None).await.unwrap();
+    history.add_step_log("r1", "test", "ok\n", 0).await.unwrap();
+    history.add_step_log("r1", "build", "[... truncated ...]\n", 2048).await.unwrap();
+
+    let logs = history.step_logs("r1").await.unwrap();
+    let summary: Vec<_> = logs.iter().map(|l| (l.step_name.as_str(), l.truncated_bytes)).collect();
+    assert_eq!(summary, vec![("build", 2048), ("test", 0)]);
+    assert_eq!(logs[1].body, "ok\n");
+
+    history.add_provision_log("r1", "install_zone", "installed\n", 0).await.unwrap();
+    history.add_provision_log("r1", "clone_repos", "cloned\n", 0).await.unwrap();
+    history.add_provision_log("r1", "test", "phase\n", 0).await.unwrap();
+    let logs = history.step_logs("r1").await.unwrap();
+    let names: Vec<_> = logs.iter().map(|l| (l.kind.as_str(), l.step_name.as_str())).collect();
+    assert_eq!(
+        names,
+        vec![
+            (PROVISION_LOG, "install_zone"),
+            (PROVISION_LOG, "clone_repos"),
+            (PROVISION_LOG, "test"),
+            (STEP_LOG, "build"),
+            (STEP_LOG, "test"),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn finds_runs_with_the_same_step_fingerprint() {
+    let history = history().await;
+
+    assert!(history.base_build("katarineko").await.unwrap().is_none());
+    history.start_apply("a1", "katarineko").await.unwrap();
+    history.finish_run("a1", RunStatus::Succeeded).await.unwrap();
+    history.start_refresh("a2", "katarineko").await.unwrap();
+    history.finish_run("a2", RunStatus::Failed).await.unwrap();
+    assert_eq!(history.base_build("katarineko").await.unwrap().unwrap().id, "a1");
+
+    history.start_run("r1", "katarineko", None, None).await.unwrap();
+    history.add_fingerprint("r1", "build", "a1", "f1").await.unwrap();
+    history.start_run("r2", "katarineko", None, None).await.unwrap();
+    history.add_fingerprint("r2", "build", "a1", "f1").await.unwrap();
+    history.start_run("r3", "other", None, None).await.unwrap();
+    history.add_fingerprint("r3", "build", "a1", "f1").await.unwrap();
+
+    let matching = |step: &'static str, base: &'static str, fingerprint: &'static str| {
+        let history = &history;
+        async move { history.matching_fingerprint("katarineko", step, base, fingerprint).await.unwrap() }
+    };
+    assert_eq!(matching("build", "a1", "f1").await.as_deref(), Some("r2"));
+    assert_eq!(matching("build", "a1", "f2").await, None);
+    assert_eq!(matching("build", "a0", "f1").await, None);
+    assert_eq!(matching("test", "a1", "f1").await, None);
+}
+
+fn transition(step: &str, state: StepState, at: i64, log_lines: i64) -> StepTransition {
+    StepTransition {
+        step_name: step.to_string(),
+        state: state.as_str().to_string(),
+        at,
+        log_lines,
+    }
+}
+
+#[tokio::test]
+async fn replays_step_transitions_in_order() {
+    let history = history().await;
+    history.start_run("r1", "katarineko", None, None).await.unwrap();
+
+    let build = [
+        transition("build", StepState::Running, 1_000, 0),
+        transition("build", StepState::Output, 2_500, 40),
+        transition("build", StepState::Succeeded, 4_000, 52),
+    ];
+    let lint = [
+        transition("lint", StepState::Running, 1_200, 0),
+        transition("lint", StepState::Failed, 2_000, 3),
+    ];
+    history.add_transitions("r1", &build).await.unwrap();
+    history.add_transitions("r1", &lint).await.unwrap();
+
+    let timeline = history.timeline("r1").await.unwrap();
+    let order: Vec<_> = timeline.iter().map(|t| (t.step_name.as_str(), t.at)).collect();
+    assert_eq!(
+        order,
+        [("build", 1_000), ("lint", 1_200), ("lint", 2_000), ("build", 2_500), ("build", 4_000)]
+    );
+
+    let spans = StepSpan::from_timeline(&timeline);
+    assert_eq!(spans.len(), 2);
+    assert_eq!((spans[0].offset, spans[0].duration, spans[0].log_lines), (0, 3_000, 52));
+    assert_eq!(spans[0].state, "succeeded");
+    assert_eq!((spans[1].offset, spans[1].duration, spans[1].state.as_str()), (200, 800, "failed"));
+    assert_eq!(spans[1].bar(3_000), (6, 26));
+    assert!(history.timeline("r2").await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn records_the_outcome_of_steps() {
+    let history = history().await;
+    let build = [
+        transition("build", StepState::Running, 1_000, 0),
+        transition("build", StepState::Output, 2_000, 10),
+        transition("build", StepState::Failed, 3_000, 12),
+    ];
+
+    let result = StepResultRecord::from_transitions("r1", "build", &build, Some(2)).unwrap();
+    assert_eq!(result.status, "failed");
+    assert_eq!((result.started_at, result.finished_at, result.exit_code), (1_000, Some(3_000), Some(2)));
+    let running = StepResultRecord::from_transitions("r1", "build", &build[..2], None).unwrap();
+    assert_eq!((running.status.as_str(), running.finished_at), ("running", None));
+    assert_eq!(StepResultRecord::from_transitions("r1", "build", &[], None), None);
+
+    for (run, exit_code) in [("r1", Some(2)), ("r2", Some(0))] {
+        history.start_run(run, "katarineko", None, None).await.unwrap();
+        let result = StepResultRecord::from_transitions(run, "build", &build, exit_code).unwrap();
+        history.add_step_result(&result).await.unwrap();
+    }
+    let skipped = [transition("lint", StepState::Skipped, 500, 0)];
+    history
+        .add_step_result(&StepResultRecord::from_transitions("r1", "lint", &skipped, None).unwrap())
+        .await
+        .unwrap();
+
+    let results = history.step_results("r1").await.unwrap();
+    let outcomes: Vec<_> = results.iter().map(|r| (r.step_name.as_str(), r.status.as_str(), r.exit_code)).collect();
+    assert_eq!(outcomes, [("lint", "skipped", None), ("build", "failed", Some(2))]);
+
+    let past: Vec<_> = history
+        .step_history("katarineko", "build", 10)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.run_id)
+        .collect();
+    assert_eq!(past, ["r2", "r1"]);
+    assert!(history.step_history("mikeneko", "build", 10).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn prunes_old_step_logs() {
+    let history = history().await;
+
+    history.start_run("r1", "katarineko", None, None).await.unwrap();
+    history.start_run("r2", "mikeneko", None, None).await.unwrap();
+    for run in ["r1", "r2"] {
+        history.add_step_log(run, "build", "ok\n", 0).await.unwrap();
+    }
+
+    assert_eq!(history.prune_step_logs("katarineko", now() - 60).await.unwrap(), 0);
+    assert_eq!(history.prune_step_logs("katarineko", now() + 1).await.unwrap(), 1);
+
+    assert!(history.step_logs("r1").await.unwrap().is_empty());
+    assert_eq!(history.step_logs("r2").await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn summarizes_applies_with_previous_timings() {
+    let history = history().await;
+    let vp = ValidatedPipeline {
+        name: "katarineko".to_string(),
+        ..Default::default()
+    };
+
+    let summary = vp.apply_summary(&history).await.unwrap();
+    assert!(summary.contains("install zone ci_katarineko_base"), "{}", summary);
+    assert!(summary.contains("run steps: none"), "{}", summary);
+    assert!(summary.contains("Phases: create_dataset, install_zone"), "{}", summary);
+
+    // Failed applies don't count towards the estimate
+    history.start_apply("a1", "katarineko").await.unwrap();
+    history.record_event("a1", "install_zone", now() - 300, None).await.unwrap();
+    history.finish_run("a1", RunStatus::Failed).await.unwrap();
+    assert!(vp.apply_summary(&history).await.unwrap().contains("Phases: "));
+
+    history.start_apply("a2", "katarineko").await.unwrap();
+    history.record_event("a2", "install_zone", now() - 120, None).await.unwrap();
+    history.finish_run("a2", RunStatus::Succeeded).await.unwrap();
+    let summary = vp.apply_summary(&history).await.unwrap();
+    assert!(summary.contains("install_zone         ~12"), "{}", summary);
+    assert!(summary.contains("create_dataset       ?"), "{}", summary);
+}
@@ -0,0 +1,34 @@
+use renzokutai::interrupt::{Cancellation, is_interrupted};
+use std::time::Duration;
+
+#[tokio::test]
+async fn cuts_work_short_once_cancelled() {
+    let cancellation = Cancellation::new();
+    let run = cancellation.clone();
+
+    let cancel = async {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        cancellation.cancel();
+    };
+    let work = run.bound(async {
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        Ok(())
+    });
+    let ((), result) = tokio::join!(cancel, work);
+
+    assert!(is_interrupted(&result.unwrap_err()));
+    assert!(run.is_cancelled());
+    // Work started after the cancellation doesn't run at all
+    assert!(is_interrupted(&run.bound(async { Ok(()) }).await.unwrap_err()));
+}
+
+#[tokio::test]
+async fn runs_are_cancelled_independently() {
+    let cancelled = Cancellation::new();
+    cancelled.cancel();
+
+    let next = Cancellation::new();
+    assert!(!next.is_cancelled());
+    assert_eq!(next.bound(async { Ok(3) }).await.unwrap(), 3);
+    assert!(!is_interrupted(&anyhow::anyhow!("Step build exited with code 1")));
+}
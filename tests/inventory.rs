@@ -0,0 +1,61 @@
+use renzokutai::inventory::{Inventory, parse_dataset_name, parse_vnic_name, parse_zone_name};
+use renzokutai::zones::vnic_name;
+
+fn owner(pipeline: &str, run: Option<&str>) -> Option<(String, Option<String>)> {
+    Some((pipeline.to_string(), run.map(str::to_string)))
+}
+
+fn lines(lines: &[&str]) -> Vec<String> {
+    lines.iter().map(|l| l.to_string()).collect()
+}
+
+#[test]
+fn attributes_names_to_pipelines_and_runs() {
+    assert_eq!(parse_zone_name("ci_my_pipeline_base"), owner("my_pipeline", None));
+    assert_eq!(parse_zone_name("ci_katarineko_a1b2"), owner("katarineko", Some("a1b2")));
+    assert_eq!(parse_zone_name("global"), None);
+
+    assert_eq!(parse_vnic_name("ci_katarineko_a1b2_internal0", &[]), owner("katarineko", Some("a1b2")));
+    let long = "a-pipeline-name-too-long-for-a-link".to_string();
+    let hashed = vnic_name(&long, "base");
+    assert_eq!(parse_vnic_name(&hashed, std::slice::from_ref(&long)), owner(&long, None));
+    assert_eq!(parse_vnic_name(&hashed, &[]), None);
+
+    assert_eq!(parse_dataset_name("rpool/zones/ci/katarineko"), owner("katarineko", None));
+    assert_eq!(parse_dataset_name("rpool/zones/ci/katarineko/pkgin-cache"), owner("katarineko", None));
+    assert_eq!(parse_dataset_name("rpool/zones/ci/katarineko/caches/cargo"), owner("katarineko", None));
+    assert_eq!(parse_dataset_name("rpool/zones/ci/katarineko/a1b2_swap"), owner("katarineko", Some("a1b2")));
+    assert_eq!(parse_dataset_name("rpool/zones/ci/katarineko/a1b2/root"), None);
+    assert_eq!(parse_dataset_name("rpool/zones/ci"), None);
+    assert_eq!(parse_dataset_name("rpool/zones/cistern"), None);
+}
+
+#[test]
+fn finds_leftovers() {
+    let mut inventory = Inventory::parse(
+        &lines(&[
+            "0:global:running:/::ipkg:shared",
+            "3:ci_katarineko_base:installed:/zones/ci/katarineko/base:uuid:pkgsrc:excl",
+            "-:ci_katarineko_a1b2:configured:/zones/ci/katarineko/a1b2::pkgsrc:excl",
+            "-:ci_gone_base:installed:/zones/ci/gone/base::pkgsrc:excl",
+        ]),
+        &lines(&["net0:phys:up", "ci_katarineko_a1b2_internal0:vnic:up", "internal0:etherstub:unknown"]),
+        &lines(&["rpool/zones/ci\t1024", "rpool/zones/ci/katarineko/base\t2048"]),
+        &[],
+    );
+    inventory.pipelines = vec!["katarineko".to_string()];
+
+    let names = |resources: &[renzokutai::inventory::Resource]| {
+        resources.iter().map(|r| r.name.clone()).collect::<Vec<_>>()
+    };
+    assert_eq!(names(&inventory.zones), ["ci_katarineko_base", "ci_katarineko_a1b2", "ci_gone_base"]);
+    assert_eq!(names(&inventory.vnics), ["ci_katarineko_a1b2_internal0"]);
+    assert_eq!(names(&inventory.datasets), ["rpool/zones/ci/katarineko/base"]);
+    assert_eq!(inventory.datasets[0].state, "2048 bytes used");
+
+    let leftovers: Vec<bool> = inventory.zones.iter().map(|z| z.is_leftover(&inventory.pipelines)).collect();
+    assert_eq!(leftovers, [false, true, true]);
+
+    inventory.zones[1].run_status = Some("running".to_string());
+    assert!(!inventory.zones[1].is_leftover(&inventory.pipelines));
+}
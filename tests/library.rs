@@ -0,0 +1,45 @@
+use renzokutai::config::StepShell;
+use renzokutai::config::library::{LIBRARY_DIR, script_command, script_words, validate_script};
+
+#[test]
+fn runs_library_scripts_from_the_checkout() {
+    assert_eq!(script_command("build.sh", StepShell::Sh).unwrap(), "/usr/bin/sh -x './build.sh'");
+    assert_eq!(
+        script_command("lib://rust/build.sh", StepShell::Sh).unwrap(),
+        format!("/usr/bin/sh -x '{}/rust/build.sh'", LIBRARY_DIR)
+    );
+}
+
+#[test]
+fn runs_scripts_through_their_shell() {
+    assert_eq!(script_command("build.sh", StepShell::Bash).unwrap(), "/usr/bin/bash -x './build.sh'");
+    assert_eq!(script_command("build.sh", StepShell::Ksh).unwrap(), "/usr/bin/ksh -x './build.sh'");
+    assert_eq!(script_command("ci/build", StepShell::Exec).unwrap(), "exec './ci/build'");
+    assert_eq!("exec".parse::<StepShell>().unwrap(), StepShell::Exec);
+    assert!("zsh".parse::<StepShell>().is_err());
+}
+
+#[test]
+fn quotes_script_paths_and_arguments() {
+    assert_eq!(script_words("'my build.sh' --release \"a b\" c\\ d").unwrap(), ["my build.sh", "--release", "a b", "c d"]);
+    assert_eq!(
+        script_command("'my build.sh' --target x86_64 'it''s' ;rm", StepShell::Sh).unwrap(),
+        "/usr/bin/sh -x './my build.sh' '--target' 'x86_64' 'its' ';rm'"
+    );
+    assert_eq!(
+        script_command("deploy.sh \"it's\"", StepShell::Sh).unwrap(),
+        r"/usr/bin/sh -x './deploy.sh' 'it'\''s'"
+    );
+    assert!(script_words("'build.sh").is_err());
+    assert!(script_words("  ").is_err());
+}
+
+#[test]
+fn keeps_library_scripts_inside_the_library() {
+    assert!(validate_script("build.sh").is_ok());
+    assert!(validate_script("lib://rust/build.sh --release").is_ok());
+    assert!(validate_script("lib://").is_err());
+    assert!(validate_script("lib:///etc/passwd").is_err());
+    assert!(validate_script("lib://rust/../../etc/passwd").is_err());
+    assert!(validate_script("\"build.sh").is_err());
+}
@@ -0,0 +1,66 @@
+use renzokutai::color::{self, ColorMode};
+use renzokutai::config::{EnvVar, MatrixAxis};
+
+fn axis(name: &str, values: &str) -> MatrixAxis {
+    MatrixAxis {
+        name: name.to_string(),
+        values: MatrixAxis::parse_values(values).unwrap(),
+    }
+}
+
+fn var(name: &str, value: &str) -> EnvVar {
+    EnvVar {
+        name: name.to_string(),
+        value: value.to_string(),
+    }
+}
+
+#[test]
+fn expands_every_combination() {
+    let axes = [axis("RUST", "1.80, 1.81"), axis("PKGSRC", "2024Q4,2025Q1,2025Q2")];
+    let combinations = MatrixAxis::combinations(&axes);
+
+    assert_eq!(combinations.len(), 6);
+    assert_eq!(combinations[0], vec![var("RUST", "1.80"), var("PKGSRC", "2024Q4")]);
+    assert_eq!(combinations[5], vec![var("RUST", "1.81"), var("PKGSRC", "2025Q2")]);
+    assert_eq!(
+        MatrixAxis::tags(&combinations[0]).iter().map(|t| t.to_string()).collect::<Vec<_>>(),
+        ["RUST=1.80", "PKGSRC=2024Q4"]
+    );
+}
+
+#[test]
+fn validates_axes() {
+    let mut axes = Vec::new();
+    MatrixAxis::set(&mut axes, "RUST".to_string(), "1.80,1.81").unwrap();
+    MatrixAxis::set(&mut axes, "RUST".to_string(), "1.82").unwrap();
+    assert_eq!(axes, vec![axis("RUST", "1.82")]);
+
+    assert!(MatrixAxis::set(&mut axes, "rust-version".to_string(), "1").is_err());
+    assert!(MatrixAxis::set(&mut axes, "OS".to_string(), " , ").is_err());
+    assert!(MatrixAxis::set(&mut axes, "OS".to_string(), "a,b,a").is_err());
+    assert!(MatrixAxis::unset(&mut axes, "OS").is_err());
+    MatrixAxis::unset(&mut axes, "RUST").unwrap();
+    assert!(axes.is_empty());
+}
+
+#[test]
+fn reports_results_per_axis_value() {
+    color::init(ColorMode::Never);
+    let axes = [axis("RUST", "1.80,1.81"), axis("OS", "a,b")];
+    let results: Vec<_> = MatrixAxis::combinations(&axes)
+        .into_iter()
+        .map(|combination| {
+            let ok = !combination.contains(&var("RUST", "1.81")) || combination.contains(&var("OS", "a"));
+            (combination, ok)
+        })
+        .collect();
+
+    assert_eq!(
+        MatrixAxis::report(&axes, &results),
+        "RUST=1.80: 2 succeeded, 0 failed\n\
+         RUST=1.81: 1 succeeded, 1 failed\n\
+         OS=a: 2 succeeded, 0 failed\n\
+         OS=b: 1 succeeded, 1 failed"
+    );
+}
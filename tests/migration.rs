@@ -0,0 +1,38 @@
+use renzokutai::config::migration::{SCHEMA_VERSION, migrate_xml};
+use renzokutai::config::{FileFormat, ValidatedPipeline};
+
+const UNVERSIONED: &str = r#"<pipeline name="old">
+    <repos/>
+    <packages/>
+    <steps>
+        <step name="build" script="build.sh"/>
+        <step name="test" script="test.sh" depends="build"/>
+    </steps>
+</pipeline>"#;
+
+#[test]
+fn moves_step_dependencies_to_elements() {
+    let migrated = migrate_xml(UNVERSIONED).unwrap().unwrap();
+    let pipeline: ValidatedPipeline = FileFormat::Xml.parse(&migrated).unwrap();
+
+    assert_eq!(pipeline.version, SCHEMA_VERSION);
+    let depends: Vec<Vec<&str>> = pipeline
+        .steps
+        .iter()
+        .map(|s| s.depends.iter().map(|d| d.name.as_str()).collect())
+        .collect();
+    assert_eq!(depends, [vec![], vec!["build"]]);
+}
+
+#[test]
+fn leaves_current_pipelines_alone() {
+    let current = format!(r#"<pipeline version="{}" name="new"><steps/></pipeline>"#, SCHEMA_VERSION);
+    assert_eq!(migrate_xml(&current).unwrap(), None);
+}
+
+#[test]
+fn rejects_unknown_versions() {
+    let newer = format!(r#"<pipeline version="{}" name="new"/>"#, SCHEMA_VERSION + 1);
+    assert!(migrate_xml(&newer).is_err());
+    assert!(migrate_xml(r#"<pipeline version="two" name="new"/>"#).is_err());
+}
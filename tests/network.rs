@@ -0,0 +1,41 @@
+use renzokutai::network::{Subnet, allocate};
+
+fn subnet(s: &str) -> Subnet {
+    s.parse().unwrap()
+}
+
+#[test]
+fn parses_subnets() {
+    let s = subnet("10.0.5.0/24");
+    assert_eq!(s.to_string(), "10.0.5.0/24");
+    assert_eq!(s.gateway().to_string(), "10.0.5.1");
+    assert_eq!(s.zone_address().to_string(), "10.0.5.100");
+
+    assert!("10.0.5.0".parse::<Subnet>().is_err());
+    assert!("10.0.5.1/24".parse::<Subnet>().is_err());
+    assert!("10.0.5.0/28".parse::<Subnet>().is_err());
+    assert!("10.0.500.0/24".parse::<Subnet>().is_err());
+}
+
+#[test]
+fn detects_overlaps() {
+    assert!(subnet("10.0.0.0/16").overlaps(&subnet("10.0.5.0/24")));
+    assert!(subnet("10.0.5.0/24").overlaps(&subnet("10.0.0.0/16")));
+    assert!(!subnet("10.0.5.0/24").overlaps(&subnet("10.0.6.0/24")));
+    assert!(subnet("10.0.0.0/16").contains(&subnet("10.0.255.0/24")));
+}
+
+#[test]
+fn allocates_first_free_subnet() {
+    let pool = subnet("10.0.0.0/22");
+
+    assert_eq!(allocate(&pool, &[]), Some(subnet("10.0.0.0/24")));
+    assert_eq!(
+        allocate(&pool, &[subnet("10.0.0.0/24"), subnet("10.0.2.0/24")]),
+        Some(subnet("10.0.1.0/24"))
+    );
+    assert_eq!(
+        allocate(&pool, &[subnet("10.0.0.0/23"), subnet("10.0.2.0/23")]),
+        None
+    );
+}
@@ -0,0 +1,107 @@
+use renzokutai::color::{self, ColorMode};
+use renzokutai::config::{LOGS_DIR_VAR, LogRouter, OutputOverflow, OutputStream, ProvisionLog, StepLog, StepLogFile};
+use std::time::{Duration, SystemTime};
+
+#[test]
+fn keeps_output_within_the_limit() {
+    let mut log = StepLog::new(1024);
+    log.push("building".to_string());
+    log.push("done".to_string());
+
+    assert!(!log.exceeded());
+    assert_eq!(log.render(), "building\ndone\n");
+}
+
+#[test]
+fn truncates_the_middle_of_long_output() {
+    // Lines of 10 bytes with their newline, 3 fit in each half
+    let mut log = StepLog::new(60);
+    for i in 0..10 {
+        log.push(format!("line {:04}", i));
+    }
+
+    assert!(log.exceeded());
+    assert_eq!(log.truncated_bytes(), 40);
+    assert_eq!(log.lines(), 10);
+    assert_eq!(
+        log.render(),
+        "line 0000\nline 0001\nline 0002\n\
+         [... 4 lines (40 bytes) truncated ...]\n\
+         line 0007\nline 0008\nline 0009\n"
+    );
+}
+
+#[test]
+fn parses_overflow_policies() {
+    assert_eq!("truncate".parse::<OutputOverflow>().unwrap(), OutputOverflow::Truncate);
+    assert_eq!("fail".parse::<OutputOverflow>().unwrap(), OutputOverflow::Fail);
+    assert!("ignore".parse::<OutputOverflow>().is_err());
+}
+
+#[test]
+fn converts_sizes_to_bytes() {
+    assert_eq!(renzokutai::zfs::size_bytes("512").unwrap(), 512);
+    assert_eq!(renzokutai::zfs::size_bytes("10M").unwrap(), 10 << 20);
+    assert_eq!(renzokutai::zfs::size_bytes("2g").unwrap(), 2 << 30);
+    assert!(renzokutai::zfs::size_bytes("M").is_err());
+}
+
+#[test]
+fn keeps_provisioning_output_by_line() {
+    let log = ProvisionLog::default();
+    log.push_output("Preparing to install zone\nInstalling: done\n");
+
+    assert_eq!(log.render(), "Preparing to install zone\nInstalling: done\n");
+    assert_eq!(log.truncated_bytes(), 0);
+}
+
+#[test]
+fn writes_the_whole_output_to_a_file() {
+    let dir = std::env::temp_dir().join(format!("renzokutai-logs-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    // SAFETY: the only test of this binary reading it
+    unsafe { std::env::set_var(LOGS_DIR_VAR, &dir) };
+
+    let path = StepLogFile::path("web", "r1", "build");
+    assert_eq!(path, dir.join("web").join("r1").join("build.log"));
+
+    let file = StepLogFile::open(&path).unwrap();
+    file.push("stdout", "compiling");
+    file.push("stderr", "warning: unused");
+    drop(file);
+    // Retries append to the output of the previous attempts
+    StepLogFile::open(&path).unwrap().push("stdout", "done");
+
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "[stdout] compiling\n[stderr] warning: unused\n[stdout] done\n"
+    );
+
+    // Runs written to since the retention started are kept
+    assert_eq!(StepLogFile::prune("web", SystemTime::UNIX_EPOCH).unwrap(), 0);
+    assert!(path.exists());
+    assert_eq!(StepLogFile::prune("web", SystemTime::now() + Duration::from_secs(1)).unwrap(), 1);
+    assert!(!dir.join("web").join("r1").exists());
+    assert_eq!(StepLogFile::prune("mobile", SystemTime::now()).unwrap(), 0);
+}
+
+#[test]
+fn prefixes_the_output_of_each_step() {
+    color::init(ColorMode::Never);
+    let router = LogRouter::new(["build", "integration-test"]);
+
+    assert_eq!(router.format("build", OutputStream::Stdout, "compiling"), "build            | compiling");
+    assert_eq!(
+        router.format("integration-test", OutputStream::Stderr, "warning"),
+        "integration-test | warning"
+    );
+
+    // Grouped, lines are held until the step is over
+    let grouped = router.grouped(true);
+    grouped.line("build", OutputStream::Stdout, "compiling");
+    grouped.line("integration-test", OutputStream::Stdout, "starting");
+    grouped.line("build", OutputStream::Stdout, "done");
+    assert_eq!(grouped.take("build"), ["build            | compiling", "build            | done"]);
+    assert!(grouped.take("build").is_empty());
+    assert_eq!(grouped.take("integration-test").len(), 1);
+}
@@ -0,0 +1,63 @@
+use renzokutai::config::{Package, Provider, ValidatedPackage, ValidatedPackages};
+
+fn package(provider: &str, name: &str, version: Option<&str>) -> ValidatedPackage {
+    ValidatedPackage {
+        provider: provider.parse().unwrap(),
+        name: name.to_string(),
+        version: version.map(str::to_string),
+    }
+}
+
+#[test]
+fn pins_the_version_in_the_install_commands() {
+    let packages: ValidatedPackages = vec![
+        package("pkgsrc", "rust", Some("1.78")),
+        package("pkg", "cmake", Some("3.29")),
+        package("pkgsrc", "jq", None),
+    ]
+    .into();
+
+    assert_eq!(
+        packages.install_commands(),
+        vec![
+            "pkg install -q 'cmake@3.29' || [ $? -eq 4 ]".to_string(),
+            "pkgin -y install 'rust-1.78*' 'jq'".to_string(),
+        ]
+    );
+}
+
+#[test]
+fn rejects_versions_the_shell_would_split() {
+    let mut package = Package::default();
+
+    assert!(package.set("version".to_string(), "1.78 beta".to_string()).is_err());
+    assert!(package.set("version".to_string(), "-1".to_string()).is_err());
+    assert!(package.set("version".to_string(), "1.78;true".to_string()).is_err());
+    package.set("version".to_string(), "1.78.0".to_string()).unwrap();
+    package.set("name".to_string(), "rust".to_string()).unwrap();
+    package.set("provider".to_string(), "pkgsrc".to_string()).unwrap();
+    assert_eq!(package.validate().unwrap().version.as_deref(), Some("1.78.0"));
+}
+
+#[test]
+fn replaces_a_package_pinned_at_another_version() {
+    let host: ValidatedPackages = vec![package("pkgsrc", "rust", Some("1.77")), package("pkgsrc", "jq", None)].into();
+    let own: ValidatedPackages = vec![package("pkgsrc", "rust", Some("1.78"))].into();
+
+    let specs: Vec<String> = host.extended_by(own).iter().map(ValidatedPackage::spec).collect();
+    assert_eq!(specs, ["jq", "rust-1.78*"]);
+}
+
+#[test]
+fn rejects_unknown_providers() {
+    let mut package = Package::default();
+    package.set("name".to_string(), "rust".to_string()).unwrap();
+
+    assert!(package.set("provider".to_string(), "apt".to_string()).is_err());
+    assert!(package.validate().is_err());
+    package.set("provider".to_string(), "pkgsrc".to_string()).unwrap();
+    assert_eq!(package.validate().unwrap().provider, Provider::PkgSrc);
+
+    package.provider = renzokutai::config::Value::Set("brew".to_string());
+    assert!(package.validate().is_err());
+}
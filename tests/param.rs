@@ -0,0 +1,65 @@
+use renzokutai::config::{EnvVar, Param};
+
+fn param(name: &str, default: &str) -> Param {
+    Param {
+        name: name.to_string(),
+        default: default.to_string(),
+    }
+}
+
+fn var(name: &str, value: &str) -> EnvVar {
+    EnvVar {
+        name: name.to_string(),
+        value: value.to_string(),
+    }
+}
+
+#[test]
+fn overrides_the_defaults() {
+    let params = [param("VERSION", "dev"), param("TARGET", "x86_64")];
+
+    assert_eq!(
+        Param::resolve(&params, &[]).unwrap(),
+        vec![var("VERSION", "dev"), var("TARGET", "x86_64")]
+    );
+    assert_eq!(
+        Param::resolve(&params, &[var("TARGET", "aarch64"), var("TARGET", "riscv64")]).unwrap(),
+        vec![var("VERSION", "dev"), var("TARGET", "riscv64")]
+    );
+    assert!(Param::resolve(&params, &[var("UNKNOWN", "1")]).is_err());
+}
+
+#[test]
+fn expands_references() {
+    let resolved = [var("VERSION", "1.2"), var("LOOP", "${param.VERSION}")];
+
+    assert_eq!(
+        Param::expand("release-${param.VERSION}.sh", &resolved).unwrap(),
+        "release-1.2.sh"
+    );
+    assert_eq!(Param::expand("${param.LOOP}-$HOME", &resolved).unwrap(), "${param.VERSION}-$HOME");
+    assert!(Param::expand("${param.MISSING}", &resolved).is_err());
+    assert!(Param::expand("${param.VERSION", &resolved).is_err());
+}
+
+#[test]
+fn checks_references_against_the_declared_params() {
+    let params = [param("VERSION", "dev")];
+
+    assert_eq!(Param::references("a ${param.VERSION} b ${param.X}").unwrap(), vec!["VERSION", "X"]);
+    assert!(Param::check_references("build-${param.VERSION}.sh", &params).is_ok());
+    assert!(Param::check_references("build-${param.TARGET}.sh", &params).is_err());
+}
+
+#[test]
+fn edits_params() {
+    let mut params = Vec::new();
+    Param::set(&mut params, "VERSION".to_string(), "dev".to_string()).unwrap();
+    Param::set(&mut params, "VERSION".to_string(), "1.0".to_string()).unwrap();
+    assert_eq!(params, vec![param("VERSION", "1.0")]);
+
+    assert!(Param::set(&mut params, "not-a-name".to_string(), "1".to_string()).is_err());
+    assert!(Param::unset(&mut params, "TARGET").is_err());
+    Param::unset(&mut params, "VERSION").unwrap();
+    assert!(params.is_empty());
+}
@@ -0,0 +1,197 @@
+use proptest::prelude::*;
+use renzokutai::config::{CfgCommand, DependsAction, ExportFormat, Filter, MovePosition, ParseError, parse_command};
+
+fn identifier() -> impl Strategy<Value = String> {
+    "[a-zA-Z_][a-zA-Z0-9_]{0,15}"
+}
+
+fn value() -> impl Strategy<Value = String> {
+    "(?s).{0,30}"
+}
+
+fn command() -> impl Strategy<Value = CfgCommand> {
+    prop_oneof![
+        (identifier(), proptest::collection::vec((identifier(), value()), 0..4)).prop_map(
+            |(ty, kvs)| CfgCommand::Select {
+                ty,
+                filters: kvs.into_iter().map(|(key, value)| Filter { key, value }).collect(),
+                index: None,
+            }
+        ),
+        (identifier(), 1usize..1000).prop_map(|(ty, index)| CfgCommand::Select {
+            ty,
+            filters: Vec::new(),
+            index: Some(index),
+        }),
+        proptest::collection::vec((identifier(), value()), 1..4).prop_map(|pairs| CfgCommand::Set { pairs }),
+        identifier().prop_map(|key| CfgCommand::Unset { key }),
+        identifier().prop_map(|ty| CfgCommand::Add { ty }),
+        (prop_oneof![Just(DependsAction::Add), Just(DependsAction::Remove)], value())
+            .prop_map(|(action, step)| CfgCommand::Depends { action, step }),
+        (identifier(), value()).prop_map(|(key, value)| CfgCommand::EnvSet { key, value }),
+        identifier().prop_map(|key| CfgCommand::EnvUnset { key }),
+        (identifier(), value()).prop_map(|(key, value)| CfgCommand::SecretSet { key, value }),
+        identifier().prop_map(|key| CfgCommand::SecretUnset { key }),
+        (identifier(), value()).prop_map(|(key, value)| CfgCommand::MatrixSet { key, value }),
+        identifier().prop_map(|key| CfgCommand::MatrixUnset { key }),
+        (identifier(), value()).prop_map(|(key, value)| CfgCommand::ParamSet { key, value }),
+        identifier().prop_map(|key| CfgCommand::ParamUnset { key }),
+        (value(), prop_oneof![Just(MovePosition::Before), Just(MovePosition::After)], value())
+            .prop_map(|(step, position, other)| CfgCommand::Move { step, position, other }),
+        ("[a-zA-Z0-9][a-zA-Z0-9_-]{0,15}", proptest::option::of(identifier()))
+            .prop_map(|(pipeline, ty)| CfgCommand::Import { pipeline, ty }),
+        value().prop_map(|path| CfgCommand::ImportDrone { path }),
+        (
+            identifier(),
+            proptest::collection::vec((identifier(), value()), 0..3),
+            proptest::option::of(value()),
+        )
+            .prop_map(|(ty, kvs, name)| CfgCommand::Copy {
+                ty,
+                filters: kvs.into_iter().map(|(key, value)| Filter { key, value }).collect(),
+                name,
+            }),
+        Just(CfgCommand::Print),
+        Just(CfgCommand::Info),
+        Just(CfgCommand::History),
+        Just(CfgCommand::Undo),
+        Just(CfgCommand::Redo),
+        Just(CfgCommand::Help),
+        Just(CfgCommand::Validate),
+        Just(CfgCommand::End),
+        Just(CfgCommand::Exit),
+        Just(CfgCommand::Save),
+        Just(CfgCommand::Diff),
+        (
+            prop_oneof![Just(ExportFormat::Xml), Just(ExportFormat::Json), Just(ExportFormat::Toml), Just(ExportFormat::Yaml)],
+            proptest::option::of(value()),
+        )
+            .prop_map(|(format, path)| CfgCommand::Export { format, path }),
+        Just(CfgCommand::Apply),
+        Just(CfgCommand::Commit),
+        Just(CfgCommand::Revert),
+    ]
+}
+
+fn set(line: &str) -> String {
+    match parse_command(line) {
+        Ok(CfgCommand::Set { pairs }) if pairs.len() == 1 => pairs[0].1.clone(),
+        other => panic!("unexpected parse of {:?}: {:?}", line, other),
+    }
+}
+
+fn set_pairs(line: &str) -> Vec<(String, String)> {
+    match parse_command(line) {
+        Ok(CfgCommand::Set { pairs }) => pairs,
+        other => panic!("unexpected parse of {:?}: {:?}", line, other),
+    }
+}
+
+fn select(line: &str) -> Vec<(String, String)> {
+    match parse_command(line) {
+        Ok(CfgCommand::Select { filters, .. }) => {
+            filters.into_iter().map(|f| (f.key, f.value)).collect()
+        }
+        other => panic!("unexpected parse of {:?}: {:?}", line, other),
+    }
+}
+
+#[test]
+fn parses_multiple_select_filters() {
+    let expected = vec![
+        ("name".to_string(), "build".to_string()),
+        ("script".to_string(), "build.sh".to_string()),
+    ];
+
+    assert_eq!(select("select step"), Vec::new());
+    assert_eq!(select("select step name=build script=build.sh"), expected);
+    assert_eq!(select("select step name=build,script=build.sh"), expected);
+    assert_eq!(select("select step name=build, script=\"build.sh\""), expected);
+    assert_eq!(
+        select("select step name=\"build all\" script=x"),
+        vec![("name".to_string(), "build all".to_string()), ("script".to_string(), "x".to_string())]
+    );
+}
+
+#[test]
+fn parses_quoted_values() {
+    assert_eq!(set("set script=./run tests.sh"), "./run tests.sh");
+    assert_eq!(set(r#"set script="./run tests.sh""#), "./run tests.sh");
+    assert_eq!(set("set script='./run tests.sh'"), "./run tests.sh");
+    assert_eq!(set(r#"set name="elixir""#), "elixir");
+    assert_eq!(set(r#"set name="""#), "");
+    assert_eq!(set(r#"set name="say \"hi\"\n""#), "say \"hi\"\n");
+    assert_eq!(set(r#"set name='it\'s'"#), "it's");
+    assert_eq!(
+        set("set url=https://example.com/repo?ref=main&depth=1"),
+        "https://example.com/repo?ref=main&depth=1"
+    );
+}
+
+#[test]
+fn parses_multiple_set_pairs() {
+    let pair = |k: &str, v: &str| (k.to_string(), v.to_string());
+
+    assert_eq!(
+        set_pairs("set name=build script=build.sh"),
+        vec![pair("name", "build"), pair("script", "build.sh")]
+    );
+    assert_eq!(
+        set_pairs("set script=./run tests.sh name=\"build all\""),
+        vec![pair("script", "./run tests.sh"), pair("name", "build all")]
+    );
+    assert_eq!(set_pairs("set name= script=x"), vec![pair("name", ""), pair("script", "x")]);
+    assert_eq!(set_pairs("set url=https://x/?a=b"), vec![pair("url", "https://x/?a=b")]);
+    assert_eq!(set_pairs("set script=./run.sh ARG=1"), vec![pair("script", "./run.sh"), pair("ARG", "1")]);
+    assert_eq!(
+        set_pairs("set script=\"./run.sh ARG=1\" name=build"),
+        vec![pair("script", "./run.sh ARG=1"), pair("name", "build")]
+    );
+}
+
+#[test]
+fn parses_exit_aliases() {
+    assert!(matches!(parse_command("exit"), Ok(CfgCommand::Exit)));
+    assert!(matches!(parse_command("  quit"), Ok(CfgCommand::Exit)));
+    assert!(matches!(parse_command("export"), Ok(CfgCommand::Export { .. })));
+}
+
+#[test]
+fn explains_parse_errors() {
+    let error = |line: &str| parse_command(line).unwrap_err().to_string();
+
+    assert_eq!(parse_command("   "), Err(ParseError::Empty));
+    assert_eq!(error("frobnicate step"), "Unknown command 'frobnicate', type help for the list");
+    assert_eq!(error("set"), "Expected key=value after 'set'");
+    assert_eq!(error("set colour"), "Expected key=value after 'set', found 'colour'");
+    assert_eq!(error("end now"), "Expected nothing after 'end', found 'now'");
+    assert_eq!(
+        parse_command("move step test around build"),
+        Err(ParseError::Unexpected {
+            command: "move".to_string(),
+            expected: "step <name> before|after <name>",
+            found: Some("around".to_string()),
+        })
+    );
+}
+
+proptest! {
+    #[test]
+    fn never_panics(input in any::<String>()) {
+        let _ = parse_command(&input);
+    }
+
+    #[test]
+    fn never_panics_on_command_like_input(input in "(select|set|add|end|info) ?[a-z=\"' ]{0,20}") {
+        let _ = parse_command(&input);
+    }
+
+    #[test]
+    fn render_parse_round_trip(cmd in command()) {
+        let rendered = cmd.to_string();
+        let parsed = parse_command(&rendered).unwrap();
+
+        prop_assert_eq!(&parsed, &cmd);
+        prop_assert_eq!(parsed.to_string(), rendered);
+    }
+}
@@ -0,0 +1,123 @@
+use renzokutai::config::{DEFAULT_RETRY_DELAY, Step, Value};
+use renzokutai::policy::{DEFAULT_BOOT_TIMEOUT, Deadline, Policy, PolicyOverrides, format_duration, parse_duration};
+use std::time::Duration;
+
+#[test]
+fn parses_durations() {
+    assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+    assert_eq!(parse_duration("30m").unwrap(), Duration::from_secs(30 * 60));
+    assert_eq!(parse_duration("2h").unwrap(), Duration::from_secs(2 * 60 * 60));
+    assert_eq!(parse_duration("7d").unwrap(), Duration::from_secs(7 * 24 * 60 * 60));
+
+    for invalid in ["", "30", "m", "0m", "1w", "-5m", "1.5h"] {
+        assert!(parse_duration(invalid).is_err(), "{} should be rejected", invalid);
+    }
+}
+
+#[test]
+fn formats_durations_with_the_largest_unit() {
+    assert_eq!(format_duration(Duration::from_secs(90)), "90s");
+    assert_eq!(format_duration(Duration::from_secs(120)), "2m");
+    assert_eq!(format_duration(Duration::from_secs(36 * 60 * 60)), "36h");
+    assert_eq!(format_duration(Duration::from_secs(14 * 24 * 60 * 60)), "14d");
+}
+
+#[test]
+fn pipelines_override_the_host_policy() {
+    let host = Policy {
+        step_timeout: Some(Duration::from_secs(600)),
+        run_timeout: Some(Duration::from_secs(3600)),
+        log_retention: Some(Duration::from_secs(30 * 24 * 60 * 60)),
+        ..Policy::default()
+    };
+    let policy = host
+        .with_overrides(&PolicyOverrides {
+            step_timeout: Some("1h".to_string()),
+            boot_timeout: Some("10m".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+
+    assert_eq!(policy.step_timeout, Some(Duration::from_secs(3600)));
+    assert_eq!(policy.run_timeout, host.run_timeout);
+    assert_eq!(policy.boot_timeout, Duration::from_secs(600));
+    assert_eq!(policy.log_retention, host.log_retention);
+    assert_eq!(host.boot_timeout, DEFAULT_BOOT_TIMEOUT);
+
+    assert!(
+        host.with_overrides(&PolicyOverrides {
+            run_timeout: Some("soon".to_string()),
+            ..Default::default()
+        })
+        .is_err()
+    );
+}
+
+#[test]
+fn pipelines_bound_the_whole_run() {
+    let policy = Policy::default()
+        .with_overrides(&PolicyOverrides {
+            total_timeout: Some("2h".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(policy.total_timeout, Some(Duration::from_secs(2 * 60 * 60)));
+    assert!(policy.to_string().contains("total_timeout: 2h"));
+    assert_eq!(Deadline::after(None), None);
+}
+
+#[tokio::test]
+async fn cuts_work_short_past_the_deadline() {
+    let deadline = Deadline::after(Some(Duration::from_millis(20)));
+    let slow = async {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        Ok(())
+    };
+    let err = Deadline::bound(deadline, slow).await.unwrap_err();
+    assert!(err.to_string().contains("timeout"), "{}", err);
+    assert!(deadline.unwrap().passed());
+
+    let deadline = Deadline::after(Some(Duration::from_secs(60)));
+    assert_eq!(Deadline::bound(deadline, async { Ok(1) }).await.unwrap(), 1);
+    assert_eq!(Deadline::bound(None, async { Ok(2) }).await.unwrap(), 2);
+}
+
+#[test]
+fn steps_override_the_step_timeout() {
+    let mut step = Step {
+        name: Value::Set("build".to_string()),
+        script: Value::Set("build.sh".to_string()),
+        ..Default::default()
+    };
+    assert!(step.set("timeout".to_string(), "soon".to_string()).is_err());
+
+    let inherited = Some(Duration::from_secs(60 * 60));
+    let validated = step.validate(&Default::default()).unwrap();
+    assert_eq!(validated.timeout(inherited).unwrap(), inherited);
+
+    step.set("timeout".to_string(), "10m".to_string()).unwrap();
+    let validated = step.validate(&Default::default()).unwrap();
+    assert_eq!(validated.timeout(inherited).unwrap(), Some(Duration::from_secs(10 * 60)));
+    assert_eq!(validated.timeout(None).unwrap(), Some(Duration::from_secs(10 * 60)));
+}
+
+#[test]
+fn backs_off_between_step_retries() {
+    let mut step = Step {
+        name: Value::Set("flaky".to_string()),
+        script: Value::Set("flaky.sh".to_string()),
+        ..Default::default()
+    };
+    assert!(step.set("retries".to_string(), "11".to_string()).is_err());
+    assert!(step.set("retries".to_string(), "-1".to_string()).is_err());
+    step.set("retries".to_string(), "3".to_string()).unwrap();
+
+    let validated = step.validate(&Default::default()).unwrap();
+    assert_eq!(validated.retries, Some(3));
+    assert_eq!(validated.retry_delay(1).unwrap(), DEFAULT_RETRY_DELAY);
+
+    step.set("retry_delay".to_string(), "30s".to_string()).unwrap();
+    let validated = step.validate(&Default::default()).unwrap();
+    let delays: Vec<u64> = (1..=3).map(|attempt| validated.retry_delay(attempt).unwrap().as_secs()).collect();
+    assert_eq!(delays, [30, 60, 120]);
+}
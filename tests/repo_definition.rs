@@ -0,0 +1,85 @@
+use renzokutai::config::repo_definition::{DEFINITION_FILE, merge, read};
+use renzokutai::config::{ValidatedStep, ValidatedSteps};
+
+fn step(name: &str, script: &str) -> ValidatedStep {
+    ValidatedStep {
+        name: name.to_string(),
+        script: script.to_string(),
+        ..Default::default()
+    }
+}
+
+fn host() -> ValidatedSteps {
+    vec![step("build", "build.sh"), step("test", "test.sh")].into()
+}
+
+#[test]
+fn merges_the_steps_of_the_repository_over_the_host() {
+    let xml = r#"
+        <renzokutai>
+            <steps>
+                <step name="test" script="ci/test.sh"/>
+                <step name="lint" script="ci/lint.sh">
+                    <depend><name>build</name></depend>
+                </step>
+            </steps>
+        </renzokutai>"#;
+
+    let steps = merge(xml, &host()).unwrap();
+    let scripts: Vec<(&str, &str)> = steps.iter().map(|s| (s.name.as_str(), s.script.as_str())).collect();
+    assert_eq!(scripts, [("build", "build.sh"), ("test", "ci/test.sh"), ("lint", "ci/lint.sh")]);
+}
+
+#[test]
+fn keeps_the_host_steps_without_a_steps_section() {
+    assert_eq!(merge("<renzokutai/>", &host()).unwrap(), host());
+}
+
+#[test]
+fn rejects_invalid_definitions() {
+    assert!(merge("<renzokutai><steps>", &host()).is_err());
+
+    let unknown_dependency = r#"
+        <renzokutai>
+            <steps>
+                <step name="lint" script="ci/lint.sh"><depend><name>missing</name></depend></step>
+            </steps>
+        </renzokutai>"#;
+    assert!(merge(unknown_dependency, &host()).is_err());
+}
+
+#[test]
+fn rejects_secret_files_in_definitions() {
+    let xml = r#"
+        <renzokutai>
+            <steps>
+                <step name="leak" script="cat /tmp/x">
+                    <secret_file source="/etc/shadow" target="/tmp/x" mode="0600"/>
+                </step>
+            </steps>
+        </renzokutai>"#;
+
+    let err = merge(xml, &host()).unwrap_err();
+    assert!(err.to_string().contains("secret_file"), "{}", err);
+}
+
+#[test]
+fn reads_definitions_only_from_inside_the_zone() {
+    let root = std::env::temp_dir().join(format!("renzokutai-definition-{}", std::process::id()));
+    let checkout = root.join("zone/root/checkout");
+    std::fs::create_dir_all(&checkout).unwrap();
+    let zone_root = root.join("zone/root");
+
+    assert_eq!(read(&zone_root, "/checkout").unwrap(), None);
+
+    std::fs::write(checkout.join(DEFINITION_FILE), "<renzokutai/>").unwrap();
+    assert_eq!(read(&zone_root, "/checkout").unwrap().as_deref(), Some("<renzokutai/>"));
+
+    // A link committed to the repository must not reach host files
+    std::fs::write(root.join("host-file"), "secret").unwrap();
+    std::fs::remove_file(checkout.join(DEFINITION_FILE)).unwrap();
+    std::os::unix::fs::symlink(root.join("host-file"), checkout.join(DEFINITION_FILE)).unwrap();
+    assert!(read(&zone_root, "/checkout").is_err());
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
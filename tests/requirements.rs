@@ -0,0 +1,36 @@
+use renzokutai::config::PackageRequirement;
+
+#[test]
+fn parses_step_requirements() {
+    let requires = PackageRequirement::parse_list("pkg:cmake, pkgsrc:ninja-build").unwrap();
+    assert_eq!(
+        requires,
+        vec![
+            PackageRequirement {
+                provider: "pkg".to_string(),
+                name: "cmake".to_string()
+            },
+            PackageRequirement {
+                provider: "pkgsrc".to_string(),
+                name: "ninja-build".to_string()
+            },
+        ]
+    );
+
+    assert!(PackageRequirement::parse_list("cmake").is_err());
+    assert!(PackageRequirement::parse_list("apt:cmake").is_err());
+    assert!(PackageRequirement::parse_list("pkg:").is_err());
+}
+
+#[test]
+fn installs_one_batch_per_provider() {
+    let requires = PackageRequirement::parse_list("pkgsrc:ninja-build,pkg:cmake,pkgsrc:jq").unwrap();
+    assert_eq!(
+        PackageRequirement::install_commands(&requires),
+        vec![
+            "pkg install -q 'cmake' || [ $? -eq 4 ]".to_string(),
+            "pkgin -y install 'ninja-build' 'jq'".to_string(),
+        ]
+    );
+    assert!(PackageRequirement::install_commands(&[]).is_empty());
+}
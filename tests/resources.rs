@@ -0,0 +1,36 @@
+use renzokutai::config::Resources;
+
+#[test]
+fn parses_cpu_limits_into_caps() {
+    assert_eq!(Resources::parse_cpu_limit("2").unwrap(), 200);
+    assert_eq!(Resources::parse_cpu_limit("0.5").unwrap(), 50);
+    for invalid in ["0", "-1", "two", "inf", ""] {
+        assert!(Resources::parse_cpu_limit(invalid).is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn wraps_commands_in_resource_controls() {
+    let command = "cd /src && ./build.sh".to_string();
+    assert_eq!(Resources::default().wrap("build", command.clone()), command);
+
+    let memory = Resources {
+        memory: Some(1024),
+        cpu_cap: None,
+    };
+    assert_eq!(
+        memory.wrap("build", command.clone()),
+        "prctl -n process.max-address-space -t privileged -v 1024 -e deny -i process $$ && cd /src && ./build.sh"
+    );
+
+    let cpu = Resources {
+        memory: None,
+        cpu_cap: Some(150),
+    };
+    assert_eq!(
+        cpu.wrap("build it", "echo 'hi'".to_string()),
+        "(projmod -s -K 'project.cpu-cap=(privileged,150,deny)' renzokutai_build_it 2>/dev/null \
+         || projadd -K 'project.cpu-cap=(privileged,150,deny)' renzokutai_build_it) \
+         && newtask -p renzokutai_build_it sh -c 'echo '\\''hi'\\'''"
+    );
+}
@@ -0,0 +1,36 @@
+use renzokutai::config::{REDACTED, Secret};
+use std::os::unix::fs::PermissionsExt;
+
+fn secret(name: &str, value: &str) -> Secret {
+    Secret {
+        name: name.to_string(),
+        value: value.to_string(),
+    }
+}
+
+#[test]
+fn masks_secret_values() {
+    let secrets = [secret("TOKEN", "abc"), secret("LONG_TOKEN", "abcdef"), secret("EMPTY", "")];
+
+    assert_eq!(Secret::redact("token=abc", &secrets), format!("token={}", REDACTED));
+    assert_eq!(Secret::redact("abcdef abc", &secrets), format!("{} {}", REDACTED, REDACTED));
+    assert_eq!(Secret::redact("nothing here", &secrets), "nothing here");
+    assert!(!format!("{:?}", secrets[0]).contains("abc"));
+}
+
+#[test]
+fn stores_secrets_readable_by_the_owner_only() {
+    let path = std::env::temp_dir().join(format!("renzokutai-secrets-{}", std::process::id()));
+    assert!(Secret::load(&path).unwrap().is_empty());
+
+    Secret::save(&path, &[secret("TOKEN", "abc"), secret("PASSWORD", "it's \"quoted\"")]).unwrap();
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    assert_eq!(
+        Secret::load(&path).unwrap(),
+        vec![secret("PASSWORD", "it's \"quoted\""), secret("TOKEN", "abc")]
+    );
+
+    Secret::save(&path, &[]).unwrap();
+    assert!(!path.exists());
+}
@@ -0,0 +1,137 @@
+#![cfg(feature = "daemon")]
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use renzokutai::config::{TriggerKind, ValidatedPipeline, ValidatedPipelineTrigger};
+use renzokutai::trigger::{CronTrigger, RunRequest, RunSender, Scheduler, Trigger, WatchTrigger};
+use std::time::Duration;
+
+/// Asks for a fixed list of runs, like a schedule firing once
+struct Fixed(Vec<RunRequest>);
+
+impl Trigger for Fixed {
+    fn name(&self) -> &str {
+        "fixed"
+    }
+
+    fn listen(self: Box<Self>, requests: RunSender) -> BoxFuture<'static, Result<()>> {
+        Box::pin(async move {
+            for request in self.0 {
+                requests.send(request).await?;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[tokio::test]
+async fn schedules_requests_of_every_trigger() {
+    let mut scheduler = Scheduler::new();
+    let mut nightly = RunRequest::new("katarineko", "cron");
+    nightly.branch = Some("main".to_string());
+
+    scheduler.add(Box::new(Fixed(vec![nightly.clone()])));
+    scheduler.add(Box::new(Fixed(vec![RunRequest::new("mikeneko", "poll")])));
+
+    let mut received = vec![scheduler.next().await.unwrap(), scheduler.next().await.unwrap()];
+    received.sort_by(|a, b| a.pipeline.cmp(&b.pipeline));
+    assert_eq!(received, vec![nightly, RunRequest::new("mikeneko", "poll")]);
+}
+
+#[tokio::test]
+async fn tells_submitters_when_runs_cannot_start() {
+    let scheduler = Scheduler::new();
+    let submitter = scheduler.submitter();
+    tokio::spawn(scheduler.run());
+
+    let err = submitter.submit(RunRequest::new("no-such-pipeline", "web")).await.unwrap_err();
+    assert!(err.to_string().contains("Unknown pipeline no-such-pipeline"), "{}", err);
+}
+
+#[test]
+fn starts_runs_through_pipelineadm() {
+    let mut request = RunRequest::new("katarineko", "web");
+    request.commit = Some("abc123".to_string());
+    request.tags = vec!["release=1.2".parse().unwrap()];
+
+    assert_eq!(
+        request.args(),
+        vec!["-p", "katarineko", "run", "--commit", "abc123", "--tag", "release=1.2", "--actor", "web"]
+    );
+}
+
+#[tokio::test]
+async fn runs_against_the_watched_tree_once_changes_settle() {
+    let dir = std::env::temp_dir().join(format!("renzokutai-watch-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let mut scheduler = Scheduler::new();
+    scheduler.add(Box::new(WatchTrigger::new("katarineko", &dir, Duration::from_millis(200))));
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    for i in 0..3 {
+        std::fs::write(dir.join("main.rs"), format!("fn main() {{ {} }}", i)).unwrap();
+    }
+    let request = tokio::time::timeout(Duration::from_secs(5), scheduler.next())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(request.actor, "watch");
+    assert_eq!(request.local_src, Some(dir));
+
+    // The burst of writes asked for a single run
+    assert!(tokio::time::timeout(Duration::from_millis(500), scheduler.next()).await.is_err());
+}
+
+fn scheduled(name: &str, exprs: &[&str], schedule_exclude: Option<&str>) -> ValidatedPipeline {
+    ValidatedPipeline {
+        name: name.to_string(),
+        enabled: true,
+        timezone: Some("Asia/Tokyo".to_string()),
+        schedule_exclude: schedule_exclude.map(str::to_string),
+        triggers: exprs
+            .iter()
+            .map(|expr| ValidatedPipelineTrigger {
+                kind: TriggerKind::Cron,
+                expr: expr.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .into(),
+        ..ValidatedPipeline::default()
+    }
+}
+
+#[test]
+fn fires_cron_triggers_in_the_pipeline_timezone() {
+    // 2026-10-18 03:00 in Tokyo, a Sunday
+    let now = 1_792_260_000;
+    let mut disabled = scheduled("disabled", &["0 3 * * *"], None);
+    disabled.enabled = false;
+    let pipelines = [
+        scheduled("nightly", &["0 3 * * *", "0 * * * *"], None),
+        scheduled("utc-nightly", &["0 18 * * *"], None),
+        scheduled("weekdays", &["0 3 * * *"], Some("weekends")),
+        scheduled("later", &["5 3 * * *"], None),
+        disabled,
+        scheduled("untriggered", &[], None),
+    ];
+
+    let due = CronTrigger::due(&pipelines, now);
+    assert_eq!(due, vec![RunRequest::new("nightly", "cron")]);
+    assert_eq!(CronTrigger::due(&pipelines, now + 59), due);
+    assert_eq!(CronTrigger::due(&pipelines, now + 5 * 60), vec![RunRequest::new("later", "cron")]);
+}
+
+#[test]
+fn skips_only_the_pipelines_whose_triggers_are_broken() {
+    let now = 1_792_260_000;
+    let mut elsewhere = scheduled("elsewhere", &["0 3 * * *"], None);
+    elsewhere.timezone = Some("Nowhere/Atlantis".to_string());
+    let pipelines = [
+        scheduled("broken", &["0 3 * * *", "61 * * * *"], None),
+        elsewhere,
+        scheduled("nightly", &["0 3 * * *"], None),
+    ];
+
+    assert_eq!(CronTrigger::due(&pipelines, now), vec![RunRequest::new("nightly", "cron")]);
+}
@@ -0,0 +1,28 @@
+use renzokutai::version::is_newer;
+
+#[test]
+fn compares_dotted_versions_numerically() {
+    assert!(is_newer("0.10.0", "0.9.0"));
+    assert!(is_newer("v1.2.1", "1.2.0"));
+    assert!(!is_newer("1.2.0", "1.10.0"));
+    assert!(!is_newer("1.2.0", "1.2.0"));
+}
+
+#[test]
+fn pads_versions_with_zeros() {
+    assert!(!is_newer("1.0.0", "1.0"));
+    assert!(!is_newer("1.0", "1.0.0"));
+    assert!(is_newer("1.0.1", "1.0"));
+}
+
+#[test]
+fn puts_pre_releases_before_their_release() {
+    assert!(!is_newer("1.2.0-rc1", "1.2.0"));
+    assert!(is_newer("1.2.0", "1.2.0-rc1"));
+    assert!(is_newer("1.2.0-rc1", "1.1.9"));
+    assert!(is_newer("1.2.0-rc.2", "1.2.0-rc.1"));
+    assert!(is_newer("1.2.0-rc.10", "1.2.0-rc.9"));
+    assert!(is_newer("1.2.0-rc.1", "1.2.0-beta.3"));
+    assert!(is_newer("1.2.0-alpha.beta", "1.2.0-alpha.1"));
+    assert!(!is_newer("1.2.0+build.5", "1.2.0"));
+}
@@ -0,0 +1,269 @@
+use proptest::prelude::*;
+use renzokutai::config::migration::SCHEMA_VERSION;
+use renzokutai::config::{
+    ArtifactNeed, ArtifactPath, Artifacts, EnvVar, FileFormat, TriggerKind, ValidatedBuildCache, MatrixAxis, PackageRequirement, Param, Provider, SecretFile, ValidatedDependency, ValidatedPackage, ValidatedPipeline, ValidatedPipelineTrigger, ValidatedRepo, ValidatedService, ValidatedStep,
+};
+
+fn text() -> impl Strategy<Value = String> {
+    // Surrounding whitespace and empty values must survive too
+    "[ ]{0,2}[a-zA-Z0-9 ._/:?=&<>\"'-]{0,20}[ ]{0,2}"
+}
+
+/// Matrix values are a trimmed comma separated list, so they can't be blank
+fn matrix_value() -> impl Strategy<Value = String> {
+    "[a-zA-Z0-9][a-zA-Z0-9 ._/:?=&<>\"'-]{0,20}[a-zA-Z0-9]"
+}
+
+fn package() -> impl Strategy<Value = ValidatedPackage> {
+    (
+        text(),
+        prop_oneof![Just(Provider::Pkg), Just(Provider::PkgSrc)],
+        proptest::option::of("[0-9][0-9a-z.]{0,10}"),
+    )
+        .prop_map(|(name, provider, version)| ValidatedPackage { name, provider, version })
+}
+
+fn repo() -> impl Strategy<Value = ValidatedRepo> {
+    (
+        text(),
+        proptest::option::of("[a-z0-9][a-z0-9./_-]{0,15}"),
+        proptest::option::of(1u32..1000),
+        proptest::option::of("[A-Z][A-Z0-9_]{0,10}"),
+        any::<bool>(),
+        proptest::option::of("/[a-z0-9/_-]{1,15}"),
+    )
+        .prop_map(|(url, reference, depth, credential, submodules, dest)| ValidatedRepo {
+            url,
+            reference,
+            depth,
+            credential,
+            submodules,
+            dest,
+        })
+}
+
+fn env() -> impl Strategy<Value = Vec<EnvVar>> {
+    prop::collection::vec(("[A-Z_][A-Z0-9_]{0,10}", text()), 0..3)
+        .prop_map(|vars| vars.into_iter().map(|(name, value)| EnvVar { name, value }).collect())
+}
+
+fn matrix() -> impl Strategy<Value = Vec<MatrixAxis>> {
+    prop::collection::vec(("[A-Z_][A-Z0-9_]{0,10}", prop::collection::btree_set(matrix_value(), 1..3)), 0..3).prop_map(|axes| {
+        axes.into_iter()
+            .map(|(name, values)| MatrixAxis {
+                name,
+                values: values.into_iter().collect(),
+            })
+            .collect()
+    })
+}
+
+fn params() -> impl Strategy<Value = Vec<Param>> {
+    prop::collection::vec(("[A-Z_][A-Z0-9_]{0,10}", text()), 0..3)
+        .prop_map(|params| params.into_iter().map(|(name, default)| Param { name, default }).collect())
+}
+
+fn step() -> impl Strategy<Value = ValidatedStep> {
+    (
+        text(),
+        text(),
+        prop::collection::vec(text(), 0..3),
+        prop::collection::vec((text(), text()), 0..2),
+        prop::collection::vec((text(), text(), "0[0-7]{3}"), 0..2),
+        prop::collection::vec((text(), text()), 0..2),
+        env(),
+        size(),
+        proptest::option::of(prop_oneof!["truncate", "fail"]),
+        size(),
+        proptest::option::of("[1-9](\\.5)?"),
+        (
+            duration(),
+            proptest::option::of(0u32..=10),
+            duration(),
+            proptest::option::of(prop_oneof![
+                Just("branch == 'main'".to_string()),
+                Just("env.RUN_TESTS != \"0\" || tag.nightly == 'yes'".to_string()),
+            ]),
+            prop::collection::vec("[a-z/]{1,10}(\\*\\.tgz)?", 0..3),
+            proptest::option::of("[a-z/]{1,10}(\\*\\.rs)?"),
+            proptest::option::of(text()),
+            user(),
+            proptest::option::of(prop_oneof!["build", "test", "deploy"].prop_map(String::from)),
+            proptest::option::of(prop_oneof!["on_success", "always"].prop_map(String::from)),
+            (
+                prop::collection::vec("[a-z/]{1,10}", 0..2),
+                prop::collection::vec("[a-z/]{1,10}", 0..2),
+                proptest::option::of(prop_oneof!["sh", "bash", "ksh", "exec"].prop_map(String::from)),
+            ),
+        ),
+    )
+        .prop_map(|(name, script, depends, needs, secrets, requires, env, output_limit, on_output_limit, memory_limit, cpu_limit, (timeout, retries, retry_delay, when, artifacts, fingerprint, fingerprint_command, user, stage, run, (produces, consumes, shell)))| ValidatedStep {
+            name,
+            script,
+            depends: depends
+                .into_iter()
+                .map(|name| ValidatedDependency { name })
+                .collect(),
+            needs_artifacts: needs
+                .into_iter()
+                .map(|(step, path)| ArtifactNeed { step, path })
+                .collect(),
+            artifacts: Artifacts {
+                paths: artifacts.into_iter().map(|path| ArtifactPath { path }).collect(),
+            },
+            produces: produces.into_iter().map(|path| ArtifactPath { path }).collect(),
+            consumes: consumes.into_iter().map(|path| ArtifactPath { path }).collect(),
+            secret_files: secrets
+                .into_iter()
+                .map(|(source, target, mode)| SecretFile {
+                    source,
+                    target,
+                    mode,
+                })
+                .collect(),
+            requires: requires
+                .into_iter()
+                .map(|(provider, name)| PackageRequirement { provider, name })
+                .collect(),
+            env,
+            output_limit,
+            on_output_limit,
+            memory_limit,
+            cpu_limit,
+            timeout,
+            retries,
+            retry_delay,
+            when,
+            fingerprint,
+            fingerprint_command,
+            user,
+            stage,
+            run,
+            shell,
+            secrets: Vec::new(),
+            workdir: None,
+            producers: Vec::new(),
+        })
+}
+
+fn user() -> impl Strategy<Value = Option<String>> {
+    proptest::option::of("[a-z][a-z0-9_-]{0,15}")
+}
+
+fn size() -> impl Strategy<Value = Option<String>> {
+    proptest::option::of("[1-9][0-9]{0,3}[KMG]")
+}
+
+fn subnet() -> impl Strategy<Value = Option<String>> {
+    proptest::option::of((0u8..=255, 0u8..=255).prop_map(|(b, c)| format!("10.{}.{}.0/24", b, c)))
+}
+
+fn schedule() -> impl Strategy<Value = Option<String>> {
+    proptest::option::of(prop_oneof![Just("daily"), Just("weekly"), Just("monthly")].prop_map(String::from))
+}
+
+fn duration() -> impl Strategy<Value = Option<String>> {
+    proptest::option::of("[1-9][0-9]{0,2}[smhd]")
+}
+
+fn cache() -> impl Strategy<Value = ValidatedBuildCache> {
+    ("/[a-z]{1,8}(/[a-z.]{1,8})?", "[a-z0-9_-]{1,12}").prop_map(|(path, key)| ValidatedBuildCache { path, key })
+}
+
+fn trigger() -> impl Strategy<Value = ValidatedPipelineTrigger> {
+    prop_oneof![Just("0 3 * * *"), Just("*/15 9-17 * * mon-fri"), Just("@weekly")].prop_map(|expr| ValidatedPipelineTrigger {
+        kind: TriggerKind::Cron,
+        expr: expr.to_string(),
+    })
+}
+
+fn service() -> impl Strategy<Value = ValidatedService> {
+    (
+        "[a-z][a-z0-9_-]{0,11}",
+        text(),
+        proptest::option::of(text()),
+        duration(),
+    )
+        .prop_map(|(name, script, ready, ready_timeout)| ValidatedService { name, script, ready, ready_timeout })
+}
+
+fn pipeline() -> impl Strategy<Value = ValidatedPipeline> {
+    (
+        text(),
+        size(),
+        size(),
+        subnet(),
+        (
+            schedule(),
+            proptest::option::of(prop_oneof!["UTC", "Europe/Madrid"]),
+            proptest::option::of("(weekends|mon|12-25|2026-01-06)(,fri)?"),
+            prop::collection::vec(trigger(), 0..3),
+            prop::collection::vec(service(), 0..3),
+        ),
+        any::<bool>(),
+        (
+            user(),
+            proptest::option::of("[a-z][a-z0-9_-]{0,15}"),
+            (proptest::option::of(text()), proptest::option::of("[a-z]{1,8}@[a-z]{1,8}\\.org"), prop::collection::vec("[a-z0-9_-]{1,10}", 0..3)),
+        ),
+        (duration(), duration(), duration(), duration(), duration(), proptest::option::of(1u32..=16)),
+        prop::collection::vec(repo(), 0..3),
+        prop::collection::vec(package(), 0..3),
+        (prop::collection::vec(step(), 0..4), env(), matrix(), prop::collection::vec(cache(), 0..3), params()),
+    )
+        .prop_map(|(name, disk_quota, swap, subnet, (base_refresh, timezone, schedule_exclude, triggers, services), enabled, (user, extends, (description, owner, labels)), durations, repos, packages, (steps, env, matrix, caches, params))| {
+            let (step_timeout, run_timeout, total_timeout, boot_timeout, log_retention, max_parallel) = durations;
+            ValidatedPipeline {
+                version: SCHEMA_VERSION,
+                name,
+                description,
+                owner,
+                labels,
+                extends,
+                disk_quota,
+                swap,
+                subnet,
+                base_refresh,
+                timezone,
+                schedule_exclude,
+                enabled,
+                step_timeout,
+                run_timeout,
+                total_timeout,
+                boot_timeout,
+                log_retention,
+                max_parallel,
+                user,
+                repos: repos.into(),
+                packages: packages.into(),
+                steps: steps.into(),
+                caches: caches.into(),
+                triggers: triggers.into(),
+                services: services.into(),
+                env,
+                secrets: Vec::new(),
+                matrix,
+                params,
+            }
+        })
+}
+
+proptest! {
+    #[test]
+    fn xml_round_trip(pipeline in pipeline()) {
+        let xml = serde_xml_rs::to_string(&pipeline).unwrap();
+        let parsed: ValidatedPipeline = serde_xml_rs::from_str(&xml).unwrap();
+
+        prop_assert_eq!(parsed, pipeline);
+    }
+
+    #[test]
+    fn toml_and_yaml_round_trip(pipeline in pipeline()) {
+        for format in [FileFormat::Toml, FileFormat::Yaml] {
+            let text = format.to_string(&pipeline).unwrap();
+            let parsed: ValidatedPipeline = format.parse(&text).unwrap();
+
+            prop_assert_eq!(&parsed, &pipeline);
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use renzokutai::zones::{
+    CHECKOUT_DIR, MAX_LINK_NAME, MAX_PIPELINE_NAME, MAX_USER_NAME, share_command, validate_pipeline_name, validate_user_name,
+    vnic_name,
+};
+
+#[test]
+fn keeps_short_vnic_names_readable() {
+    assert_eq!(vnic_name("elixir", "base"), "ci_elixir_base_internal0");
+    assert_eq!(vnic_name("elixir", "a1b2"), "ci_elixir_a1b2_internal0");
+}
+
+#[test]
+fn shortens_long_vnic_names() {
+    let long = "a_rather_long_pipeline_name";
+    let base = vnic_name(long, "base");
+    let run = vnic_name(long, "a1b2");
+
+    assert!(base.len() <= MAX_LINK_NAME, "{}", base);
+    assert!(run.len() <= MAX_LINK_NAME, "{}", run);
+    assert_ne!(base, run);
+    assert_eq!(base, vnic_name(long, "base"));
+    assert_ne!(run, vnic_name("another_rather_long_pipeline", "a1b2"));
+    assert!(run.ends_with(|c: char| c.is_ascii_digit()));
+
+    let max = "x".repeat(MAX_PIPELINE_NAME);
+    assert!(vnic_name(&max, "zzzz").len() <= MAX_LINK_NAME);
+    assert!(vnic_name("has-dash", "base").chars().all(|c| c.is_ascii_alphanumeric() || c == '_'));
+}
+
+#[test]
+fn validates_pipeline_names() {
+    assert!(validate_pipeline_name("elixir").is_ok());
+    assert!(validate_pipeline_name("my-pipeline_2").is_ok());
+    assert!(validate_pipeline_name("").is_err());
+    assert!(validate_pipeline_name("_hidden").is_err());
+    assert!(validate_pipeline_name("has space").is_err());
+    assert!(validate_pipeline_name("../escape").is_err());
+    assert!(validate_pipeline_name(&"x".repeat(MAX_PIPELINE_NAME)).is_ok());
+    assert!(validate_pipeline_name(&"x".repeat(MAX_PIPELINE_NAME + 1)).is_err());
+}
+
+#[test]
+fn validates_user_names() {
+    assert!(validate_user_name("builder").is_ok());
+    assert!(validate_user_name("ci_user-2").is_ok());
+    assert!(validate_user_name("").is_err());
+    assert!(validate_user_name("2fast").is_err());
+    assert!(validate_user_name("Builder").is_err());
+    assert!(validate_user_name("ci; rm -rf /").is_err());
+    assert!(validate_user_name(&"x".repeat(MAX_USER_NAME)).is_ok());
+    assert!(validate_user_name(&"x".repeat(MAX_USER_NAME + 1)).is_err());
+}
+
+#[test]
+fn shares_paths_with_the_step_users() {
+    let command = share_command(&[CHECKOUT_DIR, "/tmp/renzokutai/build/artifacts"]);
+
+    assert!(!CHECKOUT_DIR.starts_with("/root"));
+    assert!(!command.contains("/root"), "{}", command);
+    assert!(command.contains("chgrp -R renzokutai '/opt/renzokutai/checkout' '/tmp/renzokutai/build/artifacts'"), "{}", command);
+}